@@ -1,5 +1,3 @@
-use lalrpop;
-
 fn main() {
     lalrpop::process_root().unwrap();
 }
@@ -0,0 +1,272 @@
+//! Deserialization of an evaluated program to plain Rust types.
+//!
+//! A fully evaluated term (see [`Program::eval_full`](../program/struct.Program.html#method.eval_full))
+//! implements serde's `Deserializer` trait, so that any type implementing `Deserialize` can be
+//! extracted from the result of a Nickel program. Records are mapped to maps or structs, lists to
+//! sequences, enum tags to unit variants and enum variants to the variants with data of the same
+//! name. Enriched values are transparently unwrapped, while terms without a counterpart in the
+//! serde data model, such as functions or labels, cause a
+//! [`RustDeserializationError`](./enum.RustDeserializationError.html).
+use crate::term::{RichTerm, Term};
+use serde::de::value::{MapAccessDeserializer, MapDeserializer, SeqDeserializer};
+use serde::de::{Deserializer, IntoDeserializer, Visitor};
+use std::fmt;
+
+/// An error occurring while deserializing a Nickel term to a Rust value.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RustDeserializationError {
+    /// The term was not of the expected type.
+    InvalidType { expected: String, occurred: String },
+    /// The term has no counterpart in the serde data model (functions, labels, symbols, ...).
+    UnimplementedType { occurred: String },
+    /// A generic error raised by the `Deserialize` implementation of the target type.
+    Other(String),
+}
+
+impl fmt::Display for RustDeserializationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RustDeserializationError::InvalidType { expected, occurred } => {
+                write!(f, "invalid type: {}, expected: {}", occurred, expected)
+            }
+            RustDeserializationError::UnimplementedType { occurred } => {
+                write!(f, "unsupported type for deserialization: {}", occurred)
+            }
+            RustDeserializationError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RustDeserializationError {}
+
+impl serde::de::Error for RustDeserializationError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        RustDeserializationError::Other(msg.to_string())
+    }
+}
+
+/// Return a description of the type of a term for error messages.
+fn type_name(t: &Term) -> String {
+    t.type_of().unwrap_or_else(|| String::from("Other"))
+}
+
+impl<'de> Deserializer<'de> for Term {
+    type Error = RustDeserializationError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            // Integral numbers are passed as integers, so that they can be deserialized to
+            // integer types. The float visitors of serde accept integers as well.
+            Term::Num(n) if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 => {
+                visitor.visit_i64(n as i64)
+            }
+            Term::Num(n) => visitor.visit_f64(n),
+            Term::Bool(b) => visitor.visit_bool(b),
+            Term::Str(s) => visitor.visit_string(s),
+            Term::Enum(id) => visitor.visit_enum(id.0.into_deserializer()),
+            Term::EnumVariant(id, t) => visitor.visit_enum(MapAccessDeserializer::new(
+                MapDeserializer::new(std::iter::once((id.0, *t.term))),
+            )),
+            Term::List(ts) => {
+                visitor.visit_seq(SeqDeserializer::new(ts.into_iter().map(|t| *t.term)))
+            }
+            Term::Record(map) => visitor.visit_map(MapDeserializer::new(
+                map.into_iter().map(|(id, t)| (id.0, *t.term)),
+            )),
+            Term::DefaultValue(t)
+            | Term::ContractWithDefault(_, _, t)
+            | Term::ContractWithValue(_, _, t)
+            | Term::Docstring(_, t)
+            | Term::MergeStrategy(_, t)
+            | Term::Priority(_, t) => t.term.deserialize_any(visitor),
+            t => Err(RustDeserializationError::UnimplementedType {
+                occurred: type_name(&t),
+            }),
+        }
+    }
+
+    /// Nickel does not have a null value: a term is always deserialized to `Some(_)`.
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Term::Enum(id) => visitor.visit_enum(id.0.into_deserializer()),
+            Term::EnumVariant(id, t) => visitor.visit_enum(MapAccessDeserializer::new(
+                MapDeserializer::new(std::iter::once((id.0, *t.term))),
+            )),
+            Term::DefaultValue(t)
+            | Term::ContractWithDefault(_, _, t)
+            | Term::ContractWithValue(_, _, t)
+            | Term::Docstring(_, t)
+            | Term::MergeStrategy(_, t)
+            | Term::Priority(_, t) => t.term.deserialize_enum(_name, _variants, visitor),
+            t => Err(RustDeserializationError::InvalidType {
+                expected: String::from("Enum"),
+                occurred: type_name(&t),
+            }),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, RustDeserializationError> for Term {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+/// The position of a term is irrelevant to deserialization: a rich term is deserialized as its
+/// inner term.
+impl<'de> Deserializer<'de> for RichTerm {
+    type Error = RustDeserializationError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.term.deserialize_any(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.term.deserialize_option(visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.term.deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RustDeserializationError;
+    use crate::program::Program;
+    use crate::term::RichTerm;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    fn eval_full(s: &str) -> RichTerm {
+        Program::new_from_str(s, "<test>")
+            .eval_full()
+            .expect("evaluation shouldn't fail")
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(f64::deserialize(eval_full("1 + 1.5")), Ok(2.5));
+        assert_eq!(u16::deserialize(eval_full("40 + 40")), Ok(80));
+        assert_eq!(bool::deserialize(eval_full("isZero 0")), Ok(true));
+        assert_eq!(
+            String::deserialize(eval_full("\"a\" ++ \"b\"")),
+            Ok(String::from("ab"))
+        );
+        assert_eq!(Option::<f64>::deserialize(eval_full("1")), Ok(Some(1.0)));
+    }
+
+    #[test]
+    fn lists_and_records() {
+        assert_eq!(
+            Vec::<f64>::deserialize(eval_full("[1, (1 + 1), 3]")),
+            Ok(vec![1.0, 2.0, 3.0])
+        );
+
+        let mut expected = HashMap::new();
+        expected.insert(String::from("a"), 1.0);
+        expected.insert(String::from("b"), 2.0);
+        assert_eq!(
+            HashMap::<String, f64>::deserialize(eval_full("{a = 1; b = a + 1}")),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn structs_and_enums() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum Protocol {
+            Http,
+            Https,
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum Listen {
+            Tcp { port: u16 },
+            Unix(String),
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Server {
+            host: String,
+            port: u16,
+            protocol: Protocol,
+            tags: Vec<String>,
+            listen: Vec<Listen>,
+        }
+
+        assert_eq!(
+            Server::deserialize(eval_full(
+                "{host = \"localhost\"; port = Default(80); protocol = `Https; tags = [\"a\"];
+                  listen = [(`Tcp {port = 80}), `Unix \"/tmp/socket\"]}"
+            )),
+            Ok(Server {
+                host: String::from("localhost"),
+                port: 80,
+                protocol: Protocol::Https,
+                tags: vec![String::from("a")],
+                listen: vec![
+                    Listen::Tcp { port: 80 },
+                    Listen::Unix(String::from("/tmp/socket"))
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn unsupported_terms() {
+        assert_eq!(
+            f64::deserialize(eval_full("fun x => x")),
+            Err(RustDeserializationError::UnimplementedType {
+                occurred: String::from("Fun")
+            })
+        );
+        assert!(f64::deserialize(eval_full("\"a\"")).is_err());
+    }
+}
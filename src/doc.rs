@@ -0,0 +1,466 @@
+//! Generation of documentation from the metadata of record fields.
+//!
+//! A program evaluating to a record is walked field by field. Each field is evaluated without
+//! forcing enriched values (see [`eval_meta`](../eval/fn.eval_meta.html)), such that its
+//! documentation, contract and default value can be extracted. Fields which evaluate to a record
+//! are walked recursively, but other fields are only evaluated up to their metadata: in
+//! particular, their value is never fully evaluated.
+//!
+//! A field which fails to evaluate, for example because it depends on a field which only has a
+//! contract, does not stop the generation: the metadata found before the failure is kept.
+//!
+//! The result is a list of [`FieldDoc`](./struct.FieldDoc.html), one for each field path, which
+//! can be rendered as Markdown or JSON.
+use crate::error::EvalError;
+use crate::eval::{self, Closure, Environment};
+use crate::identifier::Ident;
+use crate::program::ImportResolver;
+use crate::term::{RichTerm, Term};
+use crate::types::Types;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// The available output formats of the documentation.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DocFormat {
+    Markdown,
+    Json,
+}
+
+impl fmt::Display for DocFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DocFormat::Markdown => write!(f, "Markdown"),
+            DocFormat::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+impl FromStr for DocFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(DocFormat::Markdown),
+            "json" => Ok(DocFormat::Json),
+            _ => Err(format!(
+                "unknown documentation format `{}` (expected markdown or json)",
+                s
+            )),
+        }
+    }
+}
+
+/// The documentation of a record field.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FieldDoc {
+    /// The path of the field from the top-level record.
+    pub path: Vec<Ident>,
+    /// The content of the `Docstring` attached to the field.
+    pub doc: Option<String>,
+    /// The type of the contract attached to the field.
+    pub contract: Option<Types>,
+    /// Whether the field is optional, that is, may be absent.
+    pub optional: bool,
+    /// The fully evaluated default value of the field. Not set if the default value is a record,
+    /// whose fields are documented separately.
+    pub default: Option<Term>,
+}
+
+impl FieldDoc {
+    fn new(path: Vec<Ident>) -> Self {
+        FieldDoc {
+            path,
+            doc: None,
+            contract: None,
+            optional: false,
+            default: None,
+        }
+    }
+
+    /// Return the path of the field as a dot-separated string.
+    pub fn path_str(&self) -> String {
+        let ids: Vec<&str> = self.path.iter().map(|Ident(id)| id.as_str()).collect();
+        ids.join(".")
+    }
+}
+
+/// Extract the documentation of the fields of a record.
+///
+/// `t` must evaluate to a record. The fields are listed in depth-first order, sorted by name at
+/// each level.
+pub fn extract<R>(
+    t: RichTerm,
+    global_env: &Environment,
+    resolver: &mut R,
+) -> Result<Vec<FieldDoc>, EvalError>
+where
+    R: ImportResolver,
+{
+    let (rt, env) = eval::eval_meta(Closure::atomic_closure(t), global_env, resolver)?;
+    let mut walker = Walker {
+        global_env,
+        resolver,
+        recursive: true,
+        docs: Vec::new(),
+        visiting: Vec::new(),
+    };
+
+    match *rt.term {
+        Term::Record(map) | Term::RecRecord(map, _) => {
+            walker.fields((map, env), Vec::new())?;
+            Ok(walker.docs)
+        }
+        t => Err(EvalError::TypeError(
+            String::from("Record"),
+            String::from("documentation"),
+            None,
+            RichTerm {
+                term: Box::new(t),
+                pos: rt.pos,
+            },
+        )),
+    }
+}
+
+/// Extract the metadata of the fields of an evaluated record, given as its content and its
+/// environment, without walking the fields which are records themselves.
+///
+/// The fields are sorted by name.
+pub fn summary<R>(
+    map: HashMap<Ident, RichTerm>,
+    env: Environment,
+    global_env: &Environment,
+    resolver: &mut R,
+) -> Result<Vec<FieldDoc>, EvalError>
+where
+    R: ImportResolver,
+{
+    let mut walker = Walker {
+        global_env,
+        resolver,
+        recursive: false,
+        docs: Vec::new(),
+        visiting: Vec::new(),
+    };
+
+    walker.fields((map, env), Vec::new())?;
+    Ok(walker.docs)
+}
+
+/// The content of an evaluated record, together with its environment.
+type Fields = (HashMap<Ident, RichTerm>, Environment);
+
+/// The state of the traversal of a record.
+struct Walker<'a, R> {
+    global_env: &'a Environment,
+    resolver: &'a mut R,
+    /// Whether the fields which are records are walked as well.
+    recursive: bool,
+    /// The documentation collected so far.
+    docs: Vec<FieldDoc>,
+    /// The thunks of the fields being currently walked, used to detect a field which contains
+    /// itself, such as `a` in `{a = {b = a}}`.
+    visiting: Vec<Rc<RefCell<Closure>>>,
+}
+
+impl<'a, R: ImportResolver> Walker<'a, R> {
+    /// Document the fields of an evaluated record.
+    fn fields(&mut self, (map, env): Fields, path: Vec<Ident>) -> Result<(), EvalError> {
+        let mut fields: Vec<(Ident, RichTerm)> = map.into_iter().collect();
+        fields.sort_by(|(id1, _), (id2, _)| id1.0.cmp(&id2.0));
+
+        for (id, t) in fields {
+            let mut field_path = path.clone();
+            field_path.push(id);
+
+            // Fields are generally variables pointing to thunks, as records are put in share
+            // normal form by the program transformations.
+            let thunk = match t.as_ref() {
+                Term::Var(var) => env.get(var).map(|(thunk, _, _)| thunk.clone()),
+                _ => None,
+            };
+            if let Some(thunk) = &thunk {
+                if self.visiting.iter().any(|rc| Rc::ptr_eq(rc, thunk)) {
+                    self.docs.push(FieldDoc::new(field_path));
+                    continue;
+                }
+                self.visiting.push(thunk.clone());
+            }
+
+            self.field(
+                Closure {
+                    body: t,
+                    env: env.clone(),
+                },
+                field_path,
+            )?;
+
+            if thunk.is_some() {
+                self.visiting.pop();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Document a field, and its subfields if it evaluates to a record.
+    fn field(&mut self, mut clos: Closure, path: Vec<Ident>) -> Result<(), EvalError> {
+        let mut field_doc = FieldDoc::new(path.clone());
+
+        let record = loop {
+            let (rt, env) = match eval::eval_meta(clos, self.global_env, self.resolver) {
+                Ok(result) => result,
+                Err(_) => break None,
+            };
+
+            match *rt.term {
+                Term::Docstring(s, t) => {
+                    field_doc.doc.get_or_insert(s);
+                    clos = Closure { body: t, env };
+                }
+                Term::Optional(t) => {
+                    field_doc.optional = true;
+                    clos = Closure { body: t, env };
+                }
+                Term::MergeStrategy(_, t) | Term::Priority(_, t) => clos = Closure { body: t, env },
+                Term::Contract(ty, _) => {
+                    field_doc.contract = Some(ty);
+                    break None;
+                }
+                Term::ContractWithDefault(ty, _, t) => {
+                    field_doc.contract = Some(ty);
+                    break self.default(&mut field_doc, Closure { body: t, env });
+                }
+                Term::ContractWithValue(ty, _, t) => {
+                    field_doc.contract = Some(ty);
+                    clos = Closure { body: t, env };
+                }
+                Term::DefaultValue(t) => {
+                    break self.default(&mut field_doc, Closure { body: t, env });
+                }
+                Term::Record(map) | Term::RecRecord(map, _) => break Some((map, env)),
+                _ => break None,
+            }
+        };
+
+        self.docs.push(field_doc);
+        match record {
+            Some(fields) if self.recursive => self.fields(fields, path)?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate the default value of a field. If it is a record, return its content so that its
+    /// fields can be documented. Otherwise, fully evaluate it and store it in `field_doc`, unless
+    /// its evaluation fails.
+    fn default(&mut self, field_doc: &mut FieldDoc, clos: Closure) -> Option<Fields> {
+        let (rt, env) = eval::eval_meta(clos, self.global_env, self.resolver).ok()?;
+
+        match *rt.term {
+            Term::Record(map) | Term::RecRecord(map, _) => Some((map, env)),
+            t => {
+                let mut env_full = self.global_env.clone();
+                env_full.extend(env);
+                let t = RichTerm {
+                    term: Box::new(t),
+                    pos: rt.pos,
+                };
+                field_doc.default = eval::eval_full(t, env_full, self.resolver)
+                    .ok()
+                    .map(|rt| *rt.term);
+                None
+            }
+        }
+    }
+}
+
+/// Return a compact representation of a fully evaluated term: JSON if it can be serialized, or a
+/// shallow representation otherwise.
+fn value_repr(t: &Term) -> serde_json::Value {
+    let rt = RichTerm::from(t.clone());
+    serde_json::to_value(&rt).unwrap_or_else(|_| serde_json::Value::String(t.shallow_repr()))
+}
+
+/// Render the documentation as Markdown: one section per field.
+pub fn to_markdown(docs: &[FieldDoc]) -> String {
+    let sections: Vec<String> = docs
+        .iter()
+        .map(|field_doc| {
+            let mut section = format!("## `{}`\n", field_doc.path_str());
+
+            if let Some(doc) = &field_doc.doc {
+                section.push_str(&format!("\n{}\n", doc));
+            }
+
+            let mut items = Vec::new();
+            if let Some(ty) = &field_doc.contract {
+                items.push(format!("- Contract: `{}`", ty));
+            }
+            if field_doc.optional {
+                items.push(String::from("- Optional"));
+            }
+            if let Some(t) = &field_doc.default {
+                items.push(format!("- Default: `{}`", value_repr(t)));
+            }
+            if !items.is_empty() {
+                section.push_str(&format!("\n{}\n", items.join("\n")));
+            }
+
+            section
+        })
+        .collect();
+
+    sections.join("\n")
+}
+
+/// The JSON representation of a field documentation.
+#[derive(Serialize)]
+struct JsonFieldDoc {
+    path: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    doc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contract: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    optional: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<serde_json::Value>,
+}
+
+/// Render the documentation as a JSON list of fields.
+pub fn to_json(docs: &[FieldDoc]) -> String {
+    let json_docs: Vec<JsonFieldDoc> = docs
+        .iter()
+        .map(|field_doc| JsonFieldDoc {
+            path: field_doc.path.iter().map(|Ident(id)| id.clone()).collect(),
+            doc: field_doc.doc.clone(),
+            contract: field_doc.contract.as_ref().map(|ty| format!("{}", ty)),
+            optional: field_doc.optional,
+            default: field_doc.default.as_ref().map(value_repr),
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&json_docs)
+        .expect("doc::to_json(): documentation should always be serializable")
+}
+
+/// Render the documentation in the given format.
+pub fn render(docs: &[FieldDoc], format: DocFormat) -> String {
+    match format {
+        DocFormat::Markdown => to_markdown(docs),
+        DocFormat::Json => to_json(docs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+    use crate::types::AbsType;
+
+    fn docs(src: &str) -> Vec<FieldDoc> {
+        Program::new_from_str(src, "<test>").doc().unwrap()
+    }
+
+    fn path(s: &str) -> Vec<Ident> {
+        s.split('.').map(Ident::from).collect()
+    }
+
+    #[test]
+    fn extract() {
+        let result = docs(
+            "let srv = merge
+                {port = Docstring(\"The port\", ContractDefault(Num, 80)); host = Contract(Str)}
+                {host = \"localhost\"} in
+             {server = Docstring(\"The server\", srv); name = Default(\"a\"); broken = 1 + true}",
+        );
+
+        assert_eq!(
+            result,
+            vec![
+                FieldDoc::new(path("broken")),
+                FieldDoc {
+                    default: Some(Term::Str(String::from("a"))),
+                    ..FieldDoc::new(path("name"))
+                },
+                FieldDoc {
+                    doc: Some(String::from("The server")),
+                    ..FieldDoc::new(path("server"))
+                },
+                FieldDoc {
+                    contract: Some(Types(AbsType::Str())),
+                    ..FieldDoc::new(path("server.host"))
+                },
+                FieldDoc {
+                    doc: Some(String::from("The port")),
+                    contract: Some(Types(AbsType::Num())),
+                    default: Some(Term::Num(80.0)),
+                    ..FieldDoc::new(path("server.port"))
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn default_records_and_cycles() {
+        let result = docs("{a = Default({b = Docstring(\"b\", 1)}); c = {d = c}}");
+        let paths: Vec<String> = result.iter().map(FieldDoc::path_str).collect();
+
+        // The walk of `c` stops as soon as a thunk is visited twice along the same path.
+        assert_eq!(paths[..4], ["a", "a.b", "c", "c.d"]);
+        assert_eq!(result[1].doc, Some(String::from("b")));
+    }
+
+    #[test]
+    fn defined_fields() {
+        let result = docs("{port | Num | doc \"The port\" = 80; srv | {_: Num} = {a = 1}}");
+
+        assert_eq!(
+            result,
+            vec![
+                FieldDoc {
+                    doc: Some(String::from("The port")),
+                    contract: Some(Types(AbsType::Num())),
+                    ..FieldDoc::new(path("port"))
+                },
+                FieldDoc {
+                    contract: Some(Types(AbsType::DynRecord(Box::new(Types(AbsType::Num()))))),
+                    ..FieldDoc::new(path("srv"))
+                },
+                FieldDoc::new(path("srv.a")),
+            ]
+        );
+    }
+
+    #[test]
+    fn non_record() {
+        assert!(Program::new_from_str("1", "<test>").doc().is_err());
+    }
+
+    #[test]
+    fn render() {
+        let result =
+            docs("{a | Num | default = 1 | doc \"The a field\"; b = 2; c | Str | optional}");
+
+        assert_eq!(
+            to_markdown(&result),
+            "## `a`\n\nThe a field\n\n- Contract: `Num`\n- Default: `1`\n\n## `b`\n\n\
+             ## `c`\n\n- Contract: `Str`\n- Optional\n"
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&to_json(&result)).unwrap(),
+            serde_json::json!([
+                {"path": ["a"], "doc": "The a field", "contract": "Num", "default": 1},
+                {"path": ["b"]},
+                {"path": ["c"], "contract": "Str", "optional": true},
+            ])
+        );
+    }
+}
@@ -5,11 +5,13 @@
 use crate::eval::{CallStack, StackElem};
 use crate::identifier::Ident;
 use crate::label;
-use crate::label::ty_path;
-use crate::parser::lexer::LexicalError;
+use crate::label::{access_path, ty_path};
+use crate::parser::error::ParseError as InternalParseError;
+use crate::parser::lexer::{LexicalError, NormalToken, Token};
 use crate::parser::utils::mk_span;
 use crate::position::RawSpan;
-use crate::term::RichTerm;
+use crate::serialize::ExportFormat;
+use crate::term::{MergeStrategy, RichTerm, Term};
 use crate::types::Types;
 use codespan::{FileId, Files};
 use codespan_reporting::diagnostic::{Diagnostic, Label, LabelStyle};
@@ -19,16 +21,17 @@ use std::fmt::Write;
 #[derive(Debug, PartialEq, Clone)]
 pub enum Error {
     EvalError(EvalError),
-    TypecheckError(TypecheckError),
+    TypecheckError(Box<TypecheckError>),
     ParseError(ParseError),
     ImportError(ImportError),
+    SerializationError(SerializationError),
 }
 
 /// An error occurring during evaluation.
 #[derive(Debug, PartialEq, Clone)]
 pub enum EvalError {
     /// A blame occurred: a contract have been broken somewhere.
-    BlameError(label::Label, Option<CallStack>),
+    BlameError(Box<label::Label>, Option<CallStack>),
     /// Mismatch between the expected type and the actual type of an expression.
     TypeError(
         /* expected type */ String,
@@ -62,9 +65,43 @@ pub enum EvalError {
         /* left operand */ RichTerm,
         /* right operand */ RichTerm,
         /* original merge */ Option<RawSpan>,
+        /* path of the merged field */ Vec<Ident>,
+    ),
+    /// Attempted to merge two values of a field with different merge strategies, such as
+    /// `merge {l | merge concat = [1]} {l | merge elementwise = [2]}`.
+    MergeStrategyMismatch(
+        /* left strategy */ MergeStrategy,
+        /* left field definition */ Option<RawSpan>,
+        /* right strategy */ MergeStrategy,
+        /* right field definition */ Option<RawSpan>,
+        /* original merge */ Option<RawSpan>,
+        /* path of the merged field */ Vec<Ident>,
     ),
     /// An unbound identifier was referenced.
     UnboundIdentifier(Ident, Option<RawSpan>),
+    /// A dynamic field was added to a record which already has a field with the same name.
+    DuplicateField(
+        /* field identifier */ String,
+        /* record */ RichTerm,
+        /* field definition */ Option<RawSpan>,
+    ),
+    /// A record destructured by a closed record pattern has a field which is not in the pattern.
+    UnexpectedField(
+        /* field identifier */ String,
+        /* record */ RichTerm,
+        /* pattern position */ Option<RawSpan>,
+    ),
+    /// A list destructured by a list pattern does not have the length of the pattern.
+    PatternMismatch(
+        /* length of the pattern */ usize,
+        /* list */ RichTerm,
+        /* pattern position */ Option<RawSpan>,
+    ),
+    /// No case of a match expression matched the value.
+    NonExhaustiveMatch(
+        /* matched value */ RichTerm,
+        /* match position */ Option<RawSpan>,
+    ),
     /// An unexpected internal error.
     InternalError(String, Option<RawSpan>),
     /// Errors occurring rarely enough to not deserve a dedicated variant.
@@ -101,7 +138,7 @@ pub enum TypecheckError {
         /* the actual type */ Types,
         Option<RawSpan>,
     ),
-    /// Two incompatible kind (enum vs record) have been deduced for the same identifier of a row type.
+    /// An enum tag has been deduced to be both a bare tag and a variant carrying a value.
     RowKindMismatch(
         Ident,
         /* the expected type */ Option<Types>,
@@ -131,7 +168,7 @@ pub enum TypecheckError {
     /// direct failure to unify `{| .. , x: T1, .. }` and `{| .., x: T2, .. }`.
     RowConflict(
         Ident,
-        /* the second type assignment which violates the constraint */ Option<Types>,
+        /* the second type assignment which violates the constraint */ Option<Box<Types>>,
         /* the expected type of the subexpression */ Types,
         /* the actual type of the subexpression */ Types,
         Option<RawSpan>,
@@ -145,7 +182,7 @@ pub enum TypecheckError {
     /// which deserves a good reporting, that can be caused e.g. by applying a function to an
     /// argument of a wrong type in some cases:
     ///
-    /// ```
+    /// ```text
     /// Promise(Num, let id_mono = fun x => x in let _ign = id_mono true in id_mono 0)
     /// ```
     ///
@@ -158,6 +195,15 @@ pub enum TypecheckError {
         /* the error on the subtype unification */ Box<TypecheckError>,
         Option<RawSpan>,
     ),
+    /// The type of the value matched by a match expression without a catch-all case contains
+    /// enum tags which are not handled by any case, or is an open enum type, which may contain
+    /// other tags than the ones of the cases.
+    NonExhaustiveMatch(
+        /* the missing tags */ Vec<Ident>,
+        /* whether the type of the matched value is open */ bool,
+        /* the match position */ Option<RawSpan>,
+        /* the matched value position */ Option<RawSpan>,
+    ),
 }
 
 /// An error occurring during parsing.
@@ -177,6 +223,39 @@ pub enum ParseError {
     UnmatchedCloseBrace(RawSpan),
     /// Invalid escape sequence in a string literal.
     InvalidEscapeSequence(RawSpan),
+    /// A block comment is not closed before the end of the file.
+    UnterminatedComment(RawSpan),
+    /// A malformed number literal, or a number literal whose value is not representable.
+    InvalidNumLiteral(RawSpan),
+    /// A field path is defined several times in a record literal.
+    DuplicateField(
+        /* field path */ String,
+        /* first definition */ RawSpan,
+        /* second definition */ RawSpan,
+    ),
+    /// An unknown list merge strategy was given in a field annotation.
+    UnknownMergeStrategy(String, RawSpan),
+    /// An unknown keyword was used in a field annotation.
+    UnknownAnnotation(String, RawSpan),
+    /// A default value annotation `| default` is not followed by a value.
+    MissingDefaultValue(RawSpan),
+    /// The definition of a field is followed by annotations or by another definition.
+    AnnotationAfterDefinition(RawSpan),
+    /// A field has both a default value and a definition.
+    DefaultWithDefinition(
+        /* default annotation */ RawSpan,
+        /* definition */ RawSpan,
+    ),
+    /// A field with a priority annotation has no definition.
+    UndefinedPriority(RawSpan),
+    /// A doc comment does not precede a record field.
+    MisplacedDocComment(RawSpan),
+    /// An error occurring when parsing a data file, such as an imported JSON file.
+    DataFormatError(
+        /* format */ String,
+        /* error message */ String,
+        Option<RawSpan>,
+    ),
 }
 
 /// An error occurring during the resolution of an import.
@@ -195,6 +274,22 @@ pub enum ImportError {
     ),
 }
 
+/// An error occurring during the serialization of an evaluated term.
+#[derive(Debug, PartialEq, Clone)]
+pub enum SerializationError {
+    /// Encountered a term which has no counterpart in the target format, such as a function.
+    NotSerializable(RichTerm),
+    /// The top-level value is not a record, but the format requires one (e.g. TOML).
+    NonRecordTopLevel(ExportFormat, RichTerm),
+    /// A number which cannot be represented in the format, such as `NaN` in JSON.
+    UnrepresentableNum(ExportFormat, RichTerm),
+    /// A list which mixes records with other values, which the format cannot represent (e.g.
+    /// TOML).
+    MixedList(ExportFormat, RichTerm),
+    /// An error raised by the serialization backend.
+    Other(ExportFormat, String),
+}
+
 impl From<EvalError> for Error {
     fn from(error: EvalError) -> Error {
         Error::EvalError(error)
@@ -209,7 +304,7 @@ impl From<ParseError> for Error {
 
 impl From<TypecheckError> for Error {
     fn from(error: TypecheckError) -> Error {
-        Error::TypecheckError(error)
+        Error::TypecheckError(Box::new(error))
     }
 }
 
@@ -219,21 +314,38 @@ impl From<ImportError> for Error {
     }
 }
 
+impl From<SerializationError> for Error {
+    fn from(error: SerializationError) -> Error {
+        Error::SerializationError(error)
+    }
+}
+
 impl ParseError {
-    pub fn from_lalrpop<T>(
-        error: lalrpop_util::ParseError<usize, T, LexicalError>,
+    pub fn from_lalrpop(
+        error: lalrpop_util::ParseError<usize, Token<'_>, InternalParseError>,
         file_id: FileId,
     ) -> ParseError {
         match error {
             lalrpop_util::ParseError::InvalidToken { location } => {
                 ParseError::UnexpectedToken(mk_span(file_id, location, location + 1), Vec::new())
             }
+            // Doc comments are only expected before a record field.
+            lalrpop_util::ParseError::UnrecognizedToken {
+                token: Some((start, Token::Normal(NormalToken::DocComment(_)), end)),
+                ..
+            }
+            | lalrpop_util::ParseError::ExtraToken {
+                token: (start, Token::Normal(NormalToken::DocComment(_)), end),
+            }
+            | lalrpop_util::ParseError::User {
+                error: InternalParseError::MisplacedDocComment(start, end),
+            } => ParseError::MisplacedDocComment(mk_span(file_id, start, end)),
             lalrpop_util::ParseError::UnrecognizedToken {
                 token: Some((start, _, end)),
                 expected,
             } => ParseError::UnexpectedToken(mk_span(file_id, start, end), expected),
             lalrpop_util::ParseError::User {
-                error: LexicalError::Generic(start, end),
+                error: InternalParseError::Lexical(LexicalError::Generic(start, end)),
             } => ParseError::UnexpectedToken(mk_span(file_id, start, end), Vec::new()),
             lalrpop_util::ParseError::UnrecognizedToken {
                 token: None,
@@ -243,11 +355,43 @@ impl ParseError {
                 token: (start, _, end),
             } => ParseError::ExtraToken(mk_span(file_id, start, end)),
             lalrpop_util::ParseError::User {
-                error: LexicalError::UnmatchedCloseBrace(location),
+                error: InternalParseError::Lexical(LexicalError::UnmatchedCloseBrace(location)),
             } => ParseError::UnmatchedCloseBrace(mk_span(file_id, location, location + 1)),
             lalrpop_util::ParseError::User {
-                error: LexicalError::InvalidEscapeSequence(location),
+                error: InternalParseError::Lexical(LexicalError::InvalidEscapeSequence(location)),
             } => ParseError::InvalidEscapeSequence(mk_span(file_id, location, location + 1)),
+            lalrpop_util::ParseError::User {
+                error: InternalParseError::Lexical(LexicalError::UnterminatedComment(location)),
+            } => ParseError::UnterminatedComment(mk_span(file_id, location, location + 2)),
+            lalrpop_util::ParseError::User {
+                error: InternalParseError::Lexical(LexicalError::InvalidNumLiteral(start, end)),
+            } => ParseError::InvalidNumLiteral(mk_span(file_id, start, end)),
+            lalrpop_util::ParseError::User {
+                error: InternalParseError::DuplicateField(path, (l1, r1), (l2, r2)),
+            } => {
+                ParseError::DuplicateField(path, mk_span(file_id, l1, r1), mk_span(file_id, l2, r2))
+            }
+            lalrpop_util::ParseError::User {
+                error: InternalParseError::UnknownMergeStrategy(strategy, start, end),
+            } => ParseError::UnknownMergeStrategy(strategy, mk_span(file_id, start, end)),
+            lalrpop_util::ParseError::User {
+                error: InternalParseError::UnknownAnnotation(annot, start, end),
+            } => ParseError::UnknownAnnotation(annot, mk_span(file_id, start, end)),
+            lalrpop_util::ParseError::User {
+                error: InternalParseError::MissingDefaultValue(start, end),
+            } => ParseError::MissingDefaultValue(mk_span(file_id, start, end)),
+            lalrpop_util::ParseError::User {
+                error: InternalParseError::AnnotationAfterDefinition(start, end),
+            } => ParseError::AnnotationAfterDefinition(mk_span(file_id, start, end)),
+            lalrpop_util::ParseError::User {
+                error: InternalParseError::DefaultWithDefinition((l1, r1), (l2, r2)),
+            } => ParseError::DefaultWithDefinition(
+                mk_span(file_id, l1, r1),
+                mk_span(file_id, l2, r2),
+            ),
+            lalrpop_util::ParseError::User {
+                error: InternalParseError::UndefinedPriority(start, end),
+            } => ParseError::UndefinedPriority(mk_span(file_id, start, end)),
         }
     }
 }
@@ -309,7 +453,7 @@ fn secondary(span: &RawSpan) -> Label<FileId> {
 /// For example, if one wants to report an error on a record, `alt_term` may be defined to `{ ...  }`.
 /// Then, if this record has no position (`span_opt` is `None`), the error will be reported as:
 ///
-/// ```
+/// ```text
 /// error: some error
 ///   -- <unkown> (generated by evaluation):1:2
 ///   |
@@ -326,7 +470,7 @@ fn secondary(span: &RawSpan) -> Label<FileId> {
 ///    additional text placed at the end of diagnostic. What you lose:
 ///     - pretty formatting of annotations for such snippets
 ///     - style consistency: the style of the error now depends on the term being from the source
-///     or a byproduct of evaluation
+///       or a byproduct of evaluation
 /// 3. Add the term to files, take 1: pass a reference to files so that the code building the
 ///    diagnostic can itself add arbitrary snippets if necessary, and get back their `FileId`. This
 ///    is what is done here.
@@ -434,11 +578,7 @@ fn report_ty_path(l: &label::Label, files: &mut Files<String>) -> (Label<FileId>
         let last = l
             .path
             .iter()
-            .filter(|elt| match *elt {
-                ty_path::Elem::Field(_) => false,
-                _ => true,
-            })
-            .last()
+            .rfind(|elt| !matches!(*elt, ty_path::Elem::Field(_)))
             .unwrap();
         match last {
                 ty_path::Elem::Domain if l.polarity => {
@@ -499,6 +639,31 @@ fn report_ty_path(l: &label::Label, files: &mut Files<String>) -> (Label<FileId>
     (label, notes)
 }
 
+/// Summarize which value broke a contract and where it is located in the evaluated term, as in
+/// "`servers[2].port`: expected Num, got \"80\"".
+///
+/// Return `None` if neither the value nor its access path is known.
+fn report_blamed_value(l: &label::Label) -> Option<String> {
+    if l.value.is_none() && l.access_path.is_empty() {
+        return None;
+    }
+
+    let ty = format!("{}", l.types);
+    let (start, end) = ty_path::span(l.path.iter().peekable(), &l.types);
+    let mut note = String::new();
+
+    // Writing in a string should not raise an error, whence the fearless `unwrap()`
+    if !l.access_path.is_empty() {
+        write!(&mut note, "`{}`: ", access_path::to_string(&l.access_path)).unwrap();
+    }
+    write!(&mut note, "expected {}", &ty[start..end]).unwrap();
+    if let Some(value) = &l.value {
+        write!(&mut note, ", got {}", value).unwrap();
+    }
+
+    Some(note)
+}
+
 /// Process a raw callstack by grouping elements belonging to the same call and getting rid of
 /// elements that are not associated to a call.
 ///
@@ -552,21 +717,17 @@ pub fn process_callstack(cs: &CallStack, contract_id: FileId) -> Vec<(Option<Ide
         }
     }
 
-    let it = cs.iter().filter(|elem| match elem {
-        StackElem::Var(_, _, Some(RawSpan { src_id, .. }))
+    let it = cs.iter().filter(|elem| {
+        matches!(elem, StackElem::Var(_, _, Some(RawSpan { src_id, .. }))
         | StackElem::App(Some(RawSpan { src_id, .. }))
-            if *src_id != contract_id =>
-        {
-            true
-        }
-        _ => false,
+            if *src_id != contract_id)
     });
 
     // To decide how to fuse calls, we need to see two successive elements of the callstack at each
     // iteration. To do so, we create a zipper of the original iterator with a copy of the iterator
     // shifted by one element, which returns options to be able to iter until the very last element
     // (it is padded with an ending `None`).
-    let shifted = it.clone().skip(1).map(|elem| Some(elem)).chain(Some(None));
+    let shifted = it.clone().skip(1).map(Some).chain(Some(None));
     let mut it = it.peekable();
 
     // The call element being currently built.
@@ -653,6 +814,7 @@ impl ToDiagnostic<FileId> for Error {
             Error::TypecheckError(err) => err.to_diagnostic(files, contract_id),
             Error::EvalError(err) => err.to_diagnostic(files, contract_id),
             Error::ImportError(err) => err.to_diagnostic(files, contract_id),
+            Error::SerializationError(err) => err.to_diagnostic(files, contract_id),
         }
     }
 }
@@ -686,7 +848,10 @@ impl ToDiagnostic<FileId> for EvalError {
                     write!(&mut msg, ".").unwrap();
                 }
 
-                let (path_label, notes) = report_ty_path(&l, files);
+                let (path_label, mut notes) = report_ty_path(l, files);
+                if let Some(note) = report_blamed_value(l) {
+                    notes.insert(0, note);
+                }
                 let labels = vec![
                     path_label,
                     Label::primary(
@@ -736,9 +901,9 @@ impl ToDiagnostic<FileId> for EvalError {
                 let labels = match orig_pos_opt {
                     Some(pos) if orig_pos_opt != &t.pos => vec![
                         primary(pos).with_message(label),
-                        secondary_term(&t, files).with_message("evaluated to this"),
+                        secondary_term(t, files).with_message("evaluated to this"),
                     ],
-                    _ => vec![primary_term(&t, files).with_message(label)],
+                    _ => vec![primary_term(t, files).with_message(label)],
                 };
 
                 vec![Diagnostic::error()
@@ -749,10 +914,10 @@ impl ToDiagnostic<FileId> for EvalError {
             EvalError::NotAFunc(t, arg, pos_opt) => vec![Diagnostic::error()
                 .with_message("Not a function")
                 .with_labels(vec![
-                    primary_term(&t, files)
+                    primary_term(t, files)
                         .with_message("this term is applied, but it is not a function"),
                     secondary_alt(
-                        &pos_opt,
+                        pos_opt,
                         format!(
                             "({}) ({})",
                             (*t.term).shallow_repr(),
@@ -810,24 +975,158 @@ impl ToDiagnostic<FileId> for EvalError {
                     .with_labels(labels)
                     .with_notes(notes)]
             }
-            EvalError::MergeIncompatibleArgs(t1, t2, span_opt) => {
+            EvalError::MergeIncompatibleArgs(t1, t2, span_opt, path) => {
                 let mut labels = vec![
-                    primary_term(&t1, files).with_message("cannot merge this expression"),
-                    primary_term(&t2, files).with_message("with this expression"),
+                    primary_term(t1, files).with_message("cannot merge this expression"),
+                    primary_term(t2, files).with_message("with this expression"),
                 ];
 
                 if let Some(span) = span_opt {
-                    labels.push(secondary(&span).with_message("merged here"));
+                    labels.push(secondary(span).with_message("merged here"));
                 }
 
-                vec![Diagnostic::error()
+                let mut diagnostic = Diagnostic::error()
                     .with_message("Non mergeable terms")
-                    .with_labels(labels)]
+                    .with_labels(labels);
+
+                if !path.is_empty() {
+                    let path: Vec<&str> = path.iter().map(|Ident(id)| id.as_str()).collect();
+                    diagnostic = diagnostic.with_notes(vec![format!(
+                        "while merging the field `{}`",
+                        path.join(".")
+                    )]);
+                }
+
+                vec![diagnostic]
+            }
+            EvalError::MergeStrategyMismatch(s1, span1, s2, span2, span_opt, path) => {
+                let labels = [(s1, span1), (s2, span2)]
+                    .iter()
+                    .filter_map(|(s, span)| {
+                        span.as_ref().map(|span| {
+                            primary(span)
+                                .with_message(format!("defined with the merge strategy `{}`", s))
+                        })
+                    })
+                    .chain(
+                        span_opt
+                            .as_ref()
+                            .map(|span| secondary(span).with_message("merged here")),
+                    )
+                    .collect();
+
+                let mut notes = Vec::new();
+
+                if !path.is_empty() {
+                    let path: Vec<&str> = path.iter().map(|Ident(id)| id.as_str()).collect();
+                    notes.push(format!("while merging the field `{}`", path.join(".")));
+                }
+
+                notes.push(String::from(
+                    "both definitions of a field must use the same merge strategy",
+                ));
+
+                vec![Diagnostic::error()
+                    .with_message(format!(
+                        "Incompatible merge strategies `{}` and `{}`",
+                        s1, s2
+                    ))
+                    .with_labels(labels)
+                    .with_notes(notes)]
             }
             EvalError::UnboundIdentifier(Ident(ident), span_opt) => vec![Diagnostic::error()
                 .with_message("Unbound identifier")
                 .with_labels(vec![primary_alt(span_opt, ident.clone(), files)
                     .with_message("this identifier is unbound")])],
+            EvalError::DuplicateField(field, t, span_opt) => {
+                let mut labels = Vec::new();
+
+                if let Some(span) = span_opt {
+                    labels.push(
+                        primary(span).with_message(format!("field `{}` defined here", field)),
+                    );
+                }
+
+                if let Some(ref span) = t.pos {
+                    labels.push(
+                        secondary(span)
+                            .with_message(format!("field `{}` already exists here", field)),
+                    );
+                }
+
+                vec![Diagnostic::error()
+                    .with_message(format!("Multiple definitions of the field `{}`", field))
+                    .with_labels(labels)]
+            }
+            EvalError::UnexpectedField(field, t, span_opt) => {
+                let mut labels = Vec::new();
+
+                if let Some(span) = span_opt {
+                    labels.push(
+                        primary(span)
+                            .with_message(format!("this pattern has no field `{}`", field)),
+                    );
+                }
+
+                if let Some(ref span) = t.pos {
+                    labels.push(secondary(span).with_message("this record"));
+                }
+
+                vec![Diagnostic::error()
+                    .with_message(format!("Unexpected field `{}`", field))
+                    .with_labels(labels)
+                    .with_notes(vec![String::from(
+                        "a record pattern only accepts other fields if it ends with `..`, as in \
+                         `{a, ..}`",
+                    )])]
+            }
+            EvalError::PatternMismatch(n, t, span_opt) => {
+                let mut labels = Vec::new();
+
+                if let Some(span) = span_opt {
+                    labels.push(
+                        primary(span)
+                            .with_message(format!("this pattern expects a list of length {}", n)),
+                    );
+                }
+
+                if let Some(ref span) = t.pos {
+                    labels.push(secondary(span).with_message("this list"));
+                }
+
+                let len = match t.as_ref() {
+                    Term::List(ts) => ts.len(),
+                    _ => 0,
+                };
+
+                vec![Diagnostic::error()
+                    .with_message("Pattern mismatch")
+                    .with_labels(labels)
+                    .with_notes(vec![format!(
+                        "a list pattern only matches lists of the same length, but the list has \
+                         length {}",
+                        len
+                    )])]
+            }
+            EvalError::NonExhaustiveMatch(t, span_opt) => {
+                let mut labels = Vec::new();
+
+                if let Some(span) = span_opt {
+                    labels.push(primary(span).with_message("in this match expression"));
+                }
+
+                if let Some(ref span) = t.pos {
+                    labels.push(secondary(span).with_message("this value"));
+                }
+
+                vec![Diagnostic::error()
+                    .with_message("Non-exhaustive match")
+                    .with_labels(labels)
+                    .with_notes(vec![format!(
+                        "No case matched the value {}",
+                        (*t.term).shallow_repr()
+                    )])]
+            }
             EvalError::Other(msg, span_opt) => {
                 let labels = span_opt
                     .as_ref()
@@ -861,7 +1160,7 @@ impl ToDiagnostic<FileId> for ParseError {
             ParseError::UnexpectedEOF(file_id, _expected) => {
                 Diagnostic::error().with_message(format!(
                     "Unexpected end of file when parsing {}",
-                    files.name(file_id.clone()).to_string_lossy()
+                    files.name(*file_id).to_string_lossy()
                 ))
             }
             ParseError::UnexpectedToken(span, _expected) => Diagnostic::error()
@@ -876,6 +1175,93 @@ impl ToDiagnostic<FileId> for ParseError {
             ParseError::InvalidEscapeSequence(span) => Diagnostic::error()
                 .with_message("Invalid escape sequence")
                 .with_labels(vec![primary(span)]),
+            ParseError::UnterminatedComment(span) => Diagnostic::error()
+                .with_message("Unterminated block comment")
+                .with_labels(vec![
+                    primary(span).with_message("this comment is never closed")
+                ]),
+            ParseError::InvalidNumLiteral(span) => Diagnostic::error()
+                .with_message("Invalid number literal")
+                .with_labels(vec![primary(span)])
+                .with_notes(vec![String::from(
+                    "a number is written in decimal, as in `1`, `1.5` or `1.5e-3`, or as a \
+                     hexadecimal, octal or binary integer, as in `0xff`, `0o755` or `0b1010`, \
+                     and must be finite",
+                )]),
+            ParseError::DuplicateField(path, first, second) => Diagnostic::error()
+                .with_message(format!("Multiple definitions of the field `{}`", path))
+                .with_labels(vec![
+                    primary(second).with_message("redefined here"),
+                    secondary(first).with_message("first defined here"),
+                ])
+                .with_notes(vec![String::from(
+                    "a field can only be defined once in a record literal",
+                )]),
+            ParseError::UnknownMergeStrategy(strategy, span) => Diagnostic::error()
+                .with_message(format!("Unknown merge strategy `{}`", strategy))
+                .with_labels(vec![primary(span)])
+                .with_notes(vec![String::from(
+                    "the available merge strategies are `elementwise` and `concat`",
+                )]),
+            ParseError::UnknownAnnotation(annot, span) => Diagnostic::error()
+                .with_message(format!("Unknown field annotation `{}`", annot))
+                .with_labels(vec![primary(span)])
+                .with_notes(vec![String::from(
+                    "the annotations taking an argument are `doc \"...\"`, `default = ...`, \
+                     `priority <number>` and `merge <strategy>`",
+                )]),
+            ParseError::MissingDefaultValue(span) => Diagnostic::error()
+                .with_message("Default annotation without a value")
+                .with_labels(vec![primary(span)])
+                .with_notes(vec![String::from(
+                    "a default value is written `| default = <value>`, as in \
+                     `port | default = 80`",
+                )]),
+            ParseError::AnnotationAfterDefinition(span) => Diagnostic::error()
+                .with_message("Field definition followed by an annotation")
+                .with_labels(vec![
+                    primary(span).with_message("nothing can follow this definition")
+                ])
+                .with_notes(vec![String::from(
+                    "the annotations of a field come before its definition, as in \
+                     `port | Num = 80`",
+                )]),
+            ParseError::DefaultWithDefinition(default, definition) => Diagnostic::error()
+                .with_message("Field with both a default value and a definition")
+                .with_labels(vec![
+                    primary(definition)
+                        .with_message("this definition would always override the default value"),
+                    secondary(default).with_message("default value"),
+                ])
+                .with_notes(vec![String::from(
+                    "a field is either defined, as in `port | Num = 80`, or given a default \
+                     value, as in `port | Num | default = 80`",
+                )]),
+            ParseError::UndefinedPriority(span) => Diagnostic::error()
+                .with_message("Priority without definition")
+                .with_labels(vec![
+                    primary(span).with_message("this field has a priority but no definition")
+                ])
+                .with_notes(vec![String::from(
+                    "a priority applies to the definition of a field, as in \
+                     `port | priority 10 = 8080`",
+                )]),
+            ParseError::MisplacedDocComment(span) => Diagnostic::error()
+                .with_message("Doc comment not followed by a record field")
+                .with_labels(vec![primary(span)])
+                .with_notes(vec![String::from(
+                    "a doc comment `/// ...` documents the record field which follows it: use \
+                     `// ...` for an ordinary comment",
+                )]),
+            ParseError::DataFormatError(format, msg, span_opt) => Diagnostic::error()
+                .with_message(format!("{} parse error", format))
+                .with_labels(
+                    span_opt
+                        .as_ref()
+                        .map(|span| vec![primary(span)])
+                        .unwrap_or_default(),
+                )
+                .with_notes(vec![msg.clone()]),
         };
 
         vec![diagnostic]
@@ -892,7 +1278,7 @@ impl ToDiagnostic<FileId> for TypecheckError {
             span_opt
                 .as_ref()
                 .map(|span| vec![primary(span).with_message("this expression")])
-                .unwrap_or(Vec::new())
+                .unwrap_or_default()
         }
 
         match self {
@@ -951,10 +1337,9 @@ impl ToDiagnostic<FileId> for TypecheckError {
                     ])]
             ,
             TypecheckError::RowKindMismatch(Ident(ident), expd, actual, span_opt) => {
-                let (expd_str, actual_str) = match (expd, actual) {
-                    (Some(_), None) => ("an enum type", "a record type"),
-                    (None, Some(_)) => ("a record type", "an enum type"),
-                    _ => panic!("error::to_diagnostic()::RowKindMismatch: unexpected configuration for `expd` and `actual`"),
+                let describe = |ty: &Option<Types>| match ty {
+                    Some(ty) => format!("to carry a value of type `{}`", ty),
+                    None => String::from("to be a bare tag"),
                 };
 
                vec![
@@ -962,8 +1347,8 @@ impl ToDiagnostic<FileId> for TypecheckError {
                         .with_message("Incompatible row kinds.")
                         .with_labels(mk_expr_label(span_opt))
                         .with_notes(vec![
-                        format!("The row type of `{}` was expected to be `{}`, but was inferred to be `{}`", ident, expd_str, actual_str),
-                        String::from("Enum row types and record row types are not compatible"),
+                        format!("The tag `{}` was expected {}, but was inferred {}", ident, describe(expd), describe(actual)),
+                        String::from("A bare tag and a variant carrying a value are not compatible"),
                     ])]
 
             }
@@ -1072,6 +1457,49 @@ vec![
 
                 diags
             }
+            TypecheckError::NonExhaustiveMatch(tags, open, span_opt, value_span_opt) => {
+                let mut labels = Vec::new();
+
+                if let Some(span) = span_opt {
+                    labels.push(primary(span).with_message("in this match expression"));
+                }
+
+                if let Some(span) = value_span_opt {
+                    labels.push(secondary(span).with_message("this value"));
+                }
+
+                let tag_list = tags
+                    .iter()
+                    .map(|Ident(id)| format!("`{}`", id))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let mut notes = Vec::new();
+                let message = match tags.len() {
+                    0 => String::from("Non-exhaustive match"),
+                    1 => {
+                        notes.push(format!("The type of the matched value contains the tag {}, which is not handled by any case", tag_list));
+                        format!("Non-exhaustive match: missing case {}", tag_list)
+                    }
+                    _ => {
+                        notes.push(format!("The type of the matched value contains the tags {}, which are not handled by any case", tag_list));
+                        format!("Non-exhaustive match: missing cases {}", tag_list)
+                    }
+                };
+
+                if *open {
+                    notes.push(String::from("The type of the matched value is an open enum type, which may contain other tags than the ones of the cases"));
+                    notes.push(String::from("Add a catch-all case `_ => ...`"));
+                } else {
+                    notes.push(String::from(
+                        "Add a case for each missing tag, or a catch-all case `_ => ...`",
+                    ));
+                }
+
+                vec![Diagnostic::error()
+                    .with_message(message)
+                    .with_labels(labels)
+                    .with_notes(notes)]
+            }
         }
     }
 }
@@ -1107,3 +1535,66 @@ impl ToDiagnostic<FileId> for ImportError {
         }
     }
 }
+
+impl ToDiagnostic<FileId> for SerializationError {
+    fn to_diagnostic(
+        &self,
+        files: &mut Files<String>,
+        _contract_id: Option<FileId>,
+    ) -> Vec<Diagnostic<FileId>> {
+        match self {
+            SerializationError::NotSerializable(rt) => {
+                let occurred = rt
+                    .as_ref()
+                    .type_of()
+                    .unwrap_or_else(|| String::from("<unevaluated>"));
+
+                vec![Diagnostic::error()
+                    .with_message("Non serializable term")
+                    .with_labels(vec![
+                        primary_term(rt, files).with_message(format!("this {}", occurred))
+                    ])
+                    .with_notes(vec![String::from(
+                        "Only records, lists, strings, numbers, booleans and enum tags can be \
+                         serialized. Functions, labels and enriched values without a definition \
+                         (such as a lone contract) cannot.",
+                    )])]
+            }
+            SerializationError::NonRecordTopLevel(format, rt) => {
+                let occurred = rt
+                    .as_ref()
+                    .type_of()
+                    .unwrap_or_else(|| String::from("<unevaluated>"));
+
+                vec![Diagnostic::error()
+                    .with_message(format!("Cannot export a {} to {}", occurred, format))
+                    .with_labels(vec![
+                        primary_term(rt, files).with_message(format!("this is a {}", occurred))
+                    ])
+                    .with_notes(vec![format!(
+                        "The top-level value of a {} document must be a record.",
+                        format
+                    )])]
+            }
+            SerializationError::UnrepresentableNum(format, rt) => vec![Diagnostic::error()
+                .with_message(format!("Number not representable in {}", format))
+                .with_labels(vec![primary_term(rt, files).with_message("this number")])
+                .with_notes(vec![format!(
+                    "The {} format cannot represent NaN or infinite numbers.",
+                    format
+                )])],
+            SerializationError::MixedList(format, rt) => vec![Diagnostic::error()
+                .with_message(format!("List not representable in {}", format))
+                .with_labels(vec![primary_term(rt, files)
+                    .with_message("this list mixes records with other values")])
+                .with_notes(vec![format!(
+                    "A list exported to {} must contain either only records, or no record at all, \
+                     including inside nested lists.",
+                    format
+                )])],
+            SerializationError::Other(format, msg) => vec![Diagnostic::error()
+                .with_message(format!("Serialization to {} failed", format))
+                .with_notes(vec![msg.clone()])],
+        }
+    }
+}
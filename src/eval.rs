@@ -10,74 +10,87 @@
 //! - The main stack, storing arguments, thunks and pending computations
 //! - A pair of [environments](type.Environment.html), mapping identifiers to [closures](type.Closure.html):
 //!     * The global environment contains builtin functions accessible from anywhere, and alive
-//!     during the whole evaluation
+//!       during the whole evaluation
 //!     * The local environment contains the variables in scope of the current term and is subject
-//!     to garbage collection (currently reference counting based)
+//!       to garbage collection (currently reference counting based)
 //! - A [callstack](type.CallStack.html), mainly for error reporting purpose
 //!
 //! Depending on the shape of the current term, the following actions are preformed:
 //!
 //! ## Core calculus
 //! - **Var(id)**: the term bound to `id` in the environment is fetched, and an update thunk is
-//! pushed on the stack to indicate that once this term has been evaluated, the content of the
-//! variable must be updated
+//!   pushed on the stack to indicate that once this term has been evaluated, the content of the
+//!   variable must be updated
 //! - **App(func, arg)**: a closure containing the argument and the current environment is pushed
-//! on the stack, and the applied term `func` is evaluated
+//!   on the stack, and the applied term `func` is evaluated
 //! - **Let(id, term, body)**: `term` is bound to `id` in the environment, and the machine proceeds with the evaluation of the body
 //! - **Fun(id, body)**: Try to pop an argument from the stack. If there is some, we bound it to
-//! `id` in the environment, and proceed with the body of the function. Otherwise, we are done: the
-//! end result is an unapplied function
+//!   `id` in the environment, and proceed with the body of the function. Otherwise, we are done: the
+//!   end result is an unapplied function
 //! - **Thunk on stack**: If the evaluation of the current term is done, and there is one (or
-//! several) thunk on the stack, this means we have to perform an update. Consecutive thunks are
-//! popped from the stack and are updated to point to the current evaluated term.
+//!   several) thunk on the stack, this means we have to perform an update. Consecutive thunks are
+//!   popped from the stack and are updated to point to the current evaluated term.
 //! - **Import**: Import must have been resolved before the evaluation starts. An unresolved import
-//! causes an [`InternalError`](../error/enum.EvalError.html#variant.InternalError). A resolved
-//! import, identified by a `FileId`, is retrieved from the import resolver and evaluation proceeds.
+//!   causes an [`InternalError`](../error/enum.EvalError.html#variant.InternalError). A resolved
+//!   import, identified by a `FileId`, is retrieved from the import resolver and evaluation proceeds.
+//! - **RecRecord(fields)**: the fields are closed over the record, that is, each field is put in
+//!   an environment binding the names of the fields of the record to their definitions. The
+//!   original [definitions](enum.FieldDef.html) of the fields are kept in the environment as well,
+//!   such that [merge](../merge/index.html) can close them again over the merged record: recursive
+//!   records are late-bound.
 //!
 //! ## Contracts
 //!
 //! - **`Assume(type, label, term)`** (or `Promise(type, label, term)`): replace the current term
-//! with the contract corresponding to `types`, applied to label and term (`contract label term`).
+//!   with the contract corresponding to `types`, applied to label and term (`contract label term`).
 //!
 //! ## Operators
 //!
 //! Operators are strict by definition. To evaluate say `exp1 + exp2`, the following steps
 //! have to be performed:
 //! - `exp1` needs to be evaluated. The result must be saved somewhere, together with the resulting
-//! environment
+//!   environment
 //! - `exp2`: same thing for `exp2`
 //! - Finally, the implementation of `+` can proceed with the computation
 //!
 //! We detail the case of binary operators, as the case of unary ones is similar and simpler.
 //!
 //! - **Op(op, first, second)**: push an `OpFirst` element on the stack, which saves the operator
-//! `op`, the second argument `second` and the current environment, and proceed with the evaluation
-//! of `first`
+//!   `op`, the second argument `second` and the current environment, and proceed with the evaluation
+//!   of `first`
 //! - **OpFirst on stack**: if the evaluation of the current term is done and there is an `OpFirst`
-//! marker on the stack, then:
+//!   marker on the stack, then:
 //!     1. Extract the saved operator, the second argument and the environment `env2` from the marker
 //!     2. Push an `OpSecond` marker, saving the operator and the evaluated form of the first
 //!        argument with its environment
 //!     3. Proceed with the evaluation of the second argument in environment `env2`
 //! - **OpSecond on stack**: once the second term is evaluated, we can get back the operator and
-//! the first term evaluated, and forward all both arguments evaluated and their respective
-//! environment to the specific implementation of the operator (located in
-//! [operation](../operation/index.html), or in [merge](../merge/index.html) for `merge`).
+//!   the first term evaluated, and forward all both arguments evaluated and their respective
+//!   environment to the specific implementation of the operator (located in
+//!   [operation](../operation/index.html), or in [merge](../merge/index.html) for `merge`).
 //!
 //! ## Enriched values
 //!
 //! The evaluation of enriched values is controlled by the parameter `enriched_strict`. If it is
 //! set to true (which is usually the case), the machine tries to extract a simple value from it:
 //!  - **Contract**: raise an error. This usually means that an access to a field was attempted,
-//!  and that this field had a contract to satisfy, but it was never defined.
+//!    and that this field had a contract to satisfy, but it was never defined.
 //!  - **Default(value)**: an access to a field which has a default value. Proceed with the
-//!  evaluation of this value
+//!    evaluation of this value
 //!  - **ContractDefault(type, label, value)**: same as above, but the field also has an attached
-//!  contract.  Proceed with the evaluation of `Assume(type, label, value)` to ensure that the
-//!  default value satisfies this contract.
+//!    contract.  Proceed with the evaluation of `Assume(type, label, value)` to ensure that the
+//!    default value satisfies this contract.
+//!  - **ContractWithValue(type, label, value)**: a field with a contract and a definition. Proceed
+//!    with the evaluation of `Assume(type, label, value)`.
+//!  - **Optional(t)**: an access to an optional field, which is usually prevented by record
+//!    operations as such a field has no definition. Proceed with the evaluation of `t`.
+//!  - **MergeStrategy(strategy, t)** and **Priority(priority, t)**: the strategy and the
+//!    priority only matter to merge. Proceed with the evaluation of `t`.
 //!
 //!  If `enriched_strict` is set to false, as it is when evaluating `merge`, the machine does not
-//!  evaluate enriched values further, and consider the term evaluated.
+//!  evaluate enriched values further, and consider the term evaluated. The arguments of the other
+//!  operators are always evaluated with `enriched_strict` set to true, such that a contract
+//!  applied to a default value inside a merge still sees the value.
 //!
 //! # Garbage collection
 //!
@@ -89,17 +102,22 @@
 //! something to consider at some point.
 use crate::error::EvalError;
 use crate::identifier::Ident;
+use crate::merge::merge_op;
 use crate::operation::{continuate_operation, OperationCont};
 use crate::position::RawSpan;
 use crate::program::ImportResolver;
 use crate::stack::Stack;
-use crate::term::{RichTerm, StrChunk, Term, UnaryOp};
+use crate::term::{MergeStrategy, RichTerm, StrChunk, Term, UnaryOp};
+use crate::transformations::fresh_var;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::{Rc, Weak};
 
 /// An environment, which is a mapping from identifiers to closures.
-pub type Environment = HashMap<Ident, (Rc<RefCell<Closure>>, IdentKind)>;
+///
+/// The variables bound to the fields of an evaluated recursive record also carry the definition
+/// of the field, before it was closed over the record.
+pub type Environment = HashMap<Ident, (Rc<RefCell<Closure>>, IdentKind, Option<Rc<FieldDef>>)>;
 
 /// A call stack, saving the history of function calls.
 ///
@@ -140,6 +158,156 @@ impl Closure {
     }
 }
 
+/// The definition of a field of a recursive record, before it is closed over the record.
+///
+/// Recursive records are late-bound: when a recursive record is merged, the fields referring to
+/// other fields of the record see the fields of the merged record. To this end, an evaluated
+/// recursive record keeps the definition of its fields, which is closed again over each record
+/// it is merged into.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldDef {
+    /// A field of a record literal: its closure, in which the given fields of the record are
+    /// bound recursively.
+    Rec(Closure, Rc<Vec<Ident>>),
+    /// The merge of two fields, whether both fields are optional, and the path of the field in
+    /// the merged records.
+    Merge(Rc<FieldDef>, Rc<FieldDef>, bool, Vec<Ident>),
+    /// The application of a function to a field, as done by `mapRec`.
+    App(Closure, Rc<FieldDef>),
+}
+
+impl FieldDef {
+    /// Get the definition of a field given its term and its environment. A field which is not
+    /// bound to a definition is considered as a field of a record literal without recursive
+    /// references.
+    pub fn of(t: RichTerm, env: &Environment) -> Rc<FieldDef> {
+        if let Term::Var(id) = t.as_ref() {
+            if let Some((_, _, Some(def))) = env.get(id) {
+                return Rc::clone(def);
+            }
+        }
+
+        Rc::new(FieldDef::Rec(
+            Closure {
+                body: t,
+                env: env.clone(),
+            },
+            Rc::new(Vec::new()),
+        ))
+    }
+
+    /// The closures of the definitions merged into this field, from left to right.
+    pub fn definitions(&self) -> Vec<&Closure> {
+        match self {
+            FieldDef::Rec(closure, _) => vec![closure],
+            FieldDef::Merge(def1, def2, _, _) => {
+                let mut defs = def1.definitions();
+                defs.extend(def2.definitions());
+                defs
+            }
+            FieldDef::App(_, def) => def.definitions(),
+        }
+    }
+
+    /// Close the definition over a record, whose fields are bound in `rec_env`. The fields which
+    /// are not part of this record keep their former binding.
+    fn close(&self, rec_env: &Environment) -> Closure {
+        match self {
+            FieldDef::Rec(closure, ids) => {
+                let mut env = closure.env.clone();
+                for id in ids.iter() {
+                    if let Some(binding) = rec_env.get(id) {
+                        env.insert(id.clone(), binding.clone());
+                    }
+                }
+
+                Closure {
+                    body: closure.body.clone(),
+                    env,
+                }
+            }
+            FieldDef::Merge(def1, def2, optional, path) => {
+                let mut env = HashMap::new();
+                let mut bind = |def: &FieldDef| {
+                    let var = fresh_var();
+                    let thunk = Rc::new(RefCell::new(def.close(rec_env)));
+                    env.insert(var.clone(), (thunk, IdentKind::Record(), None));
+                    RichTerm::from(Term::Var(var))
+                };
+                let t1 = bind(def1);
+                let t2 = bind(def2);
+                let body: RichTerm =
+                    Term::Op2(merge_op(MergeStrategy::Elementwise, path.clone()), t1, t2).into();
+                // The merge of two optional fields must be seen as optional without being
+                // evaluated, by operations such as `hasField` or `fieldsOf`.
+                let body = if *optional {
+                    Term::Optional(body).into()
+                } else {
+                    body
+                };
+
+                Closure { body, env }
+            }
+            FieldDef::App(f, def) => {
+                let mut env = HashMap::new();
+                let f_var = fresh_var();
+                env.insert(
+                    f_var.clone(),
+                    (Rc::new(RefCell::new(f.clone())), IdentKind::Record(), None),
+                );
+                let arg_var = fresh_var();
+                env.insert(
+                    arg_var.clone(),
+                    (
+                        Rc::new(RefCell::new(def.close(rec_env))),
+                        IdentKind::Record(),
+                        None,
+                    ),
+                );
+
+                Closure {
+                    body: Term::App(Term::Var(f_var).into(), Term::Var(arg_var).into()).into(),
+                    env,
+                }
+            }
+        }
+    }
+}
+
+/// Build a recursive record from the definitions of its fields.
+///
+/// Each definition is closed over the record. The fields of the record are returned as variables
+/// bound in `env` to the resulting closures, together with their definition.
+pub fn close_record(
+    defs: HashMap<Ident, Rc<FieldDef>>,
+    env: &mut Environment,
+) -> HashMap<Ident, RichTerm> {
+    // The closures of the fields are only known once all the thunks have been created, hence
+    // they are filled afterwards.
+    let rec_env: Environment = defs
+        .keys()
+        .map(|id| {
+            let thunk = Rc::new(RefCell::new(Closure::atomic_closure(
+                Term::Var(id.clone()).into(),
+            )));
+            (id.clone(), (thunk, IdentKind::Record(), None))
+        })
+        .collect();
+
+    defs.into_iter()
+        .map(|(id, def)| {
+            let (thunk, _, _) = rec_env.get(&id).unwrap();
+            *thunk.borrow_mut() = def.close(&rec_env);
+            let var = fresh_var();
+            env.insert(
+                var.clone(),
+                (Rc::clone(thunk), IdentKind::Record(), Some(def)),
+            );
+            (id, Term::Var(var).into())
+        })
+        .collect()
+}
+
 /// Determine if a thunk is worth being put on the stack for future update.
 ///
 /// Typically, WHNFs and enriched values will not be evaluated to a simpler expression and are not
@@ -159,16 +327,184 @@ fn should_update(t: &Term) -> bool {
 ///
 /// - `t0`: the term to evaluate
 /// - `global_env`: the global environment containing the builtin functions of the language. Accessible from anywhere in the
-/// program.
+///   program.
 /// - `resolver`: the interface to fetch imports.
 pub fn eval<R>(t0: RichTerm, global_env: Environment, resolver: &mut R) -> Result<Term, EvalError>
 where
     R: ImportResolver,
 {
-    let mut clos = Closure::atomic_closure(t0);
+    eval_closure(Closure::atomic_closure(t0), &global_env, resolver, true).map(|(rt, _)| *rt.term)
+}
+
+/// Fully evaluate a term.
+///
+/// Contrary to [`eval`](./fn.eval.html), which stops as soon as a weak head normal form is
+/// reached, the term is first forced using `deepSeq`, and the content of records and lists is
+/// then substituted back in the result. The returned term does not depend on an environment
+/// anymore, and can be inspected or exported as is, as long as it does not contain functions.
+///
+/// The result keeps the position of the value it evaluated to, or the one of `t0` if this value
+/// was generated by evaluation, so that errors on the whole result, such as a failed export, can
+/// still be reported in the source.
+pub fn eval_full<R>(
+    t0: RichTerm,
+    global_env: Environment,
+    resolver: &mut R,
+) -> Result<RichTerm, EvalError>
+where
+    R: ImportResolver,
+{
+    let pos = t0.pos.clone();
+    let var = fresh_var();
+    // Desugar to let var = t0 in deepSeq var var
+    let wrapper = RichTerm::let_in(
+        &var.0,
+        t0,
+        RichTerm::app(
+            Term::Op1(UnaryOp::DeepSeq(Vec::new()), RichTerm::var(var.0.clone())).into(),
+            RichTerm::var(var.0.clone()),
+        ),
+    );
+
+    let (rt, env) = eval_closure(
+        Closure::atomic_closure(wrapper),
+        &global_env,
+        resolver,
+        true,
+    )?;
+    let rt = subst(rt, env, &global_env, resolver)?;
+    Ok(RichTerm {
+        pos: rt.pos.or(pos),
+        ..rt
+    })
+}
+
+/// Determine if a field of an evaluated record is an optional field without a definition.
+///
+/// The fields of an evaluated record are either constants or variables bound to thunks. Such
+/// thunks are inspected without being evaluated: an optional field is only introduced by a record
+/// literal or by the merge of two optional fields, which both put the `Optional` enriched value at
+/// the top of the content of the thunk.
+pub fn is_optional_field(t: &RichTerm, env: &Environment) -> bool {
+    let mut visited = Vec::new();
+    let mut next = match t.as_ref() {
+        Term::Optional(_) => return true,
+        Term::Var(id) => env.get(id).map(|(thunk, _, _)| thunk.clone()),
+        _ => None,
+    };
+
+    // Follow chains of variables, which may be cyclic, as in `{a = a}`.
+    while let Some(thunk) = next {
+        if visited.iter().any(|other| Rc::ptr_eq(other, &thunk)) {
+            return false;
+        }
+
+        let clos = thunk.borrow();
+        next = match clos.body.as_ref() {
+            Term::Optional(_) => return true,
+            Term::Var(id) => clos.env.get(id).map(|(thunk, _, _)| thunk.clone()),
+            _ => None,
+        };
+        drop(clos);
+        visited.push(thunk);
+    }
+
+    false
+}
+
+/// Evaluate a closure to a weak head normal form, and return it together with its environment.
+///
+/// Contrary to [`eval`](./fn.eval.html), the environment is kept, such that the content of an
+/// evaluated record or list can be inspected.
+pub fn eval_whnf<R>(
+    clos: Closure,
+    global_env: &Environment,
+    resolver: &mut R,
+) -> Result<(RichTerm, Environment), EvalError>
+where
+    R: ImportResolver,
+{
+    eval_closure(clos, global_env, resolver, true)
+}
+
+/// Evaluate a closure to a weak head normal form without forcing enriched values.
+///
+/// Contrary to [`eval`](./fn.eval.html), a default value, a docstring or a contract reached at the
+/// top-level is returned as it is, together with its environment, instead of being unwrapped.
+/// This gives access to the metadata attached to a value.
+pub fn eval_meta<R>(
+    clos: Closure,
+    global_env: &Environment,
+    resolver: &mut R,
+) -> Result<(RichTerm, Environment), EvalError>
+where
+    R: ImportResolver,
+{
+    eval_closure(clos, global_env, resolver, false)
+}
+
+/// Substitute the content of an evaluated record or list.
+///
+/// The fields of an evaluated record, as well as the elements of an evaluated list, are generally
+/// variables which point to thunks in the environment, or pending operations such as a merge.
+/// This function evaluates them in their environment and substitutes the result back,
+/// recursively. Other terms, including functions, are left untouched.
+fn subst<R>(
+    rt: RichTerm,
+    env: Environment,
+    global_env: &Environment,
+    resolver: &mut R,
+) -> Result<RichTerm, EvalError>
+where
+    R: ImportResolver,
+{
+    let RichTerm { term, pos } = rt;
+
+    let mut subst_closure = |body: RichTerm| -> Result<RichTerm, EvalError> {
+        let clos = Closure {
+            body,
+            env: env.clone(),
+        };
+        let (rt, env) = eval_closure(clos, global_env, resolver, true)?;
+        subst(rt, env, global_env, resolver)
+    };
+
+    let term = match *term {
+        Term::Record(map) => Term::Record(
+            map.into_iter()
+                .filter(|(_, t)| !is_optional_field(t, &env))
+                .map(|(id, t)| Ok((id, subst_closure(t)?)))
+                .collect::<Result<HashMap<Ident, RichTerm>, EvalError>>()?,
+        ),
+        Term::List(ts) => Term::List(
+            ts.into_iter()
+                .map(subst_closure)
+                .collect::<Result<Vec<RichTerm>, EvalError>>()?,
+        ),
+        Term::EnumVariant(id, t) => Term::EnumVariant(id, subst_closure(t)?),
+        term => term,
+    };
+
+    Ok(RichTerm {
+        term: Box::new(term),
+        pos,
+    })
+}
+
+/// Evaluate a closure to a weak head normal form, and return it together with its environment.
+/// `enriched_strict` is the initial value of the corresponding parameter of the abstract machine
+/// (see the [module documentation](./index.html)).
+fn eval_closure<R>(
+    mut clos: Closure,
+    global_env: &Environment,
+    resolver: &mut R,
+    mut enriched_strict: bool,
+) -> Result<(RichTerm, Environment), EvalError>
+where
+    R: ImportResolver,
+{
     let mut call_stack = CallStack::new();
     let mut stack = Stack::new();
-    let mut enriched_strict = true;
 
     loop {
         let Closure {
@@ -181,12 +517,12 @@ where
         let term = *boxed_term;
         clos = match term {
             Term::Var(x) => {
-                let (thunk, id_kind) = env
+                let (thunk, id_kind, _) = env
                     .remove(&x)
                     .or_else(|| {
                         global_env
                             .get(&x)
-                            .map(|(rc, id_kind)| (rc.clone(), id_kind.clone()))
+                            .map(|(rc, id_kind, _)| (rc.clone(), id_kind.clone(), None))
                     })
                     .ok_or(EvalError::UnboundIdentifier(x.clone(), pos.clone()))?;
                 std::mem::drop(env); // thunk may be a 1RC pointer
@@ -220,7 +556,28 @@ where
                     body: s,
                     env: env.clone(),
                 }));
-                env.insert(x, (Rc::clone(&thunk), IdentKind::Let()));
+                env.insert(x, (Rc::clone(&thunk), IdentKind::Let(), None));
+                Closure { body: t, env }
+            }
+            Term::LetRec(bindings, t) => {
+                // The environment of the bound terms is only known once all the thunks have been
+                // created, hence it is filled afterwards.
+                let thunks: Vec<_> = bindings
+                    .into_iter()
+                    .map(|(x, s)| {
+                        let thunk = Rc::new(RefCell::new(Closure {
+                            body: s,
+                            env: HashMap::new(),
+                        }));
+                        env.insert(x, (Rc::clone(&thunk), IdentKind::Let(), None));
+                        thunk
+                    })
+                    .collect();
+
+                for thunk in thunks {
+                    thunk.borrow_mut().env = env.clone();
+                }
+
                 Closure { body: t, env }
             }
             Term::Op1(op, t) => {
@@ -229,7 +586,13 @@ where
                     env: env.clone(),
                 });
 
-                stack.push_op_cont(OperationCont::Op1(op, t.pos.clone()), call_stack.len(), pos);
+                let prev_strict = enriched_strict;
+                enriched_strict = true;
+                stack.push_op_cont(
+                    OperationCont::Op1(op, t.pos.clone(), prev_strict),
+                    call_stack.len(),
+                    pos,
+                );
                 Closure { body: t, env }
             }
             Term::Op2(op, fst, snd) => {
@@ -292,57 +655,29 @@ where
                     env,
                 }
             }
-            Term::RecRecord(ts) => {
+            Term::RecRecord(ts, _) => {
                 // Thanks to the share normal form transformation, the content is either a constant or a
-                // variable.
-                let rec_env =
-                    ts.iter()
-                        .try_fold(HashMap::new(), |mut rec_env, (id, rt)| match rt.as_ref() {
-                            &Term::Var(ref var_id) => {
-                                let (thunk, id_kind) = env.get(var_id).ok_or(
-                                    EvalError::UnboundIdentifier(var_id.clone(), rt.pos.clone()),
-                                )?;
-                                rec_env.insert(id.clone(), (thunk.clone(), id_kind.clone()));
-                                Ok(rec_env)
-                            }
-                            _ => {
-                                // If we are in this branch, the term must be a constant after the
-                                // share normal form transformation, hence it should not need an
-                                // environment, which is it is dropped.
-                                let closure = Closure {
-                                    body: rt.clone(),
-                                    env: HashMap::new(),
-                                };
-                                rec_env.insert(
-                                    id.clone(),
-                                    (Rc::new(RefCell::new(closure)), IdentKind::Let()),
-                                );
-                                Ok(rec_env)
+                // variable. A constant does not need an environment, which is dropped.
+                let ids: Rc<Vec<Ident>> = Rc::new(ts.keys().cloned().collect());
+                let defs = ts
+                    .into_iter()
+                    .map(|(id, rt)| {
+                        let closure = match rt.as_ref() {
+                            Term::Var(var_id) => {
+                                let (thunk, _, _) = env.get(var_id).ok_or_else(|| {
+                                    EvalError::UnboundIdentifier(var_id.clone(), rt.pos.clone())
+                                })?;
+                                thunk.borrow().clone()
                             }
-                        })?;
-
-                let new_ts = ts.into_iter().map(|(id, rt)| {
-                    let RichTerm { term, pos } = rt;
-                    match *term {
-                        Term::Var(var_id) => {
-                            // We already checked for unbound identifier in the previous fold, so this
-                            // get should always succeed.
-                            let (thunk, _) = env.get(&var_id).unwrap();
-                            thunk.borrow_mut().env.extend(rec_env.clone());
-                            (
-                                id,
-                                RichTerm {
-                                    term: Box::new(Term::Var(var_id)),
-                                    pos,
-                                },
-                            )
-                        }
-                        _ => (id, RichTerm { term, pos }),
-                    }
-                });
+                            _ => Closure::atomic_closure(rt),
+                        };
+                        Ok((id, Rc::new(FieldDef::Rec(closure, Rc::clone(&ids)))))
+                    })
+                    .collect::<Result<HashMap<_, _>, EvalError>>()?;
+
                 Closure {
                     body: RichTerm {
-                        term: Box::new(Term::Record(new_ts.collect())),
+                        term: Box::new(Term::Record(close_record(defs, &mut env))),
                         pos,
                     },
                     env,
@@ -357,7 +692,11 @@ where
                     pos,
                 ));
             }
-            enriched @ Term::DefaultValue(_) | enriched @ Term::Docstring(_, _)
+            enriched @ Term::DefaultValue(_)
+            | enriched @ Term::Docstring(_, _)
+            | enriched @ Term::Optional(_)
+            | enriched @ Term::MergeStrategy(_, _)
+            | enriched @ Term::Priority(_, _)
                 if enriched_strict =>
             {
                 /* Since we are forcing an enriched value, we are morally breaking subject
@@ -385,15 +724,23 @@ where
                     env,
                 } = update_closure;
                 let t = match *enriched_box {
-                    Term::DefaultValue(t) | Term::Docstring(_, t) => t,
-                    _ => panic!("eval::eval(): previous match enforced that a term is a default or a docstring, but matched something else")
+                    Term::DefaultValue(t)
+                    | Term::Docstring(_, t)
+                    | Term::Optional(t)
+                    | Term::MergeStrategy(_, t)
+                    | Term::Priority(_, t) => t,
+                    _ => panic!("eval::eval(): previous match enforced that a term is a default, a docstring, an optional field, a merge strategy or a priority, but matched something else")
                 };
                 Closure { body: t, env }
             }
-            Term::ContractWithDefault(ty, label, t) if enriched_strict => Closure {
-                body: Term::Assume(ty, label, t).into(),
-                env,
-            },
+            Term::ContractWithDefault(ty, label, t) | Term::ContractWithValue(ty, label, t)
+                if enriched_strict =>
+            {
+                Closure {
+                    body: Term::Assume(ty, label, t).into(),
+                    env,
+                }
+            }
             Term::ResolvedImport(id) => {
                 if let Some(t) = resolver.get(id) {
                     Closure::atomic_closure(t)
@@ -430,7 +777,10 @@ where
                         &mut enriched_strict,
                     );
 
-                    if let Err(EvalError::BlameError(l, _)) = cont_result {
+                    if let Err(EvalError::BlameError(mut l, _)) = cont_result {
+                        if let Some(path) = stack.deep_seq_path() {
+                            l.access_path = path.clone();
+                        }
                         return Err(EvalError::BlameError(l, Some(call_stack)));
                     }
                     cont_result?
@@ -442,10 +792,16 @@ where
                     let (arg, pos_app) = stack.pop_arg().expect("Condition already checked.");
                     call_stack.push(StackElem::App(pos_app));
                     let thunk = Rc::new(RefCell::new(arg));
-                    env.insert(x, (thunk, IdentKind::Lam()));
+                    env.insert(x, (thunk, IdentKind::Lam(), None));
                     Closure { body: t, env }
                 } else {
-                    return Ok(Term::Fun(x, t));
+                    return Ok((
+                        RichTerm {
+                            term: Box::new(Term::Fun(x, t)),
+                            pos,
+                        },
+                        env,
+                    ));
                 }
             }
             // Otherwise, this is either an ill-formed application, or we are done
@@ -461,7 +817,13 @@ where
                         pos_app,
                     ));
                 } else {
-                    return Ok(t);
+                    return Ok((
+                        RichTerm {
+                            term: Box::new(t),
+                            pos,
+                        },
+                        env,
+                    ));
                 }
             }
         }
@@ -480,7 +842,7 @@ fn update_thunks(stack: &mut Stack, closure: &Closure) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::error::ImportError;
+    use crate::error::{Error, ImportError, ParseError};
     use crate::label::Label;
     use crate::program::resolvers::{DummyResolver, SimpleResolver};
     use crate::term::StrChunk;
@@ -513,7 +875,7 @@ mod tests {
         if let Err(EvalError::BlameError(l, _)) =
             eval_no_import(Term::Op1(UnaryOp::Blame(), Term::Lbl(label.clone()).into()).into())
         {
-            assert_eq!(l, label);
+            assert_eq!(*l, label);
         } else {
             panic!("This evaluation should've returned a BlameError!");
         }
@@ -527,8 +889,11 @@ mod tests {
 
     #[test]
     fn only_fun_are_applicable() {
-        eval_no_import(RichTerm::app(Term::Bool(true).into(), Term::Num(45.).into()).into())
-            .unwrap_err();
+        eval_no_import(RichTerm::app(
+            Term::Bool(true).into(),
+            Term::Num(45.).into(),
+        ))
+        .unwrap_err();
     }
 
     #[test]
@@ -658,7 +1023,7 @@ mod tests {
                 RichTerm::let_in(var, Term::Import(String::from(import)).into(), body),
                 resolver,
             )
-        };
+        }
 
         // let x = import "does_not_exist" in x
         match mk_import(
@@ -670,13 +1035,13 @@ mod tests {
         .unwrap_err()
         {
             ImportError::IOError(_, _, _) => (),
-            _ => assert!(false),
+            _ => panic!(),
         };
 
         // let x = import "bad" in x
         match mk_import("x", "bad", RichTerm::var(String::from("x")), &mut resolver).unwrap_err() {
             ImportError::ParseError(_, _) => (),
-            _ => assert!(false),
+            _ => panic!(),
         };
 
         // let x = import "two" in x
@@ -750,6 +1115,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn data_imports() {
+        let mut resolver = SimpleResolver::new();
+        resolver.add_source(
+            String::from("data.json"),
+            String::from("{\"a\": {\"b\": [1, 2]}}"),
+        );
+        resolver.add_source(String::from("data.yaml"), String::from("a:\n  b: yaml\n"));
+        resolver.add_source(String::from("data.toml"), String::from("[a]\nb = true\n"));
+        resolver.add_source(String::from("bad.json"), String::from("{\"a\": "));
+
+        fn import_field<R>(import: &str, resolver: &mut R) -> Result<Term, Error>
+        where
+            R: ImportResolver,
+        {
+            let t = RichTerm::let_in(
+                "x",
+                Term::Import(String::from(import)).into(),
+                Term::Op1(
+                    UnaryOp::StaticAccess(Ident::from("b")),
+                    Term::Op1(
+                        UnaryOp::StaticAccess(Ident::from("a")),
+                        RichTerm::var(String::from("x")),
+                    )
+                    .into(),
+                )
+                .into(),
+            );
+            let t = transform(t, resolver)?;
+            Ok(eval(t, HashMap::new(), resolver)?)
+        }
+
+        match import_field("data.json", &mut resolver).unwrap() {
+            Term::List(ts) => assert_eq!(ts.len(), 2),
+            t => panic!("expected a list, got {:?}", t),
+        }
+        assert_eq!(
+            import_field("data.yaml", &mut resolver).unwrap(),
+            Term::Str(String::from("yaml"))
+        );
+        assert_eq!(
+            import_field("data.toml", &mut resolver).unwrap(),
+            Term::Bool(true)
+        );
+        match import_field("bad.json", &mut resolver).unwrap_err() {
+            Error::ImportError(ImportError::ParseError(ParseError::DataFormatError(..), _)) => (),
+            err => panic!("expected a parse error, got {:?}", err),
+        }
+    }
+
     #[test]
     fn interpolation_simple() {
         let mut chunks = vec![
@@ -821,7 +1236,10 @@ mod tests {
             body: Term::Num(1.0).into(),
             env: HashMap::new(),
         }));
-        global_env.insert(Ident::from("g"), (Rc::clone(&thunk), IdentKind::Let()));
+        global_env.insert(
+            Ident::from("g"),
+            (Rc::clone(&thunk), IdentKind::Let(), None),
+        );
 
         let t = RichTerm::let_in(
             "x",
@@ -11,7 +11,7 @@ pub mod ty_path {
     //!
     //! Checking higher-order contracts can involve a good share of intermediate contract checking.
     //! Take the following example:
-    //! ```
+    //! ```text
     //! Assume((Num -> Num) -> Num) -> Num -> Num, fun ev => fun cst => ev (fun x => cst))
     //! ```
     //! Once called, various checks will be performed on the arguments of functions and their return
@@ -22,7 +22,7 @@ pub mod ty_path {
     //! 4. etc.
     //!
     //! Each check can be linked to a base type occurrence (here, a `Num`) in the original type:
-    //! ```
+    //! ```text
     //! (Num -> Num) -> Num) -> Num -> Num
     //!  ^^^1   ^^^2    ^^^3    etc.
     //! ```
@@ -59,10 +59,7 @@ pub mod ty_path {
 
     /// Determine if the path has only `Field` components.
     pub fn is_only_field(p: &Path) -> bool {
-        p.iter().all(|elt| match *elt {
-            Elem::Field(_) => true,
-            _ => false,
-        })
+        p.iter().all(|elt| matches!(*elt, Elem::Field(_)))
     }
 
     /// Return the position span encoded by a type path in the string representation of the
@@ -139,8 +136,11 @@ pub mod ty_path {
                     _ => panic!(),
                 }
             }
-            (AbsType::StaticRecord(rows), Some(Elem::Field(ident))) => {
-                // initial "{ {| "
+            // The type of a variant is located in an enum type as the one of a field in a record
+            // type.
+            (AbsType::StaticRecord(rows), Some(Elem::Field(ident)))
+            | (AbsType::Enum(rows), Some(Elem::Field(ident))) => {
+                // initial "{ {| " or "< (| "
                 let mut start_offset = 5;
                 // middle ": " between the field name and the type
                 let id_offset = 2;
@@ -163,6 +163,11 @@ pub mod ty_path {
                                 + end_offset;
                             row = &tail.0;
                         }
+                        // A bare enum tag
+                        AbsType::RowExtend(id, None, tail) => {
+                            start_offset += format!("{}", id).len() + end_offset;
+                            row = &tail.0;
+                        }
                         _ => panic!(),
                     }
                 }
@@ -176,6 +181,51 @@ pub mod ty_path {
     }
 }
 
+pub mod access_path {
+    //! Access paths.
+    //!
+    //! While a type path locates the part of a contract which failed, an access path locates the
+    //! value which broke it inside the configuration being evaluated, as a sequence of record
+    //! fields and list indices from the root, such as `servers[2].port`.
+    //!
+    //! Access paths are computed when a term is fully evaluated (see `DeepSeq` in
+    //! [`UnaryOp`](../../term/enum.UnaryOp.html)): values which are only evaluated to a weak head
+    //! normal form, as by [`Program::eval`](../../program/struct.Program.html#method.eval) or by a
+    //! field access, do not record one: the path through which they were reached is not tracked
+    //! when contracts are applied.
+    //! [`Program::query`](../../program/struct.Program.html#method.query) prepends the queried
+    //! path to the access path computed when fully evaluating the field.
+
+    use crate::identifier::Ident;
+
+    /// An element of an access path.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Elem {
+        Field(Ident),
+        Index(usize),
+    }
+
+    pub type Path = Vec<Elem>;
+
+    /// Render an access path as it would be written in Nickel, using `[i]` for list indices.
+    pub fn to_string(path: &[Elem]) -> String {
+        let mut result = String::new();
+
+        for elem in path {
+            match elem {
+                Elem::Field(Ident(id)) if result.is_empty() => result.push_str(id),
+                Elem::Field(Ident(id)) => {
+                    result.push('.');
+                    result.push_str(id);
+                }
+                Elem::Index(i) => result.push_str(&format!("[{}]", i)),
+            }
+        }
+
+        result
+    }
+}
+
 /// A blame label.
 ///
 /// A label is associated to a contract check (an assume, a promise or a contract as an enriched
@@ -187,7 +237,7 @@ pub mod ty_path {
 /// One crucial aspect of first class contracts is to be able to check higher-order types, which
 /// are types with arrows in it. Consider the simplest example:
 ///
-/// ```
+/// ```text
 /// Assume(Num -> Num, f)
 /// ```
 ///
@@ -203,9 +253,9 @@ pub mod ty_path {
 /// => f (Assume(Num, arg)))`, but we want to report the failures of the two introduced
 /// subcontracts in a different way:
 ///  - The inner one (on the argument) says that `f` has been misused: it has been applied to
-///  something that is not a `Num`.
+///    something that is not a `Num`.
 ///  - The outer one says that `f` failed to satisfy its contract, as it has been provided with a
-///  `Num` (otherwise the inner contracts would have failed before) but failed to deliver a `Num`.
+///    `Num` (otherwise the inner contracts would have failed before) but failed to deliver a `Num`.
 ///
 /// This duality caller/callee or function/context is indicated by the polarity: the outer
 /// corresponds to a *positive* polarity (the contract is on the term), while the inner corresponds
@@ -226,6 +276,13 @@ pub struct Label {
     pub polarity: bool,
     /// The path of the type being currently checked in the original type.
     pub path: ty_path::Path,
+    /// A truncated representation of the value which broke the contract, if it is known (see
+    /// `BlameWith` in [`BinaryOp`](../term/enum.BinaryOp.html)).
+    pub value: Option<String>,
+    /// The access path of the value which broke the contract in the fully evaluated term. It is
+    /// empty when the contract is broken outside of a full evaluation (see
+    /// [`access_path`](access_path/index.html)).
+    pub access_path: access_path::Path,
 }
 
 impl Label {
@@ -241,6 +298,8 @@ impl Label {
             },
             polarity: false,
             path: Vec::new(),
+            value: None,
+            access_path: Vec::new(),
         }
     }
 }
@@ -0,0 +1,50 @@
+//! The Nickel interpreter, as a library.
+//!
+//! The main entry point is [`Program`](./program/struct.Program.html), which loads a source (from
+//! a string, a file or any reader), and exposes the different phases of the interpretation:
+//! parsing, typechecking and evaluation. Errors are returned as structured
+//! [`Error`](./error/enum.Error.html) values, which can then be reported by the program (see
+//! [`Program::report_to`](./program/struct.Program.html#method.report_to)) or inspected by the
+//! caller.
+//!
+//! A fully evaluated term can be converted to a Rust-native value through
+//! [serde](https://serde.rs/), using the deserializer implemented in the [deserialize
+//! module](./deserialize/index.html):
+//!
+//! ```
+//! use nickel::program::Program;
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct Server {
+//!     host: String,
+//!     port: f64,
+//! }
+//!
+//! let mut prog = Program::new_from_str(r#"{host = "localhost"; port = 40 + 40}"#, "<example>");
+//! let server = Server::deserialize(prog.eval_full().unwrap()).unwrap();
+//! assert_eq!(server.host, "localhost");
+//! assert_eq!(server.port, 80.0);
+//! ```
+
+pub mod deserialize;
+pub mod doc;
+pub mod error;
+pub mod eval;
+pub mod identifier;
+pub mod label;
+pub mod lsp;
+pub mod merge;
+pub mod operation;
+pub mod parser;
+pub mod position;
+pub mod pretty;
+pub mod program;
+pub mod repl;
+pub mod serialize;
+pub mod stack;
+pub mod stdlib;
+pub mod term;
+pub mod transformations;
+pub mod typecheck;
+pub mod types;
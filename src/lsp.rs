@@ -0,0 +1,1005 @@
+//! The Nickel language server.
+//!
+//! An implementation of the [Language Server Protocol](https://microsoft.github.io/language-server-protocol/)
+//! for Nickel source files, which is started by `nickel lsp` and communicates with the editor over
+//! the standard input and output. The server supports:
+//! - Diagnostics: each time a document is opened or modified, it is parsed and typechecked, and
+//!   the resulting errors are published as LSP diagnostics.
+//! - Hover: the type of the expression under the cursor, together with the content of the
+//!   `Docstring` of the corresponding definition, if any.
+//! - Go-to-definition: for identifiers bound by a `let`, a function or a recursive record.
+//!
+//! Documents are analyzed by a [`Program`](../program/struct.Program.html) built from their
+//! content. The positions of binders are not stored in the AST, but are recorded by the parser
+//! (see [`Program::binders`](../program/struct.Program.html#method.binders)). The binder of a
+//! binding construct is found among them between the start of the construct and the start of the
+//! bound expression, whose positions are known.
+use crate::identifier::Ident;
+use crate::position::RawSpan;
+use crate::program::Program;
+use crate::term::{BinaryOp, MatchPattern, Pattern, RichTerm, StrChunk, Term, UnaryOp};
+use crate::types::{AbsType, Types};
+use codespan::FileId;
+use codespan_reporting::diagnostic::{self, LabelStyle, Severity};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::{GotoDefinition, HoverRequest, Request as RequestTrait};
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability, Location,
+    MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Run the language server over the standard input and output, until the client asks it to exit.
+pub fn stdio() -> Result<(), Box<dyn Error + Sync + Send>> {
+    let (connection, io_threads) = Connection::stdio();
+    run(&connection)?;
+    // The writer thread only stops once the connection, which holds the sending end of its
+    // channel, is dropped.
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Run the language server on a connection, until the client asks it to exit.
+pub fn run(connection: &Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server::new();
+    for msg in &connection.receiver {
+        match msg {
+            Message::Request(req) => {
+                if connection.handle_shutdown(&req)? {
+                    return Ok(());
+                }
+                connection.sender.send(server.handle_request(req).into())?;
+            }
+            Message::Notification(notif) => {
+                if let Some(params) = server.handle_notification(notif) {
+                    let notif = Notification::new(String::from(PublishDiagnostics::METHOD), params);
+                    connection.sender.send(notif.into())?;
+                }
+            }
+            Message::Response(_) => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// The state of the server: the analysis of each open document.
+struct Server {
+    documents: HashMap<Url, Document>,
+}
+
+impl Server {
+    fn new() -> Self {
+        Server {
+            documents: HashMap::new(),
+        }
+    }
+
+    /// Handle a request and return the response to send back.
+    fn handle_request(&self, req: Request) -> Response {
+        let id = req.id.clone();
+
+        match req.method.as_str() {
+            HoverRequest::METHOD => match req.extract::<HoverParams>(HoverRequest::METHOD) {
+                Ok((id, params)) => Response::new_ok(id, self.hover(params)),
+                Err(err) => invalid_params(id, err),
+            },
+            GotoDefinition::METHOD => {
+                match req.extract::<GotoDefinitionParams>(GotoDefinition::METHOD) {
+                    Ok((id, params)) => Response::new_ok(id, self.definition(params)),
+                    Err(err) => invalid_params(id, err),
+                }
+            }
+            _ => Response::new_err(
+                id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request `{}`", req.method),
+            ),
+        }
+    }
+
+    /// Handle a notification. Return the diagnostics to publish if a document was opened,
+    /// modified or closed.
+    fn handle_notification(&mut self, notif: Notification) -> Option<PublishDiagnosticsParams> {
+        match notif.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = notif
+                    .extract::<<DidOpenTextDocument as NotificationTrait>::Params>(
+                        DidOpenTextDocument::METHOD,
+                    )
+                    .ok()?;
+                let doc = params.text_document;
+                Some(self.update(doc.uri, doc.text, Some(doc.version)))
+            }
+            DidChangeTextDocument::METHOD => {
+                let params = notif
+                    .extract::<<DidChangeTextDocument as NotificationTrait>::Params>(
+                        DidChangeTextDocument::METHOD,
+                    )
+                    .ok()?;
+                // The server only supports full synchronization: the last change holds the whole
+                // content of the document.
+                let text = params.content_changes.into_iter().last()?.text;
+                let doc = params.text_document;
+                Some(self.update(doc.uri, text, Some(doc.version)))
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = notif
+                    .extract::<<DidCloseTextDocument as NotificationTrait>::Params>(
+                        DidCloseTextDocument::METHOD,
+                    )
+                    .ok()?;
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                Some(PublishDiagnosticsParams::new(uri, Vec::new(), None))
+            }
+            _ => None,
+        }
+    }
+
+    /// Analyze the new content of a document and return its diagnostics.
+    fn update(&mut self, uri: Url, text: String, version: Option<i32>) -> PublishDiagnosticsParams {
+        let doc = Document::new(&uri, text);
+        let diagnostics = doc.diagnostics.clone();
+        self.documents.insert(uri.clone(), doc);
+        PublishDiagnosticsParams::new(uri, diagnostics, version)
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let pos = params.text_document_position_params;
+        let doc = self.documents.get(&pos.text_document.uri)?;
+        let offset = offset(&doc.text, pos.position)?;
+        let (span, contents) = doc.hover(offset)?;
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: contents,
+            }),
+            range: Some(range(&doc.text, &span)),
+        })
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let pos = params.text_document_position_params;
+        let doc = self.documents.get(&pos.text_document.uri)?;
+        let offset = offset(&doc.text, pos.position)?;
+        let span = doc.definition(offset)?;
+
+        Some(GotoDefinitionResponse::Scalar(Location::new(
+            pos.text_document.uri,
+            range(&doc.text, &span),
+        )))
+    }
+}
+
+/// Build the response to a request whose parameters could not be decoded.
+fn invalid_params(id: lsp_server::RequestId, err: impl std::fmt::Debug) -> Response {
+    Response::new_err(
+        id,
+        ErrorCode::InvalidParams as i32,
+        format!("invalid parameters: {:?}", err),
+    )
+}
+
+/// A binding introduced by a `let`, a function or a recursive record.
+struct Binding {
+    /// The bound identifier.
+    ident: Ident,
+    /// The position of the binder, if it could be determined.
+    span: Option<RawSpan>,
+    /// The type of the bound expression, if it is annotated or is a literal.
+    ty: Option<Types>,
+    /// The documentation of the bound expression, if any.
+    doc: Option<String>,
+}
+
+/// The analysis of a document.
+struct Document {
+    /// The content of the document.
+    text: String,
+    /// The parse and typecheck errors of the document.
+    diagnostics: Vec<Diagnostic>,
+    /// The bindings of the document.
+    bindings: Vec<Binding>,
+    /// The occurrences of bound variables, together with the index of the corresponding binding.
+    usages: Vec<(RawSpan, usize)>,
+    /// The types of the subterms of the document, as recorded by the typechecker.
+    types: Vec<(RawSpan, Types)>,
+    /// The types of literals, which are not inferred by the typechecker outside of a `Promise`.
+    literals: Vec<(RawSpan, Types)>,
+}
+
+impl Document {
+    /// Parse and typecheck a document.
+    ///
+    /// If the document does not parse, only its diagnostics are available. If it does not
+    /// typecheck, the bindings are available but not the types of subterms.
+    fn new(uri: &Url, text: String) -> Self {
+        let mut program = Program::new_from_str(text.clone(), uri.as_str());
+        let mut doc = Document {
+            text,
+            diagnostics: Vec::new(),
+            bindings: Vec::new(),
+            usages: Vec::new(),
+            types: Vec::new(),
+            literals: Vec::new(),
+        };
+
+        let result = program.parse_term().and_then(|t| {
+            let binders = program.binders()?.to_vec();
+            let mut collector = Collector::new(&binders);
+            collector.collect(&t, &HashMap::new());
+            doc.bindings = collector.bindings;
+            doc.usages = collector.usages;
+            doc.literals = collector.literals;
+
+            program.typecheck_with_table()
+        });
+
+        match result {
+            Ok((_, types)) => doc.types = types,
+            Err(err) => {
+                let main_id = program.main_id();
+                doc.diagnostics = program
+                    .diagnostics(&err)
+                    .iter()
+                    .map(|d| to_lsp_diagnostic(d, uri, main_id, &doc.text))
+                    .collect();
+            }
+        }
+
+        doc
+    }
+
+    /// Return the binding whose binder or one of whose usages is located at `offset`, together
+    /// with the span of this binder or usage.
+    fn binding_at(&self, offset: usize) -> Option<(&RawSpan, &Binding)> {
+        let binder = self.bindings.iter().find_map(|b| match &b.span {
+            Some(span) if contains(span, offset) => Some((span, b)),
+            _ => None,
+        });
+
+        binder.or_else(|| {
+            self.usages
+                .iter()
+                .find(|(span, _)| contains(span, offset))
+                .map(|(span, i)| (span, &self.bindings[*i]))
+        })
+    }
+
+    /// Return the most precise type of the smallest subterm located at `offset`.
+    fn type_at(&self, offset: usize) -> Option<(RawSpan, Types)> {
+        let smallest = self
+            .types
+            .iter()
+            .chain(self.literals.iter())
+            .filter(|(span, _)| contains(span, offset))
+            .map(|(span, _)| span)
+            .min_by_key(|span| span.end.to_usize() - span.start.to_usize())?;
+
+        let mut candidates = self
+            .types
+            .iter()
+            .chain(self.literals.iter())
+            .filter(|(span, _)| span == smallest)
+            .map(|(_, ty)| ty);
+        let first = candidates.next()?;
+        let ty = candidates
+            .find(|ty| !is_dyn(ty))
+            .filter(|_| is_dyn(first))
+            .unwrap_or(first);
+
+        Some((smallest.clone(), ty.clone()))
+    }
+
+    /// Return the span and the markdown content of the hover information at `offset`.
+    fn hover(&self, offset: usize) -> Option<(RawSpan, String)> {
+        if let Some((span, binding)) = self.binding_at(offset) {
+            let ty = binding
+                .ty
+                .clone()
+                .or_else(|| self.type_at(offset).map(|(_, ty)| ty))
+                .unwrap_or(Types(AbsType::Dyn()));
+            let mut contents = format!("```nickel\n{} : {}\n```", binding.ident, ty);
+            if let Some(doc) = &binding.doc {
+                contents.push_str("\n\n");
+                contents.push_str(doc);
+            }
+
+            return Some((span.clone(), contents));
+        }
+
+        self.type_at(offset)
+            .map(|(span, ty)| (span, format!("```nickel\n{}\n```", ty)))
+    }
+
+    /// Return the position of the binder of the variable located at `offset`.
+    fn definition(&self, offset: usize) -> Option<RawSpan> {
+        self.binding_at(offset)
+            .and_then(|(_, binding)| binding.span.clone())
+    }
+}
+
+/// Traverse a term to collect its bindings and the occurrences of bound variables.
+struct Collector<'a> {
+    /// The binders of the term with their position, in the order of the source.
+    binders: &'a [(Ident, RawSpan)],
+    bindings: Vec<Binding>,
+    usages: Vec<(RawSpan, usize)>,
+    literals: Vec<(RawSpan, Types)>,
+}
+
+impl<'a> Collector<'a> {
+    fn new(binders: &'a [(Ident, RawSpan)]) -> Self {
+        Collector {
+            binders,
+            bindings: Vec::new(),
+            usages: Vec::new(),
+            literals: Vec::new(),
+        }
+    }
+
+    /// Return the binders located between the start of `from` and the start of `to`.
+    fn binders_between(
+        &self,
+        from: &Option<RawSpan>,
+        to: &Option<RawSpan>,
+    ) -> impl Iterator<Item = &'a (Ident, RawSpan)> {
+        let range = match (from, to) {
+            (Some(from), Some(to)) if from.start <= to.start => Some((from.start, to.start)),
+            _ => None,
+        };
+
+        self.binders.iter().filter(move |(_, span)| {
+            range.is_some_and(|(start, end)| start <= span.start && span.start < end)
+        })
+    }
+
+    /// Return the position of the last binder of `ident` located between the start of `from` and
+    /// the start of `to`.
+    fn binder(
+        &self,
+        ident: &Ident,
+        from: &Option<RawSpan>,
+        to: &Option<RawSpan>,
+    ) -> Option<RawSpan> {
+        self.binders_between(from, to)
+            .filter(|(id, _)| id == ident)
+            .last()
+            .map(|(_, span)| span.clone())
+    }
+
+    /// Add a binding to the environment.
+    fn bind(
+        &mut self,
+        env: &mut HashMap<Ident, usize>,
+        ident: &Ident,
+        span: Option<RawSpan>,
+        value: Option<&RichTerm>,
+    ) {
+        let (ty, doc) = value.map(metadata).unwrap_or((None, None));
+        env.insert(ident.clone(), self.bindings.len());
+        self.bindings.push(Binding {
+            ident: ident.clone(),
+            span,
+            ty,
+            doc,
+        });
+    }
+
+    fn collect(&mut self, rt: &RichTerm, env: &HashMap<Ident, usize>) {
+        if let (Some(ty), Some(span)) = (literal_type(rt.as_ref()), &rt.pos) {
+            self.literals.push((span.clone(), ty));
+        }
+
+        match rt.as_ref() {
+            Term::Var(id) => {
+                if let (Some(i), Some(span)) = (env.get(id), &rt.pos) {
+                    self.usages.push((span.clone(), *i));
+                }
+            }
+            Term::Let(id, t1, t2) => {
+                // `let x = t1`: the binder is the last binder of `x` before `t1`.
+                let span = self.binder(id, &rt.pos, &t1.pos);
+                self.collect(t1, env);
+                let mut env = env.clone();
+                self.bind(&mut env, id, span, Some(t1));
+                self.collect(t2, &env);
+            }
+            Term::Fun(_, _) => {
+                // The parser desugars `fun x y => body` to nested functions which share the same
+                // position.
+                let mut params = Vec::new();
+                let mut body = rt;
+                while let Term::Fun(id, t) = body.as_ref() {
+                    params.push(id);
+                    body = t;
+                    if body.pos != rt.pos {
+                        break;
+                    }
+                }
+
+                // The binders of the parameters come in order, but may be interleaved with the
+                // binders of patterns, as in `fun x {y} => body`.
+                let mut binders = self.binders_between(&rt.pos, &body.pos);
+                let mut env = env.clone();
+                for id in params {
+                    let span = binders
+                        .find(|(binder, _)| binder == id)
+                        .map(|(_, span)| span.clone());
+                    self.bind(&mut env, id, span, None);
+                }
+                self.collect(body, &env);
+            }
+            Term::FunPattern(pat, t) => {
+                let mut env = env.clone();
+                self.bind_pattern(&mut env, pat);
+                self.collect(t, &env);
+            }
+            Term::LetRec(bindings, t) => {
+                // `let rec x = t1, y = t2`: the binder of each term is the last binder of its
+                // identifier before it.
+                let mut env = env.clone();
+                for (id, t) in bindings {
+                    let span = self.binder(id, &rt.pos, &t.pos);
+                    self.bind(&mut env, id, span, Some(t));
+                }
+                bindings.iter().for_each(|(_, t)| self.collect(t, &env));
+                self.collect(t, &env);
+            }
+            Term::LetPattern(pat, t1, t2) => {
+                self.collect(t1, env);
+                let mut env = env.clone();
+                self.bind_pattern(&mut env, pat);
+                self.collect(t2, &env);
+            }
+            Term::Match(cases, t) => {
+                self.collect(t, env);
+                for (pat, body) in cases {
+                    let mut env = env.clone();
+                    if let MatchPattern::Destruct(pat) | MatchPattern::Enum(_, Some(pat)) = pat {
+                        self.bind_pattern(&mut env, pat);
+                    }
+                    self.collect(body, &env);
+                }
+            }
+            Term::RecRecord(fields, spans) => {
+                // `{..; x = t; ..}`: the binder is the name of the field, whose position is kept
+                // by the parser.
+                let mut env = env.clone();
+                for (id, t) in fields {
+                    self.bind(&mut env, id, spans.get(id).cloned(), Some(t));
+                }
+                fields.values().for_each(|t| self.collect(t, &env));
+            }
+            Term::Record(fields) => fields.values().for_each(|t| self.collect(t, env)),
+            Term::List(terms) => terms.iter().for_each(|t| self.collect(t, env)),
+            Term::StrChunks(chunks) => self.collect_chunks(chunks, env),
+            Term::App(t1, t2) => {
+                self.collect(t1, env);
+                self.collect(t2, env);
+            }
+            Term::Op1(op, t) => {
+                match op {
+                    UnaryOp::Switch(cases, default) => {
+                        cases.values().for_each(|t| self.collect(t, env));
+                        default.iter().for_each(|t| self.collect(t, env));
+                    }
+                    UnaryOp::MapRec(f) => self.collect(f, env),
+                    UnaryOp::ChunksConcat(_, chunks) => self.collect_chunks(chunks, env),
+                    _ => (),
+                }
+                self.collect(t, env);
+            }
+            Term::Op2(op, t1, t2) => {
+                if let BinaryOp::DynExtend(t) = op {
+                    self.collect(t, env);
+                }
+                self.collect(t1, env);
+                self.collect(t2, env);
+            }
+            Term::Promise(_, _, t)
+            | Term::Assume(_, _, t)
+            | Term::EnumVariant(_, t)
+            | Term::Wrapped(_, t)
+            | Term::DefaultValue(t)
+            | Term::ContractWithDefault(_, _, t)
+            | Term::ContractWithValue(_, _, t)
+            | Term::Docstring(_, t)
+            | Term::Optional(t)
+            | Term::MergeStrategy(_, t)
+            | Term::Priority(_, t) => self.collect(t, env),
+            Term::Bool(_)
+            | Term::Num(_)
+            | Term::Str(_)
+            | Term::Lbl(_)
+            | Term::Enum(_)
+            | Term::Sym(_)
+            | Term::Contract(_, _)
+            | Term::Import(_)
+            | Term::ResolvedImport(_) => (),
+        }
+    }
+
+    /// Bind the variables of a destructuring pattern, and collect the default values of its fields.
+    fn bind_pattern(&mut self, env: &mut HashMap<Ident, usize>, pat: &Pattern) {
+        match pat {
+            Pattern::Ident(id) => self.bind(env, id, None, None),
+            Pattern::Record(fields, _, _) => {
+                for field in fields {
+                    if let Some(t) = &field.default {
+                        self.collect(t, env);
+                    }
+
+                    match &field.pattern {
+                        // `a`, `a = x` or `a ? t`: the binder is the last binder of the variable
+                        // before the default value, if any.
+                        Pattern::Ident(id) => {
+                            let to =
+                                field
+                                    .default
+                                    .as_ref()
+                                    .and_then(|t| t.pos.clone())
+                                    .or_else(|| {
+                                        field.pos.as_ref().map(|pos| RawSpan {
+                                            start: pos.end,
+                                            ..pos.clone()
+                                        })
+                                    });
+                            let span = self.binder(id, &field.pos, &to);
+                            self.bind(env, id, span, None);
+                        }
+                        pat => self.bind_pattern(env, pat),
+                    }
+                }
+            }
+            Pattern::List(pats, _) => pats.iter().for_each(|pat| self.bind_pattern(env, pat)),
+        }
+    }
+
+    fn collect_chunks(&mut self, chunks: &[StrChunk<RichTerm>], env: &HashMap<Ident, usize>) {
+        for chunk in chunks {
+            if let StrChunk::Expr(t) = chunk {
+                self.collect(t, env);
+            }
+        }
+    }
+}
+
+/// Return the type of a literal.
+fn literal_type(t: &Term) -> Option<Types> {
+    match t {
+        Term::Bool(_) => Some(Types(AbsType::Bool())),
+        Term::Num(_) => Some(Types(AbsType::Num())),
+        Term::Str(_) | Term::StrChunks(_) => Some(Types(AbsType::Str())),
+        Term::List(_) => Some(Types(AbsType::List())),
+        _ => None,
+    }
+}
+
+/// Return the type and the documentation of a bound expression, as given by its annotations.
+fn metadata(rt: &RichTerm) -> (Option<Types>, Option<String>) {
+    let mut doc = None;
+    let mut t = rt.as_ref();
+
+    let ty = loop {
+        match t {
+            Term::Docstring(s, inner) => {
+                doc.get_or_insert_with(|| s.clone());
+                t = inner.as_ref();
+            }
+            Term::DefaultValue(inner)
+            | Term::Optional(inner)
+            | Term::MergeStrategy(_, inner)
+            | Term::Priority(_, inner) => t = inner.as_ref(),
+            Term::Promise(annot, _, _)
+            | Term::Assume(annot, _, _)
+            | Term::Contract(annot, _)
+            | Term::ContractWithDefault(annot, _, _)
+            | Term::ContractWithValue(annot, _, _) => break Some(annot.clone()),
+            t => break literal_type(t),
+        }
+    };
+
+    (ty, doc)
+}
+
+fn is_dyn(ty: &Types) -> bool {
+    matches!(ty, Types(AbsType::Dyn()))
+}
+
+/// Determine if a span contains a byte offset. The end of the span is included, so that the
+/// cursor right after an identifier still designates it.
+fn contains(span: &RawSpan, offset: usize) -> bool {
+    span.start.to_usize() <= offset && offset <= span.end.to_usize()
+}
+
+/// Convert a byte offset in a source to an LSP position, whose character offset is counted in
+/// UTF-16 code units.
+fn position(src: &str, offset: usize) -> Position {
+    let before = &src[..offset.min(src.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
+
+/// Convert an LSP position to a byte offset in a source. Return `None` if the line does not
+/// exist. A character offset past the end of a line designates the end of this line.
+fn offset(src: &str, pos: Position) -> Option<usize> {
+    let line_start = match pos.line {
+        0 => 0,
+        n => src.match_indices('\n').nth(n as usize - 1)?.0 + 1,
+    };
+
+    let mut utf16_count = 0;
+    for (i, c) in src[line_start..].char_indices() {
+        if c == '\n' || utf16_count >= pos.character as usize {
+            return Some(line_start + i);
+        }
+        utf16_count += c.len_utf16();
+    }
+
+    Some(src.len())
+}
+
+fn range(src: &str, span: &RawSpan) -> Range {
+    Range::new(
+        position(src, span.start.to_usize()),
+        position(src, span.end.to_usize()),
+    )
+}
+
+/// Convert a diagnostic of the program to an LSP diagnostic.
+///
+/// LSP diagnostics are attached to a single range of the document: the primary label located in
+/// the document, or the first secondary label if there is none. The other labels located in the
+/// document become related information, and the notes are appended to the message. Labels
+/// pointing to other sources (such as the standard library) are dropped.
+fn to_lsp_diagnostic(
+    diagnostic: &diagnostic::Diagnostic<FileId>,
+    uri: &Url,
+    main_id: FileId,
+    src: &str,
+) -> Diagnostic {
+    let to_range = |label: &diagnostic::Label<FileId>| {
+        let span = RawSpan {
+            src_id: label.file_id,
+            start: (label.range.start as u32).into(),
+            end: (label.range.end as u32).into(),
+        };
+        range(src, &span)
+    };
+
+    let labels: Vec<_> = diagnostic
+        .labels
+        .iter()
+        .filter(|label| label.file_id == main_id)
+        .collect();
+    let main_label = labels
+        .iter()
+        .find(|label| label.style == LabelStyle::Primary)
+        .or_else(|| labels.first())
+        .copied();
+
+    let mut message = diagnostic.message.clone();
+    if let Some(label) = main_label.filter(|label| !label.message.is_empty()) {
+        message.push_str(&format!("\n{}", label.message));
+    }
+    for note in diagnostic.notes.iter() {
+        message.push_str(&format!("\n{}", note));
+    }
+
+    let related: Vec<_> = labels
+        .iter()
+        .filter(|label| !main_label.is_some_and(|main| std::ptr::eq(main, **label)))
+        .map(|label| DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), to_range(label)),
+            message: label.message.clone(),
+        })
+        .collect();
+
+    let severity = match diagnostic.severity {
+        Severity::Bug | Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Note => DiagnosticSeverity::INFORMATION,
+        Severity::Help => DiagnosticSeverity::HINT,
+    };
+
+    Diagnostic {
+        range: main_label.map(to_range).unwrap_or_default(),
+        severity: Some(severity),
+        source: Some(String::from("nickel")),
+        message,
+        related_information: if related.is_empty() {
+            None
+        } else {
+            Some(related)
+        },
+        ..Diagnostic::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_server::RequestId;
+    use lsp_types::notification::{Exit, Initialized};
+    use lsp_types::request::{Initialize, Shutdown};
+    use lsp_types::{
+        DidOpenTextDocumentParams, InitializeParams, InitializedParams, TextDocumentIdentifier,
+        TextDocumentItem, TextDocumentPositionParams,
+    };
+    use serde_json::Value;
+    use std::thread;
+
+    /// A scripted client, talking to a server running in another thread.
+    struct Client {
+        connection: Connection,
+        server: Option<thread::JoinHandle<()>>,
+        next_id: i32,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (client, server) = Connection::memory();
+            let handle = thread::spawn(move || run(&server).unwrap());
+            let mut client = Client {
+                connection: client,
+                server: Some(handle),
+                next_id: 0,
+            };
+
+            client.request::<Initialize>(InitializeParams::default());
+            client.notify::<Initialized>(InitializedParams {});
+            client
+        }
+
+        fn request<R: RequestTrait>(&mut self, params: R::Params) -> Value {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let req = Request::new(id.clone(), String::from(R::METHOD), params);
+            self.connection.sender.send(req.into()).unwrap();
+
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(resp) => {
+                    assert_eq!(resp.id, id);
+                    resp.result.unwrap()
+                }
+                msg => panic!("expected a response, got {:?}", msg),
+            }
+        }
+
+        fn notify<N: NotificationTrait>(&mut self, params: N::Params) {
+            let notif = Notification::new(String::from(N::METHOD), params);
+            self.connection.sender.send(notif.into()).unwrap();
+        }
+
+        /// Open a document and return the published diagnostics.
+        fn open(&mut self, uri: &Url, text: &str) -> Vec<Diagnostic> {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    String::from("nickel"),
+                    0,
+                    String::from(text),
+                ),
+            });
+
+            match self.connection.receiver.recv().unwrap() {
+                Message::Notification(notif) => {
+                    let params: PublishDiagnosticsParams =
+                        notif.extract(PublishDiagnostics::METHOD).unwrap();
+                    assert_eq!(&params.uri, uri);
+                    params.diagnostics
+                }
+                msg => panic!("expected diagnostics, got {:?}", msg),
+            }
+        }
+
+        fn hover(&mut self, uri: &Url, line: u32, character: u32) -> Option<Hover> {
+            let result = self.request::<HoverRequest>(HoverParams {
+                text_document_position_params: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri.clone()),
+                    Position::new(line, character),
+                ),
+                work_done_progress_params: Default::default(),
+            });
+            serde_json::from_value(result).unwrap()
+        }
+
+        fn definition(&mut self, uri: &Url, line: u32, character: u32) -> Option<Range> {
+            let result = self.request::<GotoDefinition>(GotoDefinitionParams {
+                text_document_position_params: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri.clone()),
+                    Position::new(line, character),
+                ),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            });
+
+            match serde_json::from_value(result).unwrap() {
+                Some(GotoDefinitionResponse::Scalar(location)) => {
+                    assert_eq!(&location.uri, uri);
+                    Some(location.range)
+                }
+                None => None,
+                Some(resp) => panic!("unexpected definition response {:?}", resp),
+            }
+        }
+    }
+
+    impl Drop for Client {
+        fn drop(&mut self) {
+            if thread::panicking() {
+                return;
+            }
+
+            self.request::<Shutdown>(());
+            self.notify::<Exit>(());
+            self.server.take().unwrap().join().unwrap();
+        }
+    }
+
+    fn uri() -> Url {
+        Url::parse("file:///test.ncl").unwrap()
+    }
+
+    fn range(l1: u32, c1: u32, l2: u32, c2: u32) -> Range {
+        Range::new(Position::new(l1, c1), Position::new(l2, c2))
+    }
+
+    fn hover_value(hover: Option<Hover>) -> String {
+        match hover.expect("expected hover information").contents {
+            HoverContents::Markup(content) => content.value,
+            contents => panic!("unexpected hover contents {:?}", contents),
+        }
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut client = Client::start();
+        let uri = uri();
+
+        assert_eq!(client.open(&uri, "let x = 1 in x + 1"), Vec::new());
+
+        let diags = client.open(&uri, "let x = 1 in\nPromise(Num, x ++ \"a\")");
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diags[0].range.start.line, 1);
+
+        let diags = client.open(&uri, "let x = in 1");
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].range, range(0, 8, 0, 10));
+
+        let diags = client.open(&uri, "let x = 1 in y");
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].range, range(0, 13, 0, 14));
+    }
+
+    #[test]
+    fn hover() {
+        let mut client = Client::start();
+        let uri = uri();
+        let src = "let x = Docstring(\"The answer\", Assume(Num, 42)) in\n\
+                   let f = fun y => y in\n\
+                   Promise(Str, \"a\" ++ \"b\")";
+        assert_eq!(client.open(&uri, src), Vec::new());
+
+        // Binder of `x`: annotated type and documentation.
+        let value = hover_value(client.hover(&uri, 0, 4));
+        assert!(value.contains("x : Num"));
+        assert!(value.contains("The answer"));
+
+        // Inside a promise: type inferred by the typechecker.
+        assert!(hover_value(client.hover(&uri, 2, 14)).contains("Str"));
+
+        // Out of any expression.
+        assert_eq!(client.hover(&uri, 10, 0), None);
+    }
+
+    #[test]
+    fn definition() {
+        let mut client = Client::start();
+        let uri = uri();
+        let src = "let x = 1 in\n\
+                   let f = fun a b => a + b + x in\n\
+                   {foo = f 1 bar; bar = foo}";
+        assert_eq!(client.open(&uri, src), Vec::new());
+
+        // Let-bound identifiers.
+        assert_eq!(client.definition(&uri, 1, 27), Some(range(0, 4, 0, 5)));
+        assert_eq!(client.definition(&uri, 2, 7), Some(range(1, 4, 1, 5)));
+        // Function-bound identifiers.
+        assert_eq!(client.definition(&uri, 1, 19), Some(range(1, 12, 1, 13)));
+        assert_eq!(client.definition(&uri, 1, 23), Some(range(1, 14, 1, 15)));
+        // Recursive record fields.
+        assert_eq!(client.definition(&uri, 2, 11), Some(range(2, 16, 2, 19)));
+        assert_eq!(client.definition(&uri, 2, 22), Some(range(2, 1, 2, 4)));
+        // Literals have no definition.
+        assert_eq!(client.definition(&uri, 0, 8), None);
+
+        let src = "let {a, b = c ? a} = {a = 1} in\n\
+                   fun {d} => a + c + d";
+        assert_eq!(client.open(&uri, src), Vec::new());
+
+        // Identifiers bound by patterns.
+        assert_eq!(client.definition(&uri, 1, 11), Some(range(0, 5, 0, 6)));
+        assert_eq!(client.definition(&uri, 1, 15), Some(range(0, 12, 0, 13)));
+        assert_eq!(client.definition(&uri, 1, 19), Some(range(1, 5, 1, 6)));
+        assert_eq!(client.definition(&uri, 0, 16), Some(range(0, 5, 0, 6)));
+
+        let src = "let rec f = fun n => g n, g = fun n => f n in f 1";
+        assert_eq!(client.open(&uri, src), Vec::new());
+
+        // Recursive let bindings.
+        assert_eq!(client.definition(&uri, 0, 21), Some(range(0, 26, 0, 27)));
+        assert_eq!(client.definition(&uri, 0, 39), Some(range(0, 8, 0, 9)));
+        assert_eq!(client.definition(&uri, 0, 46), Some(range(0, 8, 0, 9)));
+
+        let src = "let r = {a = 1} in\n\
+                   match { {a} => a, _ => r } r";
+        assert_eq!(client.open(&uri, src), Vec::new());
+
+        // Identifiers bound by the pattern of a match case.
+        assert_eq!(client.definition(&uri, 1, 15), Some(range(1, 9, 1, 10)));
+        assert_eq!(client.definition(&uri, 1, 23), Some(range(0, 4, 0, 5)));
+
+        let src = "let x = 1 in\n\
+                   let f = fun x => \"x = ${x}\" in\n\
+                   let x = f x in\n\
+                   x";
+        assert_eq!(client.open(&uri, src), Vec::new());
+
+        // Shadowed identifiers, and identifiers inside strings.
+        assert_eq!(client.definition(&uri, 1, 24), Some(range(1, 12, 1, 13)));
+        assert_eq!(client.definition(&uri, 2, 10), Some(range(0, 4, 0, 5)));
+        assert_eq!(client.definition(&uri, 3, 0), Some(range(2, 4, 2, 5)));
+
+        let src = "{a | Num = 1; b = a;\n \
+                   c | default = 1; d = c;\n \
+                   e | doc \"x\" = 1; f = e;\n \
+                   g | priority 1 = 1; h = g;\n \
+                   i.j = 1; k = i}";
+        assert_eq!(client.open(&uri, src), Vec::new());
+
+        // Annotated fields and field paths.
+        assert_eq!(client.definition(&uri, 0, 18), Some(range(0, 1, 0, 2)));
+        assert_eq!(client.definition(&uri, 1, 22), Some(range(1, 1, 1, 2)));
+        assert_eq!(client.definition(&uri, 2, 22), Some(range(2, 1, 2, 2)));
+        assert_eq!(client.definition(&uri, 3, 25), Some(range(3, 1, 3, 2)));
+        assert_eq!(client.definition(&uri, 4, 14), Some(range(4, 1, 4, 2)));
+    }
+
+    #[test]
+    fn positions() {
+        let src = "a\né𝄞b\nc";
+        assert_eq!(position(src, 0), Position::new(0, 0));
+        assert_eq!(position(src, 2), Position::new(1, 0));
+        // `é` is one UTF-16 code unit long, `𝄞` is two.
+        assert_eq!(position(src, 8), Position::new(1, 3));
+        assert_eq!(offset(src, Position::new(1, 3)), Some(8));
+        assert_eq!(offset(src, Position::new(1, 10)), Some(9));
+        assert_eq!(offset(src, Position::new(2, 0)), Some(10));
+        assert_eq!(offset(src, Position::new(3, 0)), None);
+    }
+}
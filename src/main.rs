@@ -1,30 +1,333 @@
 //! Entry point of the program.
-mod error;
-mod eval;
-mod identifier;
-mod label;
-mod merge;
-mod operation;
-mod parser;
-mod position;
-mod program;
-mod stack;
-mod stdlib;
-mod term;
-mod transformations;
-mod typecheck;
-mod types;
-
-use crate::program::Program;
-
-extern crate either;
+use codespan::Files;
+use codespan_reporting::term::termcolor::ColorChoice;
+use nickel::doc::{self, DocFormat, FieldDoc};
+use nickel::error::Error;
+use nickel::identifier::Ident;
+use nickel::lsp;
+use nickel::position::RawSpan;
+use nickel::program::{Program, QueryResult};
+use nickel::repl::rustyline_frontend;
+use nickel::serialize::{self, ExportFormat};
+use nickel::term::{RichTerm, Term};
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Exit code when the input could not be read.
+const EXIT_IO: i32 = 1;
+/// Exit code of a parse error.
+const EXIT_PARSE: i32 = 2;
+/// Exit code of a typechecking error.
+const EXIT_TYPECHECK: i32 = 3;
+/// Exit code of an evaluation error, including contract failures.
+const EXIT_EVAL: i32 = 4;
+/// Exit code of an import error.
+const EXIT_IMPORT: i32 = 5;
+/// Exit code of a serialization error.
+const EXIT_SERIALIZATION: i32 = 6;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "nickel",
+    about = "The interpreter of the Nickel language.",
+    after_help = "EXIT CODES:
+    0    success
+    1    the input could not be read
+    2    parse error
+    3    typechecking error
+    4    evaluation error
+    5    import error
+    6    serialization error"
+)]
+struct Opt {
+    /// When to use colors in error messages: auto, always or never.
+    #[structopt(long, global = true, default_value = "auto", parse(try_from_str = parse_color))]
+    color: ColorChoice,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Evaluate a program (the default), and print a summary of the result. The content of records
+    /// and lists is not evaluated: use `export` to print it.
+    Eval {
+        /// The program to evaluate. Read from the standard input if absent.
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// Typecheck a program without evaluating it, and print its type.
+    Typecheck {
+        /// The program to typecheck. Read from the standard input if absent.
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// Fully evaluate a program and export the result to a data format.
+    Export {
+        /// The program to export. Read from the standard input if absent.
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+        /// The export format: json, yaml or toml.
+        #[structopt(long, default_value = "json")]
+        format: ExportFormat,
+    },
+    /// Print the value and the metadata (documentation, contract and default value) of a field.
+    ///
+    /// Only the parts of the program needed to access the field are evaluated.
+    Query {
+        /// The path of the field, as a dot-separated list of identifiers, such as `server.port`.
+        path: FieldPath,
+        /// The program to query. Read from the standard input if absent.
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// Generate the documentation of the fields of a record from their metadata.
+    Doc {
+        /// The program to document, which must evaluate to a record. Read from the standard input
+        /// if absent.
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+        /// The output format: markdown or json.
+        #[structopt(long, default_value = "markdown")]
+        format: DocFormat,
+    },
+    /// Reformat programs in place, or the standard input to the standard output if no file is
+    /// given.
+    Fmt {
+        /// The programs to format.
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+    /// Start an interactive session.
+    Repl,
+    /// Start a language server, communicating over the standard input and output.
+    Lsp,
+}
+
+/// The path of a field in a record, such as `server.port`.
+#[derive(Debug)]
+struct FieldPath(Vec<Ident>);
+
+impl FromStr for FieldPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split('.')
+            .map(|id| {
+                if id.is_empty() {
+                    Err(format!("invalid field path `{}`", s))
+                } else {
+                    Ok(Ident::from(id))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(FieldPath)
+    }
+}
+
+/// Parse the value of the `--color` option. `auto` only enables colors if the standard error
+/// output is a terminal.
+fn parse_color(s: &str) -> Result<ColorChoice, String> {
+    match s {
+        "auto" if io::stderr().is_terminal() => Ok(ColorChoice::Auto),
+        "auto" | "never" => Ok(ColorChoice::Never),
+        "always" => Ok(ColorChoice::Always),
+        _ => Err(format!(
+            "invalid color option `{}` (expected auto, always or never)",
+            s
+        )),
+    }
+}
+
+/// Return the exit code corresponding to an error.
+fn exit_code(error: &Error) -> i32 {
+    match error {
+        Error::ParseError(_) => EXIT_PARSE,
+        Error::TypecheckError(_) => EXIT_TYPECHECK,
+        Error::EvalError(_) => EXIT_EVAL,
+        Error::ImportError(_) => EXIT_IMPORT,
+        Error::SerializationError(_) => EXIT_SERIALIZATION,
+    }
+}
+
+/// Return a representation of a fully evaluated term: JSON if it can be serialized, or a shallow
+/// representation otherwise.
+fn repr(t: Term) -> String {
+    let rt = RichTerm::from(t);
+    serialize::to_json(&rt).unwrap_or_else(|_| rt.as_ref().shallow_repr())
+}
+
+/// Return the location of the start of a span, as `file:line:column`.
+fn location(files: &Files<String>, span: &RawSpan) -> String {
+    let name = files.name(span.src_id).to_string_lossy();
+    match files.location(span.src_id, span.start) {
+        Ok(loc) => format!("{}:{}:{}", name, loc.line.number(), loc.column.number()),
+        Err(_) => name.into_owned(),
+    }
+}
+
+/// Print a field of a queried record on one line, with its metadata written as annotations.
+fn field_summary(field: FieldDoc) -> String {
+    let mut summary = field.path_str();
+    if let Some(ty) = field.contract {
+        summary.push_str(&format!(" | {}", ty));
+    }
+    if let Some(t) = field.default {
+        summary.push_str(&format!(" | default = {}", repr(t)));
+    }
+    if field.optional {
+        summary.push_str(" | optional");
+    }
+    if let Some(doc) = field.doc {
+        summary.push_str(&format!(": {}", doc.lines().next().unwrap_or_default()));
+    }
+    summary
+}
+
+/// Print the result of a query.
+fn print_query_result(result: QueryResult, files: &Files<String>) {
+    if let Some(doc) = result.doc {
+        println!("* documentation: {}", doc);
+    }
+    if let Some(ty) = result.contract {
+        println!("* contract: {}", ty);
+    }
+    if let Some(t) = result.default {
+        println!("* default: {}", repr(t));
+    }
+    if result.optional {
+        println!("* optional");
+    }
+    if let Some(t) = result.value {
+        println!("* value: {}", repr(t));
+    }
+    if let Some(fields) = result.fields {
+        println!("* fields:");
+        for field in fields {
+            println!("  - {}", field_summary(field));
+        }
+    }
+    for span in result.definitions {
+        println!("* defined at: {}", location(files, &span));
+    }
+    for span in result.overridden {
+        println!("* overrides: {}", location(files, &span));
+    }
+}
+
+/// Load a program from a file, or from the standard input if `file` is `None`.
+fn load(file: Option<PathBuf>) -> Program {
+    let result = match &file {
+        Some(path) => Program::new_from_file(path),
+        None => Program::new_from_stdin(),
+    };
+
+    match result {
+        Ok(p) => p,
+        Err(err) => {
+            let name = file
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from("<stdin>"));
+            eprintln!("Error when reading {}: {}", name, err);
+            process::exit(EXIT_IO);
+        }
+    }
+}
+
+/// Format each file in place, or the standard input if `files` is empty. Stop at the first error.
+fn fmt(files: Vec<PathBuf>) -> (Program, Result<(), Error>) {
+    if files.is_empty() {
+        let mut p = load(None);
+        let result = p.format().map(|s| print!("{}", s));
+        return (p, result);
+    }
+
+    let mut last = None;
+    for path in files {
+        let mut p = load(Some(path.clone()));
+        match p.format() {
+            Ok(s) => {
+                if let Err(err) = fs::write(&path, s) {
+                    eprintln!("Error when writing {}: {}", path.to_string_lossy(), err);
+                    process::exit(EXIT_IO);
+                }
+            }
+            Err(err) => return (p, Err(err)),
+        }
+        last = Some(p);
+    }
+
+    (last.unwrap(), Ok(()))
+}
 
 fn main() {
-    match Program::new_from_stdin() {
-        Ok(mut p) => match p.eval() {
-            Ok(t) => println!("Done: {:?}", t),
-            Err(err) => p.report(err),
-        },
-        Err(msg) => eprintln!("Error when reading the source: {}", msg),
+    let opt = Opt::from_args();
+    let command = opt.command.unwrap_or(Command::Eval { file: None });
+
+    if let Command::Repl = command {
+        // The error has already been reported by the REPL, which holds the corresponding sources.
+        if let Err(err) = rustyline_frontend::repl(opt.color) {
+            process::exit(exit_code(&err));
+        }
+        return;
+    }
+
+    if let Command::Lsp = command {
+        if let Err(err) = lsp::stdio() {
+            eprintln!("Language server error: {}", err);
+            process::exit(EXIT_IO);
+        }
+        return;
+    }
+
+    let (mut program, result) = match command {
+        Command::Eval { file } => {
+            let mut p = load(file);
+            let result = p.eval().map(|t| println!("{}", t.shallow_repr()));
+            (p, result)
+        }
+        Command::Typecheck { file } => {
+            let mut p = load(file);
+            let result = p.typecheck().map(|ty| println!("{}", ty));
+            (p, result)
+        }
+        Command::Export { file, format } => {
+            let mut p = load(file);
+            let result = match p.eval_full() {
+                Ok(t) => serialize::export(&t, format)
+                    .map(|s| println!("{}", s))
+                    .map_err(Error::from),
+                Err(err) => Err(err),
+            };
+            (p, result)
+        }
+        Command::Query { path, file } => {
+            let mut p = load(file);
+            let result = p
+                .query(&path.0)
+                .map(|result| print_query_result(result, p.files()));
+            (p, result)
+        }
+        Command::Doc { file, format } => {
+            let mut p = load(file);
+            let result = p
+                .doc()
+                .map(|docs| println!("{}", doc::render(&docs, format)));
+            (p, result)
+        }
+        Command::Fmt { files } => fmt(files),
+        Command::Repl | Command::Lsp => unreachable!(),
     };
+
+    if let Err(err) = result {
+        let code = exit_code(&err);
+        program.set_color(opt.color);
+        program.report(err);
+        process::exit(code);
+    }
 }
@@ -11,7 +11,16 @@
 //! - All the fields of `r1` that are not in `r2`
 //! - All the fields of `r2` that are not in `r1`
 //! - Fields that are both in `r1` and `r2` are recursively merged: for a field `f`, the result
-//! contains the binding `f = merge r1.f r2.f`
+//!   contains the binding `f = merge r1.f r2.f`
+//!
+//! Recursive records are late-bound: the fields of `r1` and `r2` which refer to other fields of
+//! their record see the fields of the result instead. For example, merging `{a = 1; b = a + 1}`
+//! with `{a = 10}` gives a record where `b` evaluates to `11`. The fields are thus computed anew
+//! in the result, even if they have been evaluated in `r1` or `r2`. Record contracts and
+//! `mapRec` keep the definitions of the fields they are applied to, such that a record is still
+//! late-bound after having gone through a contract. A record contract attaches the contract of
+//! each field to its definition as a `ContractWithValue`, such that the contract is checked again
+//! against the values the field is merged with, including those with a higher priority.
 //!
 //! As fields are recursively merged, merge needs to operate on any value, not only on records.
 //!
@@ -21,44 +30,77 @@
 //!
 //! - *Function*: merging a function with anything else fails
 //! - *Values*: merging any other values succeeds if and only if these two values are equals, in which case it evaluates to
-//! this common value.
-//!
-//! Note that merging of lists is not yet implemented.
+//!   this common value.
+//! - *List*: merging two lists depends on the merge strategy. By default, the lists must have the
+//!   same length, and their elements are merged pairwise. With the `concat` strategy, set with a
+//!   `MergeStrategy` enriched value, the lists are concatenated.
 //!
 //! ## On enriched values
 //!
-//! Enriched values (currently `Contract`, `Default`, `ContractDefault` or `Docstring`) get their
-//! special powers from their interaction with the merge operator.
+//! Enriched values (currently `Contract`, `Default`, `ContractDefault`, `ContractValue`,
+//! `Docstring`, `Optional`, `MergeStrategy` or `Priority`) get their special powers from their
+//! interaction with the merge operator.
 //!
 //! ### Enriched/Enriched
 //!
 //! - *Contract/contract*: merging two contracts evaluates to a contract which is the composition
-//! of the two
+//!   of the two
 //! - *Default/default*: merging two default values evaluates to a default which value is the merge
-//! of the two
+//!   of the two
 //! - *Contract/default*: merging a `Default` with a `Contract` evaluates to a `ContractDefault`
 //! - *ContractDefault/_*: Merging `ContractDefault` is done component-wise: with another
-//! `ContractDefault`, it evaluates to a `ContractDefault` where the two contracts as well as the
-//! two default values are respectively merged together. With either just a `Contract` or a
-//! `Default`, it simply merges the corresponding component and let the other unchanged.
+//!   `ContractDefault`, it evaluates to a `ContractDefault` where the two contracts as well as the
+//!   two default values are respectively merged together. With either just a `Contract` or a
+//!   `Default`, it simply merges the corresponding component and let the other unchanged.
+//! - *ContractValue/_*: a `ContractValue` is a contract together with a definition. Merging it
+//!   with a `Contract`, a `ContractDefault` or another `ContractValue` evaluates to a
+//!   `ContractValue` where the contracts are merged together, as well as the definitions if both
+//!   have one. Default values are dropped.
+//!
+//! - *Optional/optional*: merging two optional fields evaluates to an optional field whose inner
+//!   term is the merge of the two inner terms
+//! - *Priority/priority*: merging two definitions with different priorities evaluates to the one
+//!   with the highest priority, the other being dropped. The lower priority definition is dropped
+//!   as a whole, even if both are records: their fields are not merged. To override only some
+//!   fields of a record, the priority must be set on these fields instead. With equal priorities,
+//!   the inner terms are merged, and the result keeps the common priority
+//! - *Priority/default*: merging a `Priority` with a `Default` drops the default value
+//! - *Priority/contract*: merging a `Priority` with a `Contract` or a `ContractDefault` merges the
+//!   inner term with the contract, and keeps the priority around the resulting contract check.
+//!   With a `ContractValue`, the definition is compared to the priority as a simple value, but the
+//!   contract is kept in any case
 //!
 //! ### Enriched/Simple
 //!
+//! - *MergeStrategy*: merging a merge strategy (with inner term `inner`) with another term `t`
+//!   merges `inner` and `t` using this strategy for lists, and evaluates to this result wrapped
+//!   in the merge strategy. Merging two different strategies fails
+//! - *Optional*: merging an optional field (with inner term `inner`) with another term `t` drops
+//!   the marker and evaluates to the merge of `inner` and `t`. In particular, merging a record
+//!   with an optional field that it does not define leaves the field optional, while a definition
+//!   makes it a normal field
+//! - *Priority*: a simple value has the normal priority `0`, below `force`. Merging a `Priority`
+//!   with a simple value thus behaves as for two priorities
+//!
 //! - *Docstring*: merging a docstring (with inner term `inner`) with another term `t` recursively merges
-//! `inner` and `t`, and evaluates to this result wrapped in the original docstring (`t` may be a simple value or an
-//! enriched one here)
+//!   `inner` and `t`, and evaluates to this result wrapped in the original docstring (`t` may be a simple value or an
+//!   enriched one here)
 //! - *Default erasure*: merging a `Default` with a simple value drops the default value and
-//! evaluates to the simple value
+//!   evaluates to the simple value
 //! - *Contract check*: merging a `Contract` or a `ContractDefault` with a simple value `t`
-//! evaluates to a contract check, that is an `Assume(..., t)`
+//!   evaluates to a `ContractValue`, which remembers the contract and is checked as an
+//!   `Assume(..., t)` when its value is needed. Merging a `ContractValue` with a simple value `t`
+//!   merges its definition with `t`, and keeps the contract
 use crate::error::EvalError;
-use crate::eval::{Closure, Environment};
+use crate::eval::{close_record, is_optional_field, Closure, Environment, FieldDef};
+use crate::identifier::Ident;
 use crate::label::Label;
 use crate::position::RawSpan;
-use crate::term::{BinaryOp, RichTerm, Term};
+use crate::term::{BinaryOp, MergePriority, MergeStrategy, RichTerm, Term};
 use crate::transformations::Closurizable;
 use crate::types::{AbsType, Types};
 use std::collections::HashMap;
+use std::rc::Rc;
 
 /// Compute the merge of two evaluated operands.
 pub fn merge(
@@ -67,6 +109,8 @@ pub fn merge(
     t2: RichTerm,
     env2: Environment,
     pos_op: Option<RawSpan>,
+    strategy: MergeStrategy,
+    path: Vec<Ident>,
 ) -> Result<Closure, EvalError> {
     let RichTerm {
         term: t1,
@@ -92,6 +136,7 @@ pub fn merge(
                         pos: pos2,
                     },
                     pos_op,
+                    path,
                 ))
             }
         }
@@ -109,6 +154,7 @@ pub fn merge(
                         pos: pos2,
                     },
                     pos_op,
+                    path,
                 ))
             }
         }
@@ -126,6 +172,7 @@ pub fn merge(
                         pos: pos2,
                     },
                     pos_op,
+                    path,
                 ))
             }
         }
@@ -143,9 +190,67 @@ pub fn merge(
                         pos: pos2,
                     },
                     pos_op,
+                    path,
                 ))
             }
         }
+        // An optional field stays optional only if it is optional on both sides. Otherwise, the
+        // marker is dropped and the underlying enriched values are merged as usual.
+        (Term::Optional(t1), Term::Optional(t2)) => {
+            let Closure { body, env } = mk_merge_closure(t1, env1, t2, env2, strategy, &path);
+            let body = Term::Optional(body).into();
+            Ok(Closure { body, env })
+        }
+        (Term::Optional(t1), t2) => Ok(mk_merge_closure(
+            t1,
+            env1,
+            RichTerm {
+                term: Box::new(t2),
+                pos: pos2,
+            },
+            env2,
+            strategy,
+            &path,
+        )),
+        (t1, Term::Optional(t2)) => Ok(mk_merge_closure(
+            RichTerm {
+                term: Box::new(t1),
+                pos: pos1,
+            },
+            env1,
+            t2,
+            env2,
+            strategy,
+            &path,
+        )),
+        // A merge strategy is kept around the result, such that it applies to the following merges
+        // as well. Two different strategies can't be reconciled.
+        (Term::MergeStrategy(s1, t1), Term::MergeStrategy(s2, t2)) if s1 == s2 => {
+            let Closure { body, env } = mk_merge_closure(t1, env1, t2, env2, s1, &path);
+            let body = Term::MergeStrategy(s1, body).into();
+            Ok(Closure { body, env })
+        }
+        (Term::MergeStrategy(s1, _), Term::MergeStrategy(s2, _)) => Err(
+            EvalError::MergeStrategyMismatch(s1, pos1, s2, pos2, pos_op, path),
+        ),
+        (Term::MergeStrategy(s, t1), t2) => {
+            let t2 = RichTerm {
+                term: Box::new(t2),
+                pos: pos2,
+            };
+            let Closure { body, env } = mk_merge_closure(t1, env1, t2, env2, s, &path);
+            let body = Term::MergeStrategy(s, body).into();
+            Ok(Closure { body, env })
+        }
+        (t1, Term::MergeStrategy(s, t2)) => {
+            let t1 = RichTerm {
+                term: Box::new(t1),
+                pos: pos1,
+            };
+            let Closure { body, env } = mk_merge_closure(t1, env1, t2, env2, s, &path);
+            let body = Term::MergeStrategy(s, body).into();
+            Ok(Closure { body, env })
+        }
         // Right-biased: when merging two docstrings (s1,t2) and (s2,t2), the right one will end up
         // as the outermost position in the resulting term (s2,(s1,merge t1 t2))
         (t1, Term::Docstring(s, t2)) => {
@@ -157,6 +262,8 @@ pub fn merge(
                 env1,
                 t2,
                 env2,
+                strategy,
+                &path,
             );
             let body = Term::Docstring(s, body).into();
             Ok(Closure { body, env })
@@ -170,19 +277,57 @@ pub fn merge(
                     pos: pos2,
                 },
                 env2,
+                strategy,
+                &path,
             );
             let body = Term::Docstring(s, body).into();
             Ok(Closure { body, env })
         }
+        // Priorities must be handled before contracts, such that the priority of a definition is
+        // kept when a contract is applied to it.
+        (Term::Priority(p1, t1), Term::Priority(p2, t2)) => {
+            if p1 > p2 {
+                Ok(keep_priority(p1, t1, pos1, env1))
+            } else if p1 < p2 {
+                Ok(keep_priority(p2, t2, pos2, env2))
+            } else {
+                let Closure { body, env } = mk_merge_closure(t1, env1, t2, env2, strategy, &path);
+                let body = Term::Priority(p1, body).into();
+                Ok(Closure { body, env })
+            }
+        }
+        (Term::Priority(p, t1), t2) => merge_priority(
+            (p, t1, pos1, env1),
+            RichTerm {
+                term: Box::new(t2),
+                pos: pos2,
+            },
+            env2,
+            false,
+            strategy,
+            path,
+        ),
+        (t1, Term::Priority(p, t2)) => merge_priority(
+            (p, t2, pos2, env2),
+            RichTerm {
+                term: Box::new(t1),
+                pos: pos1,
+            },
+            env1,
+            true,
+            strategy,
+            path,
+        ),
         // Default merging
         (Term::DefaultValue(t1), Term::DefaultValue(t2)) => {
-            let Closure { body, env } = mk_merge_closure(t1, env1, t2, env2);
+            let Closure { body, env } = mk_merge_closure(t1, env1, t2, env2, strategy, &path);
             let body = Term::DefaultValue(body).into();
             Ok(Closure { body, env })
         }
         (Term::DefaultValue(t1), Term::ContractWithDefault(ty, lbl, t2))
         | (Term::ContractWithDefault(ty, lbl, t2), Term::DefaultValue(t1)) => {
-            let Closure { body, mut env } = mk_merge_closure(t1, env1, t2, env2.clone());
+            let Closure { body, mut env } =
+                mk_merge_closure(t1, env1, t2, env2.clone(), strategy, &path);
             let ty_closure = ty.closurize(&mut env, env2);
             let body = Term::ContractWithDefault(ty_closure, lbl, body).into();
             Ok(Closure { body, env })
@@ -190,7 +335,8 @@ pub fn merge(
         // Composed contracts carry and blame their original label. As any contract, the composite
         // still requires a label, but it will be ignored, so we can provide a dummy one.
         (Term::ContractWithDefault(ty1, lbl1, t1), Term::ContractWithDefault(ty2, lbl2, t2)) => {
-            let Closure { body, mut env } = mk_merge_closure(t1, env1.clone(), t2, env2.clone());
+            let Closure { body, mut env } =
+                mk_merge_closure(t1, env1.clone(), t2, env2.clone(), strategy, &path);
             let body = Term::ContractWithDefault(
                 merge_types_closure(&mut env, ty1, lbl1, env1, ty2, lbl2, env2),
                 Label::dummy(),
@@ -256,6 +402,58 @@ pub fn merge(
             let body = Term::ContractWithDefault(ty_closure, Label::dummy(), t_closure).into();
             Ok(Closure { body, env })
         }
+        // A contract merged with a definition is kept as metadata of the definition.
+        (Term::ContractWithValue(ty1, lbl1, t1), Term::ContractWithValue(ty2, lbl2, t2)) => {
+            let Closure { body, mut env } =
+                mk_merge_closure(t1, env1.clone(), t2, env2.clone(), strategy, &path);
+            let body = Term::ContractWithValue(
+                merge_types_closure(&mut env, ty1, lbl1, env1, ty2, lbl2, env2),
+                Label::dummy(),
+                body,
+            )
+            .into();
+            Ok(Closure { body, env })
+        }
+        (Term::Contract(ty1, lbl1), Term::ContractWithValue(ty2, lbl2, t))
+        | (Term::ContractWithDefault(ty1, lbl1, _), Term::ContractWithValue(ty2, lbl2, t)) => {
+            let mut env = HashMap::new();
+            let ty_closure =
+                merge_types_closure(&mut env, ty1, lbl1, env1, ty2, lbl2, env2.clone());
+            let t_closure = t.closurize(&mut env, env2);
+            let body = Term::ContractWithValue(ty_closure, Label::dummy(), t_closure).into();
+            Ok(Closure { body, env })
+        }
+        (Term::ContractWithValue(ty1, lbl1, t), Term::Contract(ty2, lbl2))
+        | (Term::ContractWithValue(ty1, lbl1, t), Term::ContractWithDefault(ty2, lbl2, _)) => {
+            let mut env = HashMap::new();
+            let ty_closure =
+                merge_types_closure(&mut env, ty1, lbl1, env1.clone(), ty2, lbl2, env2);
+            let t_closure = t.closurize(&mut env, env1);
+            let body = Term::ContractWithValue(ty_closure, Label::dummy(), t_closure).into();
+            Ok(Closure { body, env })
+        }
+        (Term::ContractWithValue(ty, lbl, t1), t2) => {
+            let t2 = RichTerm {
+                term: Box::new(t2),
+                pos: pos2,
+            };
+            let Closure { body, mut env } =
+                mk_merge_closure(t1, env1.clone(), t2, env2, strategy, &path);
+            let ty_closure = ty.closurize(&mut env, env1);
+            let body = Term::ContractWithValue(ty_closure, lbl, body).into();
+            Ok(Closure { body, env })
+        }
+        (t1, Term::ContractWithValue(ty, lbl, t2)) => {
+            let t1 = RichTerm {
+                term: Box::new(t1),
+                pos: pos1,
+            };
+            let Closure { body, mut env } =
+                mk_merge_closure(t1, env1, t2, env2.clone(), strategy, &path);
+            let ty_closure = ty.closurize(&mut env, env2);
+            let body = Term::ContractWithValue(ty_closure, lbl, body).into();
+            Ok(Closure { body, env })
+        }
         (Term::Contract(ty, lbl), t) | (Term::ContractWithDefault(ty, lbl, _), t) => {
             let mut env = HashMap::new();
             let t = RichTerm {
@@ -264,7 +462,7 @@ pub fn merge(
             };
             let ty_closure = ty.closurize(&mut env, env1);
             let t_closure = t.closurize(&mut env, env2);
-            let body = Term::Assume(ty_closure, lbl, t_closure).into();
+            let body = Term::ContractWithValue(ty_closure, lbl, t_closure).into();
             Ok(Closure { body, env })
         }
         (t, Term::Contract(ty, lbl)) | (t, Term::ContractWithDefault(ty, lbl, _)) => {
@@ -275,43 +473,78 @@ pub fn merge(
             };
             let t_closure = t.closurize(&mut env, env1);
             let ty_closure = ty.closurize(&mut env, env2);
-            let body = Term::Assume(ty_closure, lbl, t_closure).into();
+            let body = Term::ContractWithValue(ty_closure, lbl, t_closure).into();
             Ok(Closure { body, env })
         }
         // Merge put together the fields of records, and recursively merge
         // fields that are present in both terms
         (Term::Record(m1), Term::Record(m2)) => {
-            /* Terms inside m1 and m2 may capture variables of resp. env1 and env2.  Morally, we
-             * need to store closures, or a merge of closures, inside the resulting record.  We use
-             * the same trick as in the evaluation of the operator DynExtend, and replace each such
-             * term by a variable bound to an appropriate closure in the environment
+            /* Terms inside m1 and m2 may capture variables of resp. env1 and env2. Morally, we
+             * need to store closures, or a merge of closures, inside the resulting record. The
+             * definitions of the fields are gathered, and closed over the resulting record, such
+             * that the fields of a recursive record see the fields of the merged record.
              */
-            let mut m = HashMap::new();
-            let mut env = HashMap::new();
-            let (mut left, mut center, mut right) = hashmap::split(m1, m2);
+            let (left, center, right) = hashmap::split(m1, m2);
+            let mut defs = HashMap::new();
+
+            for (field, t) in left {
+                defs.insert(field, FieldDef::of(t, &env1));
+            }
 
-            for (field, t) in left.drain() {
-                m.insert(field, t.closurize(&mut env, env1.clone()));
+            for (field, t) in right {
+                defs.insert(field, FieldDef::of(t, &env2));
             }
 
-            for (field, t) in right.drain() {
-                m.insert(field, t.closurize(&mut env, env2.clone()));
+            for (field, (t1, t2)) in center {
+                let optional = is_optional_field(&t1, &env1) && is_optional_field(&t2, &env2);
+                let mut field_path = path.clone();
+                field_path.push(field.clone());
+                let def = FieldDef::Merge(
+                    FieldDef::of(t1, &env1),
+                    FieldDef::of(t2, &env2),
+                    optional,
+                    field_path,
+                );
+                defs.insert(field, Rc::new(def));
             }
 
-            for (field, (t1, t2)) in center.drain() {
-                m.insert(
-                    field,
+            let mut env = HashMap::new();
+            let m = close_record(defs, &mut env);
+            Ok(Closure {
+                body: Term::Record(m).into(),
+                env,
+            })
+        }
+        (Term::List(ts1), Term::List(ts2)) if strategy == MergeStrategy::Concat => {
+            let mut env = HashMap::new();
+            let mut ts: Vec<RichTerm> = ts1
+                .into_iter()
+                .map(|t| t.closurize(&mut env, env1.clone()))
+                .collect();
+            ts.extend(ts2.into_iter().map(|t| t.closurize(&mut env, env2.clone())));
+
+            Ok(Closure {
+                body: Term::List(ts).into(),
+                env,
+            })
+        }
+        (Term::List(ts1), Term::List(ts2)) if ts1.len() == ts2.len() => {
+            let mut env = HashMap::new();
+            let ts: Vec<RichTerm> = ts1
+                .into_iter()
+                .zip(ts2)
+                .map(|(t1, t2)| {
                     Term::Op2(
-                        BinaryOp::Merge(),
+                        merge_op(MergeStrategy::Elementwise, path.clone()),
                         t1.closurize(&mut env, env1.clone()),
                         t2.closurize(&mut env, env2.clone()),
                     )
-                    .into(),
-                );
-            }
+                    .into()
+                })
+                .collect();
 
             Ok(Closure {
-                body: Term::Record(m).into(),
+                body: Term::List(ts).into(),
                 env,
             })
         }
@@ -326,16 +559,34 @@ pub fn merge(
                 pos: pos2,
             },
             pos_op,
+            path,
         )),
     }
 }
 
-/// Take two terms together with their environment, and return a closure representing their merge.
-fn mk_merge_closure(t1: RichTerm, env1: Environment, t2: RichTerm, env2: Environment) -> Closure {
+/// The merge operator merging lists with the given strategy, and the field of the merged records
+/// given by `path`.
+pub fn merge_op(strategy: MergeStrategy, path: Vec<Ident>) -> BinaryOp<RichTerm> {
+    match strategy {
+        MergeStrategy::Elementwise if path.is_empty() => BinaryOp::Merge(),
+        strategy => BinaryOp::MergeWith(strategy, path),
+    }
+}
+
+/// Take two terms together with their environment, and return a closure representing their merge
+/// with the given list merge strategy, at the given field path.
+fn mk_merge_closure(
+    t1: RichTerm,
+    env1: Environment,
+    t2: RichTerm,
+    env2: Environment,
+    strategy: MergeStrategy,
+    path: &[Ident],
+) -> Closure {
     let mut env = HashMap::new();
 
     let body = Term::Op2(
-        BinaryOp::Merge(),
+        merge_op(strategy, path.to_vec()),
         t1.closurize(&mut env, env1),
         t2.closurize(&mut env, env2),
     )
@@ -344,6 +595,65 @@ fn mk_merge_closure(t1: RichTerm, env1: Environment, t2: RichTerm, env2: Environ
     Closure { body, env }
 }
 
+/// Rebuild a definition with a priority which wins a merge.
+fn keep_priority(p: MergePriority, t: RichTerm, pos: Option<RawSpan>, env: Environment) -> Closure {
+    Closure {
+        body: RichTerm {
+            term: Box::new(Term::Priority(p, t)),
+            pos,
+        },
+        env,
+    }
+}
+
+/// Merge a definition `Priority(p, t)`, given as its components together with the position of the
+/// whole definition and its environment, with a term `other` without priority. `prio_on_right`
+/// indicates if the definition with a priority is the right operand of the original merge.
+///
+/// A default value is dropped, and contracts are merged with the inner definition `t`. Otherwise,
+/// `other` is a definition with the normal priority, which is compared to `p`. The contract of a
+/// definition with a contract is kept even if the definition itself is dropped.
+fn merge_priority(
+    (p, t, pos, prio_env): (MergePriority, RichTerm, Option<RawSpan>, Environment),
+    other: RichTerm,
+    other_env: Environment,
+    prio_on_right: bool,
+    strategy: MergeStrategy,
+    path: Vec<Ident>,
+) -> Result<Closure, EvalError> {
+    let combine = |t, prio_env, other, other_env| {
+        let Closure { body, env } = if prio_on_right {
+            mk_merge_closure(other, other_env, t, prio_env, strategy, &path)
+        } else {
+            mk_merge_closure(t, prio_env, other, other_env, strategy, &path)
+        };
+        Ok(Closure {
+            body: Term::Priority(p, body).into(),
+            env,
+        })
+    };
+
+    match other.as_ref() {
+        Term::DefaultValue(_) => Ok(keep_priority(p, t, pos, prio_env)),
+        Term::Contract(_, _) | Term::ContractWithDefault(_, _, _) => {
+            combine(t, prio_env, other, other_env)
+        }
+        Term::ContractWithValue(ty, lbl, _) if p > MergePriority::NORMAL => {
+            let contract = RichTerm {
+                term: Box::new(Term::Contract(ty.clone(), lbl.clone())),
+                pos: other.pos.clone(),
+            };
+            combine(t, prio_env, contract, other_env)
+        }
+        _ if p > MergePriority::NORMAL => Ok(keep_priority(p, t, pos, prio_env)),
+        _ if p < MergePriority::NORMAL => Ok(Closure {
+            body: other,
+            env: other_env,
+        }),
+        _ => combine(t, prio_env, other, other_env),
+    }
+}
+
 /// Compose two contracts, given as terms.
 ///
 /// To compose contracts `c1` and `c2`, construct the term `fun _l x => c1 l1 (c2 l2 x)`, where
@@ -355,7 +665,7 @@ fn mk_merge_closure(t1: RichTerm, env1: Environment, t2: RichTerm, env2: Environ
 /// involved (see the [corresponding
 /// notes](https://github.com/tweag/nickel/blob/master/notes/intersection-and-union-types.md) in
 /// the repository).
-fn merge_contracts(c1: RichTerm, l1: Label, c2: RichTerm, l2: Label) -> Types {
+pub fn merge_contracts(c1: RichTerm, l1: Label, c2: RichTerm, l2: Label) -> Types {
     let contract = RichTerm::fun(
         "_l".to_string(),
         RichTerm::fun(
@@ -370,7 +680,7 @@ fn merge_contracts(c1: RichTerm, l1: Label, c2: RichTerm, l2: Label) -> Types {
         ),
     );
 
-    Types(AbsType::Flat(contract.into()))
+    Types(AbsType::Flat(contract))
 }
 
 /// [Closurize](../transformations/trait.Closurizable.html) two types with their respective
@@ -397,6 +707,7 @@ pub mod hashmap {
     /// Split two hashmaps m1 and m2 in three parts (left,center,right), where left holds bindings
     /// `(key,value)` where key is not in `m2.keys()`, right is the dual (keys of m2 that are not
     /// in m1), and center holds bindings for keys that are both in m1 and m2.
+    #[allow(clippy::type_complexity)]
     pub fn split<K, V1, V2>(
         m1: HashMap<K, V1>,
         m2: HashMap<K, V2>,
@@ -8,17 +8,19 @@
 //! implement the actual semantics of operators.
 use crate::error::EvalError;
 use crate::eval::Environment;
-use crate::eval::{CallStack, Closure};
+use crate::eval::{is_optional_field, CallStack, Closure, FieldDef, IdentKind};
 use crate::identifier::Ident;
-use crate::label::ty_path;
+use crate::label::{access_path, ty_path};
 use crate::merge;
 use crate::merge::merge;
 use crate::position::RawSpan;
 use crate::stack::Stack;
-use crate::term::{BinaryOp, RichTerm, StrChunk, Term, UnaryOp};
-use crate::transformations::Closurizable;
+use crate::term::{BinaryOp, MergeStrategy, RichTerm, StrChunk, Term, UnaryOp};
+use crate::transformations::{fresh_var, Closurizable};
 use simple_counter::*;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 generate_counter!(FreshVariableCounter, usize);
 
@@ -28,6 +30,7 @@ pub enum OperationCont {
     Op1(
         /* unary operation */ UnaryOp<Closure>,
         /* original position of the argument before evaluation */ Option<RawSpan>,
+        /* previous value of enriched_strict */ bool,
     ),
     // The last parameter saves the strictness mode before the evaluation of the operator
     Op2First(
@@ -59,7 +62,8 @@ pub fn continuate_operation(
     let (cont, cs_len, pos) = stack.pop_op_cont().expect("Condition already checked");
     call_stack.truncate(cs_len);
     match cont {
-        OperationCont::Op1(u_op, arg_pos) => {
+        OperationCont::Op1(u_op, arg_pos, prev_strict) => {
+            *enriched_strict = prev_strict;
             process_unary_operation(u_op, clos, arg_pos, stack, pos)
         }
         OperationCont::Op2First(b_op, mut snd_clos, fst_pos, prev_strict) => {
@@ -170,7 +174,7 @@ fn process_unary_operation(
             }
         }
         UnaryOp::IsRecord() => match *t {
-            Term::Record(_) | Term::RecRecord(_) => {
+            Term::Record(_) | Term::RecRecord(_, _) => {
                 Ok(Closure::atomic_closure(Term::Bool(true).into()))
             }
             _ => Ok(Closure::atomic_closure(Term::Bool(false).into())),
@@ -233,7 +237,7 @@ fn process_unary_operation(
         }
         UnaryOp::Blame() => {
             if let Term::Lbl(l) = *t {
-                Err(EvalError::BlameError(l, None))
+                Err(EvalError::BlameError(Box::new(l), None))
             } else {
                 Err(EvalError::TypeError(
                     String::from("Label"),
@@ -244,8 +248,11 @@ fn process_unary_operation(
             }
         }
         UnaryOp::Embed(_id) => {
-            if let en @ Term::Enum(_) = *t {
-                Ok(Closure::atomic_closure(en.into()))
+            if let Term::Enum(_) | Term::EnumVariant(_, _) = *t {
+                Ok(Closure {
+                    body: RichTerm { term: t, pos },
+                    env,
+                })
             } else {
                 Err(EvalError::TypeError(
                     String::from("Enum"),
@@ -256,32 +263,35 @@ fn process_unary_operation(
             }
         }
         UnaryOp::Switch(mut m, d) => {
-            if let Term::Enum(en) = *t {
-                match m.remove(&en) {
-                    Some(clos) => Ok(clos),
-                    None => match d {
-                        Some(clos) => Ok(clos),
-                        None => Err(EvalError::TypeError(
-                            String::from("Enum"),
-                            String::from("switch"),
-                            arg_pos,
-                            RichTerm {
-                                term: Box::new(Term::Enum(en)),
-                                pos,
-                            },
-                        )),
-                    },
-                }
+            // A variant is dispatched on its tag.
+            let case = match *t {
+                Term::Enum(ref en) | Term::EnumVariant(ref en, _) => m.remove(en),
+                _ => None,
+            };
+
+            match case.or(d) {
+                Some(clos) => Ok(clos),
+                None => Err(EvalError::TypeError(
+                    String::from("Enum"),
+                    String::from("switch"),
+                    arg_pos,
+                    RichTerm { term: t, pos },
+                )),
+            }
+        }
+        UnaryOp::IsVariant(id) => Ok(Closure::atomic_closure(
+            Term::Bool(matches!(*t, Term::EnumVariant(ref tag, _) if *tag == id)).into(),
+        )),
+        UnaryOp::Payload() => {
+            if let Term::EnumVariant(_, payload) = *t {
+                Ok(Closure { body: payload, env })
             } else {
-                match d {
-                    Some(clos) => Ok(clos),
-                    None => Err(EvalError::TypeError(
-                        String::from("Enum"),
-                        String::from("switch"),
-                        arg_pos,
-                        RichTerm { term: t, pos },
-                    )),
-                }
+                Err(EvalError::TypeError(
+                    String::from("Enum"),
+                    String::from("payload"),
+                    arg_pos,
+                    RichTerm { term: t, pos },
+                ))
             }
         }
         UnaryOp::ChangePolarity() => {
@@ -369,9 +379,9 @@ fn process_unary_operation(
         UnaryOp::StaticAccess(id) => {
             if let Term::Record(mut static_map) = *t {
                 match static_map.remove(&id) {
-                    Some(e) => Ok(Closure { body: e, env }),
+                    Some(e) if !is_optional_field(&e, &env) => Ok(Closure { body: e, env }),
 
-                    None => Err(EvalError::FieldMissing(
+                    _ => Err(EvalError::FieldMissing(
                         id.0,
                         String::from("(.)"),
                         RichTerm {
@@ -392,7 +402,11 @@ fn process_unary_operation(
         }
         UnaryOp::FieldsOf() => {
             if let Term::Record(map) = *t {
-                let mut fields: Vec<String> = map.keys().map(|Ident(id)| id.clone()).collect();
+                let mut fields: Vec<String> = map
+                    .iter()
+                    .filter(|(_, t)| !is_optional_field(t, &env))
+                    .map(|(Ident(id), _)| id.clone())
+                    .collect();
                 fields.sort();
                 let terms = fields.into_iter().map(|id| Term::Str(id).into()).collect();
                 Ok(Closure::atomic_closure(Term::List(terms).into()))
@@ -405,22 +419,109 @@ fn process_unary_operation(
                 ))
             }
         }
+        UnaryOp::MatchFailure() => Err(EvalError::NonExhaustiveMatch(
+            RichTerm { term: t, pos },
+            pos_op,
+        )),
+        UnaryOp::CheckFields(ids) => {
+            if let Term::Record(ref map) = *t {
+                let mut extra: Vec<&Ident> = map
+                    .iter()
+                    .filter(|(id, t)| !ids.contains(id) && !is_optional_field(t, &env))
+                    .map(|(id, _)| id)
+                    .collect();
+                extra.sort_by(|Ident(id1), Ident(id2)| id1.cmp(id2));
+
+                match extra.first() {
+                    Some(Ident(id)) => Err(EvalError::UnexpectedField(
+                        id.clone(),
+                        RichTerm { term: t, pos },
+                        pos_op,
+                    )),
+                    None => Ok(Closure {
+                        body: RichTerm { term: t, pos },
+                        env,
+                    }),
+                }
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("Record"),
+                    String::from("record pattern"),
+                    arg_pos,
+                    RichTerm { term: t, pos },
+                ))
+            }
+        }
+        UnaryOp::CheckLength(n) => match *t {
+            Term::List(ref ts) if ts.len() == n => Ok(Closure {
+                body: RichTerm { term: t, pos },
+                env,
+            }),
+            Term::List(_) => Err(EvalError::PatternMismatch(
+                n,
+                RichTerm { term: t, pos },
+                pos_op,
+            )),
+            _ => Err(EvalError::TypeError(
+                String::from("List"),
+                String::from("list pattern"),
+                arg_pos,
+                RichTerm { term: t, pos },
+            )),
+        },
         UnaryOp::MapRec(f) => {
             if let Term::Record(rec) = *t {
                 let f_as_var = f.body.closurize(&mut env, f.env);
 
+                // Optional fields without a definition are kept as they are.
                 let rec = rec
                     .into_iter()
                     .map(|e| {
                         let (Ident(s), t) = e;
-                        (
-                            Ident(s.clone()),
-                            Term::App(
-                                Term::App(f_as_var.clone(), Term::Str(s.clone()).into()).into(),
-                                t.clone(),
-                            )
-                            .into(),
-                        )
+                        if is_optional_field(&t, &env) {
+                            return (Ident(s), t);
+                        }
+
+                        let f_applied: RichTerm =
+                            Term::App(f_as_var.clone(), Term::Str(s.clone()).into()).into();
+                        let body: RichTerm = Term::App(f_applied.clone(), t.clone()).into();
+
+                        // The fields of a recursive record keep their definition, which is mapped
+                        // as well, such that the result can still be merged as a recursive record.
+                        let def = match t.as_ref() {
+                            Term::Var(id) => env.get(id).and_then(|(_, _, def)| def.clone()),
+                            _ => None,
+                        };
+
+                        if let (Some(def), Term::Var(id), Term::Var(f_id)) =
+                            (def, t.as_ref(), f_as_var.as_ref())
+                        {
+                            let mut f_env = HashMap::new();
+                            f_env.insert(f_id.clone(), env[f_id].clone());
+                            let mut field_env = f_env.clone();
+                            field_env.insert(id.clone(), env[id].clone());
+
+                            let thunk = Rc::new(RefCell::new(Closure {
+                                body,
+                                env: field_env,
+                            }));
+                            let f = Closure {
+                                body: f_applied,
+                                env: f_env,
+                            };
+                            let var = fresh_var();
+                            env.insert(
+                                var.clone(),
+                                (
+                                    thunk,
+                                    IdentKind::Record(),
+                                    Some(Rc::new(FieldDef::App(f, def))),
+                                ),
+                            );
+                            (Ident(s), Term::Var(var).into())
+                        } else {
+                            (Ident(s), body)
+                        }
                     })
                     .collect();
 
@@ -445,31 +546,62 @@ fn process_unary_operation(
                 Err(EvalError::NotEnoughArgs(2, String::from("seq"), pos_op))
             }
         }
-        UnaryOp::DeepSeq() => {
-            /// Build a closure that forces a given list of terms, and at the end resumes the
-            /// evaluation of the argument on the top of the stack.
+        UnaryOp::DeepSeq(path) => {
+            /// Build a closure that forces a given list of terms, each one together with its
+            /// access path, and at the end resumes the evaluation of the argument on the top of
+            /// the stack.
             ///
             /// Requires its first argument to be non-empty.
             fn seq_terms<I>(mut terms: I, env: Environment) -> Result<Closure, EvalError>
             where
-                I: Iterator<Item = RichTerm>,
+                I: Iterator<Item = (access_path::Path, RichTerm)>,
             {
-                let first = terms
+                let (first_path, first) = terms
                     .next()
                     .expect("expected the argument to be a non-empty iterator");
-                let body = terms.fold(Term::Op1(UnaryOp::DeepSeq(), first).into(), |acc, t| {
-                    Term::App(Term::Op1(UnaryOp::DeepSeq(), t).into(), acc).into()
-                });
+                let body = terms.fold(
+                    Term::Op1(UnaryOp::DeepSeq(first_path), first).into(),
+                    |acc, (path, t)| {
+                        Term::App(Term::Op1(UnaryOp::DeepSeq(path), t).into(), acc).into()
+                    },
+                );
 
                 Ok(Closure { body, env })
+            }
+
+            let extend = |elem| {
+                let mut path = path.clone();
+                path.push(elem);
+                path
             };
 
             match *t {
-                Term::Record(map) if !map.is_empty() => {
-                    let terms = map.into_iter().map(|(_, t)| t);
+                Term::Record(map) if map.values().any(|t| !is_optional_field(t, &env)) => {
+                    let mut terms = map
+                        .into_iter()
+                        .filter(|(_, t)| !is_optional_field(t, &env))
+                        .collect::<Vec<_>>();
+                    // The last term is forced first (see `seq_terms`). Sort the fields so that
+                    // they are forced in alphabetical order, and that the same error is reported
+                    // when several of them break a contract.
+                    terms.sort_by(|(Ident(id1), _), (Ident(id2), _)| id2.cmp(id1));
+                    let terms = terms
+                        .into_iter()
+                        .map(|(id, t)| (extend(access_path::Elem::Field(id)), t));
+                    seq_terms(terms, env)
+                }
+                Term::List(ts) if !ts.is_empty() => {
+                    // As for records, force the elements starting from the first one
+                    let terms = ts
+                        .into_iter()
+                        .enumerate()
+                        .rev()
+                        .map(|(i, t)| (extend(access_path::Elem::Index(i)), t));
                     seq_terms(terms, env)
                 }
-                Term::List(ts) if !ts.is_empty() => seq_terms(ts.into_iter(), env),
+                Term::EnumVariant(_, payload) => {
+                    seq_terms(std::iter::once((path.clone(), payload)), env)
+                }
                 _ => {
                     if stack.count_args() >= 1 {
                         let (next, _) = stack.pop_arg().expect("Condition already checked.");
@@ -500,7 +632,7 @@ fn process_unary_operation(
         UnaryOp::ListTail() => {
             if let Term::List(ts) = *t {
                 let mut ts_it = ts.into_iter();
-                if let Some(_) = ts_it.next() {
+                if ts_it.next().is_some() {
                     Ok(Closure {
                         body: Term::List(ts_it.collect()).into(),
                         env,
@@ -858,8 +990,19 @@ fn process_binary_operation(
                 (Term::Str(s1), Term::Str(s2)) => Term::Bool(s1 == s2),
                 (Term::Lbl(l1), Term::Lbl(l2)) => Term::Bool(l1 == l2),
                 (Term::Sym(s1), Term::Sym(s2)) => Term::Bool(s1 == s2),
+                (Term::Enum(id1), Term::Enum(id2)) => Term::Bool(id1 == id2),
+                (Term::EnumVariant(id1, t1), Term::EnumVariant(id2, t2)) if id1 == id2 => {
+                    eq_all(std::iter::once((t1, t2)), &env1, &env2, &mut env)
+                }
                 (Term::Record(m1), Term::Record(m2)) => {
-                    let (left, center, right) = merge::hashmap::split(m1, m2);
+                    // Optional fields without a definition are ignored.
+                    let defined = |m: HashMap<Ident, RichTerm>, env: &Environment| {
+                        m.into_iter()
+                            .filter(|(_, t)| !is_optional_field(t, env))
+                            .collect::<HashMap<_, _>>()
+                    };
+                    let (left, center, right) =
+                        merge::hashmap::split(defined(m1, &env1), defined(m2, &env2));
 
                     if !left.is_empty() || !right.is_empty() {
                         Term::Bool(false)
@@ -873,7 +1016,7 @@ fn process_binary_operation(
                     }
                 }
                 (Term::List(l1), Term::List(l2)) if l1.len() == l2.len() => {
-                    eq_all(l1.into_iter().zip(l2.into_iter()), &env1, &env2, &mut env)
+                    eq_all(l1.into_iter().zip(l2), &env1, &env2, &mut env)
                 }
                 (_, _) => Term::Bool(false),
             };
@@ -1019,13 +1162,31 @@ fn process_binary_operation(
                 ))
             }
         }
+        BinaryOp::BlameWith() => {
+            if let Term::Lbl(mut l) = *t2 {
+                l.value = Some(value_repr(&t1));
+                Err(EvalError::BlameError(Box::new(l), None))
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("Label"),
+                    String::from("blameWith, 2nd argument"),
+                    snd_pos,
+                    RichTerm {
+                        term: t2,
+                        pos: pos2,
+                    },
+                ))
+            }
+        }
         BinaryOp::DynAccess() => {
             if let Term::Str(id) = *t1 {
                 if let Term::Record(mut static_map) = *t2 {
                     match static_map.remove(&Ident(id.clone())) {
-                        Some(e) => Ok(Closure { body: e, env: env2 }),
-                        None => Err(EvalError::FieldMissing(
-                            format!("{}", id),
+                        Some(e) if !is_optional_field(&e, &env2) => {
+                            Ok(Closure { body: e, env: env2 })
+                        }
+                        _ => Err(EvalError::FieldMissing(
+                            id.to_string(),
                             String::from("(.$)"),
                             RichTerm {
                                 term: Box::new(Term::Record(static_map)),
@@ -1060,14 +1221,23 @@ fn process_binary_operation(
         BinaryOp::DynExtend(clos) => {
             if let Term::Str(id) = *t1 {
                 if let Term::Record(mut static_map) = *t2 {
-                    let as_var = clos.body.closurize(&mut env2, clos.env);
-                    match static_map.insert(Ident(id.clone()), as_var) {
-                        Some(_) => Err(EvalError::Other(format!("$[ .. ]: tried to extend record with the field {}, but it already exists", id), pos_op)),
-                        None => Ok(Closure {
-                            body: Term::Record(static_map).into(),
-                            env: env2,
-                        }),
+                    if static_map.contains_key(&Ident(id.clone())) {
+                        return Err(EvalError::DuplicateField(
+                            id,
+                            RichTerm {
+                                term: Box::new(Term::Record(static_map)),
+                                pos: pos2,
+                            },
+                            fst_pos,
+                        ));
                     }
+
+                    let as_var = clos.body.closurize(&mut env2, clos.env);
+                    static_map.insert(Ident(id), as_var);
+                    Ok(Closure {
+                        body: Term::Record(static_map).into(),
+                        env: env2,
+                    })
                 } else {
                     Err(EvalError::TypeError(
                         String::from("Record"),
@@ -1096,7 +1266,7 @@ fn process_binary_operation(
                 if let Term::Record(mut static_map) = *t2 {
                     match static_map.remove(&Ident(id.clone())) {
                         None => Err(EvalError::FieldMissing(
-                            format!("{}", id),
+                            id.to_string(),
                             String::from("(-$)"),
                             RichTerm {
                                 term: Box::new(Term::Record(static_map)),
@@ -1132,12 +1302,54 @@ fn process_binary_operation(
                 ))
             }
         }
+        BinaryOp::SelectField() => {
+            if let Term::Str(id) = *t1 {
+                if let Term::Record(mut static_map) = *t2 {
+                    match static_map.remove_entry(&Ident(id.clone())) {
+                        None => Err(EvalError::FieldMissing(
+                            id,
+                            String::from("selectField"),
+                            RichTerm {
+                                term: Box::new(Term::Record(static_map)),
+                                pos: pos2,
+                            },
+                            pos_op,
+                        )),
+                        Some((id, t)) => Ok(Closure {
+                            body: Term::Record(vec![(id, t)].into_iter().collect()).into(),
+                            env: env2,
+                        }),
+                    }
+                } else {
+                    Err(EvalError::TypeError(
+                        String::from("Record"),
+                        String::from("selectField, 2nd argument"),
+                        snd_pos,
+                        RichTerm {
+                            term: t2,
+                            pos: pos2,
+                        },
+                    ))
+                }
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("Str"),
+                    String::from("selectField, 1st argument"),
+                    fst_pos,
+                    RichTerm {
+                        term: t1,
+                        pos: pos1,
+                    },
+                ))
+            }
+        }
         BinaryOp::HasField() => {
             if let Term::Str(id) = *t1 {
                 if let Term::Record(static_map) = *t2 {
-                    Ok(Closure::atomic_closure(
-                        Term::Bool(static_map.contains_key(&Ident(id))).into(),
-                    ))
+                    let has_field = static_map
+                        .get(&Ident(id))
+                        .is_some_and(|t| !is_optional_field(t, &env2));
+                    Ok(Closure::atomic_closure(Term::Bool(has_field).into()))
                 } else {
                     Err(EvalError::TypeError(
                         String::from("Record"),
@@ -1161,6 +1373,36 @@ fn process_binary_operation(
                 ))
             }
         }
+        BinaryOp::HasOptionalField() => {
+            if let Term::Str(id) = *t1 {
+                if let Term::Record(static_map) = *t2 {
+                    let has_field = static_map
+                        .get(&Ident(id))
+                        .is_some_and(|t| is_optional_field(t, &env2));
+                    Ok(Closure::atomic_closure(Term::Bool(has_field).into()))
+                } else {
+                    Err(EvalError::TypeError(
+                        String::from("Record"),
+                        String::from("hasOptionalField, 2nd argument"),
+                        snd_pos,
+                        RichTerm {
+                            term: t2,
+                            pos: pos2,
+                        },
+                    ))
+                }
+            } else {
+                Err(EvalError::TypeError(
+                    String::from("Str"),
+                    String::from("hasOptionalField, 1st argument"),
+                    fst_pos,
+                    RichTerm {
+                        term: t1,
+                        pos: pos1,
+                    },
+                ))
+            }
+        }
         BinaryOp::ListConcat() => match (*t1, *t2) {
             (Term::List(ts1), Term::List(ts2)) => {
                 let mut env = Environment::new();
@@ -1269,10 +1511,57 @@ fn process_binary_operation(
             },
             env2,
             pos_op,
+            MergeStrategy::Elementwise,
+            Vec::new(),
+        ),
+        BinaryOp::MergeWith(strategy, path) => merge(
+            RichTerm {
+                term: t1,
+                pos: pos1,
+            },
+            env1,
+            RichTerm {
+                term: t2,
+                pos: pos2,
+            },
+            env2,
+            pos_op,
+            strategy,
+            path,
         ),
     }
 }
 
+/// The maximum length of the representation of an offending value in a blame error.
+const MAX_VALUE_REPR_LEN: usize = 40;
+
+/// Return a truncated representation of an evaluated term, used to report the value which broke
+/// a contract.
+///
+/// The content of records and lists has not necessarily been evaluated, and is elided: only the
+/// names of the fields of a record are shown.
+fn value_repr(t: &Term) -> String {
+    let repr = match t {
+        Term::Record(map) => {
+            let mut fields: Vec<&str> = map.keys().map(|Ident(id)| id.as_str()).collect();
+            fields.sort_unstable();
+            let fields: Vec<String> = fields
+                .into_iter()
+                .map(|id| format!("{} = ...", id))
+                .collect();
+            format!("{{{}}}", fields.join("; "))
+        }
+        t => t.shallow_repr(),
+    };
+
+    if repr.chars().count() > MAX_VALUE_REPR_LEN {
+        let truncated: String = repr.chars().take(MAX_VALUE_REPR_LEN).collect();
+        format!("{}...", truncated)
+    } else {
+        repr
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1284,7 +1573,7 @@ mod tests {
 
     #[test]
     fn ite_operation() {
-        let cont = OperationCont::Op1(UnaryOp::Ite(), None);
+        let cont = OperationCont::Op1(UnaryOp::Ite(), None, true);
         let mut stack = Stack::new();
         stack.push_arg(Closure::atomic_closure(Term::Num(5.0).into()), None);
         stack.push_arg(Closure::atomic_closure(Term::Num(46.0).into()), None);
@@ -0,0 +1,488 @@
+//! Parsing of data files (JSON, YAML and TOML) to Nickel terms.
+//!
+//! Data files can be imported from a Nickel program, just like Nickel source files. They are
+//! directly converted to the corresponding terms: objects and tables become records, arrays
+//! become lists, and scalars become numbers, strings or booleans. Each term is annotated with its
+//! position in the original file, such that errors involving imported values, such as a contract
+//! failure, can still point to the data file.
+//!
+//! YAML being a superset of JSON, JSON files are parsed using the YAML parser.
+use crate::error::ParseError;
+use crate::identifier::Ident;
+use crate::parser::utils::mk_span;
+use crate::position::RawSpan;
+use crate::term::{RichTerm, Term};
+use codespan::FileId;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use toml::Spanned;
+use yaml_rust::parser::{Event, Parser};
+use yaml_rust::scanner::{Marker, ScanError, TScalarStyle};
+use yaml_rust::Yaml;
+
+/// The format of an input file.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InputFormat {
+    Nickel,
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl InputFormat {
+    /// Determine the format of a file from its extension. Files with an unknown extension, or
+    /// without extension, are considered to be Nickel source files.
+    pub fn from_path(path: &Path) -> InputFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => InputFormat::Json,
+            Some("yaml") | Some("yml") => InputFormat::Yaml,
+            Some("toml") => InputFormat::Toml,
+            _ => InputFormat::Nickel,
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputFormat::Nickel => write!(f, "Nickel"),
+            InputFormat::Json => write!(f, "JSON"),
+            InputFormat::Yaml => write!(f, "YAML"),
+            InputFormat::Toml => write!(f, "TOML"),
+        }
+    }
+}
+
+/// Parse a data file to a term. Calling this function with the `Nickel` format is a logic error.
+pub fn parse(format: InputFormat, src: &str, file_id: FileId) -> Result<RichTerm, ParseError> {
+    match format {
+        InputFormat::Json | InputFormat::Yaml => parse_yaml(format, src, file_id),
+        InputFormat::Toml => parse_toml(src, file_id),
+        InputFormat::Nickel => panic!("parser::data::parse(): called on a Nickel source"),
+    }
+}
+
+/// Build a data file error.
+fn data_error(format: InputFormat, msg: impl Into<String>, span: Option<RawSpan>) -> ParseError {
+    ParseError::DataFormatError(format.to_string(), msg.into(), span)
+}
+
+/// Convert the character offsets returned by the YAML parser to byte offsets.
+struct Offsets(Vec<usize>);
+
+impl Offsets {
+    fn new(src: &str) -> Self {
+        let mut offsets: Vec<usize> = src.char_indices().map(|(i, _)| i).collect();
+        offsets.push(src.len());
+        Offsets(offsets)
+    }
+
+    /// Return the byte offset corresponding to a character offset.
+    fn byte(&self, char_index: usize) -> usize {
+        self.0[char_index.min(self.0.len() - 1)]
+    }
+}
+
+/// A partially built YAML collection.
+enum Pending {
+    List(Vec<RichTerm>, usize, usize),
+    Record(HashMap<Ident, RichTerm>, Option<String>, usize, usize),
+}
+
+/// Parse a YAML (or JSON) document.
+///
+/// Only the first document of a stream is considered. Anchors and aliases are supported, while
+/// tags are ignored. As Nickel has no counterpart to YAML's `null`, null values are rejected.
+pub fn parse_yaml(format: InputFormat, src: &str, file_id: FileId) -> Result<RichTerm, ParseError> {
+    let offsets = Offsets::new(src);
+    let chars: Vec<char> = src.chars().collect();
+    let mut parser = Parser::new(src.chars());
+    let mut stack: Vec<Pending> = Vec::new();
+    let mut anchors: HashMap<usize, RichTerm> = HashMap::new();
+
+    let scan_error = |err: ScanError| {
+        let start = offsets.byte(err.marker().index());
+        data_error(
+            format,
+            err.to_string(),
+            Some(mk_span(
+                file_id,
+                start,
+                offsets.byte(err.marker().index() + 1),
+            )),
+        )
+    };
+    let span = |start: usize, end: usize| mk_span(file_id, offsets.byte(start), offsets.byte(end));
+
+    loop {
+        let (event, mark) = parser.next().map_err(scan_error)?;
+
+        // A complete value, together with its anchor.
+        let (value, anchor) = match event {
+            Event::StreamEnd => {
+                return Err(data_error(format, "empty document", None));
+            }
+            Event::Scalar(s, style, anchor, _) => {
+                let end = scalar_end(&chars, &mark, &s, style);
+                let pos = Some(span(mark.index(), end));
+
+                let term = match style {
+                    TScalarStyle::Plain => match Yaml::from_str(&s) {
+                        Yaml::Integer(n) => Term::Num(n as f64),
+                        real @ Yaml::Real(_) => Term::Num(real.as_f64().unwrap()),
+                        Yaml::Boolean(b) => Term::Bool(b),
+                        Yaml::Null => {
+                            return Err(data_error(format, "null values are not supported", pos))
+                        }
+                        _ => Term::Str(s),
+                    },
+                    _ => Term::Str(s),
+                };
+
+                (
+                    RichTerm {
+                        term: Box::new(term),
+                        pos,
+                    },
+                    anchor,
+                )
+            }
+            Event::Alias(id) => match anchors.get(&id) {
+                Some(t) => (t.clone(), 0),
+                None => {
+                    let pos = Some(span(mark.index(), mark.index() + 1));
+                    return Err(data_error(format, "unknown anchor", pos));
+                }
+            },
+            Event::SequenceStart(anchor) => {
+                stack.push(Pending::List(Vec::new(), anchor, mark.index()));
+                continue;
+            }
+            Event::MappingStart(anchor) => {
+                stack.push(Pending::Record(HashMap::new(), None, anchor, mark.index()));
+                continue;
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                let (term, anchor, start, last_end) = match stack.pop() {
+                    Some(Pending::List(ts, anchor, start)) => {
+                        let last_end = ts
+                            .last()
+                            .and_then(|t| t.pos.as_ref())
+                            .map(|s| s.end.to_usize());
+                        (Term::List(ts), anchor, start, last_end)
+                    }
+                    Some(Pending::Record(map, _, anchor, start)) => {
+                        let last_end = map
+                            .values()
+                            .filter_map(|t| t.pos.as_ref())
+                            .map(|s| s.end.to_usize())
+                            .max();
+                        (Term::Record(map), anchor, start, last_end)
+                    }
+                    None => panic!("parser::data::parse_yaml(): unbalanced collection"),
+                };
+
+                // Flow collections end with a closing delimiter, while block collections end with
+                // their last element.
+                let end = match chars.get(mark.index()) {
+                    Some(']') | Some('}') => offsets.byte(mark.index() + 1),
+                    _ => last_end.unwrap_or_else(|| offsets.byte(start + 1)),
+                };
+
+                (
+                    RichTerm {
+                        term: Box::new(term),
+                        pos: Some(mk_span(file_id, offsets.byte(start), end)),
+                    },
+                    anchor,
+                )
+            }
+            _ => continue,
+        };
+
+        if anchor > 0 {
+            anchors.insert(anchor, value.clone());
+        }
+
+        match stack.last_mut() {
+            None => return Ok(value),
+            Some(Pending::List(ts, _, _)) => ts.push(value),
+            Some(Pending::Record(_, key @ None, _, _)) => match *value.term {
+                Term::Str(s) => *key = Some(s),
+                _ => {
+                    return Err(data_error(
+                        format,
+                        "keys must be strings",
+                        value.pos.clone(),
+                    ))
+                }
+            },
+            Some(Pending::Record(map, key, _, _)) => {
+                let id = Ident(key.take().unwrap());
+                if map.contains_key(&id) {
+                    return Err(data_error(
+                        format,
+                        format!("duplicate key `{}`", id),
+                        value.pos.clone(),
+                    ));
+                }
+                map.insert(id, value);
+            }
+        }
+    }
+}
+
+/// Compute the character offset of the end of a scalar in the source, given its start position.
+///
+/// The YAML parser only gives the position of the beginning of a scalar. The end of plain and
+/// quoted scalars is found by scanning the source. For block scalars, only the indicator is
+/// included in the span.
+fn scalar_end(chars: &[char], mark: &Marker, value: &str, style: TScalarStyle) -> usize {
+    let start = mark.index();
+
+    match style {
+        TScalarStyle::Plain => start + value.chars().count(),
+        TScalarStyle::DoubleQuoted => {
+            let mut i = start + 1;
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i + 1
+        }
+        TScalarStyle::SingleQuoted => {
+            let mut i = start + 1;
+            while i < chars.len() {
+                match (chars[i], chars.get(i + 1)) {
+                    ('\'', Some('\'')) => i += 2,
+                    ('\'', _) => break,
+                    _ => i += 1,
+                }
+            }
+            i + 1
+        }
+        _ => start + 1,
+    }
+    .min(chars.len())
+}
+
+/// A TOML value annotated with position information.
+enum TomlValue {
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    List(Vec<Spanned<TomlValue>>),
+    Table(Vec<(String, Spanned<TomlValue>)>),
+}
+
+/// The name of the private field used by the `toml` crate to deserialize datetimes.
+const TOML_DATETIME_FIELD: &str = "$__toml_private_datetime";
+
+impl<'de> Deserialize<'de> for TomlValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TomlValueVisitor;
+
+        impl<'de> Visitor<'de> for TomlValueVisitor {
+            type Value = TomlValue;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a TOML value")
+            }
+
+            fn visit_bool<E: de::Error>(self, b: bool) -> Result<TomlValue, E> {
+                Ok(TomlValue::Bool(b))
+            }
+
+            fn visit_i64<E: de::Error>(self, n: i64) -> Result<TomlValue, E> {
+                Ok(TomlValue::Num(n as f64))
+            }
+
+            fn visit_u64<E: de::Error>(self, n: u64) -> Result<TomlValue, E> {
+                Ok(TomlValue::Num(n as f64))
+            }
+
+            fn visit_f64<E: de::Error>(self, n: f64) -> Result<TomlValue, E> {
+                Ok(TomlValue::Num(n))
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<TomlValue, E> {
+                Ok(TomlValue::Str(String::from(s)))
+            }
+
+            fn visit_string<E: de::Error>(self, s: String) -> Result<TomlValue, E> {
+                Ok(TomlValue::Str(s))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<TomlValue, A::Error> {
+                let mut ts = Vec::new();
+                while let Some(t) = seq.next_element()? {
+                    ts.push(t);
+                }
+                Ok(TomlValue::List(ts))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TomlValue, A::Error> {
+                let mut fields = Vec::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    // Datetimes have no Nickel counterpart, and are imported as strings.
+                    if key == TOML_DATETIME_FIELD {
+                        return Ok(TomlValue::Str(map.next_value()?));
+                    }
+
+                    fields.push((key, map.next_value()?));
+                }
+                Ok(TomlValue::Table(fields))
+            }
+        }
+
+        deserializer.deserialize_any(TomlValueVisitor)
+    }
+}
+
+/// Convert a TOML value to a term.
+fn toml_to_term(value: Spanned<TomlValue>, file_id: FileId) -> RichTerm {
+    let pos = Some(mk_span(file_id, value.start(), value.end()));
+
+    let term = match value.into_inner() {
+        TomlValue::Bool(b) => Term::Bool(b),
+        TomlValue::Num(n) => Term::Num(n),
+        TomlValue::Str(s) => Term::Str(s),
+        TomlValue::List(ts) => {
+            Term::List(ts.into_iter().map(|t| toml_to_term(t, file_id)).collect())
+        }
+        TomlValue::Table(fields) => Term::Record(
+            fields
+                .into_iter()
+                .map(|(key, t)| (Ident(key), toml_to_term(t, file_id)))
+                .collect(),
+        ),
+    };
+
+    RichTerm {
+        term: Box::new(term),
+        pos,
+    }
+}
+
+/// Parse a TOML document.
+pub fn parse_toml(src: &str, file_id: FileId) -> Result<RichTerm, ParseError> {
+    let value: Spanned<TomlValue> = toml::from_str(src).map_err(|err| {
+        let span = err.line_col().map(|(line, col)| {
+            let start = src
+                .split_inclusive('\n')
+                .take(line)
+                .map(str::len)
+                .sum::<usize>()
+                + col;
+            mk_span(file_id, start, (start + 1).min(src.len()))
+        });
+        data_error(InputFormat::Toml, err.to_string(), span)
+    })?;
+
+    Ok(toml_to_term(value, file_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codespan::Files;
+
+    fn parse_str(format: InputFormat, src: &str) -> Result<RichTerm, ParseError> {
+        let file_id = Files::new().add("<test>", String::from(src));
+        parse(format, src, file_id)
+    }
+
+    /// Return the source snippet corresponding to the position of a term.
+    fn snippet<'a>(src: &'a str, rt: &RichTerm) -> &'a str {
+        let span = rt.pos.as_ref().expect("term should have a position");
+        &src[span.start.to_usize()..span.end.to_usize()]
+    }
+
+    fn field<'a>(rt: &'a RichTerm, id: &str) -> &'a RichTerm {
+        match rt.as_ref() {
+            Term::Record(map) => map.get(&Ident(String::from(id))).unwrap(),
+            t => panic!("expected a record, got {:?}", t),
+        }
+    }
+
+    #[test]
+    fn json() {
+        let src = "{\"a\": 1.5, \"b\": [true, \"x\\\"y\"], \"c\": {}}";
+        let rt = parse_str(InputFormat::Json, src).unwrap();
+
+        assert_eq!(field(&rt, "a").as_ref(), &Term::Num(1.5));
+        assert_eq!(snippet(src, field(&rt, "a")), "1.5");
+        assert_eq!(snippet(src, field(&rt, "b")), "[true, \"x\\\"y\"]");
+        assert_eq!(snippet(src, field(&rt, "c")), "{}");
+        assert_eq!(snippet(src, &rt), src);
+
+        match field(&rt, "b").as_ref() {
+            Term::List(ts) => {
+                assert_eq!(ts[0].as_ref(), &Term::Bool(true));
+                assert_eq!(ts[1].as_ref(), &Term::Str(String::from("x\"y")));
+                assert_eq!(snippet(src, &ts[1]), "\"x\\\"y\"");
+            }
+            t => panic!("expected a list, got {:?}", t),
+        }
+    }
+
+    #[test]
+    fn yaml() {
+        let src = "a: 1\nb:\n  - 'it''s'\n  - &x foo\nc: *x\nd: \"true\"\n";
+        let rt = parse_str(InputFormat::Yaml, src).unwrap();
+
+        assert_eq!(field(&rt, "a").as_ref(), &Term::Num(1.0));
+        assert_eq!(snippet(src, field(&rt, "b")), "- 'it''s'\n  - &x foo");
+        assert_eq!(field(&rt, "c").as_ref(), &Term::Str(String::from("foo")));
+        assert_eq!(field(&rt, "d").as_ref(), &Term::Str(String::from("true")));
+    }
+
+    #[test]
+    fn toml() {
+        let src = "a = 1\nb = [\"x\", 2.5]\n\n[c]\nd = true\n";
+        let rt = parse_str(InputFormat::Toml, src).unwrap();
+
+        assert_eq!(field(&rt, "a").as_ref(), &Term::Num(1.0));
+        assert_eq!(snippet(src, field(&rt, "a")), "1");
+        assert_eq!(snippet(src, field(&rt, "b")), "[\"x\", 2.5]");
+        assert_eq!(field(field(&rt, "c"), "d").as_ref(), &Term::Bool(true));
+        assert_eq!(snippet(src, field(field(&rt, "c"), "d")), "true");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse_str(InputFormat::Json, "{\"a\": 1,").is_err());
+        assert!(parse_str(InputFormat::Json, "{\"a\": null}").is_err());
+        assert!(parse_str(InputFormat::Json, "{\"a\": 1, \"a\": 2}").is_err());
+        assert!(parse_str(InputFormat::Yaml, "[1]: a").is_err());
+        assert!(parse_str(InputFormat::Toml, "a = ").is_err());
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(
+            InputFormat::from_path(Path::new("a/b.json")),
+            InputFormat::Json
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("b.yml")),
+            InputFormat::Yaml
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("b.toml")),
+            InputFormat::Toml
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("b.ncl")),
+            InputFormat::Nickel
+        );
+        assert_eq!(InputFormat::from_path(Path::new("b")), InputFormat::Nickel);
+    }
+}
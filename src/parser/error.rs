@@ -0,0 +1,44 @@
+//! Errors raised during parsing.
+//!
+//! These are the errors of the LALRPOP parser, which are wrapped in `lalrpop_util::ParseError::User`.
+//! They are converted to the [`ParseError`](../../error/enum.ParseError.html) of the interpreter,
+//! together with the other errors of LALRPOP, by
+//! [`ParseError::from_lalrpop`](../../error/enum.ParseError.html#method.from_lalrpop).
+use super::lexer::LexicalError;
+
+/// An error raised by the parser, or by the lexer it drives.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ParseError {
+    /// An error raised by the lexer.
+    Lexical(LexicalError),
+    /// A field path is defined several times in a record literal.
+    DuplicateField(
+        /* field path */ String,
+        /* first definition */ (usize, usize),
+        /* second definition */ (usize, usize),
+    ),
+    /// An unknown list merge strategy in a `| merge` field annotation.
+    UnknownMergeStrategy(String, usize, usize),
+    /// An unknown keyword in a field annotation, as in `| dc "The port"`.
+    UnknownAnnotation(String, usize, usize),
+    /// A `| default` field annotation is not followed by a value.
+    MissingDefaultValue(usize, usize),
+    /// The definition of a field is followed by other annotations or definitions, as in
+    /// `a = 1 | Num`.
+    AnnotationAfterDefinition(usize, usize),
+    /// A field has both a default value and a definition, as in `a | default = 1 = 2`.
+    DefaultWithDefinition(
+        /* default annotation */ (usize, usize),
+        /* definition */ (usize, usize),
+    ),
+    /// A field with a priority annotation has no definition.
+    UndefinedPriority(usize, usize),
+    /// A doc comment is not followed by a record field.
+    MisplacedDocComment(usize, usize),
+}
+
+impl From<LexicalError> for ParseError {
+    fn from(error: LexicalError) -> ParseError {
+        ParseError::Lexical(error)
+    }
+}
@@ -5,7 +5,7 @@
 //! expressions, which is not possible using LALRPOP's generated lexer. To see why, consider the
 //! following string:
 //!
-//! ```
+//! ```text
 //! "hello, I have 1 + ${ {a = "40"}.a } + 1 bananas."
 //! ```
 //!
@@ -29,6 +29,17 @@
 //! `0`, this is the end of the current interpolated expressions, and we leave the normal mode and
 //! go back to string mode. In our example, this is the second `}`: at this point, the lexer knows
 //! that the coming characters must be lexed as string tokens, and not as normal tokens.
+//!
+//! Besides the usual double-quoted strings, there are two other kinds of strings, each with its
+//! own string mode: multi-line strings `m#" ... "#m` and raw strings `r#" ... "#`. Both may contain
+//! double quotes and `$`, and do not process escape sequences, but they still support interpolated
+//! expressions `${ ... }`. The indentation of multi-line strings is stripped by the parser.
+//!
+//! In normal mode, line comments `// ...` and block comments `/* ... */`, which can be nested, are
+//! skipped, but can be retrieved with [`comments`](fn.comments.html) by the formatter. Doc
+//! comments `/// ...` are kept as tokens, as the parser attaches them to the record field which
+//! follows. Inside a string, all of these are just part of the string.
+use super::error::ParseError;
 use logos::Logos;
 
 /// The tokens in normal mode.
@@ -40,7 +51,29 @@ pub enum NormalToken<'input> {
 
     #[regex("_?[a-zA-Z][_a-zA-Z0-9]*")]
     Identifier(&'input str),
-    #[regex("[0-9]*\\.?[0-9]+", |lex| lex.slice().parse())]
+    /// A line comment. A comment starting with exactly three slashes is a doc comment.
+    #[regex("//([^/\n][^\n]*)?|////[^\n]*")]
+    LineComment,
+    /// A block comment, together with a boolean indicating if it is properly terminated.
+    #[token("/*", block_comment)]
+    BlockComment(bool),
+    /// A doc comment, without the leading `///` and the first following space if any.
+    #[regex("///([^/\n][^\n]*)?", |lex| {
+        let text = &lex.slice()[3..];
+        text.strip_prefix(' ').unwrap_or(text)
+    })]
+    DocComment(&'input str),
+    /// A number literal. The regular expressions are deliberately more permissive than the actual
+    /// syntax, so that a malformed literal such as `1e` or `0xfg` is reported as a whole by
+    /// [`parse_num`](fn.parse_num.html), instead of being split into several tokens.
+    ///
+    /// Only a decimal literal can have an exponent: `e` is a digit of a hexadecimal literal, such
+    /// that `0xe+1` is the sum of `0xe` and `1`.
+    #[regex(
+        "(\\.[0-9]|[1-9]|0[0-9_acdf-np-wyzACDF-NP-WYZ]|0[eE][+-]?|0\\.[0-9])([0-9a-df-zA-DF-Z_]|[eE][+-]?|\\.[0-9])*|0",
+        |lex| parse_num(lex.slice()).ok_or(())
+    )]
+    #[regex("0[xXoObB]([0-9a-zA-Z_]|\\.[0-9])*", |lex| parse_num(lex.slice()).ok_or(()))]
     NumLiteral(f64),
 
     #[token("Dyn")]
@@ -68,6 +101,11 @@ pub enum NormalToken<'input> {
     Let,
     #[token("switch")]
     Switch,
+    /// The `match` keyword. It is not lexed directly: an identifier `match` is only turned into
+    /// this keyword by [`Lexer`](struct.Lexer.html) when it is followed by a list of cases (see
+    /// [`starts_match_cases`](fn.starts_match_cases.html)), such that `match` can still be used as
+    /// the name of a variable or of a field.
+    Match,
 
     #[token("true")]
     True,
@@ -86,6 +124,8 @@ pub enum NormalToken<'input> {
     SemiCol,
     #[token(".")]
     Dot,
+    #[token("..")]
+    Ellipsis,
     #[token(".$")]
     DotDollar,
     #[token("$[")]
@@ -96,6 +136,10 @@ pub enum NormalToken<'input> {
     DollarBrace,
     #[token("\"")]
     DoubleQuote,
+    #[token("m#\"")]
+    MultiStringStart,
+    #[token("r#\"")]
+    RawStringStart,
     #[token("-$")]
     MinusDollar,
 
@@ -138,6 +182,8 @@ pub enum NormalToken<'input> {
     Backtick,
     #[token("_")]
     Underscore,
+    #[token("?")]
+    QuestionMark,
 
     #[token("tag")]
     Tag,
@@ -171,6 +217,8 @@ pub enum NormalToken<'input> {
 
     #[token("blame")]
     Blame,
+    #[token("blameWith")]
+    BlameWith,
     #[token("chngPol")]
     ChangePol,
     #[token("polarity")]
@@ -205,6 +253,10 @@ pub enum NormalToken<'input> {
     Unwrap,
     #[token("hasField")]
     HasField,
+    #[token("hasOptionalField")]
+    HasOptionalField,
+    #[token("selectField")]
+    SelectField,
     #[token("map")]
     Map,
     #[token("elemAt")]
@@ -234,6 +286,72 @@ pub enum NormalToken<'input> {
     GreaterOrEq,
 }
 
+/// Parse a number literal. Return `None` if the literal is malformed, or if its value is not
+/// representable.
+///
+/// A number literal is either:
+/// - a decimal literal, with an optional fractional part and an optional exponent, as in `1`,
+///   `1.5`, `.5` or `1.5e-3`,
+/// - a hexadecimal, octal or binary integer, as in `0xff`, `0o755` or `0b1010`.
+///
+/// Digits can be separated by underscores, as in `1_000_000`. A separator must stand between two
+/// digits: `1_`, `1__0`, `1_e5` or `0x_1` are invalid. Negative literals are handled by the
+/// parser.
+pub fn parse_num(s: &str) -> Option<f64> {
+    let radix = match s.get(..2) {
+        Some("0x") | Some("0X") => Some(16),
+        Some("0o") | Some("0O") => Some(8),
+        Some("0b") | Some("0B") => Some(2),
+        _ => None,
+    };
+
+    // Skip the radix prefix, such that a separator right after it has no digit on its left.
+    let body = &s.as_bytes()[radix.map_or(0, |_| 2)..];
+    let is_digit = |c: Option<&u8>| c.is_some_and(|c| (*c as char).is_digit(radix.unwrap_or(10)));
+    let misplaced_separator = body.iter().enumerate().any(|(i, c)| {
+        *c == b'_' && !(i > 0 && is_digit(body.get(i - 1)) && is_digit(body.get(i + 1)))
+    });
+
+    if misplaced_separator {
+        return None;
+    }
+
+    let digits: String = s.chars().filter(|c| *c != '_').collect();
+
+    let value = match radix {
+        Some(radix) => u64::from_str_radix(&digits[2..], radix).ok()? as f64,
+        None => digits.parse().ok()?,
+    };
+
+    Some(value).filter(|n: &f64| n.is_finite())
+}
+
+/// Consume the content of a block comment, the opening `/*` having already been matched. Block
+/// comments can be nested. Return `false` if the end of the input is reached before the comment is
+/// closed.
+fn block_comment<'input>(lex: &mut logos::Lexer<'input, NormalToken<'input>>) -> bool {
+    let mut depth = 1;
+    let rest = lex.remainder().as_bytes();
+    let mut i = 0;
+
+    while depth > 0 && i < rest.len() {
+        match &rest[i..] {
+            [b'/', b'*', ..] => {
+                depth += 1;
+                i += 2;
+            }
+            [b'*', b'/', ..] => {
+                depth -= 1;
+                i += 2;
+            }
+            _ => i += 1,
+        }
+    }
+
+    lex.bump(i);
+    depth == 0
+}
+
 /// The tokens in string mode.
 #[derive(Logos, Debug, PartialEq, Clone)]
 pub enum StringToken<'input> {
@@ -251,16 +369,76 @@ pub enum StringToken<'input> {
     EscapedChar(char),
 }
 
+/// The tokens in multi-line string mode.
+#[derive(Logos, Debug, PartialEq, Clone)]
+pub enum MultiStringToken<'input> {
+    #[error]
+    Error,
+
+    #[regex("[^\"$]+")]
+    Literal(&'input str),
+
+    /// A double quote which does not end the string.
+    #[token("\"")]
+    DoubleQuote,
+    /// A dollar sign which does not start an interpolated expression.
+    #[token("$")]
+    Dollar,
+    #[token("${")]
+    DollarBrace,
+    #[token("\"#m")]
+    End,
+}
+
+/// The tokens in raw string mode.
+#[derive(Logos, Debug, PartialEq, Clone)]
+pub enum RawStringToken<'input> {
+    #[error]
+    Error,
+
+    #[regex("[^\"$]+")]
+    Literal(&'input str),
+
+    /// A double quote which does not end the string.
+    #[token("\"")]
+    DoubleQuote,
+    /// A dollar sign which does not start an interpolated expression.
+    #[token("$")]
+    Dollar,
+    #[token("${")]
+    DollarBrace,
+    #[token("\"#")]
+    End,
+}
+
 /// The tokens of the modal lexer.
+///
+/// The tokens of multi-line and raw strings are converted to string tokens by the lexer, except for
+/// the delimiters ending the strings.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token<'input> {
     Normal(NormalToken<'input>),
     Str(StringToken<'input>),
+    MultiStr(MultiStringToken<'input>),
+    RawStr(RawStringToken<'input>),
 }
 
 pub enum ModalLexer<'input> {
     Normal(logos::Lexer<'input, NormalToken<'input>>),
     Str(logos::Lexer<'input, StringToken<'input>>),
+    MultiStr(logos::Lexer<'input, MultiStringToken<'input>>),
+    RawStr(logos::Lexer<'input, RawStringToken<'input>>),
+}
+
+/// The different kinds of strings, each one being lexed in its own mode.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StringKind {
+    /// A double-quoted string `"..."`.
+    Standard,
+    /// A multi-line string `m#"..."#m`.
+    Multiline,
+    /// A raw string `r#"..."#`.
+    Raw,
 }
 
 // Wrap the `next()` function of the underlying lexer.
@@ -271,16 +449,38 @@ impl<'input> Iterator for ModalLexer<'input> {
         match self {
             ModalLexer::Normal(lexer) => lexer.next().map(Token::Normal),
             ModalLexer::Str(lexer) => lexer.next().map(Token::Str),
+            ModalLexer::MultiStr(lexer) => lexer.next().map(Token::MultiStr),
+            ModalLexer::RawStr(lexer) => lexer.next().map(Token::RawStr),
         }
     }
 }
 
-// Wrap the `span()` function of the underlying lexer.
+// Wrap the `span()`, `slice()` and `remainder()` functions of the underlying lexer.
 impl<'input> ModalLexer<'input> {
     pub fn span(&self) -> std::ops::Range<usize> {
         match self {
             ModalLexer::Normal(lexer) => lexer.span(),
             ModalLexer::Str(lexer) => lexer.span(),
+            ModalLexer::MultiStr(lexer) => lexer.span(),
+            ModalLexer::RawStr(lexer) => lexer.span(),
+        }
+    }
+
+    pub fn slice(&self) -> &'input str {
+        match self {
+            ModalLexer::Normal(lexer) => lexer.slice(),
+            ModalLexer::Str(lexer) => lexer.slice(),
+            ModalLexer::MultiStr(lexer) => lexer.slice(),
+            ModalLexer::RawStr(lexer) => lexer.slice(),
+        }
+    }
+
+    pub fn remainder(&self) -> &'input str {
+        match self {
+            ModalLexer::Normal(lexer) => lexer.remainder(),
+            ModalLexer::Str(lexer) => lexer.remainder(),
+            ModalLexer::MultiStr(lexer) => lexer.remainder(),
+            ModalLexer::RawStr(lexer) => lexer.remainder(),
         }
     }
 }
@@ -291,6 +491,10 @@ pub enum LexicalError {
     UnmatchedCloseBrace(usize),
     /// Invalid escape sequence in a string literal.
     InvalidEscapeSequence(usize),
+    /// A block comment is not closed before the end of the input.
+    UnterminatedComment(usize),
+    /// A malformed number literal, or a number literal whose value is not representable.
+    InvalidNumLiteral(usize, usize),
     /// Generic lexer error
     Generic(usize, usize),
 }
@@ -315,6 +519,9 @@ pub struct Lexer<'input> {
     /// already inside an interpolated expression. In this case, once this string ends, we must
     /// restore the original brace counter, which is what this stack is used for.
     pub brace_stack: Vec<usize>,
+    /// The stack of the kinds of the strings being lexed, the last one being the innermost. It
+    /// determines which string mode to go back to at the end of an interpolated expression.
+    pub string_kinds: Vec<StringKind>,
 }
 
 impl<'input> Lexer<'input> {
@@ -323,15 +530,39 @@ impl<'input> Lexer<'input> {
             lexer: Some(ModalLexer::Normal(NormalToken::lexer(s))),
             brace_stack: Vec::new(),
             brace_count: 0,
+            string_kinds: Vec::new(),
+        }
+    }
+
+    /// Switch from normal mode to the string mode corresponding to `kind`.
+    fn morph_to_str(
+        lexer: logos::Lexer<'input, NormalToken<'input>>,
+        kind: StringKind,
+    ) -> ModalLexer<'input> {
+        match kind {
+            StringKind::Standard => ModalLexer::Str(lexer.morph()),
+            StringKind::Multiline => ModalLexer::MultiStr(lexer.morph()),
+            StringKind::Raw => ModalLexer::RawStr(lexer.morph()),
         }
     }
 
-    fn enter_str(&mut self) {
+    /// Switch from any string mode to normal mode.
+    fn morph_to_normal(lexer: ModalLexer<'input>) -> logos::Lexer<'input, NormalToken<'input>> {
+        match lexer {
+            ModalLexer::Str(lexer) => lexer.morph(),
+            ModalLexer::MultiStr(lexer) => lexer.morph(),
+            ModalLexer::RawStr(lexer) => lexer.morph(),
+            ModalLexer::Normal(_) => panic!("lexer::morph_to_normal"),
+        }
+    }
+
+    fn enter_str(&mut self, kind: StringKind) {
         match self.lexer.take() {
             Some(ModalLexer::Normal(lexer)) => {
                 self.brace_stack.push(self.brace_count);
                 self.brace_count = 0;
-                self.lexer.replace(ModalLexer::Str(lexer.morph()));
+                self.string_kinds.push(kind);
+                self.lexer.replace(Self::morph_to_str(lexer, kind));
             }
             _ => panic!("lexer::enter_str"),
         }
@@ -339,9 +570,10 @@ impl<'input> Lexer<'input> {
 
     fn enter_normal(&mut self) {
         match self.lexer.take() {
-            Some(ModalLexer::Str(lexer)) => {
+            Some(lexer) => {
                 //brace_count must be zero, and we do not push it on the stack
-                self.lexer.replace(ModalLexer::Normal(lexer.morph()));
+                self.lexer
+                    .replace(ModalLexer::Normal(Self::morph_to_normal(lexer)));
             }
             _ => panic!("lexer::enter_normal"),
         }
@@ -349,11 +581,13 @@ impl<'input> Lexer<'input> {
 
     fn leave_str(&mut self) {
         match self.lexer.take() {
-            Some(ModalLexer::Str(lexer)) => {
+            Some(lexer) => {
                 // We can only enter string mode from normal mode, so the brace stack should not be
                 // empty
                 self.brace_count = self.brace_stack.pop().unwrap();
-                self.lexer.replace(ModalLexer::Normal(lexer.morph()));
+                self.string_kinds.pop();
+                self.lexer
+                    .replace(ModalLexer::Normal(Self::morph_to_normal(lexer)));
             }
             _ => panic!("lexer::leave_str"),
         }
@@ -363,25 +597,33 @@ impl<'input> Lexer<'input> {
         match self.lexer.take() {
             Some(ModalLexer::Normal(lexer)) => {
                 // brace_count must be 0
-                self.lexer.replace(ModalLexer::Str(lexer.morph()));
+                let kind = *self.string_kinds.last().unwrap();
+                self.lexer.replace(Self::morph_to_str(lexer, kind));
             }
             _ => panic!("lexer::leave_normal"),
         }
     }
 }
 
-impl<'input> Iterator for Lexer<'input> {
-    type Item = Result<(usize, Token<'input>, usize), LexicalError>;
-
-    fn next(&mut self) -> Option<Self::Item> {
+impl<'input> Lexer<'input> {
+    /// Return the next token, including the comments which are skipped by `next()`.
+    fn next_token(&mut self) -> Option<Result<(usize, Token<'input>, usize), LexicalError>> {
         use Token::*;
 
         let lexer = self.lexer.as_mut().unwrap();
         let mut token = lexer.next();
         let span = lexer.span();
+        let slice = lexer.slice();
+        let remainder = lexer.remainder();
 
         match token.as_ref() {
-            Some(Normal(NormalToken::DoubleQuote)) => self.enter_str(),
+            Some(Normal(NormalToken::DoubleQuote)) => self.enter_str(StringKind::Standard),
+            Some(Normal(NormalToken::MultiStringStart)) => self.enter_str(StringKind::Multiline),
+            Some(Normal(NormalToken::RawStringStart)) => self.enter_str(StringKind::Raw),
+            // `match` is a keyword only when it is followed by its cases.
+            Some(Normal(NormalToken::Identifier("match"))) if starts_match_cases(remainder) => {
+                token = Some(Normal(NormalToken::Match))
+            }
             Some(Normal(NormalToken::LBrace)) => self.brace_count += 1,
             Some(Normal(NormalToken::RBrace)) => {
                 if self.brace_count == 0 {
@@ -401,6 +643,23 @@ impl<'input> Iterator for Lexer<'input> {
                 token = Some(Normal(NormalToken::DoubleQuote));
             }
             Some(Str(StringToken::DollarBrace)) => self.enter_normal(),
+            Some(MultiStr(MultiStringToken::End)) | Some(RawStr(RawStringToken::End)) => {
+                self.leave_str()
+            }
+            Some(MultiStr(MultiStringToken::DollarBrace))
+            | Some(RawStr(RawStringToken::DollarBrace)) => {
+                self.enter_normal();
+                token = Some(Str(StringToken::DollarBrace));
+            }
+            // The content of multi-line and raw strings is returned as standard string literals.
+            Some(MultiStr(MultiStringToken::Literal(_)))
+            | Some(MultiStr(MultiStringToken::DoubleQuote))
+            | Some(MultiStr(MultiStringToken::Dollar))
+            | Some(RawStr(RawStringToken::Literal(_)))
+            | Some(RawStr(RawStringToken::DoubleQuote))
+            | Some(RawStr(RawStringToken::Dollar)) => {
+                token = Some(Str(StringToken::Literal(slice)))
+            }
             // Convert escape sequences to the corresponding character.
             Some(Str(StringToken::EscapedChar(c))) => {
                 if let Some(esc) = escape_char(*c) {
@@ -409,8 +668,21 @@ impl<'input> Iterator for Lexer<'input> {
                     return Some(Err(LexicalError::InvalidEscapeSequence(span.start + 1)));
                 }
             }
+            Some(Normal(NormalToken::BlockComment(false))) => {
+                return Some(Err(LexicalError::UnterminatedComment(span.start)))
+            }
+            // A token starting with a digit can only be a number literal that failed to parse.
+            Some(Normal(NormalToken::Error))
+                if slice.starts_with(|c: char| c.is_ascii_digit())
+                    || (slice.starts_with('.') && slice.len() > 1) =>
+            {
+                return Some(Err(LexicalError::InvalidNumLiteral(span.start, span.end)))
+            }
             // Early report errors for now. This could change in the future
-            Some(Str(StringToken::Error)) | Some(Normal(NormalToken::Error)) => {
+            Some(Str(StringToken::Error))
+            | Some(Normal(NormalToken::Error))
+            | Some(MultiStr(MultiStringToken::Error))
+            | Some(RawStr(RawStringToken::Error)) => {
                 return Some(Err(LexicalError::Generic(span.start, span.end)))
             }
             _ => (),
@@ -420,6 +692,59 @@ impl<'input> Iterator for Lexer<'input> {
     }
 }
 
+impl<'input> Iterator for Lexer<'input> {
+    /// The error type is the one of the parser, which the lexer is fed to.
+    type Item = Result<(usize, Token<'input>, usize), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_token() {
+                Some(Ok((_, Token::Normal(NormalToken::LineComment), _)))
+                | Some(Ok((_, Token::Normal(NormalToken::BlockComment(_)), _))) => (),
+                next => return next.map(|result| result.map_err(ParseError::Lexical)),
+            }
+        }
+    }
+}
+
+/// Determine if a source starts with the cases of a `match`, as opposed to any other term. Both
+/// the cases and a record literal start with a brace: they are told apart by the first tokens
+/// inside the braces, a record field starting either with a doc comment, a `$` or an identifier
+/// which is not directly followed by `=>`. Comments are skipped as by the lexer, and an empty pair
+/// of braces is taken as an empty list of cases.
+fn starts_match_cases(s: &str) -> bool {
+    let mut tokens = NormalToken::lexer(s).filter(|token| {
+        !matches!(
+            token,
+            NormalToken::LineComment | NormalToken::BlockComment(_)
+        )
+    });
+
+    if tokens.next() != Some(NormalToken::LBrace) {
+        return false;
+    }
+
+    match tokens.next() {
+        Some(NormalToken::Identifier(_)) => tokens.next() == Some(NormalToken::DoubleArrow),
+        Some(NormalToken::DocComment(_)) | Some(NormalToken::Dollar) | None => false,
+        Some(_) => true,
+    }
+}
+
+/// Return the spans of the line and block comments of a source, which are skipped by the lexer.
+/// Doc comments are not included, as they are part of the AST. The lexing stops at the first error.
+pub fn comments(s: &str) -> Vec<(usize, usize)> {
+    let mut lexer = Lexer::new(s);
+    std::iter::from_fn(|| lexer.next_token())
+        .map_while(Result::ok)
+        .filter_map(|(start, token, end)| match token {
+            Token::Normal(NormalToken::LineComment)
+            | Token::Normal(NormalToken::BlockComment(_)) => Some((start, end)),
+            _ => None,
+        })
+        .collect()
+}
+
 fn escape_char(chr: char) -> Option<char> {
     match chr {
         '\'' => Some('\''),
@@ -1,11 +1,24 @@
+use crate::identifier::Ident;
+use crate::term::RichTerm;
 use lalrpop_util::lalrpop_mod;
 
 lalrpop_mod!(
     #[allow(clippy::all)]
     #[allow(unused_parens)]
+    #[allow(unused_imports)]
     pub grammar);
 
+pub mod data;
+pub mod error;
 pub mod lexer;
 #[cfg(test)]
 mod tests;
 pub mod utils;
+
+/// Either a term or a toplevel let declaration, which is a let binding without a body. Used by the
+/// REPL to extend the environment with new bindings.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExtendedTerm {
+    RichTerm(RichTerm),
+    ToplevelLet(Ident, RichTerm),
+}
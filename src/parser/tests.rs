@@ -1,30 +1,40 @@
+use super::error::ParseError;
 use super::lexer::{Lexer, LexicalError, NormalToken, StringToken, Token};
 use crate::identifier::Ident;
 use crate::term::Term::*;
-use crate::term::{BinaryOp, RichTerm, StrChunk, UnaryOp};
+use crate::term::{BinaryOp, FieldPattern, MatchPattern, Pattern, RichTerm, StrChunk, UnaryOp};
+use crate::types::{AbsType, Types};
 use codespan::Files;
+use std::collections::HashMap;
 
 fn parse(s: &str) -> Option<RichTerm> {
     let id = Files::new().add("<test>", String::from(s));
 
     println!("Parsing {}", s);
     super::grammar::TermParser::new()
-        .parse(id, Lexer::new(&s))
+        .parse(id, &mut Vec::new(), Lexer::new(s))
         .map_err(|err| println!("{:?}", err))
         .ok()
 }
 
+fn parse_err(s: &str) -> lalrpop_util::ParseError<usize, Token<'_>, ParseError> {
+    let id = Files::new().add("<test>", String::from(s));
+    super::grammar::TermParser::new()
+        .parse(id, &mut Vec::new(), Lexer::new(s))
+        .unwrap_err()
+}
+
 fn parse_without_pos(s: &str) -> RichTerm {
     let mut result = parse(s).unwrap();
     result.clean_pos();
     result
 }
 
-fn lex(s: &str) -> Result<Vec<(usize, Token, usize)>, LexicalError> {
+fn lex(s: &str) -> Result<Vec<(usize, Token<'_>, usize)>, ParseError> {
     Lexer::new(s).collect()
 }
 
-fn lex_without_pos(s: &str) -> Result<Vec<Token>, LexicalError> {
+fn lex_without_pos(s: &str) -> Result<Vec<Token<'_>>, ParseError> {
     lex(s).map(|v| v.into_iter().map(|(_, tok, _)| tok).collect())
 }
 
@@ -40,6 +50,77 @@ fn numbers() {
     assert_eq!(parse_without_pos("22.22"), Num(22.22).into());
     assert_eq!(parse_without_pos("(22)"), Num(22.0).into());
     assert_eq!(parse_without_pos("((22))"), Num(22.0).into());
+    assert_eq!(parse_without_pos(".5"), Num(0.5).into());
+    assert_eq!(parse_without_pos("1e6"), Num(1e6).into());
+    assert_eq!(parse_without_pos("1.5E-3"), Num(1.5e-3).into());
+    assert_eq!(parse_without_pos("2e+2"), Num(200.0).into());
+    assert_eq!(parse_without_pos("1_000_000"), Num(1e6).into());
+    assert_eq!(parse_without_pos("0xff"), Num(255.0).into());
+    assert_eq!(parse_without_pos("0xFF_FF"), Num(65535.0).into());
+    assert_eq!(parse_without_pos("1_0.0_1e1_0"), Num(10.01e10).into());
+    assert_eq!(parse_without_pos("0b1_0"), Num(2.0).into());
+    assert_eq!(parse_without_pos("0o755"), Num(493.0).into());
+    assert_eq!(parse_without_pos("0b1010"), Num(10.0).into());
+    assert_eq!(parse_without_pos("-1.5"), Num(-1.5).into());
+    assert_eq!(parse_without_pos("- 0x10"), Num(-16.0).into());
+    assert_eq!(parse_without_pos("0e2"), Num(0.0).into());
+    assert_eq!(parse_without_pos("0.5e1"), Num(5.0).into());
+    assert_eq!(
+        parse_without_pos("0xe+1"),
+        Op2(BinaryOp::Plus(), Num(14.0).into(), Num(1.0).into()).into()
+    );
+    assert_eq!(
+        parse_without_pos("0XE-1"),
+        Op2(BinaryOp::Sub(), Num(14.0).into(), Num(1.0).into()).into()
+    );
+    assert_eq!(
+        parse_without_pos("-x"),
+        Op2(
+            BinaryOp::Sub(),
+            Num(0.0).into(),
+            Var(Ident("x".to_string())).into()
+        )
+        .into()
+    );
+    assert_eq!(
+        parse_without_pos("1-1"),
+        Op2(BinaryOp::Sub(), Num(1.0).into(), Num(1.0).into()).into()
+    );
+}
+
+#[test]
+fn invalid_numbers() {
+    for s in &[
+        "1e",
+        "1e+",
+        "0x",
+        "0xfg",
+        "0b102",
+        "0o8",
+        "12abc",
+        "1e400",
+        "0x1_0000_0000_0000_0000",
+        "1_",
+        "1__0",
+        "1_.5",
+        "1_e5",
+        "0x_1",
+        "0b1_",
+    ] {
+        assert_eq!(
+            lex(s),
+            Err(ParseError::Lexical(LexicalError::InvalidNumLiteral(
+                0,
+                s.len()
+            ))),
+            "lexing {}",
+            s
+        );
+    }
+    assert_eq!(
+        lex("1 + 2x"),
+        Err(ParseError::Lexical(LexicalError::InvalidNumLiteral(4, 6)))
+    );
 }
 
 #[test]
@@ -146,12 +227,273 @@ fn functions() {
     );
 }
 
+#[test]
+fn destructuring() {
+    let field = |id: &str, pattern: Pattern, default: Option<RichTerm>| FieldPattern {
+        id: Ident(id.to_string()),
+        pattern,
+        default,
+        pos: None,
+    };
+    let ident = |id: &str| Pattern::Ident(Ident(id.to_string()));
+
+    assert_eq!(
+        parse_without_pos("fun {a, b = c ? 1, ..} [x, {y}] => a"),
+        FunPattern(
+            Pattern::Record(
+                vec![
+                    field("a", ident("a"), None),
+                    field("b", ident("c"), Some(Num(1.0).into())),
+                ],
+                true,
+                None,
+            ),
+            FunPattern(
+                Pattern::List(
+                    vec![
+                        ident("x"),
+                        Pattern::Record(vec![field("y", ident("y"), None)], false, None),
+                    ],
+                    None,
+                ),
+                Var(Ident("a".to_string())).into(),
+            )
+            .into(),
+        )
+        .into()
+    );
+
+    assert_eq!(
+        parse_without_pos("fun x {} => x"),
+        Fun(
+            Ident("x".to_string()),
+            FunPattern(
+                Pattern::Record(Vec::new(), false, None),
+                Var(Ident("x".to_string())).into()
+            )
+            .into()
+        )
+        .into()
+    );
+
+    assert_eq!(
+        parse_without_pos("let {a = {b}} = r in b"),
+        LetPattern(
+            Pattern::Record(
+                vec![field(
+                    "a",
+                    Pattern::Record(vec![field("b", ident("b"), None)], false, None),
+                    None
+                )],
+                false,
+                None,
+            ),
+            Var(Ident("r".to_string())).into(),
+            Var(Ident("b".to_string())).into(),
+        )
+        .into()
+    );
+
+    assert!(parse("fun {a, .., b} => a").is_none());
+    assert!(parse("fun {1} => a").is_none());
+    assert!(parse("let [x, ..] = l in x").is_none());
+}
+
 #[test]
 fn lets() {
     assert!(parse("let x1 = x2 in x3").is_some());
     assert!(parse("x (let x1 = x2 in x3) y").is_some());
 }
 
+#[test]
+fn recursive_lets() {
+    let var = |id: &str| RichTerm::from(Var(Ident(id.to_string())));
+
+    assert_eq!(
+        parse_without_pos("let rec f = fun x => f x in f"),
+        LetRec(
+            vec![(
+                Ident("f".to_string()),
+                Fun(Ident("x".to_string()), App(var("f"), var("x")).into()).into()
+            )],
+            var("f")
+        )
+        .into()
+    );
+    assert_eq!(
+        parse_without_pos("let rec a = b, b = [a, 1] in a"),
+        LetRec(
+            vec![
+                (Ident("a".to_string()), var("b")),
+                (
+                    Ident("b".to_string()),
+                    List(vec![var("a"), Num(1.0).into()]).into()
+                ),
+            ],
+            var("a")
+        )
+        .into()
+    );
+
+    assert!(parse("let rec in 1").is_none());
+    assert!(parse("let rec a = 1, in a").is_none());
+    assert!(parse("let rec {a} = r in a").is_none());
+    assert!(parse("let recc a = 1 in a").is_none());
+}
+
+#[test]
+fn rec_as_identifier() {
+    let var = |id: &str| RichTerm::from(Var(Ident(id.to_string())));
+
+    assert_eq!(
+        parse_without_pos("let rec = 1 in rec"),
+        RichTerm::let_in("rec", Num(1.).into(), var("rec"))
+    );
+    assert_eq!(
+        parse_without_pos("let rec rec = [rec] in rec"),
+        LetRec(
+            vec![(Ident("rec".to_string()), List(vec![var("rec")]).into())],
+            var("rec")
+        )
+        .into()
+    );
+    assert_eq!(
+        parse_without_pos("r.rec"),
+        Op1(UnaryOp::StaticAccess(Ident::from("rec")), var("r")).into()
+    );
+    assert!(matches!(
+        parse_without_pos("{rec = 1}").as_ref(),
+        RecRecord(map, _) if map.get(&Ident::from("rec")) == Some(&Num(1.).into())
+    ));
+    assert!(parse("fun rec => rec").is_some());
+}
+
+#[test]
+fn matches() {
+    let var = |id: &str| RichTerm::from(Var(Ident(id.to_string())));
+
+    assert_eq!(
+        parse_without_pos("match { `a => 1, -2 => \"c\", \"\" => x, \"b\" => x, true => [], {x} => x, y => y, _ => 0, } t"),
+        Match(
+            vec![
+                (MatchPattern::Enum(Ident("a".to_string()), None), Num(1.0).into()),
+                (MatchPattern::Num(-2.0), mk_single_chunk("c")),
+                (MatchPattern::Str(String::new()), var("x")),
+                (MatchPattern::Str("b".to_string()), var("x")),
+                (MatchPattern::Bool(true), List(Vec::new()).into()),
+                (
+                    MatchPattern::Destruct(Pattern::Record(
+                        vec![FieldPattern {
+                            id: Ident("x".to_string()),
+                            pattern: Pattern::Ident(Ident("x".to_string())),
+                            default: None,
+                            pos: None,
+                        }],
+                        false,
+                        None,
+                    )),
+                    var("x")
+                ),
+                (
+                    MatchPattern::Destruct(Pattern::Ident(Ident("y".to_string()))),
+                    var("y")
+                ),
+                (MatchPattern::Wildcard, Num(0.0).into()),
+            ],
+            var("t")
+        )
+        .into()
+    );
+    assert_eq!(
+        parse_without_pos("match {} f x"),
+        App(Match(Vec::new(), var("f")).into(), var("x")).into()
+    );
+
+    assert_eq!(
+        parse_without_pos("match { `a x => x, `b {} => 0 } t"),
+        Match(
+            vec![
+                (
+                    MatchPattern::Enum(
+                        Ident("a".to_string()),
+                        Some(Pattern::Ident(Ident("x".to_string())))
+                    ),
+                    var("x")
+                ),
+                (
+                    MatchPattern::Enum(
+                        Ident("b".to_string()),
+                        Some(Pattern::Record(Vec::new(), false, None))
+                    ),
+                    Num(0.0).into()
+                ),
+            ],
+            var("t")
+        )
+        .into()
+    );
+
+    assert!(parse("match { 1 + 1 => 2 } x").is_none());
+    assert!(parse("match { `a => 1 }").is_none());
+}
+
+#[test]
+fn match_as_identifier() {
+    let var = |id: &str| RichTerm::from(Var(Ident(id.to_string())));
+
+    assert_eq!(
+        parse_without_pos("let match = 1 in match"),
+        RichTerm::let_in("match", Num(1.).into(), var("match"))
+    );
+    assert_eq!(
+        parse_without_pos("r.match"),
+        Op1(UnaryOp::StaticAccess(Ident::from("match")), var("r")).into()
+    );
+    assert!(matches!(
+        parse_without_pos("{match = 1}").as_ref(),
+        RecRecord(map, _) if map.get(&Ident::from("match")) == Some(&Num(1.).into())
+    ));
+    assert_eq!(
+        parse_without_pos("f match x"),
+        RichTerm::app(RichTerm::app(var("f"), var("match")), var("x"))
+    );
+
+    // Followed by its cases, even on the next line or after a comment, `match` is the keyword.
+    assert_eq!(
+        parse_without_pos("match\n  {} x"),
+        Match(Vec::new(), var("x")).into()
+    );
+    assert_eq!(
+        parse_without_pos("match /* cases */ { _ => 0 } x"),
+        Match(vec![(MatchPattern::Wildcard, Num(0.).into())], var("x")).into()
+    );
+    assert_eq!(
+        parse_without_pos("match // cases\n { y => y } x"),
+        Match(
+            vec![(
+                MatchPattern::Destruct(Pattern::Ident(Ident("y".to_string()))),
+                var("y")
+            )],
+            var("x")
+        )
+        .into()
+    );
+
+    // Followed by a record literal, `match` is a variable.
+    let applied = |s| match parse_without_pos(s).as_ref() {
+        App(f, r) => {
+            *f == var("match")
+                && matches!(r.as_ref(), RecRecord(..) | Op2(BinaryOp::DynExtend(_), ..))
+        }
+        _ => false,
+    };
+    assert!(applied("match {a = 1}"));
+    assert!(applied("match { /* fields */ a.b = 1 }"));
+    assert!(applied("match {a | Num}"));
+    assert!(applied("match {$x = 1}"));
+    assert!(applied("match {\n  /// Doc\n  a = 1\n}"));
+}
+
 #[test]
 fn unary_op() {
     assert_eq!(
@@ -173,6 +515,22 @@ fn enum_terms() {
         parse_without_pos("`foo"),
         Enum(Ident("foo".to_string())).into(),
     );
+    assert_eq!(
+        parse_without_pos("`foo 1"),
+        EnumVariant(Ident("foo".to_string()), Num(1.0).into()).into(),
+    );
+    assert_eq!(
+        parse_without_pos("`foo {} x"),
+        App(
+            EnumVariant(
+                Ident("foo".to_string()),
+                RecRecord(HashMap::new(), HashMap::new()).into()
+            )
+            .into(),
+            RichTerm::var("x".to_string())
+        )
+        .into(),
+    );
 
     assert_eq!(
         parse_without_pos("switch { foo => true, bar => false, _ => 456, } 123"),
@@ -203,7 +561,8 @@ fn record_terms() {
                 (Ident("c".to_string()), Num(3.).into())
             ]
             .into_iter()
-            .collect()
+            .collect(),
+            HashMap::new()
         )
         .into()
     );
@@ -225,7 +584,8 @@ fn record_terms() {
                     (Ident("d".to_string()), Num(42.).into()),
                 ]
                 .into_iter()
-                .collect()
+                .collect(),
+                HashMap::new()
             )
             .into()
         )
@@ -233,6 +593,253 @@ fn record_terms() {
     );
 }
 
+#[test]
+fn field_paths() {
+    let fields = |fields: Vec<(&str, RichTerm)>| -> HashMap<Ident, RichTerm> {
+        fields
+            .into_iter()
+            .map(|(id, t)| (Ident(id.to_string()), t))
+            .collect()
+    };
+    let rec_record =
+        |f: Vec<(&str, RichTerm)>| -> RichTerm { RecRecord(fields(f), HashMap::new()).into() };
+    let record = |f: Vec<(&str, RichTerm)>| -> RichTerm { Record(fields(f)).into() };
+
+    assert_eq!(
+        parse_without_pos("{a.b.c = 1}"),
+        rec_record(vec![(
+            "a",
+            record(vec![("b", record(vec![("c", Num(1.).into())]))])
+        )])
+    );
+
+    assert_eq!(
+        parse_without_pos("{a.b = 1; c = 2; a.d = 3}"),
+        rec_record(vec![
+            (
+                "a",
+                Op2(
+                    BinaryOp::Merge(),
+                    record(vec![("b", Num(1.).into())]),
+                    record(vec![("d", Num(3.).into())]),
+                )
+                .into()
+            ),
+            ("c", Num(2.).into()),
+        ])
+    );
+
+    assert_eq!(
+        parse_without_pos("{/// doc\na.b = 1}"),
+        rec_record(vec![(
+            "a",
+            record(vec![(
+                "b",
+                Docstring(String::from("doc"), Num(1.).into()).into()
+            )])
+        )])
+    );
+
+    // Only the record written by the user is recursive
+    assert_eq!(
+        parse_without_pos("{b = 1; a.b = b}"),
+        rec_record(vec![
+            ("a", record(vec![("b", Var(Ident::from("b")).into())])),
+            ("b", Num(1.).into()),
+        ])
+    );
+}
+
+#[test]
+fn field_name_spans() {
+    let spans = |s: &str| -> Vec<(String, usize, usize)> {
+        match parse(s).unwrap().as_ref() {
+            RecRecord(_, spans) => {
+                let mut spans: Vec<_> = spans
+                    .iter()
+                    .map(|(id, span)| (id.to_string(), span.start.to_usize(), span.end.to_usize()))
+                    .collect();
+                spans.sort();
+                spans
+            }
+            t => panic!("expected a record, got {:?}", t),
+        }
+    };
+
+    assert_eq!(
+        spans("{foo | Num = 1; bar.baz = 2; bar.qux = 3}"),
+        vec![(String::from("bar"), 16, 19), (String::from("foo"), 1, 4)]
+    );
+}
+
+#[test]
+fn duplicate_fields() {
+    assert_eq!(
+        parse_err("{a = 1; a = 2}"),
+        lalrpop_util::ParseError::User {
+            error: ParseError::DuplicateField(String::from("a"), (1, 2), (8, 9))
+        }
+    );
+    assert_eq!(
+        parse_err("{a.b = 1; c = 2; a.b = 3}"),
+        lalrpop_util::ParseError::User {
+            error: ParseError::DuplicateField(String::from("a.b"), (1, 4), (17, 20))
+        }
+    );
+    assert!(parse("{a = {b = 1}; a.c = 2; a.d.e = 3; a.d.f = 4}").is_some());
+    assert!(parse("{$a = 1; $a = 2}").is_some());
+
+    // A path conflicts with the definition of one of its prefixes.
+    assert_eq!(
+        parse_err("{a = 1; a.b = 2}"),
+        lalrpop_util::ParseError::User {
+            error: ParseError::DuplicateField(String::from("a"), (1, 2), (8, 11))
+        }
+    );
+    assert_eq!(
+        parse_err("{a.b.c = 1; a = 2}"),
+        lalrpop_util::ParseError::User {
+            error: ParseError::DuplicateField(String::from("a"), (1, 6), (12, 13))
+        }
+    );
+    assert_eq!(
+        parse_err("{a = {b = 1}; a.b = 2}"),
+        lalrpop_util::ParseError::User {
+            error: ParseError::DuplicateField(String::from("a.b"), (1, 2), (14, 17))
+        }
+    );
+    assert_eq!(
+        parse_err("{a.b = {c = 1}; a = {b.c.d = 2}}"),
+        lalrpop_util::ParseError::User {
+            error: ParseError::DuplicateField(String::from("a.b.c"), (1, 4), (16, 17))
+        }
+    );
+    // Fields without a value, or with a default value, can still be merged with subfields.
+    assert!(parse("{a | Num; a.b = 1}").is_some());
+    assert!(parse("{a | default = {}; a.b = 1}").is_some());
+    assert!(parse("{a = {b.c = 1; b.d = 2}; a.b.e = 3; a.f = 4}").is_some());
+    assert!(parse("{a = merge {b = 1} {b = 2}; c = 3}").is_some());
+    // Values which may be records are merged at run time.
+    assert!(parse("{a = x; x = 1; a.b = 1}").is_some());
+    assert!(parse("{a = x; a = y}").is_some());
+    assert!(parse("{a | force = 1; a = 2}").is_some());
+}
+
+#[test]
+fn field_annotations() {
+    let field = |s: &str| -> RichTerm {
+        match parse_without_pos(s).term.as_ref() {
+            RecRecord(map, _) => map.values().next().unwrap().clone(),
+            t => panic!("expected a record, got {:?}", t),
+        }
+    };
+
+    assert!(matches!(
+        field("{port | Num | default = 80 | doc \"The port\"}").as_ref(),
+        Docstring(doc, t) if doc == "The port" && matches!(
+            t.as_ref(),
+            ContractWithDefault(Types(AbsType::Num()), _, d) if *d == Num(80.).into()
+        )
+    ));
+    assert!(matches!(
+        field("{/// The host\nhost | Str | optional}").as_ref(),
+        Optional(t) if matches!(
+            t.as_ref(),
+            Docstring(doc, t) if doc == "The host"
+                && matches!(t.as_ref(), Contract(Types(AbsType::Str()), _))
+        )
+    ));
+    assert!(matches!(
+        field("{a | optional}").as_ref(),
+        Optional(t) if matches!(t.as_ref(), Contract(Types(AbsType::Dyn()), _))
+    ));
+    assert_eq!(field("{a | optional = 1}"), Num(1.).into());
+    assert_eq!(
+        field("{a | default = 1 | optional}"),
+        DefaultValue(Num(1.).into()).into()
+    );
+    assert!(matches!(
+        field("{a | Num | Bool}").as_ref(),
+        Contract(Types(AbsType::Flat(_)), _)
+    ));
+    assert!(matches!(
+        field("{a | merge concat = []}").as_ref(),
+        MergeStrategy(crate::term::MergeStrategy::Concat, t) if *t == List(Vec::new()).into()
+    ));
+    assert!(parse("{a | merge append = []}").is_none());
+    assert!(matches!(
+        field("{a | Num | priority -1 = 1}").as_ref(),
+        Priority(crate::term::MergePriority::Numeral(p), t)
+            if *p == -1. && matches!(t.as_ref(), ContractWithValue(Types(AbsType::Num()), _, _))
+    ));
+    assert!(matches!(
+        field("{a | force | doc \"d\" = 1}").as_ref(),
+        Docstring(_, t) if matches!(t.as_ref(), Priority(crate::term::MergePriority::Force, _))
+    ));
+    assert!(parse("{a | priority 1}").is_none());
+    assert!(parse("{a | force | default = 1}").is_none());
+    assert!(parse("{$a | Num = 1}").is_none());
+    assert!(parse("{a = 1 | Num}").is_none());
+    assert!(parse("{a | default}").is_none());
+    assert!(parse("{a | dc \"d\"}").is_none());
+
+    // A definition would silently take precedence over a default value.
+    assert_eq!(
+        parse_err("{a | Num | default = 1 = 2}"),
+        lalrpop_util::ParseError::User {
+            error: ParseError::DefaultWithDefinition((11, 18), (23, 26))
+        }
+    );
+    assert_eq!(
+        parse_err("{a | default = 1 | Num = 2}"),
+        lalrpop_util::ParseError::User {
+            error: ParseError::DefaultWithDefinition((5, 12), (23, 26))
+        }
+    );
+}
+
+#[test]
+fn annotation_keywords_as_identifiers() {
+    let static_access = |r: &str, field: &str| -> RichTerm {
+        Op1(
+            UnaryOp::StaticAccess(Ident::from(field)),
+            RichTerm::var(r.into()),
+        )
+        .into()
+    };
+
+    for keyword in &["default", "doc", "optional", "priority", "force"] {
+        let record = parse_without_pos(&format!("{{{} = 1}}", keyword));
+        assert!(matches!(
+            record.as_ref(),
+            RecRecord(map, _) if map.get(&Ident::from(*keyword)) == Some(&Num(1.).into())
+        ));
+        assert_eq!(
+            parse_without_pos(&format!("r.{}", keyword)),
+            static_access("r", keyword)
+        );
+        assert_eq!(
+            parse_without_pos(&format!("let {} = 1 in {}", keyword, keyword)),
+            RichTerm::let_in(keyword, Num(1.).into(), RichTerm::var(keyword.to_string()))
+        );
+    }
+
+    // After a `|`, they are annotation keywords
+    assert!(matches!(
+        parse_without_pos("{default | default = 1}").as_ref(),
+        RecRecord(map, _)
+            if map.get(&Ident::from("default")) == Some(&DefaultValue(Num(1.).into()).into())
+    ));
+    assert!(matches!(
+        parse_without_pos("{force | force = true}").as_ref(),
+        RecRecord(map, _) if matches!(
+            map.get(&Ident::from("force")).unwrap().as_ref(),
+            Priority(crate::term::MergePriority::Force, _)
+        )
+    ));
+    assert!(parse("{a | prio 1 = 1}").is_none());
+}
+
 #[test]
 fn string_lexing() {
     assert_eq!(
@@ -303,3 +910,172 @@ fn str_escape() {
         mk_single_chunk("$${ }$"),
     );
 }
+
+#[test]
+fn comments() {
+    assert_eq!(
+        lex_without_pos("1 // a comment\n/* a /* nested */ block\n comment */ + 2"),
+        Ok(vec![
+            Token::Normal(NormalToken::NumLiteral(1.0)),
+            Token::Normal(NormalToken::Plus),
+            Token::Normal(NormalToken::NumLiteral(2.0)),
+        ])
+    );
+    assert_eq!(
+        lex_without_pos("//// not a doc comment\n/// a doc comment\n///\n1 / 2"),
+        Ok(vec![
+            Token::Normal(NormalToken::DocComment("a doc comment")),
+            Token::Normal(NormalToken::DocComment("")),
+            Token::Normal(NormalToken::NumLiteral(1.0)),
+            Token::Normal(NormalToken::Div),
+            Token::Normal(NormalToken::NumLiteral(2.0)),
+        ])
+    );
+    assert_eq!(
+        lex("1 /* unterminated /* */"),
+        Err(ParseError::Lexical(LexicalError::UnterminatedComment(2)))
+    );
+    assert_eq!(
+        parse_without_pos("\"// /* not a comment */\""),
+        mk_single_chunk("// /* not a comment */"),
+    );
+}
+
+#[test]
+fn doc_comments() {
+    assert_eq!(
+        parse_without_pos(
+            "{\n  /// The port.\n  ///\n  ///  Defaults to 80.\n  port = 80; host = 1}"
+        ),
+        RecRecord(
+            vec![
+                (
+                    Ident("port".to_string()),
+                    Docstring(
+                        String::from("The port.\n\n Defaults to 80."),
+                        Num(80.).into()
+                    )
+                    .into()
+                ),
+                (Ident("host".to_string()), Num(1.).into()),
+            ]
+            .into_iter()
+            .collect(),
+            HashMap::new()
+        )
+        .into()
+    );
+    assert_eq!(
+        parse_without_pos("{/// doc\n$x = 1}"),
+        Op2(
+            BinaryOp::DynExtend(Docstring(String::from("doc"), Num(1.).into()).into()),
+            Var(Ident("x".to_string())).into(),
+            RecRecord(HashMap::new(), HashMap::new()).into(),
+        )
+        .into()
+    );
+    assert!(parse("/// not attached to a field\n1").is_none());
+}
+
+#[test]
+fn misplaced_doc_comments() {
+    use crate::error::ParseError;
+
+    fn parse_err(s: &str) -> ParseError {
+        let id = Files::new().add("<test>", String::from(s));
+        let err = super::grammar::TermParser::new()
+            .parse(id, &mut Vec::new(), Lexer::new(s))
+            .unwrap_err();
+        ParseError::from_lalrpop(err, id)
+    }
+
+    for s in &[
+        "/// file header\n{a = 1}",
+        "/// doc\nlet x = 1 in x",
+        "[1, /// doc\n 2]",
+        "{a = 1; /// doc\n}",
+        "{/// doc\n}",
+        "{a = 1}\n/// trailing",
+    ] {
+        match parse_err(s) {
+            ParseError::MisplacedDocComment(_) => (),
+            err => panic!(
+                "expected a misplaced doc comment for {:?}, got {:?}",
+                s, err
+            ),
+        }
+    }
+
+    assert!(parse("{/// doc\na = 1}").is_some());
+}
+
+#[test]
+fn multiline_strings() {
+    assert_eq!(
+        parse_without_pos("m#\"\n    first\n      \"second\" $HOME\n\n    third\n  \"#m"),
+        mk_single_chunk("first\n  \"second\" $HOME\n\nthird"),
+    );
+    assert_eq!(
+        parse_without_pos("m#\"no \\n escape\n   and\n     indent\"#m"),
+        mk_single_chunk("no \\n escape\nand\n  indent"),
+    );
+    assert_eq!(
+        parse_without_pos("m#\"\n  a ${x}\n    ${ m#\"\n      nested\n    \"#m } b\n  \"#m"),
+        StrChunks(vec![
+            StrChunk::Literal(String::from(" b")),
+            StrChunk::Expr(mk_single_chunk("nested")),
+            StrChunk::Literal(String::from("\n  ")),
+            StrChunk::Expr(Var(Ident("x".to_string())).into()),
+            StrChunk::Literal(String::from("a ")),
+        ])
+        .into()
+    );
+    assert_eq!(
+        parse_without_pos("m#\"\n  \"#m"),
+        StrChunks(Vec::new()).into()
+    );
+
+    // Only the identical whitespace prefix of the lines is removed when they mix tabs and spaces.
+    assert_eq!(
+        parse_without_pos("m#\"\n\t  a\n\t\tb\n  \tc\n\t  \"#m"),
+        mk_single_chunk("\t  a\n\t\tb\n  \tc"),
+    );
+    assert_eq!(
+        parse_without_pos("m#\"\n\t  a\n\t\tb\n\t   c\n\t\"#m"),
+        mk_single_chunk("  a\n\tb\n   c"),
+    );
+    assert_eq!(
+        parse_without_pos("m#\"\n\t a\n\n\t  b\n\"#m"),
+        mk_single_chunk("a\n\n b"),
+    );
+    assert!(parse("m#\"unterminated\"#").is_none());
+}
+
+#[test]
+fn raw_strings() {
+    assert_eq!(
+        parse_without_pos(r##"r#"C:\dir "quoted" $1"#"##),
+        mk_single_chunk(r#"C:\dir "quoted" $1"#),
+    );
+    assert_eq!(
+        parse_without_pos(r##"r#"a ${ {b = r#"}"#}.b } c"#"##),
+        StrChunks(vec![
+            StrChunk::Literal(String::from(" c")),
+            StrChunk::Expr(
+                Op1(
+                    UnaryOp::StaticAccess(Ident("b".to_string())),
+                    RecRecord(
+                        vec![(Ident("b".to_string()), mk_single_chunk("}"))]
+                            .into_iter()
+                            .collect(),
+                        HashMap::new()
+                    )
+                    .into()
+                )
+                .into()
+            ),
+            StrChunk::Literal(String::from("a ")),
+        ])
+        .into()
+    );
+}
@@ -1,8 +1,49 @@
 /// A few helpers to generate position spans and labels easily during parsing
+use super::error::ParseError as InternalParseError;
+use super::lexer::{NormalToken, Token};
+use crate::identifier::Ident;
 use crate::label::Label;
+use crate::merge::merge_contracts;
 use crate::position::RawSpan;
-use crate::types::Types;
+use crate::term::{BinaryOp, MergePriority, MergeStrategy, RichTerm, StrChunk, Term};
+use crate::types::{AbsType, Types};
 use codespan::FileId;
+use either::Either;
+use lalrpop_util::ParseError;
+use std::collections::HashMap;
+
+/// A field definition of a record literal: either a static field, given by its path, the span of
+/// this path and its value, or a dynamic field, given by the expression of its name and its
+/// value.
+pub type FieldDef = Either<(Vec<Ident>, (usize, usize), RichTerm), (RichTerm, RichTerm)>;
+
+/// An annotation of a record field, as in `port | Num | default = 80 | doc "The port"`.
+pub enum FieldAnnot {
+    /// `| <type>`: a contract.
+    Contract(Types, Label),
+    /// `| default = <term>`: a default value.
+    Default(RichTerm),
+    /// `| doc "<string>"`: a docstring.
+    Doc(String),
+    /// `| optional`: the field may be absent.
+    Optional,
+    /// `| merge <strategy>`: the strategy used to merge lists.
+    Merge(MergeStrategy),
+    /// `| priority <number>` or `| force`: the merge priority of the definition.
+    Priority(MergePriority),
+}
+
+/// An element of a record field following its path: either an annotation or a definition. The
+/// value of a default annotation `| default = <term>` is parsed as a definition, which follows the
+/// `default` keyword.
+pub enum FieldItem {
+    /// An annotation other than `| default`.
+    Annot(FieldAnnot),
+    /// The `default` keyword of a `| default = <term>` annotation, with its position.
+    Default(usize, usize),
+    /// `= <term>`: a definition, or the value of the preceding `default` keyword.
+    Value(RichTerm, usize, usize),
+}
 
 /// Make a span from parser byte offsets.
 pub fn mk_span(src_id: FileId, l: usize, r: usize) -> RawSpan {
@@ -21,5 +62,413 @@ pub fn mk_label(types: Types, src_id: FileId, l: usize, r: usize) -> Label {
         span: mk_span(src_id, l, r),
         polarity: true,
         path: Vec::new(),
+        value: None,
+        access_path: Vec::new(),
+    }
+}
+
+/// The leading spaces and tabs of a line.
+fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// The length of the longest common prefix of two indentations.
+fn common_prefix_len(indent1: &str, indent2: &str) -> usize {
+    indent1
+        .bytes()
+        .zip(indent2.bytes())
+        .take_while(|(c1, c2)| c1 == c2)
+        .count()
+}
+
+/// Strip the indentation of the chunks of a multi-line string.
+///
+/// If the first line, which follows the opening delimiter, is blank, it is removed. So is the last
+/// line if it only consists of whitespace. Then, the common indentation of the remaining lines,
+/// that is the longest prefix of spaces and tabs they all start with, is removed. A tab and a
+/// space never match, whatever the width of the tab: lines indented with different mixes of tabs
+/// and spaces only lose their identical prefix. Blank lines do not count in the common
+/// indentation, while an interpolated expression counts as content. If the first line was not
+/// blank, it is left as is.
+pub fn strip_indent(mut chunks: Vec<StrChunk<RichTerm>>) -> Vec<StrChunk<RichTerm>> {
+    let mut first_line_removed = false;
+    if let Some(StrChunk::Literal(s)) = chunks.first_mut() {
+        if let Some(end) = s.find('\n') {
+            if s[..end].trim_start_matches([' ', '\t']).is_empty() {
+                s.replace_range(..=end, "");
+                first_line_removed = true;
+            }
+        }
+    }
+
+    if let Some(StrChunk::Literal(s)) = chunks.last_mut() {
+        if let Some(start) = s.rfind('\n') {
+            if s[start + 1..].trim_start_matches([' ', '\t']).is_empty() {
+                s.truncate(start);
+            }
+        }
     }
+
+    // Compute the common indentation. A line may be split in several chunks, if it contains
+    // interpolated expressions: only its beginning, in the first chunk, matters.
+    let mut common: Option<String> = None;
+    let mut at_line_start = first_line_removed;
+    for (i, chunk) in chunks.iter().enumerate() {
+        match chunk {
+            StrChunk::Literal(s) => {
+                let lines: Vec<&str> = s.split('\n').collect();
+                for (j, line) in lines.iter().enumerate() {
+                    let is_line_start = j > 0 || at_line_start;
+                    let is_blank = indentation(line).len() == line.len();
+                    let followed_by_expr = j == lines.len() - 1 && i + 1 < chunks.len();
+
+                    if is_line_start && (!is_blank || followed_by_expr) {
+                        let indent = indentation(line);
+                        common = Some(match common {
+                            Some(mut common) => {
+                                common.truncate(common_prefix_len(&common, indent));
+                                common
+                            }
+                            None => String::from(indent),
+                        });
+                    }
+                }
+                at_line_start = false;
+            }
+            StrChunk::Expr(_) => at_line_start = false,
+        }
+    }
+
+    let mut at_line_start = first_line_removed;
+    for chunk in chunks.iter_mut() {
+        if let StrChunk::Literal(s) = chunk {
+            *s = s
+                .split('\n')
+                .enumerate()
+                .map(|(j, line)| {
+                    if j > 0 || at_line_start {
+                        let indent = indentation(line);
+                        // If no line counts in the common indentation, they are all blank.
+                        match &common {
+                            Some(common) => &line[common_prefix_len(indent, common)..],
+                            None => &line[indent.len()..],
+                        }
+                    } else {
+                        line
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+        at_line_start = false;
+    }
+
+    chunks.retain(|chunk| !matches!(chunk, StrChunk::Literal(s) if s.is_empty()));
+    chunks
+}
+
+/// Separate the annotations of a field from its definition, which must come last. A `default`
+/// keyword takes the value which follows it. A field can't have both a default value and a
+/// definition, as the definition would always take precedence.
+fn split_items(
+    items: Vec<FieldItem>,
+) -> Result<(Vec<FieldAnnot>, Option<RichTerm>), InternalParseError> {
+    let mut annots = Vec::new();
+    let mut value: Option<(RichTerm, usize, usize)> = None;
+    let mut default_span: Option<(usize, usize)> = None;
+    let mut items = items.into_iter();
+
+    while let Some(item) = items.next() {
+        if let Some((_, l, r)) = value {
+            return Err(InternalParseError::AnnotationAfterDefinition(l, r));
+        }
+
+        match item {
+            FieldItem::Annot(annot) => annots.push(annot),
+            FieldItem::Default(l, r) => match items.next() {
+                Some(FieldItem::Value(t, _, _)) => {
+                    annots.push(FieldAnnot::Default(t));
+                    default_span = Some((l, r));
+                }
+                _ => return Err(InternalParseError::MissingDefaultValue(l, r)),
+            },
+            FieldItem::Value(_, l, r) if default_span.is_some() => {
+                return Err(InternalParseError::DefaultWithDefinition(
+                    default_span.unwrap(),
+                    (l, r),
+                ))
+            }
+            FieldItem::Value(t, l, r) => value = Some((t, l, r)),
+        }
+    }
+
+    Ok((annots, value.map(|(t, _, _)| t)))
+}
+
+/// Lower the annotations and the optional definition of a field to enriched values.
+///
+/// Several contracts are composed, several default values are merged, and the last merge strategy
+/// and priority are kept. A definition is lowered as the merge of the contract with this
+/// definition, which keeps the contract as metadata of the field. A priority requires a
+/// definition. An optional field is wrapped in `Optional` if it has neither a definition nor a
+/// default value, and is a mere field otherwise.
+pub fn build_field(
+    src_id: FileId,
+    (l, r): (usize, usize),
+    doc: Option<String>,
+    items: Vec<FieldItem>,
+) -> Result<RichTerm, InternalParseError> {
+    let (annots, value) = split_items(items)?;
+    let mut contract: Option<(Types, Label)> = None;
+    let mut default: Option<RichTerm> = None;
+    let mut docs = Vec::new();
+    let mut optional = false;
+    let mut strategy = None;
+    let mut priority = None;
+
+    for annot in annots {
+        match annot {
+            FieldAnnot::Contract(ty, lbl) => {
+                contract = Some(match contract {
+                    Some((prev_ty, prev_lbl)) => {
+                        let ty = merge_contracts(prev_ty.contract(), prev_lbl, ty.contract(), lbl);
+                        (ty.clone(), mk_label(ty, src_id, l, r))
+                    }
+                    None => (ty, lbl),
+                })
+            }
+            FieldAnnot::Default(t) => {
+                default = Some(match default {
+                    Some(prev) => RichTerm::new(Term::Op2(BinaryOp::Merge(), prev, t)),
+                    None => t,
+                })
+            }
+            FieldAnnot::Doc(s) => docs.push(s),
+            FieldAnnot::Optional => optional = true,
+            FieldAnnot::Merge(s) => strategy = Some(s),
+            FieldAnnot::Priority(p) => priority = Some(p),
+        }
+    }
+    let is_defined = value.is_some() || default.is_some();
+    if priority.is_some() && value.is_none() {
+        return Err(InternalParseError::UndefinedPriority(l, r));
+    }
+
+    // The enriched values built from annotations are given the position of the field name.
+    let t = match (value, default, contract) {
+        (Some(t), _, None) => return Ok(wrap_field(t, priority, strategy, docs, doc, false)),
+        (Some(t), _, Some((ty, lbl))) => Term::ContractWithValue(ty, lbl, t),
+        (None, Some(t), Some((ty, lbl))) => Term::ContractWithDefault(ty, lbl, t),
+        (None, Some(t), None) => Term::DefaultValue(t),
+        (None, None, Some((ty, lbl))) => Term::Contract(ty, lbl),
+        (None, None, None) => {
+            let ty = Types(AbsType::Dyn());
+            Term::Contract(ty.clone(), mk_label(ty, src_id, l, r))
+        }
+    };
+    let t = RichTerm {
+        term: Box::new(t),
+        pos: Some(mk_span(src_id, l, r)),
+    };
+
+    Ok(wrap_field(
+        t,
+        priority,
+        strategy,
+        docs,
+        doc,
+        optional && !is_defined,
+    ))
+}
+
+/// Wrap the lowered value of a field in its priority and its merge strategy, then in its
+/// docstrings, the first ones being the outermost, and in the doc comment `doc`. The result is
+/// finally wrapped in `Optional` if `optional` is true.
+fn wrap_field(
+    t: RichTerm,
+    priority: Option<MergePriority>,
+    strategy: Option<MergeStrategy>,
+    docs: Vec<String>,
+    doc: Option<String>,
+    optional: bool,
+) -> RichTerm {
+    let t = match priority {
+        Some(priority) => RichTerm::new(Term::Priority(priority, t)),
+        None => t,
+    };
+    // The merge strategy is positioned at the field, such that a conflict between two strategies
+    // can be reported at the fields which define them.
+    let t = match strategy {
+        Some(strategy) => RichTerm {
+            pos: t.pos.clone(),
+            term: Box::new(Term::MergeStrategy(strategy, t)),
+        },
+        None => t,
+    };
+    let t = docs
+        .into_iter()
+        .rev()
+        .chain(doc)
+        .fold(t, |t, s| RichTerm::new(Term::Docstring(s, t)));
+
+    if optional {
+        RichTerm::new(Term::Optional(t))
+    } else {
+        t
+    }
+}
+
+/// Check that the identifier between `let` and the bindings of a recursive let binding is `rec`.
+///
+/// `rec` is not a reserved keyword, such that it can be used as an identifier elsewhere. Any other
+/// identifier at this position is reported as an unexpected token, where `rec` was expected.
+pub fn rec_keyword(
+    l: usize,
+    kw: &str,
+    r: usize,
+) -> Result<(), ParseError<usize, Token<'_>, InternalParseError>> {
+    if kw == "rec" {
+        Ok(())
+    } else {
+        Err(ParseError::UnrecognizedToken {
+            token: Some((l, Token::Normal(NormalToken::Identifier(kw)), r)),
+            expected: vec![String::from("\"rec\"")],
+        })
+    }
+}
+
+/// Elaborate the definition of a field path `a.b.c = t` to the definition of its first identifier
+/// as nested records, `a = {b = {c = t}}`.
+///
+/// The intermediate records are not recursive: only the record written by the user is, such that
+/// the identifiers of `t` do not refer to the fields of the path, as in `{b = 1; a.b = b}`.
+fn elaborate_field_path(path: Vec<Ident>, t: RichTerm) -> (Ident, RichTerm) {
+    let mut path = path.into_iter();
+    let first = path.next().expect("a field path is not empty");
+
+    let value = path.rev().fold(t, |acc, id| {
+        let mut map = HashMap::new();
+        map.insert(id, acc);
+        RichTerm::new(Term::Record(map))
+    });
+
+    (first, value)
+}
+
+/// The paths of the values defined by the definition of a field path, that is the path itself,
+/// or the paths of the fields of the value if it is a record literal. Only the values which are
+/// known not to be records are taken into account: a variable or a function application may well
+/// evaluate to a record, which is then merged at run time. A field without a value, or with a
+/// value which can be overridden or combined with another one, defines no path.
+fn defined_paths(path: Vec<Ident>, t: &RichTerm) -> Vec<Vec<Ident>> {
+    match t.as_ref() {
+        Term::Record(map) | Term::RecRecord(map, _) => map
+            .iter()
+            .flat_map(|(id, t)| {
+                let mut path = path.clone();
+                path.push(id.clone());
+                defined_paths(path, t)
+            })
+            .collect(),
+        // Nested field paths sharing a prefix are merged, and dynamic fields are not known
+        // statically.
+        Term::Op2(BinaryOp::Merge(), t1, t2) => {
+            let mut paths = defined_paths(path.clone(), t1);
+            paths.extend(defined_paths(path, t2));
+            paths
+        }
+        Term::Op2(BinaryOp::DynExtend(_), _, t) => defined_paths(path, t),
+        Term::Docstring(_, t)
+        | Term::Optional(t)
+        | Term::Assume(_, _, t)
+        | Term::ContractWithValue(_, _, t) => defined_paths(path, t),
+        // Lists are left out, as two lists can be merged elementwise.
+        Term::Bool(_)
+        | Term::Num(_)
+        | Term::Str(_)
+        | Term::StrChunks(_)
+        | Term::Fun(_, _)
+        | Term::FunPattern(_, _)
+        | Term::Lbl(_)
+        | Term::Enum(_)
+        | Term::EnumVariant(_, _) => vec![path],
+        _ => Vec::new(),
+    }
+}
+
+/// Build a record literal from its field definitions.
+///
+/// Static fields form a recursive record. Several definitions of the same field, which happens
+/// when field paths share a prefix, are merged. Defining the same path twice with values which
+/// are known not to be records is an error, as well as defining such a value for a path and for
+/// one of its prefixes, as in `{a = 1; a.b = 2}` or `{a = {b = 1}; a.b = 2}`. Other conflicts,
+/// as in `{a = x; a.b = 1}`, are left to merge. Dynamic fields are then added one by one.
+///
+/// The position of the name of a static field is the one of the first identifier of the first path
+/// which defines it.
+pub fn build_record<I>(src_id: FileId, fields: I) -> Result<RichTerm, InternalParseError>
+where
+    I: IntoIterator<Item = FieldDef>,
+{
+    let mut static_map = HashMap::new();
+    let mut spans = HashMap::new();
+    // The paths defined by a value, and the strict prefixes of these paths, with the span of the
+    // field which defines them.
+    let mut values: HashMap<Vec<Ident>, (usize, usize)> = HashMap::new();
+    let mut prefixes: HashMap<Vec<Ident>, (usize, usize)> = HashMap::new();
+    let mut dynamic_fields = Vec::new();
+
+    let duplicate = |path: &[Ident], prev_span, span| {
+        let path: Vec<String> = path.iter().map(Ident::to_string).collect();
+        InternalParseError::DuplicateField(path.join("."), prev_span, span)
+    };
+
+    for field in fields {
+        match field {
+            Either::Left((path, span, t)) => {
+                // The paths defined by one field are only checked against the previous fields:
+                // conflicts inside its value are already detected when parsing it, or are
+                // explicit merges which are resolved at run time.
+                let value_paths = defined_paths(path.clone(), &t);
+                for value_path in value_paths.iter() {
+                    if let Some(prev_span) =
+                        values.get(value_path).or_else(|| prefixes.get(value_path))
+                    {
+                        return Err(duplicate(value_path, *prev_span, span));
+                    }
+                    for i in 1..value_path.len() {
+                        if let Some(prev_span) = values.get(&value_path[..i]) {
+                            return Err(duplicate(&value_path[..i], *prev_span, span));
+                        }
+                    }
+                }
+                for value_path in value_paths {
+                    for i in 1..value_path.len() {
+                        prefixes.entry(value_path[..i].to_vec()).or_insert(span);
+                    }
+                    values.insert(value_path, span);
+                }
+
+                let (id, t) = elaborate_field_path(path, t);
+                // A field path starts with its first identifier, which is written as is.
+                spans
+                    .entry(id.clone())
+                    .or_insert_with(|| mk_span(src_id, span.0, span.0 + id.0.len()));
+                let t = match static_map.remove(&id) {
+                    Some(prev) => RichTerm::new(Term::Op2(BinaryOp::Merge(), prev, t)),
+                    None => t,
+                };
+                static_map.insert(id, t);
+            }
+            Either::Right(field) => dynamic_fields.push(field),
+        }
+    }
+
+    let static_rec = RichTerm::new(Term::RecRecord(static_map, spans));
+
+    Ok(dynamic_fields
+        .into_iter()
+        .fold(static_rec, |rec, (id_t, t)| {
+            RichTerm::new(Term::Op2(BinaryOp::DynExtend(t), id_t, rec))
+        }))
 }
@@ -0,0 +1,1612 @@
+//! Pretty-printing of terms and types as Nickel source code.
+//!
+//! The printer produces a canonical layout, which only depends on the AST: printing the result of
+//! parsing a printed term gives back the same text. This is what the `fmt` command relies on to
+//! reformat source files.
+//!
+//! Some constructs are desugared by the parser, and are printed back using their original syntax:
+//! `if-then-else`, the lazy boolean operators `&&` and `||`, the unary minus, functions of several
+//! arguments and the dynamic fields of record literals. The documentation of record fields is
+//! printed as doc comments, and strings of several lines as multi-line strings. The fields of
+//! records are printed in the order of their position in the source, or in alphabetical order for
+//! fields without a position.
+//!
+//! Parentheses are only inserted where required by the grammar. Records, lists, annotations,
+//! `switch` and `if-then-else` expressions are laid out on one line if they are short enough, and
+//! on several lines with one element per line otherwise. A `let` body always starts on a new line.
+//!
+//! Line and block comments are not part of the AST. When the source is available, they are
+//! printed on their own line before the closest item laid out on its own line, such as a record
+//! field or a list element, or at the end of its line for a comment which ends the line of an
+//! item (see [`pretty_with_comments`](fn.pretty_with_comments.html)).
+//!
+//! A few terms, such as labels, symbols or resolved imports, only appear during evaluation and
+//! have no concrete syntax. They are printed between `%` signs, as in `%label%`, and the result
+//! cannot be parsed back.
+use crate::identifier::Ident;
+use crate::parser::lexer;
+use crate::term::{
+    BinaryOp, MatchPattern, MergePriority, Pattern, RichTerm, StrChunk, Term, UnaryOp,
+};
+use crate::types::{AbsType, Types};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// The maximum length of a construct laid out on one line.
+const WIDTH: usize = 80;
+
+/// The number of spaces of one level of indentation.
+const INDENT: &str = "  ";
+
+/// The precedence levels of the grammar, from the loosest to the tightest.
+///
+/// A term printed at a given level is parenthesized if its own level is looser.
+mod prec {
+    /// Functions, let bindings, if-then-else and imports.
+    pub const TERM: u8 = 0;
+    /// `||`.
+    pub const OR: u8 = 1;
+    /// `&&`.
+    pub const AND: u8 = 2;
+    /// `==`.
+    pub const EQ: u8 = 3;
+    /// `<`, `<=`, `>` and `>=`.
+    pub const CMP: u8 = 4;
+    /// `!`.
+    pub const NOT: u8 = 5;
+    /// `+` and `-`.
+    pub const SUM: u8 = 6;
+    /// `*`, `/` and `%`.
+    pub const PRODUCT: u8 = 7;
+    /// `++` and `@`.
+    pub const CONCAT: u8 = 8;
+    /// The unary minus.
+    pub const NEG: u8 = 9;
+    /// Applications and primitive operators.
+    pub const APP: u8 = 10;
+    /// Record operations: `r.x`, `r.$x`, `r-$x` and `r$[x = t]`.
+    pub const ACCESS: u8 = 11;
+    /// Atoms: literals, variables, records, lists, annotations and parenthesized terms.
+    pub const ATOM: u8 = 12;
+}
+
+/// The precedence levels of types.
+mod ty_prec {
+    /// `forall`.
+    pub const FORALL: u8 = 0;
+    /// Arrows.
+    pub const ARROW: u8 = 1;
+    /// Base types, type variables, rows and parenthesized types.
+    pub const ATOM: u8 = 2;
+}
+
+/// Pretty-print a term.
+pub fn pretty(rt: &RichTerm) -> String {
+    Printer::default().term(rt, prec::TERM)
+}
+
+/// Pretty-print a term parsed from `source`, together with the line and block comments of the
+/// source, which are not part of the AST.
+///
+/// A comment is attached to the innermost item laid out on its own line which contains it or
+/// follows it: a record field, a list element, a match case or a let binding. It is printed on
+/// the line before this item, or after it if it ends the line of the item in the source. The
+/// comments which are not attached to any item are printed before or after the whole term.
+pub fn pretty_with_comments(rt: &RichTerm, source: &str) -> String {
+    let printer = Printer::new(source);
+    let s = printer.term(rt, prec::TERM);
+    let header = start(rt)
+        .map(|start| printer.take(0, start))
+        .unwrap_or_default();
+    let footer = printer.take(0, source.len());
+
+    let mut result = comment_lines(&header);
+    result.push_str(&s);
+    for comment in footer {
+        result.push_str(&format!("\n{}", comment));
+    }
+    result
+}
+
+/// Pretty-print a type.
+pub fn pretty_types(ty: &Types) -> String {
+    types(ty, ty_prec::FORALL)
+}
+
+/// A line or block comment of the source.
+struct Comment {
+    start: usize,
+    end: usize,
+    /// The text of the comment. The lines of a block comment are stripped of the indentation of
+    /// its first line, which is restored by the printer.
+    text: String,
+}
+
+/// The state of the printer, that is the comments of the source which have not been printed yet.
+/// The printer of a term without source has no comments.
+#[derive(Default)]
+struct Printer<'a> {
+    source: &'a str,
+    comments: RefCell<Vec<Comment>>,
+}
+
+/// An item laid out on its own line in a block, together with the comments printed before and
+/// after it.
+#[derive(Clone)]
+struct Item {
+    leading: Vec<String>,
+    text: String,
+    trailing: Option<String>,
+}
+
+impl From<String> for Item {
+    fn from(text: String) -> Self {
+        Item {
+            leading: Vec::new(),
+            text,
+            trailing: None,
+        }
+    }
+}
+
+impl Item {
+    fn has_comments(&self) -> bool {
+        !self.leading.is_empty() || self.trailing.is_some()
+    }
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str) -> Self {
+        let comments = lexer::comments(source)
+            .into_iter()
+            .map(|(start, end)| {
+                let column = start - source[..start].rfind('\n').map_or(0, |i| i + 1);
+                let text: Vec<&str> = source[start..end]
+                    .trim_end()
+                    .split('\n')
+                    .enumerate()
+                    .map(|(i, line)| {
+                        let indentation = line.len() - line.trim_start().len();
+                        if i == 0 {
+                            line
+                        } else {
+                            &line[indentation.min(column)..]
+                        }
+                    })
+                    .collect();
+
+                Comment {
+                    start,
+                    end,
+                    text: text.join("\n"),
+                }
+            })
+            .collect();
+
+        Printer {
+            source,
+            comments: RefCell::new(comments),
+        }
+    }
+
+    /// Take the comments which have not been printed yet and start between `from` and `to`.
+    fn take(&self, from: usize, to: usize) -> Vec<String> {
+        let mut comments = self.comments.borrow_mut();
+        let (taken, rest): (Vec<Comment>, Vec<Comment>) = comments
+            .drain(..)
+            .partition(|comment| (from..to).contains(&comment.start));
+        *comments = rest;
+        taken.into_iter().map(|comment| comment.text).collect()
+    }
+
+    /// Same as [`take`](#method.take), but for optional bounds, in which case there are no
+    /// comments to take.
+    fn take_between(&self, from: Option<usize>, to: Option<usize>) -> Vec<String> {
+        match (from, to) {
+            (Some(from), Some(to)) => self.take(from, to),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The start of the comments which have not been printed yet and directly precede `pos`, that
+    /// is which are only separated from `pos` by whitespace, or `pos` if there are none.
+    fn preceding_comments(&self, pos: usize) -> usize {
+        self.comments
+            .borrow()
+            .iter()
+            .rev()
+            .fold(pos, |pos, comment| {
+                if comment.end <= pos && self.source[comment.end..pos].trim().is_empty() {
+                    comment.start
+                } else {
+                    pos
+                }
+            })
+    }
+
+    /// Take the first comment after `end` if it is on the same line, and only separated from
+    /// `end` by separators.
+    fn take_trailing(&self, end: usize) -> Option<String> {
+        let mut comments = self.comments.borrow_mut();
+        let index = comments
+            .iter()
+            .position(|comment| comment.start >= end)
+            .filter(|index| {
+                self.source[end..comments[*index].start]
+                    .chars()
+                    .all(|c| [' ', '\t', ';', ','].contains(&c))
+            })?;
+        Some(comments.remove(index).text)
+    }
+
+    /// Attach the comments to an item printed as `text`, whose value is `t`. The comments which
+    /// start between `from` and the end of the item and have not been printed by the item itself
+    /// are printed before it, and a comment ending its line after it. `from`, which is initially
+    /// the start of the block, is then set to the end of the item.
+    fn item(&self, from: &mut Option<usize>, t: &RichTerm, text: String) -> Item {
+        let (start, end) = match (start(t), end(t)) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Item::from(text),
+        };
+
+        let leading = self.take(from.unwrap_or(start), end);
+        *from = Some(end);
+        Item {
+            leading,
+            text,
+            trailing: self.take_trailing(end),
+        }
+    }
+}
+
+/// Print comments on their own lines.
+fn comment_lines(comments: &[String]) -> String {
+    comments
+        .iter()
+        .map(|comment| format!("{}\n", comment))
+        .collect()
+}
+
+/// Indent all the non-empty lines of a string but the first one.
+fn indent(s: &str) -> String {
+    s.split('\n')
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 || line.is_empty() {
+                String::from(line)
+            } else {
+                format!("{}{}", INDENT, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn is_multiline(s: &str) -> bool {
+    s.contains('\n')
+}
+
+/// Whether a multi-line term can start on the same line as the construct it belongs to, as in
+/// `let x = {` or `fun x => fun y =>`, instead of starting on a new indented line.
+fn hangs(s: &str) -> bool {
+    let first_line = s.lines().next().unwrap_or("");
+    ["{", "[", "(", "=>"]
+        .iter()
+        .any(|end| first_line.ends_with(end))
+}
+
+/// Parenthesize a printed term if its level is looser than the required one.
+fn paren(s: String, level: u8, required: u8) -> String {
+    if level < required {
+        format!("({})", s)
+    } else {
+        s
+    }
+}
+
+/// Lay out a list of items between delimiters, either on one line if it fits and there are no
+/// comments, or with one item per line otherwise. On one line, items are separated by `sep` and a
+/// space. On several lines, each item is followed by `sep`, and the comments of the block which
+/// are not attached to any item are printed at the end.
+fn block(open: &str, items: &[Item], dangling: &[String], sep: &str, close: &str) -> String {
+    if items.is_empty() && dangling.is_empty() {
+        return format!("{}{}", open, close);
+    }
+
+    let texts: Vec<&str> = items.iter().map(|item| item.text.as_str()).collect();
+    let flat = format!("{}{}{}", open, texts.join(&format!("{} ", sep)), close);
+    let has_comments = !dangling.is_empty() || items.iter().any(Item::has_comments);
+    if flat.len() <= WIDTH && !is_multiline(&flat) && !has_comments {
+        return flat;
+    }
+
+    lines(open, items, dangling, sep, close)
+}
+
+/// Lay out a list of items between delimiters with one item per line, each one followed by `sep`.
+fn lines(open: &str, items: &[Item], dangling: &[String], sep: &str, close: &str) -> String {
+    let mut result = String::from(open);
+    for item in items {
+        for comment in &item.leading {
+            result.push_str(&format!("\n{}{}", INDENT, indent(comment)));
+        }
+        result.push_str(&format!("\n{}{}{}", INDENT, indent(&item.text), sep));
+        if let Some(comment) = &item.trailing {
+            result.push_str(&format!(" {}", indent(comment)));
+        }
+    }
+    for comment in dangling {
+        result.push_str(&format!("\n{}{}", INDENT, indent(comment)));
+    }
+    result.push('\n');
+    result.push_str(close);
+    result
+}
+
+/// Lay out the cases of a `match` or a `switch`, each one followed by a comma, either on one line
+/// if they fit and have no comments, or with one case per line otherwise.
+fn cases_block(keyword: &str, cases: &[Item]) -> String {
+    let texts: Vec<String> = cases.iter().map(|case| format!("{},", case.text)).collect();
+    let flat = format!("{} {{ {} }}", keyword, texts.join(" "));
+    if flat.len() <= WIDTH && !is_multiline(&flat) && !cases.iter().any(Item::has_comments) {
+        flat
+    } else {
+        lines(&format!("{} {{", keyword), cases, &[], ",", "}")
+    }
+}
+
+/// Lay out the arguments of an annotation such as `Promise(T, t)`, either on one line if it fits,
+/// or with one argument per line.
+fn annot(open: &str, args: &[String]) -> String {
+    let flat = format!("{}{})", open, args.join(", "));
+    if flat.len() <= WIDTH && !is_multiline(&flat) {
+        return flat;
+    }
+
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| format!("\n{}{}", INDENT, indent(arg)))
+        .collect();
+    format!("{}{}\n)", open, args.join(","))
+}
+
+/// Escape a string literal.
+fn escape(s: &str) -> String {
+    let mut result = String::new();
+
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '$' => result.push_str("\\$"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+fn str_literal(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+/// Print a number, using the scientific notation for very large or very small numbers.
+fn num(n: f64) -> (String, u8) {
+    let abs = n.abs();
+    let s = if abs != 0.0 && !(1e-5..1e16).contains(&abs) {
+        format!("{:e}", abs)
+    } else {
+        format!("{}", abs)
+    };
+
+    if n.is_sign_negative() {
+        (format!("-{}", s), prec::NEG)
+    } else {
+        (s, prec::ATOM)
+    }
+}
+
+/// Sort the entries of a map by the position of their value in the source, or by name for values
+/// without a position.
+fn sorted(map: &HashMap<Ident, RichTerm>) -> Vec<(&Ident, &RichTerm)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(Ident(id), t)| (start(t).unwrap_or(usize::MAX), id.clone()));
+    entries
+}
+
+/// The start of the position of a term. The docstrings attached to record fields by doc comments
+/// and the optional markers have no position, in which case the position of the inner term is
+/// used.
+fn start(rt: &RichTerm) -> Option<usize> {
+    match (&rt.pos, rt.as_ref()) {
+        (Some(span), _) => Some(span.start.to_usize()),
+        (None, Term::Docstring(_, t)) | (None, Term::Optional(t)) => start(t),
+        (None, _) => None,
+    }
+}
+
+/// The end of the position of a term. The enriched values built from the annotations of a record
+/// field have the position of the field name, in which case the end of their inner term is used.
+fn end(rt: &RichTerm) -> Option<usize> {
+    let inner = match rt.as_ref() {
+        Term::Docstring(_, t)
+        | Term::Optional(t)
+        | Term::DefaultValue(t)
+        | Term::ContractWithDefault(_, _, t)
+        | Term::ContractWithValue(_, _, t)
+        | Term::MergeStrategy(_, t)
+        | Term::Priority(_, t) => end(t),
+        _ => None,
+    };
+
+    rt.pos.as_ref().map(|span| span.end.to_usize()).max(inner)
+}
+
+/// Print a docstring as doc comments.
+fn doc_comments(doc: &str) -> String {
+    doc.split('\n')
+        .map(|line| match line {
+            "" => String::from("///\n"),
+            line => format!("/// {}\n", line),
+        })
+        .collect()
+}
+
+impl<'a> Printer<'a> {
+    /// Print a term at the given precedence level.
+    fn term(&self, rt: &RichTerm, required: u8) -> String {
+        let (s, level) = self.term_prec(rt);
+        paren(s, level, required)
+    }
+
+    /// Print a term and return its precedence level.
+    fn term_prec(&self, rt: &RichTerm) -> (String, u8) {
+        match rt.as_ref() {
+            Term::Bool(b) => (format!("{}", b), prec::ATOM),
+            Term::Num(n) => num(*n),
+            Term::Str(s) => (str_literal(s), prec::ATOM),
+            Term::StrChunks(chunks) => (self.str_chunks(chunks), prec::ATOM),
+            Term::Fun(_, _) | Term::FunPattern(_, _) => {
+                let mut params = Vec::new();
+                let mut body = rt;
+                loop {
+                    let (param, t) = match body.as_ref() {
+                        Term::Fun(Ident(id), t) => (id.clone(), t),
+                        Term::FunPattern(pat, t) => (self.pattern(pat), t),
+                        _ => break,
+                    };
+                    params.push(param);
+                    body = t;
+                }
+
+                let body = self.term(body, prec::TERM);
+                let params = params.join(" ");
+                let s = if is_multiline(&body) && !hangs(&body) {
+                    format!("fun {} =>\n{}{}", params, INDENT, indent(&body))
+                } else {
+                    format!("fun {} => {}", params, body)
+                };
+                (s, prec::TERM)
+            }
+            Term::Lbl(_) => (String::from("%label%"), prec::ATOM),
+            Term::Let(Ident(id), t1, t2) => (self.let_in(start(rt), id, t1, t2), prec::TERM),
+            Term::LetPattern(pat, t1, t2) => (
+                self.let_in(start(rt), &self.pattern(pat), t1, t2),
+                prec::TERM,
+            ),
+            Term::LetRec(bindings, t) => (self.let_rec(start(rt), bindings, t), prec::TERM),
+            Term::Match(cases, t) => (self.match_(start(rt), cases, t), prec::APP),
+            Term::App(t1, t2) => self.app(t1, t2),
+            Term::Var(Ident(id)) => (id.clone(), prec::ATOM),
+            Term::Enum(Ident(id)) => (format!("`{}", id), prec::ATOM),
+            Term::EnumVariant(Ident(id), t) => {
+                (format!("`{} {}", id, self.term(t, prec::ATOM)), prec::APP)
+            }
+            Term::Record(fields) | Term::RecRecord(fields, _) => {
+                (self.record(rt, fields, &[]), prec::ATOM)
+            }
+            Term::List(terms) => {
+                let mut from = start(rt);
+                let mut items = Vec::new();
+                let mut last = String::new();
+                for t in terms {
+                    let (s, level) = self.term_prec(t);
+                    last = s.clone();
+                    items.push(self.item(&mut from, t, paren(s, level, prec::ATOM)));
+                }
+                let dangling = self.take_between(from, end(rt));
+
+                // On several lines, each element is followed by a comma, and must thus be an atom.
+                let mut flat_items = items.clone();
+                if let Some(item) = flat_items.last_mut() {
+                    item.text = last;
+                }
+                let flat = block("[", &flat_items, &dangling, ",", "]");
+                if is_multiline(&flat) {
+                    (block("[", &items, &dangling, ",", "]"), prec::ATOM)
+                } else {
+                    (flat, prec::ATOM)
+                }
+            }
+            Term::Op1(op, t) => self.op1(op, t),
+            Term::Op2(op, t1, t2) => self.op2(op, t1, t2),
+            Term::Promise(ty, _, t) => (
+                annot("Promise(", &[pretty_types(ty), self.term(t, prec::TERM)]),
+                prec::ATOM,
+            ),
+            // A contract with a value has no syntax outside of a record field. It evaluates as an
+            // `Assume`.
+            Term::Assume(ty, _, t) | Term::ContractWithValue(ty, _, t) => (
+                annot("Assume(", &[pretty_types(ty), self.term(t, prec::TERM)]),
+                prec::ATOM,
+            ),
+            Term::Sym(i) => (format!("%sym{}%", i), prec::ATOM),
+            Term::Wrapped(i, t) => (
+                format!("%wrapped{}% {}", i, self.term(t, prec::ATOM)),
+                prec::APP,
+            ),
+            Term::Optional(t) => (
+                format!("%optional% {}", self.term(t, prec::ATOM)),
+                prec::APP,
+            ),
+            Term::MergeStrategy(s, t) => (
+                format!("%merge {}% {}", s, self.term(t, prec::ATOM)),
+                prec::APP,
+            ),
+            Term::Priority(p, t) => (format!("%{}% {}", p, self.term(t, prec::ATOM)), prec::APP),
+            Term::Contract(ty, _) => (format!("Contract({})", pretty_types(ty)), prec::ATOM),
+            Term::DefaultValue(t) => (annot("Default(", &[self.term(t, prec::TERM)]), prec::ATOM),
+            Term::ContractWithDefault(ty, _, t) => (
+                annot(
+                    "ContractDefault(",
+                    &[pretty_types(ty), self.term(t, prec::TERM)],
+                ),
+                prec::ATOM,
+            ),
+            Term::Docstring(s, t) => (
+                annot("Docstring(", &[str_literal(s), self.term(t, prec::TERM)]),
+                prec::ATOM,
+            ),
+            Term::Import(path) => (format!("import {}", str_literal(path)), prec::TERM),
+            Term::ResolvedImport(file_id) => (format!("%import{:?}%", file_id), prec::ATOM),
+        }
+    }
+
+    /// Print a let binding of a variable or a pattern, starting at `from` in the source. The
+    /// comments of the binding and the ones directly preceding it are printed before it, and the
+    /// ones between the binding and the body before the body.
+    fn let_in(&self, from: Option<usize>, binder: &str, t1: &RichTerm, t2: &RichTerm) -> String {
+        let bound = self.term(t1, prec::TERM);
+        let from = from.map(|from| self.preceding_comments(from));
+        let leading = comment_lines(&self.take_between(from, end(t1)));
+        let before_body = comment_lines(&self.take_between(end(t1), start(t2)));
+        let body = self.term(t2, prec::TERM);
+
+        if is_multiline(&bound) && !hangs(&bound) {
+            format!(
+                "{}let {} =\n{}{} in\n{}{}",
+                leading,
+                binder,
+                INDENT,
+                indent(&bound),
+                before_body,
+                body
+            )
+        } else {
+            format!(
+                "{}let {} = {} in\n{}{}",
+                leading, binder, bound, before_body, body
+            )
+        }
+    }
+
+    /// Print a recursive let binding. A group of several bindings is printed with one binding per
+    /// line.
+    fn let_rec(&self, from: Option<usize>, bindings: &[(Ident, RichTerm)], t: &RichTerm) -> String {
+        match bindings {
+            [(Ident(id), bound)] => self.let_in(from, &format!("rec {}", id), bound, t),
+            _ => {
+                let bound_end = bindings.last().and_then(|(_, bound)| end(bound));
+                let bindings: Vec<String> = bindings
+                    .iter()
+                    .map(|(Ident(id), bound)| {
+                        let bound = self.term(bound, prec::TERM);
+                        if is_multiline(&bound) && !hangs(&bound) {
+                            format!("{} =\n{}{}", id, INDENT, indent(&bound))
+                        } else {
+                            format!("{} = {}", id, bound)
+                        }
+                    })
+                    .map(|binding| format!("{}{}", INDENT, indent(&binding)))
+                    .collect();
+                let from = from.map(|from| self.preceding_comments(from));
+                let leading = comment_lines(&self.take_between(from, bound_end));
+                let before_body = comment_lines(&self.take_between(bound_end, start(t)));
+
+                format!(
+                    "{}let rec\n{}\nin\n{}{}",
+                    leading,
+                    bindings.join(",\n"),
+                    before_body,
+                    self.term(t, prec::TERM)
+                )
+            }
+        }
+    }
+
+    /// Print a destructuring pattern, on one line.
+    fn pattern(&self, pat: &Pattern) -> String {
+        match pat {
+            Pattern::Ident(Ident(id)) => id.clone(),
+            Pattern::Record(fields, open, _) => {
+                let mut items: Vec<String> = fields
+                    .iter()
+                    .map(|field| {
+                        let mut s = field.id.0.clone();
+                        if !matches!(&field.pattern, Pattern::Ident(id) if *id == field.id) {
+                            s.push_str(&format!(" = {}", self.pattern(&field.pattern)));
+                        }
+                        if let Some(default) = &field.default {
+                            s.push_str(&format!(" ? {}", self.term(default, prec::TERM)));
+                        }
+                        s
+                    })
+                    .collect();
+
+                if *open {
+                    items.push(String::from(".."));
+                }
+
+                format!("{{{}}}", items.join(", "))
+            }
+            Pattern::List(pats, _) => {
+                let items: Vec<String> = pats.iter().map(|pat| self.pattern(pat)).collect();
+                format!("[{}]", items.join(", "))
+            }
+        }
+    }
+
+    /// Print a match expression starting at `from` in the source. As for switch, each case is
+    /// followed by a comma, and the cases are printed one per line if they don't fit on a single
+    /// one or have comments.
+    fn match_(
+        &self,
+        from: Option<usize>,
+        cases: &[(MatchPattern, RichTerm)],
+        t: &RichTerm,
+    ) -> String {
+        let mut from = from;
+        let cases: Vec<Item> = cases
+            .iter()
+            .map(|(pat, body)| {
+                let pat = match pat {
+                    MatchPattern::Enum(Ident(id), None) => format!("`{}", id),
+                    MatchPattern::Enum(Ident(id), Some(pat)) => {
+                        format!("`{} {}", id, self.pattern(pat))
+                    }
+                    MatchPattern::Bool(b) => format!("{}", b),
+                    MatchPattern::Num(n) => num(*n).0,
+                    MatchPattern::Str(s) => str_literal(s),
+                    MatchPattern::Destruct(pat) => self.pattern(pat),
+                    MatchPattern::Wildcard => String::from("_"),
+                };
+                let case = format!("{} => {}", pat, self.term(body, prec::TERM));
+                self.item(&mut from, body, case)
+            })
+            .collect();
+
+        format!(
+            "{} {}",
+            cases_block("match", &cases),
+            self.term(t, prec::ATOM)
+        )
+    }
+
+    /// Print a string with interpolated expressions. The chunks are stored in reverse order.
+    fn str_chunks(&self, chunks: &[StrChunk<RichTerm>]) -> String {
+        if let Some(s) = self.multiline_str(chunks) {
+            return s;
+        }
+
+        let content: String = chunks
+            .iter()
+            .rev()
+            .map(|chunk| match chunk {
+                StrChunk::Literal(s) => escape(s),
+                StrChunk::Expr(t) => format!("${{{}}}", self.term(t, prec::TERM)),
+            })
+            .collect();
+
+        format!("\"{}\"", content)
+    }
+
+    /// Print a string containing several lines as a multi-line string `m#"..."#m`, with each line on
+    /// its own indented line. Return `None` if the string has only one line, or if it cannot be
+    /// represented as a multi-line string: when it contains the delimiter `"#m` or `${`, or when all
+    /// its lines are indented, as this indentation would then be stripped by the parser.
+    fn multiline_str(&self, chunks: &[StrChunk<RichTerm>]) -> Option<String> {
+        let literals = || {
+            chunks.iter().filter_map(|chunk| match chunk {
+                StrChunk::Literal(s) => Some(s),
+                StrChunk::Expr(_) => None,
+            })
+        };
+
+        if !literals().any(|s| s.contains('\n'))
+            || literals().any(|s| s.contains("\"#m") || s.contains("${"))
+        {
+            return None;
+        }
+
+        let mut result = String::from("m#\"\n");
+        let mut at_line_start = true;
+        let mut has_unindented_line = false;
+
+        for chunk in chunks.iter().rev() {
+            match chunk {
+                StrChunk::Literal(s) => {
+                    for (i, line) in s.split('\n').enumerate() {
+                        if i > 0 {
+                            result.push('\n');
+                            at_line_start = true;
+                        }
+
+                        if !line.is_empty() {
+                            if at_line_start {
+                                has_unindented_line |= !line.starts_with([' ', '\t']);
+                                result.push_str(INDENT);
+                            }
+                            result.push_str(line);
+                            at_line_start = false;
+                        }
+                    }
+                }
+                StrChunk::Expr(t) => {
+                    if at_line_start {
+                        has_unindented_line = true;
+                        result.push_str(INDENT);
+                    }
+                    result.push_str(&format!("${{{}}}", self.term(t, prec::TERM)));
+                    at_line_start = false;
+                }
+            }
+        }
+
+        result.push_str("\n\"#m");
+        Some(result).filter(|_| has_unindented_line)
+    }
+
+    /// Print a record literal `rec`, together with the dynamic fields added to it.
+    fn record(
+        &self,
+        rec: &RichTerm,
+        fields: &HashMap<Ident, RichTerm>,
+        dyn_fields: &[(&RichTerm, &RichTerm)],
+    ) -> String {
+        let mut from = start(rec);
+        let items: Vec<Item> = sorted(fields)
+            .into_iter()
+            .map(|(Ident(id), t)| (id.clone(), t))
+            .chain(
+                dyn_fields
+                    .iter()
+                    .map(|(id_t, t)| (format!("${}", self.term(id_t, prec::ATOM)), *t)),
+            )
+            .map(|(name, t)| {
+                let field = self.field(name, t);
+                self.item(&mut from, t, field)
+            })
+            .collect();
+        let dangling = self.take_between(from, end(rec));
+
+        block("{", &items, &dangling, ";", "}")
+    }
+
+    /// Print a record field. The documentation of the field, if any, is printed as doc comments.
+    /// Enriched values and contract checks of static fields are printed as annotations, such as
+    /// `port | Num | default = 80 | merge concat`.
+    fn field(&self, name: String, t: &RichTerm) -> String {
+        if name.starts_with('$') {
+            return match t.as_ref() {
+                Term::Docstring(doc, t) => format!("{}{}", doc_comments(doc), self.field(name, t)),
+                _ => format!("{} = {}", name, self.term(t, prec::TERM)),
+            };
+        }
+
+        let (optional, mut t) = match t.as_ref() {
+            Term::Optional(t) => (true, t),
+            _ => (false, t),
+        };
+        let mut docs = Vec::new();
+        while let Term::Docstring(doc, inner) = t.as_ref() {
+            docs.push(doc);
+            t = inner;
+        }
+        let strategy = match t.as_ref() {
+            Term::MergeStrategy(s, inner) => {
+                t = inner;
+                Some(s)
+            }
+            _ => None,
+        };
+        let priority = match t.as_ref() {
+            Term::Priority(p, inner) => {
+                t = inner;
+                Some(p)
+            }
+            _ => None,
+        };
+
+        let mut annots = Vec::new();
+        let value = match t.as_ref() {
+            Term::Contract(ty, _) => {
+                annots.push(pretty_types(ty));
+                None
+            }
+            Term::ContractWithDefault(ty, _, t) => {
+                annots.push(pretty_types(ty));
+                annots.push(format!("default = {}", self.term(t, prec::TERM)));
+                None
+            }
+            Term::DefaultValue(t) => {
+                annots.push(format!("default = {}", self.term(t, prec::TERM)));
+                None
+            }
+            Term::ContractWithValue(ty, _, t) => {
+                annots.push(pretty_types(ty));
+                Some(t)
+            }
+            _ => Some(t),
+        };
+
+        annots.extend(priority.map(MergePriority::to_string));
+        annots.extend(strategy.map(|s| format!("merge {}", s)));
+        // The first docstring is printed as doc comments, and the other ones as annotations.
+        let mut docs = docs.into_iter();
+        let comments = docs.next().map(|doc| doc_comments(doc)).unwrap_or_default();
+        annots.extend(docs.map(|doc| format!("doc {}", str_literal(doc))));
+        if optional {
+            // A field without annotations has the contract `Dyn`, which is then left implicit.
+            if let Term::Contract(Types(AbsType::Dyn()), _) = t.as_ref() {
+                annots.remove(0);
+            }
+            annots.push(String::from("optional"));
+        }
+
+        let annots: String = annots.iter().map(|a| format!(" | {}", a)).collect();
+        match value {
+            Some(t) => format!(
+                "{}{}{} = {}",
+                comments,
+                name,
+                annots,
+                self.term(t, prec::TERM)
+            ),
+            None => format!("{}{}{}", comments, name, annots),
+        }
+    }
+
+    fn app(&self, t1: &RichTerm, t2: &RichTerm) -> (String, u8) {
+        // `if b then t else e` is parsed as `(ite b t) e`, and `a && b` as `(and a) b`.
+        match t1.as_ref() {
+            Term::App(f, t) => {
+                if let Term::Op1(UnaryOp::Ite(), b) = f.as_ref() {
+                    return (self.ite(b, t, t2), prec::TERM);
+                }
+            }
+            Term::Op1(UnaryOp::BoolAnd(), t) => return self.infix("&&", prec::AND, t, t2),
+            Term::Op1(UnaryOp::BoolOr(), t) => return self.infix("||", prec::OR, t, t2),
+            _ => (),
+        }
+
+        (
+            format!("{} {}", self.term(t1, prec::APP), self.term(t2, prec::ATOM)),
+            prec::APP,
+        )
+    }
+
+    fn ite(&self, b: &RichTerm, t: &RichTerm, e: &RichTerm) -> String {
+        let b = self.term(b, prec::TERM);
+        let t = self.term(t, prec::TERM);
+        let e = self.term(e, prec::TERM);
+
+        let flat = format!("if {} then {} else {}", b, t, e);
+        if flat.len() <= WIDTH && !is_multiline(&flat) {
+            flat
+        } else {
+            format!(
+                "if {} then\n{}{}\nelse\n{}{}",
+                b,
+                INDENT,
+                indent(&t),
+                INDENT,
+                indent(&e)
+            )
+        }
+    }
+
+    /// Print a left-associative infix operator.
+    fn infix(&self, op: &str, level: u8, t1: &RichTerm, t2: &RichTerm) -> (String, u8) {
+        (
+            format!(
+                "{} {} {}",
+                self.term(t1, level),
+                op,
+                self.term(t2, level + 1)
+            ),
+            level,
+        )
+    }
+
+    /// Print a primitive operator applied to atoms.
+    fn prefix(&self, op: &str, args: &[&RichTerm]) -> (String, u8) {
+        let args: Vec<String> = args.iter().map(|t| self.term(t, prec::ATOM)).collect();
+        (format!("{} {}", op, args.join(" ")), prec::APP)
+    }
+
+    fn op1(&self, op: &UnaryOp<RichTerm>, t: &RichTerm) -> (String, u8) {
+        let keyword = match op {
+            UnaryOp::IsZero() => "isZero",
+            UnaryOp::IsNum() => "isNum",
+            UnaryOp::IsBool() => "isBool",
+            UnaryOp::IsStr() => "isStr",
+            UnaryOp::IsFun() => "isFun",
+            UnaryOp::IsList() => "isList",
+            UnaryOp::IsRecord() => "isRecord",
+            UnaryOp::Blame() => "blame",
+            UnaryOp::ChangePolarity() => "chngPol",
+            UnaryOp::Pol() => "polarity",
+            UnaryOp::GoDom() => "goDom",
+            UnaryOp::GoCodom() => "goCodom",
+            UnaryOp::Wrap() => "wrap",
+            UnaryOp::Seq() => "seq",
+            UnaryOp::DeepSeq(_) => "deepSeq",
+            UnaryOp::ListHead() => "head",
+            UnaryOp::ListTail() => "tail",
+            UnaryOp::ListLength() => "length",
+            UnaryOp::FieldsOf() => "fieldsOf",
+            UnaryOp::Payload() => "%payload%",
+            UnaryOp::MatchFailure() => "%matchFailure%",
+            UnaryOp::Ite() => "%ite%",
+            UnaryOp::BoolAnd() => "%and%",
+            UnaryOp::BoolOr() => "%or%",
+            UnaryOp::BoolNot() => return (format!("!{}", self.term(t, prec::NOT)), prec::NOT),
+            UnaryOp::StaticAccess(Ident(id)) => {
+                return (
+                    format!("{}.{}", self.term(t, prec::ACCESS), id),
+                    prec::ACCESS,
+                )
+            }
+            UnaryOp::Tag(s) => {
+                return (
+                    format!("tag {} {}", str_literal(s), self.term(t, prec::ATOM)),
+                    prec::APP,
+                )
+            }
+            UnaryOp::IsVariant(Ident(id)) => {
+                return (
+                    format!("%isVariant% {} {}", id, self.term(t, prec::ATOM)),
+                    prec::APP,
+                )
+            }
+            UnaryOp::CheckLength(n) => {
+                return (
+                    format!("%checkLength% {} {}", n, self.term(t, prec::ATOM)),
+                    prec::APP,
+                )
+            }
+            UnaryOp::CheckFields(ids) => {
+                let ids: Vec<&str> = ids.iter().map(|Ident(id)| id.as_str()).collect();
+                return (
+                    format!(
+                        "%checkFields% [{}] {}",
+                        ids.join(", "),
+                        self.term(t, prec::ATOM)
+                    ),
+                    prec::APP,
+                );
+            }
+            UnaryOp::Embed(Ident(id)) => {
+                return (
+                    format!("embed {} {}", id, self.term(t, prec::ATOM)),
+                    prec::APP,
+                )
+            }
+            UnaryOp::MapRec(f) => return self.prefix("mapRec", &[f, t]),
+            UnaryOp::Switch(cases, default) => {
+                let mut from = None;
+                let cases: Vec<Item> = sorted(cases)
+                    .into_iter()
+                    .map(|(Ident(id), t)| (id.as_str(), t))
+                    .chain(default.iter().map(|t| ("_", t)))
+                    .map(|(id, t)| {
+                        let case = format!("{} => {}", id, self.term(t, prec::ATOM));
+                        self.item(&mut from, t, case)
+                    })
+                    .collect();
+
+                return (
+                    format!(
+                        "{} {}",
+                        cases_block("switch", &cases),
+                        self.term(t, prec::ATOM)
+                    ),
+                    prec::APP,
+                );
+            }
+            UnaryOp::ChunksConcat(_, _) => "%chunksConcat%",
+        };
+
+        self.prefix(keyword, &[t])
+    }
+
+    fn op2(&self, op: &BinaryOp<RichTerm>, t1: &RichTerm, t2: &RichTerm) -> (String, u8) {
+        match op {
+            // The unary minus `-t` is parsed as `0 - t`.
+            // The negation of a number literal is parsed as a negative number, and must thus be
+            // printed as a subtraction.
+            BinaryOp::Sub()
+                if *t1.as_ref() == Term::Num(0.0) && !matches!(t2.as_ref(), Term::Num(_)) =>
+            {
+                (format!("-{}", self.term(t2, prec::NEG)), prec::NEG)
+            }
+            BinaryOp::Plus() => self.infix("+", prec::SUM, t1, t2),
+            BinaryOp::Sub() => self.infix("-", prec::SUM, t1, t2),
+            BinaryOp::Mult() => self.infix("*", prec::PRODUCT, t1, t2),
+            BinaryOp::Div() => self.infix("/", prec::PRODUCT, t1, t2),
+            BinaryOp::Modulo() => self.infix("%", prec::PRODUCT, t1, t2),
+            BinaryOp::PlusStr() => self.infix("++", prec::CONCAT, t1, t2),
+            BinaryOp::ListConcat() => self.infix("@", prec::CONCAT, t1, t2),
+            BinaryOp::Eq() => self.infix("==", prec::EQ, t1, t2),
+            BinaryOp::LessThan() => self.infix("<", prec::CMP, t1, t2),
+            BinaryOp::LessOrEq() => self.infix("<=", prec::CMP, t1, t2),
+            BinaryOp::GreaterThan() => self.infix(">", prec::CMP, t1, t2),
+            BinaryOp::GreaterOrEq() => self.infix(">=", prec::CMP, t1, t2),
+            BinaryOp::Unwrap() => self.prefix("unwrap", &[t1, t2]),
+            BinaryOp::GoField() => self.prefix("goField", &[t1, t2]),
+            BinaryOp::BlameWith() => self.prefix("blameWith", &[t1, t2]),
+            BinaryOp::HasField() => self.prefix("hasField", &[t1, t2]),
+            BinaryOp::HasOptionalField() => self.prefix("hasOptionalField", &[t1, t2]),
+            BinaryOp::SelectField() => self.prefix("selectField", &[t1, t2]),
+            BinaryOp::ListMap() => self.prefix("map", &[t1, t2]),
+            BinaryOp::ListElemAt() => self.prefix("elemAt", &[t1, t2]),
+            BinaryOp::Merge() => self.prefix("merge", &[t1, t2]),
+            BinaryOp::MergeWith(s, _) => self.prefix(&format!("%merge {}%", s), &[t1, t2]),
+            // `r.$x` is parsed as `DynAccess(x, r)`.
+            BinaryOp::DynAccess() => (
+                format!(
+                    "{}.${}",
+                    self.term(t2, prec::ACCESS),
+                    self.term(t1, prec::ATOM)
+                ),
+                prec::ACCESS,
+            ),
+            BinaryOp::DynRemove() => (
+                format!(
+                    "{}-${}",
+                    self.term(t2, prec::ACCESS),
+                    self.term(t1, prec::ATOM)
+                ),
+                prec::ACCESS,
+            ),
+            BinaryOp::DynExtend(_) => self.dyn_extend(op, t1, t2),
+        }
+    }
+
+    /// Print a dynamic extension `r$[x = t]`. The dynamic fields of a record literal `{$x = t}` are
+    /// parsed as extensions of the static part of the record, and are printed back as such.
+    fn dyn_extend(&self, op: &BinaryOp<RichTerm>, t1: &RichTerm, t2: &RichTerm) -> (String, u8) {
+        let mut dyn_fields = Vec::new();
+        let (mut op, mut id_t, mut r) = (op, t1, t2);
+        while let BinaryOp::DynExtend(t) = op {
+            dyn_fields.push((id_t, t));
+            match r.as_ref() {
+                Term::Op2(next_op @ BinaryOp::DynExtend(_), next_id, next_r) => {
+                    op = next_op;
+                    id_t = next_id;
+                    r = next_r;
+                }
+                _ => break,
+            }
+        }
+
+        if let Term::RecRecord(fields, _) = r.as_ref() {
+            // The innermost extension corresponds to the first dynamic field.
+            dyn_fields.reverse();
+            return (self.record(r, fields, &dyn_fields), prec::ATOM);
+        }
+
+        match op {
+            BinaryOp::DynExtend(t) => (
+                format!(
+                    "{}$[{} = {}]",
+                    self.term(t2, prec::ACCESS),
+                    self.term(t1, prec::TERM),
+                    self.term(t, prec::TERM)
+                ),
+                prec::ACCESS,
+            ),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Print a type at the given precedence level.
+fn types(ty: &Types, required: u8) -> String {
+    let (s, level) = match &ty.0 {
+        AbsType::Dyn() => (String::from("Dyn"), ty_prec::ATOM),
+        AbsType::Num() => (String::from("Num"), ty_prec::ATOM),
+        AbsType::Bool() => (String::from("Bool"), ty_prec::ATOM),
+        AbsType::Str() => (String::from("Str"), ty_prec::ATOM),
+        AbsType::List() => (String::from("List"), ty_prec::ATOM),
+        AbsType::Sym() => (String::from("%Sym%"), ty_prec::ATOM),
+        AbsType::Flat(t) => (
+            format!("#{}", Printer::default().term(t, prec::ATOM)),
+            ty_prec::ATOM,
+        ),
+        AbsType::Var(Ident(id)) => (id.clone(), ty_prec::ATOM),
+        AbsType::Forall(_, _) => {
+            let mut vars = Vec::new();
+            let mut body = ty;
+            while let Types(AbsType::Forall(Ident(id), t)) = body {
+                vars.push(id.as_str());
+                body = t;
+            }
+
+            (
+                format!("forall {}. {}", vars.join(" "), types(body, ty_prec::ARROW)),
+                ty_prec::FORALL,
+            )
+        }
+        AbsType::Arrow(dom, codom) => (
+            format!(
+                "{} -> {}",
+                types(dom, ty_prec::ATOM),
+                types(codom, ty_prec::ARROW)
+            ),
+            ty_prec::ARROW,
+        ),
+        // Outside of an enum or a record type, a row is assumed to be a record row if its first
+        // field has a type.
+        AbsType::RowEmpty() | AbsType::RowExtend(_, _, _) => (
+            row(ty, matches!(ty.0, AbsType::RowExtend(_, Some(_), _))),
+            ty_prec::ATOM,
+        ),
+        AbsType::Enum(r) if r.0.is_row_type() => (format!("<{}>", row(r, false)), ty_prec::ATOM),
+        AbsType::Enum(r) => (format!("<{}>", types(r, ty_prec::ATOM)), ty_prec::ATOM),
+        AbsType::StaticRecord(r) if r.0.is_row_type() => {
+            (format!("{{{}}}", row(r, true)), ty_prec::ATOM)
+        }
+        AbsType::StaticRecord(r) => (format!("{{{}}}", types(r, ty_prec::ATOM)), ty_prec::ATOM),
+        AbsType::DynRecord(ty) => (
+            format!("{{_: {}}}", types(ty, ty_prec::FORALL)),
+            ty_prec::ATOM,
+        ),
+    };
+
+    if level < required {
+        format!("({})", s)
+    } else {
+        s
+    }
+}
+
+/// Print a row type: `(| a, b: Num | r)` for the rows of enums, where only the tags of variants
+/// have a type, or `{| a: Num, b: Str | r}` for the rows of records.
+fn row(ty: &Types, is_record: bool) -> String {
+    let mut fields = Vec::new();
+    let mut tail = ty;
+    while let Types(AbsType::RowExtend(Ident(id), field_ty, t)) = tail {
+        match field_ty {
+            Some(field_ty) => fields.push(format!("{}: {}", id, types(field_ty, ty_prec::FORALL))),
+            None => fields.push(id.clone()),
+        }
+        tail = t;
+    }
+
+    let tail = match tail {
+        Types(AbsType::Var(Ident(id))) => format!(" {}", id),
+        _ => String::new(),
+    };
+    let (open, close) = if is_record { ("{", "}") } else { ("(", ")") };
+
+    if fields.is_empty() {
+        format!("{}| |{}{}", open, tail, close)
+    } else {
+        format!("{}| {} |{}{}", open, fields.join(", "), tail, close)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::label::Label;
+    use crate::parser::grammar::TermParser;
+    use crate::parser::lexer::Lexer;
+    use codespan::Files;
+    use std::fs;
+
+    fn try_parse(s: &str) -> Option<RichTerm> {
+        let id = Files::new().add("<test>", String::from(s));
+        TermParser::new()
+            .parse(id, &mut Vec::new(), Lexer::new(s))
+            .ok()
+    }
+
+    fn parse(s: &str) -> RichTerm {
+        try_parse(s).unwrap_or_else(|| panic!("could not parse {}", s))
+    }
+
+    /// Remove the positions of the flat types inside a type.
+    fn strip_types(ty: &mut Types) {
+        match &mut ty.0 {
+            AbsType::Flat(t) => strip(t),
+            AbsType::Arrow(ty1, ty2) => {
+                strip_types(ty1);
+                strip_types(ty2);
+            }
+            AbsType::RowExtend(_, ty1, ty2) => {
+                if let Some(ty1) = ty1 {
+                    strip_types(ty1);
+                }
+                strip_types(ty2);
+            }
+            AbsType::Forall(_, ty)
+            | AbsType::Enum(ty)
+            | AbsType::StaticRecord(ty)
+            | AbsType::DynRecord(ty) => strip_types(ty),
+            _ => (),
+        }
+    }
+
+    /// Remove the positions of a term and the labels of annotations, in order to compare the ASTs
+    /// of different sources.
+    fn strip(rt: &mut RichTerm) {
+        rt.pos = None;
+        match rt.term.as_mut() {
+            Term::Promise(ty, label, _)
+            | Term::Assume(ty, label, _)
+            | Term::Contract(ty, label)
+            | Term::ContractWithDefault(ty, label, _)
+            | Term::ContractWithValue(ty, label, _) => {
+                *label = Label::dummy();
+                strip_types(ty);
+            }
+            Term::FunPattern(pat, _) | Term::LetPattern(pat, _, _) => pat.clean_pos(),
+            Term::RecRecord(_, spans) => spans.clear(),
+            Term::Match(cases, _) => cases.iter_mut().for_each(|(pat, _)| {
+                if let MatchPattern::Destruct(pat) | MatchPattern::Enum(_, Some(pat)) = pat {
+                    pat.clean_pos()
+                }
+            }),
+            _ => (),
+        }
+        rt.term.apply_to_rich_terms(strip);
+    }
+
+    fn parse_stripped(s: &str) -> RichTerm {
+        let mut rt = parse(s);
+        strip(&mut rt);
+        rt
+    }
+
+    /// Assert that printing a parsed term and parsing it again gives back the same AST, and that
+    /// printing is idempotent.
+    fn assert_round_trip(s: &str) {
+        let printed = pretty(&parse(s));
+        assert_eq!(
+            parse_stripped(&printed),
+            parse_stripped(s),
+            "round trip failed for:\n{}\nprinted as:\n{}",
+            s,
+            printed
+        );
+        assert_eq!(pretty(&parse(&printed)), printed);
+    }
+
+    fn assert_pretty(s: &str, expected: &str) {
+        assert_eq!(pretty(&parse(s)), expected);
+        assert_round_trip(s);
+    }
+
+    /// The comments of a source, with their whitespace normalized.
+    fn comment_texts(s: &str) -> Vec<String> {
+        let mut texts: Vec<String> = lexer::comments(s)
+            .into_iter()
+            .map(|(start, end)| {
+                s[start..end]
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        texts.sort();
+        texts
+    }
+
+    /// Check that printing a source with its comments keeps its AST and all its comments, and
+    /// gives back the same text when printed again.
+    fn assert_comments_kept(s: &str) -> String {
+        let printed = pretty_with_comments(&parse(s), s);
+        assert_eq!(pretty(&parse(&printed)), pretty(&parse(s)));
+        assert_eq!(
+            comment_texts(&printed),
+            comment_texts(s),
+            "comments lost when printing:\n{}\nprinted as:\n{}",
+            s,
+            printed
+        );
+        assert_eq!(pretty_with_comments(&parse(&printed), &printed), printed);
+        printed
+    }
+
+    #[test]
+    fn basic() {
+        assert_pretty("fun   x y=>x+ y", "fun x y => x + y");
+        assert_pretty("let x=1 in x", "let x = 1 in\nx");
+        assert_pretty("if true then 1 else (2)", "if true then 1 else 2");
+        assert_pretty("[1,(1+1), `foo]", "[1, (1 + 1), `foo]");
+        assert_pretty("{a = 1; b = \"x\"}", "{a = 1; b = \"x\"}");
+        assert_pretty("{}", "{}");
+        assert_pretty("import \"a.ncl\"", "import \"a.ncl\"");
+    }
+
+    #[test]
+    fn precedence() {
+        assert_pretty("(1 + 2) * 3", "(1 + 2) * 3");
+        assert_pretty("1 + (2 * 3)", "1 + 2 * 3");
+        assert_pretty("1 - (2 - 3)", "1 - (2 - 3)");
+        assert_pretty("(1 - 2) - 3", "1 - 2 - 3");
+        assert_pretty("-(1 + 2)", "-(1 + 2)");
+        assert_pretty("- - 1", "1");
+        assert_pretty("- - x", "--x");
+        assert_pretty("0 - 1", "0 - 1");
+        assert_pretty("f (-1.5) - -2", "f (-1.5) - -2");
+        assert_pretty(
+            "[1e100, 0xff, (-1_000.5), 1e-7]",
+            "[1e100, 255, (-1000.5), 1e-7]",
+        );
+        assert_pretty("!(true && false) || true", "!(true && false) || true");
+        assert_pretty("true && (false || true)", "true && (false || true)");
+        assert_pretty("f (g x) (fun x => x)", "f (g x) (fun x => x)");
+        assert_pretty("(if true then f else g) 1", "(if true then f else g) 1");
+        assert_pretty("{a = {b = 1}}.a.b", "{a = {b = 1}}.a.b");
+        assert_pretty("(f x).a", "(f x).a");
+        assert_pretty("isZero (1 + 1)", "isZero (1 + 1)");
+        assert_pretty("merge {a = 1} (f x)", "merge {a = 1} (f x)");
+        assert_pretty("(import \"a.ncl\").x", "(import \"a.ncl\").x");
+    }
+
+    #[test]
+    fn strings() {
+        assert_pretty(r#""a\"b\\c\$d\te""#, r#""a\"b\\c\$d\te""#);
+        assert_pretty(
+            r#""a ${x + 1} b ${"c${y}"}""#,
+            r#""a ${x + 1} b ${"c${y}"}""#,
+        );
+        assert_pretty(r#""""#, r#""""#);
+        assert_pretty(
+            r#"Docstring("the \"doc\"", tag "t" x)"#,
+            r#"Docstring("the \"doc\"", tag "t" x)"#,
+        );
+    }
+
+    #[test]
+    fn multiline_strings() {
+        assert_pretty(
+            "{script = \"#!/bin/sh\\n\\n  echo \\\"\\$HOME\\\"\\n  ${cmd}\\n\"}",
+            "{\n  script = m#\"\n    #!/bin/sh\n\n      echo \"$HOME\"\n      ${cmd}\n\n  \"#m;\n}",
+        );
+        assert_pretty(
+            "m#\"\n  a\n    b ${x}\n  \"#m",
+            "m#\"\n  a\n    b ${x}\n\"#m",
+        );
+        assert_pretty(r##"r#"a\b"#"##, r#""a\\b""#);
+        assert_round_trip("\"  all\\n  indented\"");
+        assert_round_trip("\"first\\n\\t\\nlast\\n  \"");
+        assert_round_trip("\"a\\n${x}\\\\\\$ ${y}\\\"#m\"");
+        assert_round_trip("\"\\n\\n\"");
+        assert_round_trip("\"x\\n\\$${y}\\\"\"");
+    }
+
+    #[test]
+    fn types() {
+        assert_pretty("Promise(Num -> Num, f)", "Promise(Num -> Num, f)");
+        assert_pretty(
+            "Promise((Num -> Num) -> List, f)",
+            "Promise((Num -> Num) -> List, f)",
+        );
+        assert_pretty(
+            "Promise(forall a b. a -> (forall c. c -> b), f)",
+            "Promise(forall a b. a -> (forall c. c -> b), f)",
+        );
+        assert_pretty(
+            "Assume(< (| foo, bar |) > -> < (| baz | r) >, f)",
+            "Assume(<(| foo, bar |)> -> <(| baz | r)>, f)",
+        );
+        assert_pretty(
+            "Contract({ {| a: Num, b: Bool -> Str | r} })",
+            "Contract({{| a: Num, b: Bool -> Str | r}})",
+        );
+        assert_pretty("Contract({_: Num})", "Contract({_: Num})");
+        assert_pretty("Contract(#(fun l x => x))", "Contract(#(fun l x => x))");
+        assert_pretty(
+            "ContractDefault(#isPos, Default(1))",
+            "ContractDefault(#isPos, Default(1))",
+        );
+    }
+
+    #[test]
+    fn records() {
+        assert_pretty(
+            "{b = 1; a = 2; $x = 3; $(\"y\") = 4}",
+            "{b = 1; a = 2; $x = 3; $\"y\" = 4}",
+        );
+        assert_pretty("r$[x = 1].$y -$ z", "r$[x = 1].$y-$z");
+        assert_pretty(
+            "switch { foo => 1, bar => (2 + 2), _ => 3, } x",
+            "switch { foo => 1, bar => (2 + 2), _ => 3, } x",
+        );
+        assert_pretty(
+            "{a = {verylongfieldname = 1; anotherverylongfieldname = 2; yetanotherverylongname = 3}}",
+            "{\n  a = {\n    verylongfieldname = 1;\n    anotherverylongfieldname = 2;\n    yetanotherverylongname = 3;\n  };\n}",
+        );
+        assert_round_trip(
+            "[aVeryLongIdentifierToBreakTheLine, anotherVeryLongIdentifier, (1 + yetAnotherOne)]",
+        );
+    }
+
+    #[test]
+    fn patterns() {
+        assert_pretty(
+            "fun {a,b=c ?1,..} [x,y] z=>a",
+            "fun {a, b = c ? 1, ..} [x, y] z => a",
+        );
+        assert_pretty("let {a={b}}=r in b", "let {a = {b}} = r in\nb");
+        assert_pretty("fun {..} => 1", "fun {..} => 1");
+        assert_round_trip("let [{a ? x + 1}, y] = l in fun {b = [c]} => a + c");
+    }
+
+    #[test]
+    fn matches() {
+        assert_pretty(
+            "match{`a=>1,-2=>\"b\",{x,..}=>x,_=>false}y",
+            "match { `a => 1, -2 => \"b\", {x, ..} => x, _ => false, } y",
+        );
+        assert_pretty("f (match {true => 1} x)", "f (match { true => 1, } x)");
+        assert_round_trip(
+            "match { `veryLongTagName => anotherVeryLongIdentifier, [a, b] => a + b, other => fun x => x } value",
+        );
+        assert_pretty(
+            "match{`a{x}=>x,`b=>0}(`a{x=1})",
+            "match { `a {x} => x, `b => 0, } (`a {x = 1})",
+        );
+        assert_pretty("[(`a (f x)),`b]", "[(`a (f x)), `b]");
+    }
+
+    #[test]
+    fn recursive_lets() {
+        assert_pretty(
+            "let rec f=fun x=>f x in f",
+            "let rec f = fun x => f x in\nf",
+        );
+        assert_pretty(
+            "let rec a=b,b=[a] in a",
+            "let rec\n  a = b,\n  b = [a]\nin\na",
+        );
+        assert_round_trip(
+            "let rec f = {veryLongFieldName = anotherVeryLongIdentifier; yetAnotherField = g}, g = f in f",
+        );
+    }
+
+    #[test]
+    fn doc_comments() {
+        assert_pretty(
+            "{a = Docstring(\"The a field.\n\nIt is \\\"important\\\".\", 1); b = 2}",
+            "{\n  /// The a field.\n  ///\n  /// It is \"important\".\n  a = 1;\n  b = 2;\n}",
+        );
+        assert_pretty(
+            "{/// outer\nx = {/// inner\n$y = 1}}",
+            "{\n  /// outer\n  x = {\n    /// inner\n    $y = 1;\n  };\n}",
+        );
+        assert_pretty(
+            "Docstring(\"not a field\", 1)",
+            "Docstring(\"not a field\", 1)",
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            assert_comments_kept("// header\nlet x = 1 in // x\n{a = x; // a\n /* b */ b = 2}"),
+            "// header\nlet x = 1 in\n// x\n{\n  a = x; // a\n  /* b */\n  b = 2;\n}"
+        );
+        assert_eq!(
+            assert_comments_kept("[1, // one\n2\n// end\n]"),
+            "[\n  1, // one\n  2,\n  // end\n]"
+        );
+        assert_eq!(
+            assert_comments_kept("{a = 1 + /* inner */ 2}"),
+            "{\n  /* inner */\n  a = 1 + 2;\n}"
+        );
+        assert_eq!(
+            assert_comments_kept("match {\n  // first\n  `a => 1,\n  `b => 2, // second\n} x"),
+            "match {\n  // first\n  `a => 1,\n  `b => 2, // second\n} x"
+        );
+        assert_eq!(
+            assert_comments_kept("{\n  a = {\n    /* block\n       comment */\n    b = 1\n  }\n}"),
+            "{\n  a = {\n    /* block\n       comment */\n    b = 1;\n  };\n}"
+        );
+        assert_eq!(assert_comments_kept("1 + /* one */ 1"), "1 + 1\n/* one */");
+        assert_comments_kept("{/// doc\n// comment\na.b = 1; a.c = 2 // c\n}");
+        assert_comments_kept("let rec f = 1, // f\n g = 2 in\n// body\nf");
+    }
+
+    #[test]
+    fn field_annotations() {
+        assert_pretty(
+            "{port|Num|default=80|doc \"The port\"; host|Str|optional; name|Str=\"a\"}",
+            "{\n  /// The port\n  port | Num | default = 80;\n  host | Str | optional;\n  name | Str = \"a\";\n}",
+        );
+        assert_pretty(
+            "{/// doc\na|optional; b = Contract(Dyn)}",
+            "{\n  /// doc\n  a | optional;\n  b | Dyn;\n}",
+        );
+        assert_pretty(
+            "{a | doc \"x\" | doc \"y\" = 1}",
+            "{\n  /// x\n  a | doc \"y\" = 1;\n}",
+        );
+        assert_pretty(
+            "{l|merge concat|List|optional; m|merge elementwise=[1]}",
+            "{l | List | merge concat | optional; m | merge elementwise = [1]}",
+        );
+        assert_pretty(
+            "{a|Num|priority -1.5=1; b|force|merge concat=[2]}",
+            "{a | Num | priority -1.5 = 1; b | force | merge concat = [2]}",
+        );
+    }
+
+    #[test]
+    fn layout() {
+        assert_pretty(
+            "let f = fun x => let y = x in y in f",
+            "let f = fun x =>\n  let y = x in\n  y in\nf",
+        );
+        assert_pretty(
+            "if aVeryLongConditionName then aVeryLongThenBranchName else aVeryLongElseBranchName",
+            "if aVeryLongConditionName then\n  aVeryLongThenBranchName\nelse\n  aVeryLongElseBranchName",
+        );
+    }
+
+    #[test]
+    fn examples() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/examples");
+        let mut count = 0;
+
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "ncl") {
+                continue;
+            }
+
+            let source = fs::read_to_string(&path).unwrap();
+            assert!(
+                try_parse(&source).is_some(),
+                "example {} does not parse",
+                path.display()
+            );
+            assert_round_trip(&source);
+            assert_comments_kept(&source);
+            count += 1;
+        }
+
+        assert!(count > 0);
+        for stdlib in &["contracts.ncl", "lists.ncl"] {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/stdlib/").to_owned() + stdlib;
+            assert_comments_kept(&fs::read_to_string(path).unwrap());
+        }
+    }
+}
@@ -8,7 +8,7 @@
 //! Some essential functions required for evaluation, such as builtin contracts, are written in
 //! pure Nickel. Standard library files must be record literals:
 //!
-//! ```
+//! ```text
 //! {
 //!     val1 = ...
 //!     val2 = ...
@@ -21,16 +21,23 @@
 //! embedded strings are then parsed by the functions in this module (see
 //! [`mk_global_env`](./struct.Program.html#method.mk_global_env)).  Each such value is added to
 //! the global environment before the evaluation of the program.
-use crate::error::{Error, ImportError, ParseError, ToDiagnostic};
-use crate::eval;
+use crate::doc::{self, FieldDoc};
+use crate::error::{Error, EvalError, ImportError, ParseError, ToDiagnostic};
+use crate::eval::{self, Closure, Environment, FieldDef};
+use crate::identifier::Ident;
+use crate::label::access_path;
 use crate::parser;
+use crate::parser::data::InputFormat;
 use crate::parser::lexer::Lexer;
 use crate::position::RawSpan;
-use crate::term::{RichTerm, Term};
+use crate::pretty;
+use crate::term::{MergePriority, RichTerm, Term, UnaryOp};
 use crate::transformations;
-use crate::typecheck::type_check;
+use crate::typecheck::{infer_type, type_check, type_check_with_table};
+use crate::types::{AbsType, Types};
 use codespan::{FileId, Files};
-use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use codespan_reporting::diagnostic::Diagnostic;
+use codespan_reporting::term::termcolor::{ColorChoice, StandardStream, WriteColor};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ffi::OsString;
@@ -54,6 +61,128 @@ pub struct Program {
     file_cache: HashMap<String, FileId>,
     /// Cache storing parsed terms corresponding to the entries of the file database.
     term_cache: HashMap<FileId, RichTerm>,
+    /// The binders of the parsed files, with their position, as recorded by the parser.
+    binders: HashMap<FileId, Vec<(Ident, RawSpan)>>,
+    /// Whether to use colors when reporting errors on the standard error output.
+    color_opt: ColorChoice,
+}
+
+/// The result of a [query](struct.Program.html#method.query): the value of a field together with
+/// its metadata.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct QueryResult {
+    /// The documentation of the field, given by a `Docstring`.
+    pub doc: Option<String>,
+    /// The type of the contract attached to the field.
+    pub contract: Option<Types>,
+    /// The fully evaluated default value of the field.
+    pub default: Option<Term>,
+    /// Whether the field is optional, that is, may be absent.
+    pub optional: bool,
+    /// The fully evaluated value of the field, or `None` if the field only holds metadata, such as
+    /// a contract without a value, or if the value is a record.
+    pub value: Option<Term>,
+    /// The fields of the value of the field if it is a record, together with their metadata. The
+    /// fields of a record value are not evaluated further.
+    pub fields: Option<Vec<FieldDoc>>,
+    /// The positions of the definitions of the field which are in effect: the definitions of the
+    /// value, or the default values if there is no value. There are several of them when they
+    /// have been merged together.
+    pub definitions: Vec<RawSpan>,
+    /// The positions of the definitions of the field overridden by merge: the default values, and
+    /// the definitions of a lower priority.
+    pub overridden: Vec<RawSpan>,
+}
+
+/// The kind of a definition of a field, which determines if it is overridden by merge.
+#[derive(Clone, Copy, PartialEq)]
+enum DefKind {
+    Contract,
+    Default,
+    Value(MergePriority),
+}
+
+/// Determine the kind and the position of a definition without evaluating it. Variables are
+/// followed through the content of their thunk or of their `let` binding, and a term which is not
+/// an enriched value is a definition with the normal priority. Enriched values built from a field
+/// annotation may not have a position, in which case the position of the first inner term which
+/// has one is used.
+fn def_kind<'a>(
+    t: &'a RichTerm,
+    env: &Environment,
+    lets: &mut Vec<(&'a Ident, &'a RichTerm)>,
+    visited: &mut Vec<Rc<RefCell<Closure>>>,
+) -> (DefKind, Option<RawSpan>) {
+    let (kind, inner_pos) = match t.as_ref() {
+        Term::Docstring(_, t) | Term::MergeStrategy(_, t) | Term::Optional(t) => {
+            def_kind(t, env, lets, visited)
+        }
+        // The share normal form transformation puts the content of enriched values in a `let`.
+        Term::Let(id, bound, t) => {
+            lets.push((id, bound));
+            def_kind(t, env, lets, visited)
+        }
+        Term::Priority(p, t) => (DefKind::Value(*p), def_kind(t, env, lets, visited).1),
+        Term::DefaultValue(_) | Term::ContractWithDefault(_, _, _) => (DefKind::Default, None),
+        Term::Contract(_, _) => (DefKind::Contract, None),
+        Term::Var(id) => match lets.iter().rposition(|(bound_id, _)| *bound_id == id) {
+            Some(i) => {
+                let bound = lets[i].1;
+                def_kind(bound, env, &mut lets[..i].to_vec(), visited)
+            }
+            None => match env.get(id) {
+                Some((thunk, _, _)) if !visited.iter().any(|rc| Rc::ptr_eq(rc, thunk)) => {
+                    visited.push(thunk.clone());
+                    let clos = thunk.borrow();
+                    def_kind(&clos.body, &clos.env, &mut Vec::new(), visited)
+                }
+                _ => (DefKind::Value(MergePriority::NORMAL), None),
+            },
+        },
+        _ => (DefKind::Value(MergePriority::NORMAL), None),
+    };
+
+    (kind, t.pos.clone().or(inner_pos))
+}
+
+impl QueryResult {
+    /// Fill the provenance of a field from its definition: among the definitions merged into
+    /// the field, the ones of the highest priority win, and override the others as well as the
+    /// default values. Without any such definition, the default values are in effect.
+    fn provenance(&mut self, def: &FieldDef) {
+        let kinds: Vec<(DefKind, Option<RawSpan>)> = def
+            .definitions()
+            .into_iter()
+            .map(|clos| def_kind(&clos.body, &clos.env, &mut Vec::new(), &mut Vec::new()))
+            .collect();
+
+        let top = kinds
+            .iter()
+            .filter_map(|(kind, _)| match kind {
+                DefKind::Value(p) => Some(*p),
+                _ => None,
+            })
+            .fold(None, |top: Option<MergePriority>, p| match top {
+                Some(q) if q >= p => Some(q),
+                _ => Some(p),
+            });
+
+        for (kind, pos) in kinds {
+            let pos = match pos {
+                Some(pos) => pos,
+                None => continue,
+            };
+
+            match (kind, top) {
+                (DefKind::Value(p), Some(top)) if p == top => self.definitions.push(pos),
+                (DefKind::Value(_), Some(_)) | (DefKind::Default, Some(_)) => {
+                    self.overridden.push(pos)
+                }
+                (DefKind::Default, None) => self.definitions.push(pos),
+                _ => (),
+            }
+        }
+    }
 }
 
 /// Return status indicating if an import has been resolved from a file (first encounter), or was
@@ -95,7 +224,7 @@ pub trait ImportResolver {
     /// processing.
     fn resolve(
         &mut self,
-        path: &String,
+        path: &str,
         parent: Option<PathBuf>,
         pos: &Option<RawSpan>,
     ) -> Result<(ResolvedTerm, FileId), ImportError>;
@@ -107,7 +236,7 @@ pub trait ImportResolver {
     fn get(&self, file_id: FileId) -> Option<RichTerm>;
 
     /// Get a file id from the file cache.
-    fn get_id(&self, path: &String, parent: Option<PathBuf>) -> Option<FileId>;
+    fn get_id(&self, path: &str, parent: Option<PathBuf>) -> Option<FileId>;
 }
 
 impl Program {
@@ -123,22 +252,55 @@ impl Program {
     }
 
     /// Create a program by reading it from a generic source.
-    fn new_from_source<T: Read>(
+    pub fn new_from_source<T: Read>(
         mut source: T,
         source_name: impl Into<OsString>,
     ) -> std::io::Result<Program> {
         let mut buffer = String::new();
-        let mut files = Files::<String>::new();
-
         source.read_to_string(&mut buffer)?;
-        let main_id = files.add(source_name, buffer);
 
-        Ok(Program {
+        Ok(Program::new_from_str(buffer, source_name))
+    }
+
+    /// Create a program from a string.
+    pub fn new_from_str(source: impl Into<String>, source_name: impl Into<OsString>) -> Program {
+        let mut files = Files::<String>::new();
+        let main_id = files.add(source_name, source.into());
+
+        Program {
             main_id,
             files,
             file_cache: HashMap::new(),
             term_cache: HashMap::new(),
-        })
+            binders: HashMap::new(),
+            color_opt: ColorChoice::Auto,
+        }
+    }
+
+    /// Set the color policy used by [`report`](#method.report). Default to `ColorChoice::Auto`.
+    pub fn set_color(&mut self, color_opt: ColorChoice) {
+        self.color_opt = color_opt;
+    }
+
+    /// Return the id of the program source in the file database.
+    pub fn main_id(&self) -> FileId {
+        self.main_id
+    }
+
+    /// Return the file database, which holds the program source and the content of imported
+    /// files. Required to render the diagnostics returned by
+    /// [`diagnostics`](#method.diagnostics).
+    pub fn files(&self) -> &Files<String> {
+        &self.files
+    }
+
+    /// Add a source to the file database, and return its id.
+    pub(crate) fn add_source(
+        &mut self,
+        source_name: impl Into<OsString>,
+        source: impl Into<String>,
+    ) -> FileId {
+        self.files.add(source_name, source.into())
     }
 
     /// Load a part of the Nickel standard library in the given global environment.
@@ -161,7 +323,7 @@ impl Program {
             .map_err(|err| ImportError::ParseError(err, None))?;
 
         match *rt.term {
-            Term::Record(bindings) | Term::RecRecord(bindings) => {
+            Term::Record(bindings) | Term::RecRecord(bindings, _) => {
                 let ext = bindings.into_iter().map(|(id, t)| {
                     let closure = eval::Closure {
                         body: t,
@@ -169,7 +331,11 @@ impl Program {
                     };
                     (
                         id,
-                        (Rc::new(RefCell::new(closure)), eval::IdentKind::Record()),
+                        (
+                            Rc::new(RefCell::new(closure)),
+                            eval::IdentKind::Record(),
+                            None,
+                        ),
                     )
                 });
                 global_env.extend(ext);
@@ -184,7 +350,7 @@ impl Program {
     }
 
     /// Generate a global environment with values from the standard library parts.
-    fn mk_global_env(&mut self) -> Result<eval::Environment, Error> {
+    pub(crate) fn mk_global_env(&mut self) -> Result<eval::Environment, Error> {
         let mut global_env = HashMap::new();
 
         self.load_stdlib(
@@ -192,18 +358,18 @@ impl Program {
             crate::stdlib::CONTRACTS,
             &mut global_env,
         )
-        .map_err(|e| Error::from(e))?;
+        .map_err(Error::from)?;
         self.load_stdlib("<stdlib/lists.ncl>", crate::stdlib::LISTS, &mut global_env)
             .map_err(Error::from)?;
 
         // Typecheck each entry of the global environment (may be removed later, but as long as the
         // standard library is unstable, this is useful for debugging purpose)
-        global_env
-            .values()
-            .try_for_each(|(rc, _)| type_check(&rc.borrow().body, &global_env, self).map(|_| ()))?;
+        global_env.values().try_for_each(|(rc, _, _)| {
+            type_check(&rc.borrow().body, &global_env, self).map(|_| ())
+        })?;
 
         // After typechecking, we have to apply standard tranformations as well
-        global_env.values_mut().try_for_each(|(rc, _)| -> Result<(), ImportError> {
+        global_env.values_mut().try_for_each(|(rc, _, _)| -> Result<(), ImportError> {
             match Rc::get_mut(rc) {
                 Some(c) => {
                     // Temporarily replacing with a dummy closure to pass the term to transform()
@@ -226,24 +392,288 @@ impl Program {
         Ok(global_env)
     }
 
+    /// Parse if necessary and typecheck the program. Return the inferred type.
+    ///
+    /// The program is typechecked in non-strict mode, where the type of an unannotated program is
+    /// just `Dyn`. In this case, the type of the program is inferred in strict mode instead (see
+    /// [`infer_type`](../typecheck/fn.infer_type.html)), if it is well-typed in this mode.
+    pub fn typecheck(&mut self) -> Result<Types, Error> {
+        let t = self.parse_with_cache(self.main_id)?;
+        let global_env = self.mk_global_env()?;
+        match type_check(&t, &global_env, self)? {
+            Types(AbsType::Dyn()) => {
+                Ok(infer_type(&t, &global_env, self).unwrap_or(Types(AbsType::Dyn())))
+            }
+            ty => Ok(ty),
+        }
+    }
+
+    /// Parse if necessary and return the term of the program, as produced by the parser.
+    pub fn parse_term(&mut self) -> Result<RichTerm, Error> {
+        self.parse_with_cache(self.main_id).map_err(Error::from)
+    }
+
+    /// Parse if necessary and return the binders of the program, that is the identifiers
+    /// introduced by a `let`, a function or a pattern, with their position.
+    pub fn binders(&mut self) -> Result<&[(Ident, RawSpan)], Error> {
+        self.parse_with_cache(self.main_id)?;
+        Ok(self
+            .binders
+            .get(&self.main_id)
+            .map_or(&[], |binders| binders.as_slice()))
+    }
+
+    /// Parse the program and print it back in the canonical layout of the [pretty
+    /// module](../pretty/index.html), followed by a newline.
+    ///
+    /// Line and block comments are not part of the AST: they are retrieved from the source and
+    /// printed next to the closest item, such as a record field (see
+    /// [`pretty_with_comments`](../pretty/fn.pretty_with_comments.html)).
+    pub fn format(&mut self) -> Result<String, Error> {
+        let t = self.parse_term()?;
+        let source = self.files.source(self.main_id);
+        Ok(format!("{}\n", pretty::pretty_with_comments(&t, source)))
+    }
+
+    /// Parse if necessary and typecheck the program. Return the inferred type together with the
+    /// type of each subterm which has a position (see
+    /// [`type_check_with_table`](../typecheck/fn.type_check_with_table.html)).
+    pub fn typecheck_with_table(&mut self) -> Result<(Types, Vec<(RawSpan, Types)>), Error> {
+        let t = self.parse_with_cache(self.main_id)?;
+        let global_env = self.mk_global_env()?;
+        type_check_with_table(&t, &global_env, self).map_err(Error::from)
+    }
+
+    /// Parse if necessary, typecheck and apply the program transformations. Return the
+    /// transformed term together with the global environment, ready to be evaluated.
+    fn prepare_eval(&mut self) -> Result<(RichTerm, eval::Environment), Error> {
+        let t = self.parse_with_cache(self.main_id)?;
+        let global_env = self.mk_global_env()?;
+        type_check(&t, &global_env, self)?;
+        let t = transformations::transform(t, self)?;
+        Ok((t, global_env))
+    }
+
     /// Parse if necessary, typecheck and then evaluate the program.
+    ///
+    /// The evaluation stops at the first weak head normal form. In particular, the fields of a
+    /// record are not evaluated: see [`eval_full`](#method.eval_full).
     pub fn eval(&mut self) -> Result<Term, Error> {
-        let t = self
-            .parse_with_cache(self.main_id)
-            .map_err(|e| Error::from(e))?;
-        let global_env = self.mk_global_env()?;
-        type_check(&t, &global_env, self).map_err(|err| Error::from(err))?;
-        let t = transformations::transform(t, self).map_err(|err| Error::ImportError(err))?;
-        eval::eval(t, global_env, self).map_err(|e| e.into())
+        let (t, global_env) = self.prepare_eval()?;
+        eval::eval(t, global_env, self).map_err(Error::from)
+    }
+
+    /// Parse if necessary, typecheck and then fully evaluate the program, including the content
+    /// of records and lists.
+    ///
+    /// The result can be converted to a Rust value via the [deserialize
+    /// module](../deserialize/index.html).
+    pub fn eval_full(&mut self) -> Result<RichTerm, Error> {
+        let (t, global_env) = self.prepare_eval()?;
+        eval::eval_full(t, global_env, self).map_err(Error::from)
+    }
+
+    /// Query the value and the metadata of a field of the program.
+    ///
+    /// The program is evaluated only along the path, by successive field accesses. The field is
+    /// then evaluated without forcing enriched values, so that its documentation, contract and
+    /// default value can be retrieved. Finally, its value and its default value are fully
+    /// evaluated, unless the value is a record: its fields are then only summarized with their
+    /// metadata (see [`doc::summary`](../doc/fn.summary.html)). An empty path designates the
+    /// whole program.
+    ///
+    /// The provenance of the field is retrieved from the definitions kept by the record which
+    /// contains it, such that the definitions which have been merged into the field can be
+    /// traced back to their source.
+    pub fn query(&mut self, path: &[Ident]) -> Result<QueryResult, Error> {
+        let (t, global_env) = self.prepare_eval()?;
+        let mut result = QueryResult::default();
+        let mut clos = Closure::atomic_closure(t);
+        // The position of the definition of the last field of the path accessed so far, used to
+        // locate a missing field when the record has no position, as the result of a merge.
+        let mut def_pos = None;
+
+        for (i, field) in path.iter().enumerate() {
+            let (rt, env) = eval::eval_whnf(clos, &global_env, self)?;
+            let is_last = i + 1 == path.len();
+
+            clos = match *rt.term {
+                // An optional field has no value to access, but its metadata can be queried.
+                Term::Record(mut map) => match map.remove(field) {
+                    Some(t) if is_last || !eval::is_optional_field(&t, &env) => {
+                        let def = FieldDef::of(t.clone(), &env);
+                        if is_last {
+                            result.provenance(&def);
+                        }
+                        def_pos = def
+                            .definitions()
+                            .into_iter()
+                            .find_map(|clos| {
+                                def_kind(&clos.body, &clos.env, &mut Vec::new(), &mut Vec::new()).1
+                            })
+                            .or(def_pos);
+                        Closure { body: t, env }
+                    }
+                    t_opt => {
+                        map.extend(t_opt.map(|t| (field.clone(), t)));
+                        return Err(Error::from(EvalError::FieldMissing(
+                            field.0.clone(),
+                            String::from("(.)"),
+                            RichTerm {
+                                term: Box::new(Term::Record(map)),
+                                pos: rt.pos.or(def_pos),
+                            },
+                            None,
+                        )));
+                    }
+                },
+                // Let the field access report the type error.
+                t => Closure {
+                    body: Term::Op1(
+                        UnaryOp::StaticAccess(field.clone()),
+                        RichTerm {
+                            term: Box::new(t),
+                            pos: rt.pos,
+                        },
+                    )
+                    .into(),
+                    env,
+                },
+            };
+        }
+
+        self.query_value(clos, &global_env, &mut result)
+            .map_err(|err| match err {
+                // The access path of the value is relative to the queried field
+                EvalError::BlameError(mut l, call_stack) => {
+                    let mut access_path: access_path::Path =
+                        path.iter().cloned().map(access_path::Elem::Field).collect();
+                    access_path.append(&mut l.access_path);
+                    l.access_path = access_path;
+                    EvalError::BlameError(l, call_stack)
+                }
+                err => err,
+            })?;
+
+        Ok(result)
+    }
+
+    /// Evaluate a queried field, collecting its metadata in `result` until its value is reached.
+    fn query_value(
+        &mut self,
+        mut clos: Closure,
+        global_env: &Environment,
+        result: &mut QueryResult,
+    ) -> Result<(), EvalError> {
+        loop {
+            let (rt, env) = eval::eval_meta(clos, global_env, self)?;
+
+            match *rt.term {
+                Term::Docstring(s, t) => {
+                    result.doc.get_or_insert(s);
+                    clos = Closure { body: t, env };
+                }
+                Term::Optional(t) => {
+                    result.optional = true;
+                    clos = Closure { body: t, env };
+                }
+                Term::MergeStrategy(_, t) | Term::Priority(_, t) => clos = Closure { body: t, env },
+                Term::Contract(ty, _) => {
+                    result.contract = Some(ty);
+                    return Ok(());
+                }
+                Term::DefaultValue(t) => {
+                    self.query_result_value(Closure { body: t, env }, global_env, result)?;
+                    result.default = result.value.clone();
+                    return Ok(());
+                }
+                Term::ContractWithDefault(ty, label, t) => {
+                    let checked = Term::Assume(ty.clone(), label, t.clone()).into();
+                    self.query_result_value(
+                        Closure {
+                            body: checked,
+                            env: env.clone(),
+                        },
+                        global_env,
+                        result,
+                    )?;
+                    if result.value.is_some() {
+                        result.default = Some(self.eval_full_in(t, env, global_env)?);
+                    }
+                    result.contract = Some(ty);
+                    return Ok(());
+                }
+                Term::ContractWithValue(ty, label, t) => {
+                    let checked = Term::Assume(ty.clone(), label, t).into();
+                    self.query_result_value(Closure { body: checked, env }, global_env, result)?;
+                    result.contract = Some(ty);
+                    return Ok(());
+                }
+                t => {
+                    let t = RichTerm {
+                        term: Box::new(t),
+                        pos: rt.pos,
+                    };
+                    return self.query_result_value(Closure { body: t, env }, global_env, result);
+                }
+            }
+        }
+    }
+
+    /// Evaluate the value of a queried field. A record is only evaluated to a weak head normal
+    /// form, and its fields are summarized in `result`. Other values are fully evaluated.
+    fn query_result_value(
+        &mut self,
+        clos: Closure,
+        global_env: &Environment,
+        result: &mut QueryResult,
+    ) -> Result<(), EvalError> {
+        let (rt, env) = eval::eval_whnf(clos, global_env, self)?;
+
+        match *rt.term {
+            Term::Record(map) => result.fields = Some(doc::summary(map, env, global_env, self)?),
+            t => {
+                let t = RichTerm {
+                    term: Box::new(t),
+                    pos: rt.pos,
+                };
+                result.value = Some(self.eval_full_in(t, env, global_env)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fully evaluate a term in the environment `env` extended with the global environment.
+    fn eval_full_in(
+        &mut self,
+        t: RichTerm,
+        env: Environment,
+        global_env: &Environment,
+    ) -> Result<Term, EvalError> {
+        let mut env_full = global_env.clone();
+        env_full.extend(env);
+        eval::eval_full(t, env_full, self).map(|rt| *rt.term)
+    }
+
+    /// Extract the documentation of the fields of the program, which must evaluate to a record.
+    ///
+    /// See the [doc module](../doc/index.html).
+    pub fn doc(&mut self) -> Result<Vec<FieldDoc>, Error> {
+        let (t, global_env) = self.prepare_eval()?;
+        doc::extract(t, &global_env, self).map_err(Error::from)
     }
 
     /// Parse a source file. Do not try to get it from the cache, and do not populate the cache at
     /// the end either.
     fn parse(&mut self, file_id: FileId) -> Result<RichTerm, ParseError> {
         let buf = self.files.source(file_id).clone();
-        parser::grammar::TermParser::new()
-            .parse(file_id, Lexer::new(&buf))
-            .map_err(|err| ParseError::from_lalrpop(err, file_id))
+        let mut binders = Vec::new();
+        let t = parser::grammar::TermParser::new()
+            .parse(file_id, &mut binders, Lexer::new(&buf))
+            .map_err(|err| ParseError::from_lalrpop(err, file_id))?;
+        self.binders.insert(file_id, binders);
+        Ok(t)
     }
 
     /// Parse a source file and populate the corresponding entry in the cache, or just get it from
@@ -259,35 +689,44 @@ impl Program {
         })
     }
 
-    /// Pretty-print an error.
+    /// Convert an error to a list of diagnostics, which can be rendered using the file database
+    /// returned by [`files`](#method.files).
     ///
     /// This function is located here in `Program` because errors need a reference to `files` in
     /// order to produce a diagnostic (see [`label_alt`](../error/fn.label_alt.html)).
-    pub fn report(&mut self, error: Error) {
-        let writer = StandardStream::stderr(ColorChoice::Always);
+    pub fn diagnostics(&mut self, error: &Error) -> Vec<Diagnostic<FileId>> {
+        let contract_id = self.file_cache.get("<stdlib/contracts.ncl>").copied();
+        error.to_diagnostic(&mut self.files, contract_id)
+    }
+
+    /// Pretty-print an error on the given writer.
+    pub fn report_to(&mut self, error: &Error, writer: &mut dyn WriteColor) -> io::Result<()> {
         let config = codespan_reporting::term::Config::default();
-        let diagnostics = error.to_diagnostic(
-            &mut self.files,
-            self.file_cache.get("<stdlib/contracts.ncl>").copied(),
-        );
+        let diagnostics = self.diagnostics(error);
 
-        let result = diagnostics.iter().try_for_each(|d| {
-            codespan_reporting::term::emit(&mut writer.lock(), &config, &self.files, &d)
-        });
-        match result {
-            Ok(()) => (),
-            Err(err) => panic!(
+        diagnostics
+            .iter()
+            .try_for_each(|d| codespan_reporting::term::emit(writer, &config, &self.files, d))
+    }
+
+    /// Pretty-print an error on the standard error output.
+    pub fn report(&mut self, error: Error) {
+        let writer = StandardStream::stderr(self.color_opt);
+        let mut lock = writer.lock();
+
+        if let Err(err) = self.report_to(&error, &mut lock) {
+            panic!(
                 "Program::report: could not print an error on stderr: {}",
                 err
-            ),
-        };
+            );
+        }
     }
 }
 
 impl ImportResolver for Program {
     fn resolve(
         &mut self,
-        path: &String,
+        path: &str,
         parent: Option<PathBuf>,
         pos: &Option<RawSpan>,
     ) -> Result<(ResolvedTerm, FileId), ImportError> {
@@ -301,12 +740,17 @@ impl ImportResolver for Program {
         let file_id = fs::File::open(path_buf)
             .and_then(|mut file| file.read_to_string(&mut buffer))
             .map(|_| self.files.add(path, buffer))
-            .map_err(|err| ImportError::IOError(path.clone(), format!("{}", err), pos.clone()))?;
-        self.file_cache.insert(normalized, file_id.clone());
-
-        let t = self
-            .parse(file_id)
-            .map_err(|err| ImportError::ParseError(err, pos.clone()))?;
+            .map_err(|err| {
+                ImportError::IOError(String::from(path), format!("{}", err), pos.clone())
+            })?;
+        self.file_cache.insert(normalized, file_id);
+
+        let format = InputFormat::from_path(Path::new(path));
+        let t = match format {
+            InputFormat::Nickel => self.parse(file_id),
+            _ => parser::data::parse(format, self.files.source(file_id), file_id),
+        }
+        .map_err(|err| ImportError::ParseError(err, pos.clone()))?;
         Ok((
             ResolvedTerm::FromFile(t, Path::new(path).to_path_buf()),
             file_id,
@@ -317,7 +761,7 @@ impl ImportResolver for Program {
         self.term_cache.get(&file_id).cloned()
     }
 
-    fn get_id(&self, path: &String, parent: Option<PathBuf>) -> Option<FileId> {
+    fn get_id(&self, path: &str, parent: Option<PathBuf>) -> Option<FileId> {
         let (_, normalized) = with_parent(path, parent);
         self.file_cache.get(&normalized).cloned()
     }
@@ -330,11 +774,11 @@ impl ImportResolver for Program {
 /// Compute the path of a file relatively to a parent, and a string representation of the
 /// normalized full path (see [`normalize_path`](./fn.normalize_path.html). If the path is absolute
 /// or if the parent is `None`, the first component is the same as `Path::new(path).to_path_buf()`.
-fn with_parent(path: &String, parent: Option<PathBuf>) -> (PathBuf, String) {
-    let mut path_buf = parent.unwrap_or(PathBuf::new());
+fn with_parent(path: &str, parent: Option<PathBuf>) -> (PathBuf, String) {
+    let mut path_buf = parent.unwrap_or_default();
     path_buf.pop();
     path_buf.push(Path::new(path));
-    let normalized = normalize_path(path_buf.as_path()).unwrap_or_else(|| path.clone());
+    let normalized = normalize_path(path_buf.as_path()).unwrap_or_else(|| String::from(path));
 
     (path_buf, normalized)
 }
@@ -360,7 +804,7 @@ pub mod resolvers {
     impl ImportResolver for DummyResolver {
         fn resolve(
             &mut self,
-            _path: &String,
+            _path: &str,
             _parent: Option<PathBuf>,
             _pos: &Option<RawSpan>,
         ) -> Result<(ResolvedTerm, FileId), ImportError> {
@@ -375,7 +819,7 @@ pub mod resolvers {
             panic!("program::resolvers: dummy resolver should not have been invoked");
         }
 
-        fn get_id(&self, _path: &String, _parent: Option<PathBuf>) -> Option<FileId> {
+        fn get_id(&self, _path: &str, _parent: Option<PathBuf>) -> Option<FileId> {
             panic!("program::resolvers: dummy resolver should not have been invoked");
         }
     }
@@ -388,6 +832,12 @@ pub mod resolvers {
         term_cache: HashMap<FileId, Option<RichTerm>>,
     }
 
+    impl Default for SimpleResolver {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
     impl SimpleResolver {
         pub fn new() -> SimpleResolver {
             SimpleResolver {
@@ -407,30 +857,33 @@ pub mod resolvers {
     impl ImportResolver for SimpleResolver {
         fn resolve(
             &mut self,
-            path: &String,
+            path: &str,
             _parent: Option<PathBuf>,
             pos: &Option<RawSpan>,
         ) -> Result<(ResolvedTerm, FileId), ImportError> {
-            let file_id =
-                self.file_cache
-                    .get(path)
-                    .map(|id| id.clone())
-                    .ok_or(ImportError::IOError(
-                        path.clone(),
-                        String::from("Import not found by the mockup resolver."),
-                        pos.clone(),
-                    ))?;
-
-            if self.term_cache.contains_key(&file_id) {
-                Ok((ResolvedTerm::FromCache(), file_id))
-            } else {
-                self.term_cache.insert(file_id, None);
+            let file_id = self
+                .file_cache
+                .get(path)
+                .copied()
+                .ok_or(ImportError::IOError(
+                    String::from(path),
+                    String::from("Import not found by the mockup resolver."),
+                    pos.clone(),
+                ))?;
+
+            if let std::collections::hash_map::Entry::Vacant(e) = self.term_cache.entry(file_id) {
+                e.insert(None);
                 let buf = self.files.source(file_id);
-                let t = parser::grammar::TermParser::new()
-                    .parse(file_id, Lexer::new(&buf))
-                    .map_err(|e| ParseError::from_lalrpop(e, file_id))
-                    .map_err(|e| ImportError::ParseError(e, pos.clone()))?;
+                let t = match InputFormat::from_path(Path::new(path)) {
+                    InputFormat::Nickel => parser::grammar::TermParser::new()
+                        .parse(file_id, &mut Vec::new(), Lexer::new(buf))
+                        .map_err(|e| ParseError::from_lalrpop(e, file_id)),
+                    format => parser::data::parse(format, buf, file_id),
+                }
+                .map_err(|e| ImportError::ParseError(e, pos.clone()))?;
                 Ok((ResolvedTerm::FromFile(t, PathBuf::new()), file_id))
+            } else {
+                Ok((ResolvedTerm::FromCache(), file_id))
             }
         }
 
@@ -441,12 +894,11 @@ pub mod resolvers {
         fn get(&self, file_id: FileId) -> Option<RichTerm> {
             self.term_cache
                 .get(&file_id)
-                .map(|opt| opt.as_ref())
-                .flatten()
+                .and_then(|opt| opt.as_ref())
                 .cloned()
         }
 
-        fn get_id(&self, path: &String, _parent: Option<PathBuf>) -> Option<FileId> {
+        fn get_id(&self, path: &str, _parent: Option<PathBuf>) -> Option<FileId> {
             self.file_cache.get(path).copied()
         }
     }
@@ -455,8 +907,7 @@ pub mod resolvers {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::error::EvalError;
-    use crate::identifier::Ident;
+    use crate::types::AbsType;
     use std::io::Cursor;
 
     fn eval_string(s: &str) -> Result<Term, Error> {
@@ -479,13 +930,13 @@ mod tests {
         if let Ok(Term::Record(ref mut m)) = term {
             for (i, t) in res {
                 m.remove(&Ident::from(i))
-                    .unwrap_or_else(|| panic!(format!("Could not find field {} in result", i)));
+                    .unwrap_or_else(|| panic!("Could not find field {} in result", i));
 
                 let proj = format!("({}).{}", s, i);
                 if let Ok(proj_res) = eval_string(&proj) {
                     assert_eq!(proj_res, t);
                 } else {
-                    panic!(format!("evaluation of the projection on {} failed", i));
+                    panic!("evaluation of the projection on {} failed", i);
                 }
             }
 
@@ -516,6 +967,130 @@ mod tests {
         assert_eq!(Ok(Term::Num(36.0)), res);
     }
 
+    #[test]
+    fn recursive_lets() {
+        assert_eq!(
+            eval_string("let rec fact = fun n => if n == 0 then 1 else n * fact (n - 1) in fact 5"),
+            Ok(Term::Num(120.0))
+        );
+        assert_eq!(
+            eval_string(
+                "let rec even = fun n => if n == 0 then true else odd (n - 1),
+                    odd = fun n => if n == 0 then false else even (n - 1)
+                in odd 7"
+            ),
+            Ok(Term::Bool(true))
+        );
+        // The bound terms are evaluated lazily and shared.
+        assert_eq!(
+            eval_string("let rec r = {a = 1; b = r.a + 1} in r.b"),
+            Ok(Term::Num(2.0))
+        );
+        // A non recursive let still refers to the outer binding.
+        assert_eq!(
+            eval_string("let x = 1 in let x = x + 1 in x"),
+            Ok(Term::Num(2.0))
+        );
+    }
+
+    #[test]
+    fn destructuring() {
+        assert_eq!(
+            eval_string("(fun {a, b} => a + b) {a = 1; b = 2}"),
+            Ok(Term::Num(3.0))
+        );
+        assert_eq!(
+            eval_string("let {a, b = {c, d ? 2}} = {a = 1; b = {c = 3}} in a + c + d"),
+            Ok(Term::Num(6.0))
+        );
+        assert_eq!(
+            eval_string("(fun {a ? 1, b ? a + 1} => b) {}"),
+            Ok(Term::Num(2.0))
+        );
+        assert_eq!(
+            eval_string("(fun {a, ..} [x, y] => a + x - y) {a = 1; b = 2} [3, 4]"),
+            Ok(Term::Num(0.0))
+        );
+        assert_eq!(
+            eval_string("let [x, {y}] = [1, {y = 2}] in x + y"),
+            Ok(Term::Num(3.0))
+        );
+
+        match eval_string("(fun {a, b} => a + b) {a = 1}") {
+            Err(Error::EvalError(EvalError::FieldMissing(field, _, _, _))) => {
+                assert_eq!(field, "b")
+            }
+            res => panic!("expected a missing field error, got {:?}", res),
+        }
+        // The missing fields are reported before the unexpected ones
+        match eval_string("let {host} = {x = 1} in host") {
+            Err(Error::EvalError(EvalError::FieldMissing(field, _, _, Some(_)))) => {
+                assert_eq!(field, "host")
+            }
+            res => panic!("expected a missing field error, got {:?}", res),
+        }
+        for src in &["let [x, y] = [1] in x", "(fun [x] => 1 + x) [1, 2]"] {
+            match eval_string(src) {
+                Err(Error::EvalError(EvalError::PatternMismatch(_, _, Some(_)))) => (),
+                res => panic!("expected a pattern mismatch error, got {:?}", res),
+            }
+        }
+
+        // Record patterns are closed at run time too, unless they end with `..`
+        match eval_string("(fun {a, b ? 1} => a + b) {a = 1; c = 2}") {
+            Err(Error::EvalError(EvalError::UnexpectedField(field, _, _))) => {
+                assert_eq!(field, "c")
+            }
+            res => panic!("expected an unexpected field error, got {:?}", res),
+        }
+        assert_eq!(
+            eval_string("(fun {a, b ? 1, ..} => a + b) {a = 1; c = 2}"),
+            Ok(Term::Num(2.0))
+        );
+        assert_eq!(
+            eval_string("let {a} = {a = 1; b | optional} in a"),
+            Ok(Term::Num(1.0))
+        );
+    }
+
+    #[test]
+    fn matches() {
+        assert_eq!(
+            eval_string("match { `a => 1, `b => 2, `c => 3 } `b"),
+            Ok(Term::Num(2.0))
+        );
+        assert_eq!(
+            eval_string(
+                "let f = fun x => match { 0 => \"zero \", \"a\" => \"str \", true => \"bool \", _ => \"other\" } x
+                in f 0 ++ f \"a\" ++ f true ++ f 1"
+            ),
+            Ok(Term::Str(String::from("zero str bool other")))
+        );
+        assert_eq!(
+            eval_string("match { {a} => a, {a, b ? 0} => a + b, {..} => 0 } {a = 1; b = 2}"),
+            Ok(Term::Num(3.0))
+        );
+        assert_eq!(
+            eval_string("match { {a, b ? 0} => a + b, _ => 10 } {a = 1; c = 2}"),
+            Ok(Term::Num(10.0))
+        );
+        assert_eq!(
+            eval_string("match { [] => 0, [x] => x, [x, {y}] => x + y, l => 10 } [1, {y = 2}]"),
+            Ok(Term::Num(3.0))
+        );
+        assert_eq!(
+            eval_string("match { `a => 1, x => x } `b"),
+            Ok(Term::Enum(Ident::from("b")))
+        );
+
+        match eval_string("match { `a => 1, `b => 2 } `c") {
+            Err(Error::EvalError(EvalError::NonExhaustiveMatch(t, _))) => {
+                assert_eq!(*t.term, Term::Enum(Ident::from("c")))
+            }
+            res => panic!("expected a non-exhaustive match error, got {:?}", res),
+        }
+    }
+
     #[test]
     fn dynamic_if() {
         let res =
@@ -528,7 +1103,7 @@ mod tests {
     fn parse_error() {
         let res = eval_string("let g  = funky x => x in g true");
 
-        if let Ok(_) = res {
+        if res.is_ok() {
             panic!("This test should have returned Err()!");
         }
     }
@@ -586,7 +1161,7 @@ Promise(Bool, 5)
             ",
         );
 
-        if let Ok(_) = res {
+        if res.is_ok() {
             panic!("This expression should return an error!");
         }
     }
@@ -599,7 +1174,7 @@ Promise(Bool, 5)
 Assume(#alwaysTrue, false)
 ",
         );
-        if let Ok(_) = res {
+        if res.is_ok() {
             panic!("This expression should return an error!");
         }
     }
@@ -634,7 +1209,7 @@ Assume(#alwaysTrue -> #alwaysFalse, not ) true
             "let id = Assume(forall a . a -> a, fun x => false) in
             id false",
         );
-        if let Ok(_) = res {
+        if res.is_ok() {
             panic!("This expression should return an error!");
         }
     }
@@ -712,6 +1287,40 @@ Assume(#alwaysTrue -> #alwaysFalse, not ) true
         );
     }
 
+    #[test]
+    fn enum_variants() {
+        assert_eq!(
+            eval_string("match { `tcp {port} => port, `stdio => 0 } (`tcp {port = 80})"),
+            Ok(Term::Num(80.))
+        );
+        assert_eq!(
+            eval_string("switch { tcp => 1, _ => 2, } (`tcp 3)"),
+            Ok(Term::Num(1.))
+        );
+        assert_eq!(eval_string("`a 1 == `a 1"), Ok(Term::Bool(true)));
+        assert_eq!(eval_string("`a 1 == `a 2"), Ok(Term::Bool(false)));
+        assert_eq!(eval_string("`a 1 == `a"), Ok(Term::Bool(false)));
+
+        assert_eq!(
+            eval_string(
+                "let f = Promise(< (| tcp: { {| port: Num |} }, stdio |) > -> Num,
+                    fun x => match { `tcp {port} => port, `stdio => 0 } x) in
+                f (`tcp {port = 80}) + f `stdio"
+            ),
+            Ok(Term::Num(80.))
+        );
+        assert_eq!(
+            eval_string("Assume(< (| tcp: Num, stdio |) >, `tcp 1) == `tcp 1"),
+            Ok(Term::Bool(true))
+        );
+        eval_string(
+            "match { `tcp p => p + 1, _ => 0 } Assume(< (| tcp: Num, stdio |) >, `tcp \"a\")",
+        )
+        .unwrap_err();
+        eval_string("Assume(< (| tcp: Num, stdio |) >, `tcp)").unwrap_err();
+        eval_string("Assume(< (| tcp, stdio |) >, `tcp 1)").unwrap_err();
+    }
+
     #[test]
     fn row_types() {
         eval_string("Assume((| |), 123)").unwrap_err();
@@ -745,6 +1354,54 @@ Assume(#alwaysTrue -> #alwaysFalse, not ) true
             .unwrap_err();
     }
 
+    #[test]
+    fn field_paths() {
+        assert_eval_to_record(
+            "{server.http.port = 80; server.http.host = \"localhost\"; server.name = \"srv\"}.server.http",
+            vec![("port", Term::Num(80.0)), ("host", Term::Str(String::from("localhost")))],
+        );
+        assert_eq!(
+            eval_string("{a.b = 1; c = a.b + 1; a.d = c}.a.d"),
+            Ok(Term::Num(2.0))
+        );
+        // The field of a path does not capture a variable of the same name
+        assert_eq!(eval_string("{b = 1; a.b = b}.a.b"), Ok(Term::Num(1.0)));
+        assert!(
+            Program::new_from_str("{b = 1; a.b = b; a.c.b = b}", "<test>")
+                .eval_full()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn duplicate_fields() {
+        match eval_string("{a = 1; $(\"a\") = 2}") {
+            Err(Error::EvalError(EvalError::DuplicateField(field, _, _))) => {
+                assert_eq!(field, "a")
+            }
+            res => panic!("expected a duplicate field error, got {:?}", res),
+        }
+        eval_string("{$(\"a\") = 1; $(\"a\") = 2}").unwrap_err();
+
+        // The diagnostic is the same as for a field defined twice statically
+        let mut p = Program::new_from_str("{a = 1; $(\"a\") = 2}", "<test>");
+        let err = p.eval().unwrap_err();
+        assert_eq!(
+            p.diagnostics(&err)[0].message,
+            "Multiple definitions of the field `a`"
+        );
+        let mut p = Program::new_from_str("{a = 1; a = 2}", "<test>");
+        let err = p.eval().unwrap_err();
+        assert_eq!(
+            p.diagnostics(&err)[0].message,
+            "Multiple definitions of the field `a`"
+        );
+        assert_eq!(
+            eval_string("{$(\"a\") = 1; $(\"b\") = 2}.b"),
+            Ok(Term::Num(2.0))
+        );
+    }
+
     #[test]
     fn records_prims() {
         assert_eq!(
@@ -1016,6 +1673,265 @@ Assume(#alwaysTrue -> #alwaysFalse, not ) true
         eval_string("(merge (merge {a=2;} {b=Contract(Num);}) {b=Default(true);}).b").unwrap_err();
     }
 
+    #[test]
+    fn field_annotations() {
+        assert_eq!(
+            eval_string("{port | Num | default = 80 | doc \"The port\"}.port"),
+            Ok(Term::Num(80.0))
+        );
+        assert_eq!(
+            eval_string("(merge {port | Num | default = 80} {port = 8080}).port"),
+            Ok(Term::Num(8080.0))
+        );
+        assert_eq!(
+            eval_string("{a | Num | #(fun l x => if x > 0 then x else blame l) = 1}.a"),
+            Ok(Term::Num(1.0))
+        );
+        eval_string("{a | Num = true}.a").unwrap_err();
+        eval_string("(merge {port | Num} {port = \"80\"}).port").unwrap_err();
+
+        // The contract of a defined field is checked against the definitions which override it.
+        assert_eq!(
+            eval_string("(merge {port | Num = 80} {port | force = 8080}).port"),
+            Ok(Term::Num(8080.0))
+        );
+        eval_string("(merge {port | Num = 80} {port | force = \"80\"}).port").unwrap_err();
+        eval_string("(merge {port | force = \"80\"} {port | Num = 80}).port").unwrap_err();
+        eval_string("(merge (merge {port | Num} {port = 80}) {port | force = \"80\"}).port")
+            .unwrap_err();
+        assert_eq!(
+            eval_string("(merge {port | Num = 80} {port | priority -1 = \"80\"}).port"),
+            Ok(Term::Num(80.0))
+        );
+    }
+
+    #[test]
+    fn optional_fields() {
+        let schema = "{port | Num | optional; host | Str}";
+        assert_eq!(
+            eval_string(&format!(
+                "fieldsOf (merge {} {{host = \"h\"}}) == [\"host\"]",
+                schema
+            )),
+            Ok(Term::Bool(true))
+        );
+        assert_eq!(
+            eval_string(&format!(
+                "(merge {} {{port = 80; host = \"h\"}}).port",
+                schema
+            )),
+            Ok(Term::Num(80.0))
+        );
+        eval_string(&format!(
+            "(merge {} {{port = \"80\"; host = \"h\"}}).port",
+            schema
+        ))
+        .unwrap_err();
+        eval_string(&format!("(merge {} {{host = \"h\"}}).port", schema)).unwrap_err();
+
+        assert_eq!(
+            eval_string("let r = merge {a | optional} {a | Num | optional} in !(hasField \"a\" r) && hasOptionalField \"a\" r && fieldsOf r == []"),
+            Ok(Term::Bool(true))
+        );
+        assert_eq!(
+            eval_string("merge {a | optional; b = 1} {b = 1} == {b = 1}"),
+            Ok(Term::Bool(true))
+        );
+        assert_eq!(
+            eval_string("let f = fun r => r.b in f (Assume({ {| a: Num, b: Str |} }, merge {a | Num | optional} {b = \"x\"}))"),
+            Ok(Term::Str(String::from("x")))
+        );
+        eval_string("Assume({ {| a: Num, b: Str |} }, {b = \"x\"})").unwrap_err();
+    }
+
+    #[test]
+    fn merge_lists() {
+        assert_eq!(
+            eval_string("(merge {a = [1, \"b\"]} {a = [1, \"b\"]}).a == [1, \"b\"]"),
+            Ok(Term::Bool(true))
+        );
+        assert_eq!(
+            eval_string("elemAt ((merge {a = [{b = 1}]} {a = [{c = 2}]}).a) 0 == {b = 1; c = 2}"),
+            Ok(Term::Bool(true))
+        );
+        eval_string("(merge {a = [1]} {a = [2]}).a == [1]").unwrap_err();
+        eval_string("(merge {a = [1]} {a = [1, 2]}).a").unwrap_err();
+    }
+
+    #[test]
+    fn merge_lists_concat() {
+        assert_eq!(
+            eval_string("(merge {a | merge concat = [1]} {a = [2, 3]}).a == [1, 2, 3]"),
+            Ok(Term::Bool(true))
+        );
+        assert_eq!(
+            eval_string(
+                "let schema = {hosts | List | merge concat} in
+                 let r = merge (merge (merge schema {hosts = [1]}) {hosts = [2]}) {hosts = [3]} in
+                 r.hosts == [1, 2, 3]"
+            ),
+            Ok(Term::Bool(true))
+        );
+        assert_eq!(
+            eval_string("(merge {a | merge concat | default = [1]} {a = [2]}).a == [2]"),
+            Ok(Term::Bool(true))
+        );
+        assert_eq!(
+            eval_string("(merge {a | merge elementwise = [1]} {a = [1]}).a == [1]"),
+            Ok(Term::Bool(true))
+        );
+        eval_string("(merge {a | merge concat = [1]} {a | merge elementwise = [2]}).a")
+            .unwrap_err();
+    }
+
+    #[test]
+    fn merge_priorities() {
+        assert_eq!(
+            eval_string(
+                "let base = {port | priority 1 = 80; host = \"base\"} in
+                 let region = {port | priority 2 = 8080} in
+                 let host = {port = 1; host = \"base\"} in
+                 (merge (merge base region) host).port"
+            ),
+            Ok(Term::Num(8080.0))
+        );
+        assert_eq!(
+            eval_string("(merge {a | priority -1 = 1} {a = 2}).a"),
+            Ok(Term::Num(2.0))
+        );
+        assert_eq!(
+            eval_string("(merge {a = 2} {a | priority 0.5 = 1}).a"),
+            Ok(Term::Num(1.0))
+        );
+        assert_eq!(
+            eval_string("(merge {a | force = 1} {a | priority 100 = 2}).a"),
+            Ok(Term::Num(1.0))
+        );
+        assert_eq!(
+            eval_string("(merge {a | priority 1 = {b = 1}} {a | priority 1 = {c = 2}}).a.c"),
+            Ok(Term::Num(2.0))
+        );
+        eval_string("(merge {a | priority 1 = 1} {a | priority 1 = 2}).a").unwrap_err();
+        eval_string("(merge {a | force = 1} {a | force = 2}).a").unwrap_err();
+
+        // A record with a lower priority is dropped as a whole: its fields are not merged.
+        let overridden = "merge {a | priority 1 = {b = 1}} {a = {c = 2}}";
+        assert_eq!(
+            eval_string(&format!("({}).a.b", overridden)),
+            Ok(Term::Num(1.0))
+        );
+        eval_string(&format!("({}).a.c", overridden)).unwrap_err();
+        assert_eq!(
+            eval_string("(merge {a = {b | force = 1}} {a = {b = 2; c = 2}}).a.c"),
+            Ok(Term::Num(2.0))
+        );
+    }
+
+    #[test]
+    fn late_binding() {
+        assert_eq!(
+            eval_string("(merge {a = 1; b = a + 1} {a | force = 10}).b"),
+            Ok(Term::Num(11.0))
+        );
+        assert_eq!(
+            eval_string("(merge {a | default = 1; b = a + 1} {a = 10}).b"),
+            Ok(Term::Num(11.0))
+        );
+        assert_eq!(
+            eval_string(
+                "let base = {a | default = 1; b = a + 1} in
+                 let b = base.b in
+                 let merged = merge base {a = 10} in
+                 [b, (merged.b), ((merge merged {a | force = 100}).b)] == [2, 11, 101]"
+            ),
+            Ok(Term::Bool(true))
+        );
+        assert_eq!(
+            eval_string(
+                "let r = merge {a | default = 1; c | default = 0; b = a + c} {a = 10; c = a} in
+                 r.b"
+            ),
+            Ok(Term::Num(20.0))
+        );
+        assert_eq!(
+            eval_string(
+                "let c = 1 in
+                 (merge {b = c} {c = 2}).b"
+            ),
+            Ok(Term::Num(1.0))
+        );
+        assert_eq!(
+            eval_string(
+                "let base = {host | default = \"localhost\"; url = \"http://\" ++ host} in
+                 (merge {server = base} {server = {host = \"nickel.org\"}}).server.url"
+            ),
+            Ok(Term::Str(String::from("http://nickel.org")))
+        );
+    }
+
+    #[test]
+    fn late_binding_through_contracts() {
+        assert_eq!(
+            eval_string(
+                "let r = Assume({ {| a: Num, b: Num |} }, {a | default = 1; b = a + 1}) in
+                 (merge r {a = 5}).b"
+            ),
+            Ok(Term::Num(6.0))
+        );
+        assert_eq!(
+            eval_string(
+                "let r = Assume({_: Num}, {a | default = 1; b = a + 1}) in
+                 (merge r {a = 5}).b"
+            ),
+            Ok(Term::Num(6.0))
+        );
+        assert_eq!(
+            eval_string(
+                "let f = Promise(forall r. { {| a: Num | r} } -> { {| a: Num | r} }, fun x => x) in
+                 (merge (f {a | default = 1; b = a + 1}) {a = 5}).b"
+            ),
+            Ok(Term::Num(6.0))
+        );
+        assert_eq!(
+            eval_string("(merge (mapRec (fun _f x => x) {a | default = 1; b = a + 1}) {a = 5}).b"),
+            Ok(Term::Num(6.0))
+        );
+        assert_eq!(
+            eval_string("(merge (selectField \"b\" {a | default = 1; b = a + 1}) {a = 5}).b"),
+            Ok(Term::Num(6.0))
+        );
+    }
+
+    #[test]
+    fn record_contracts_after_merge() {
+        eval_string("(merge (Assume({ {| a: Num |} }, {a | default = 1})) {a = \"x\"}).a")
+            .unwrap_err();
+        eval_string("(merge (Assume({_: Num}, {a | default = 1})) {a = \"x\"}).a").unwrap_err();
+        eval_string("(merge (Assume({ {| a: Num |} }, {a = 1})) {a | force = \"x\"}).a")
+            .unwrap_err();
+        assert_eq!(
+            eval_string("(merge (Assume({ {| a: Num |} }, {a | default = 1})) {a = 2}).a"),
+            Ok(Term::Num(2.0))
+        );
+    }
+
+    #[test]
+    fn merge_priorities_enriched() {
+        assert_eq!(
+            eval_string("(merge {a | default = 1} {a | priority -10 = 2}).a"),
+            Ok(Term::Num(2.0))
+        );
+        assert_eq!(
+            eval_string("(merge {a | Num | default = 1} {a | priority -1 = 2}).a"),
+            Ok(Term::Num(2.0))
+        );
+        assert_eq!(
+            eval_string("(merge (merge {a | Num} {a | force = 1}) {a = 2}).a"),
+            Ok(Term::Num(1.0))
+        );
+        eval_string("(merge (merge {a = 2} {a | force = \"x\"}) {a | Num}).a").unwrap_err();
+    }
+
     fn make_composed_contract(value: &str) -> Result<Term, Error> {
         let s = format!(
             "let Y = fun f => (fun x => f (x x)) (fun x => f (x x)) in
@@ -1149,7 +2065,7 @@ Assume(#alwaysTrue -> #alwaysFalse, not ) true
 
         match eval_string(r#""bad type ${1 + 1}""#) {
             Err(Error::EvalError(EvalError::TypeError(_, _, _, _))) => (),
-            _ => assert!(false),
+            _ => panic!(),
         };
     }
 
@@ -1339,6 +2255,7 @@ Assume(#alwaysTrue -> #alwaysFalse, not ) true
         eval_string("\"a\" || false").unwrap_err();
     }
 
+    #[test]
     fn records_contracts_simple() {
         assert_peq!("Assume({ {| |} }, {})", "{}");
         eval_string("Assume({ {| |} }, {a=1})").unwrap_err();
@@ -1430,4 +2347,360 @@ Assume(#alwaysTrue -> #alwaysFalse, not ) true
         )
         .unwrap_err();
     }
+
+    #[test]
+    fn typecheck_api() {
+        let mut p = Program::new_from_str("Promise(Num, 1 + 1)", "<test>");
+        assert!(p.typecheck().is_ok());
+
+        let mut p = Program::new_from_str("Promise(Num, \"a\")", "<test>");
+        match p.typecheck() {
+            Err(Error::TypecheckError(_)) => (),
+            res => panic!("expected a typechecking error, got {:?}", res),
+        };
+
+        // The type of an unannotated program is inferred in strict mode if possible.
+        let typecheck = |src: &str| {
+            Program::new_from_str(src, "<test>")
+                .typecheck()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(typecheck("let x = 2 in x + x"), "Num");
+        assert_eq!(typecheck("{a = \"b\"}"), "{ {| a: Str |} }");
+        assert_eq!(typecheck("if true then 1 else \"a\""), "Dyn");
+        assert_eq!(typecheck("Assume(Num, \"a\")"), "Num");
+        assert_eq!(typecheck("fun x => x x"), "Dyn");
+    }
+
+    #[test]
+    fn eval_full_api() {
+        let mut p = Program::new_from_str("{a = {b = 1 + 1}; c = [(2 + 2), a.b]}", "<test>");
+        let result = p.eval_full().unwrap();
+
+        let inner = Term::Record(
+            vec![(Ident::from("b"), Term::Num(2.0).into())]
+                .into_iter()
+                .collect(),
+        );
+        let list = Term::List(vec![Term::Num(4.0).into(), Term::Num(2.0).into()]);
+        match *result.term {
+            Term::Record(mut m) => {
+                assert_eq!(*m.remove(&Ident::from("a")).unwrap().term, inner);
+                assert_eq!(*m.remove(&Ident::from("c")).unwrap().term, list);
+                assert!(m.is_empty());
+            }
+            t => panic!("expected a record, got {:?}", t),
+        }
+    }
+
+    #[test]
+    fn diagnostics_api() {
+        let mut p = Program::new_from_str("1 + ", "<test>");
+        let err = p.eval().unwrap_err();
+        assert!(!p.diagnostics(&err).is_empty());
+    }
+
+    #[test]
+    fn query_api() {
+        // The provenance of fields is tested in `query_provenance`.
+        let query = |src: &str, path: &str| {
+            let path: Vec<Ident> = path.split('.').map(Ident::from).collect();
+            Program::new_from_str(src, "<test>")
+                .query(&path)
+                .map(|res| QueryResult {
+                    definitions: Vec::new(),
+                    overridden: Vec::new(),
+                    ..res
+                })
+        };
+        let src = "let srv = merge
+            {port = Docstring(\"The port\", ContractDefault(Num, 80)); host = Contract(Str)}
+            {host = \"localhost\"} in
+            {server = srv; name = Default(\"a\"); unused = 1 + true}";
+
+        assert_eq!(
+            query(src, "server.port"),
+            Ok(QueryResult {
+                doc: Some(String::from("The port")),
+                contract: Some(Types(AbsType::Num())),
+                default: Some(Term::Num(80.0)),
+                value: Some(Term::Num(80.0)),
+                ..QueryResult::default()
+            })
+        );
+        assert_eq!(
+            query(src, "server.host").map(|res| res.value),
+            Ok(Some(Term::Str(String::from("localhost"))))
+        );
+        assert_eq!(
+            query(src, "name"),
+            Ok(QueryResult {
+                doc: None,
+                contract: None,
+                default: Some(Term::Str(String::from("a"))),
+                value: Some(Term::Str(String::from("a"))),
+                ..QueryResult::default()
+            })
+        );
+        assert_eq!(
+            query("{a = Contract(Num)}", "a"),
+            Ok(QueryResult {
+                contract: Some(Types(AbsType::Num())),
+                ..QueryResult::default()
+            })
+        );
+        assert_eq!(
+            query("{port | Num = 80}", "port"),
+            Ok(QueryResult {
+                contract: Some(Types(AbsType::Num())),
+                value: Some(Term::Num(80.0)),
+                ..QueryResult::default()
+            })
+        );
+        assert_eq!(
+            query("merge {port | Num} {port = 80}", "port").map(|res| res.contract),
+            Ok(Some(Types(AbsType::Num())))
+        );
+        assert!(query(src, "server.missing").is_err());
+
+        // A record is summarized without evaluating its fields.
+        assert_eq!(
+            query("{server = {host | Str; port = 1 + 1}}", "server").map(|res| {
+                (
+                    res.value,
+                    res.fields.map(|fields| {
+                        fields
+                            .into_iter()
+                            .map(|field| (field.path_str(), field.contract))
+                            .collect::<Vec<_>>()
+                    }),
+                )
+            }),
+            Ok((
+                None,
+                Some(vec![
+                    (String::from("host"), Some(Types(AbsType::Str()))),
+                    (String::from("port"), None),
+                ])
+            ))
+        );
+        assert_eq!(
+            query("{a | Num | optional}", "a"),
+            Ok(QueryResult {
+                contract: Some(Types(AbsType::Num())),
+                optional: true,
+                ..QueryResult::default()
+            })
+        );
+
+        // A missing field of a record built by merge is located at the definition of the record.
+        let src = "{server = merge {host = \"a\"} {port = 1}}";
+        match query(src, "server.missing") {
+            Err(Error::EvalError(EvalError::FieldMissing(field, _, t, _))) => {
+                assert_eq!(field, "missing");
+                let span = t.pos.unwrap();
+                assert_eq!(
+                    &src[span.start.to_usize()..span.end.to_usize()],
+                    "merge {host = \"a\"} {port = 1}"
+                );
+            }
+            res => panic!("expected a missing field, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn query_provenance() {
+        // Return the source of the definitions and of the overridden definitions of a field.
+        let provenance = |src: &str, path: &str| {
+            let path: Vec<Ident> = path.split('.').map(Ident::from).collect();
+            let res = Program::new_from_str(src, "<test>").query(&path).unwrap();
+            let snippets = |spans: Vec<RawSpan>| -> Vec<String> {
+                spans
+                    .into_iter()
+                    .map(|span| String::from(&src[span.start.to_usize()..span.end.to_usize()]))
+                    .collect()
+            };
+            (snippets(res.definitions), snippets(res.overridden))
+        };
+        let src = "let base = {
+                port | default = 80 | doc \"The port\";
+                host | default = \"localhost\";
+                tls | Bool
+            } in
+            let prod = {port = 8080; tls = true} in
+            let local = {port | priority -1 = 8000} in
+            {server = merge (merge base prod) local}";
+
+        assert_eq!(
+            provenance(src, "server.port"),
+            (
+                vec![String::from("8080")],
+                vec![String::from("port"), String::from("8000")]
+            )
+        );
+        assert_eq!(
+            provenance(src, "server.host"),
+            (vec![String::from("host")], Vec::new())
+        );
+        assert_eq!(
+            provenance(src, "server.tls"),
+            (vec![String::from("true")], Vec::new())
+        );
+        assert_eq!(
+            provenance("{a = 1; b = {c = 2}}", "b.c"),
+            (vec![String::from("2")], Vec::new())
+        );
+    }
+
+    #[test]
+    fn merge_error_path() {
+        let res = eval_string(
+            "let base = {server = {tls = {cert = \"a\"}}} in
+             (merge base {server.tls.cert = \"b\"}).server.tls.cert",
+        );
+        match res {
+            Err(Error::EvalError(EvalError::MergeIncompatibleArgs(_, _, _, path))) => {
+                assert_eq!(
+                    path,
+                    vec![
+                        Ident::from("server"),
+                        Ident::from("tls"),
+                        Ident::from("cert")
+                    ]
+                )
+            }
+            res => panic!("expected a merge error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn merge_strategy_mismatch() {
+        use crate::term::MergeStrategy;
+
+        let src = "(merge {l | merge concat = [1]} {l | merge elementwise = [2]}).l";
+        let mut p = Program::new_from_source(Cursor::new(src), "<test>").unwrap();
+        let err = p.eval().unwrap_err();
+
+        match &err {
+            Error::EvalError(EvalError::MergeStrategyMismatch(s1, span1, s2, span2, _, path)) => {
+                assert_eq!(
+                    (*s1, *s2),
+                    (MergeStrategy::Concat, MergeStrategy::Elementwise)
+                );
+                assert!(span1.is_some() && span2.is_some());
+                assert_eq!(path, &vec![Ident::from("l")]);
+            }
+            err => panic!("expected a merge strategy mismatch, got {:?}", err),
+        }
+
+        let diagnostics = p.diagnostics(&err);
+        assert_eq!(
+            diagnostics[0].message,
+            "Incompatible merge strategies `concat` and `elementwise`"
+        );
+        let labels: Vec<&str> = diagnostics[0]
+            .labels
+            .iter()
+            .map(|label| label.message.as_str())
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                "defined with the merge strategy `concat`",
+                "defined with the merge strategy `elementwise`",
+            ]
+        );
+        assert_eq!(diagnostics[0].notes[0], "while merging the field `l`");
+    }
+
+    #[test]
+    fn blame_access_path() {
+        use crate::label::access_path::{self, Elem};
+
+        let src = "let mk = fun p => {port | Num = p} in
+                   {servers = [(mk 1), (mk 2), (mk \"80\")]}";
+        let mut p = Program::new_from_str(src, "<test>");
+        match p.eval_full() {
+            Err(Error::EvalError(EvalError::BlameError(l, _))) => {
+                assert_eq!(
+                    l.access_path,
+                    vec![
+                        Elem::Field(Ident::from("servers")),
+                        Elem::Index(2),
+                        Elem::Field(Ident::from("port"))
+                    ]
+                );
+                assert_eq!(access_path::to_string(&l.access_path), "servers[2].port");
+                assert_eq!(l.value, Some(String::from("\"80\"")));
+            }
+            res => panic!("expected a blame error, got {:?}", res),
+        }
+
+        // Values which are not fully evaluated do not have an access path
+        match eval_string("{a = Assume({ {| b: Num |} }, {c = 1})}.a") {
+            Err(Error::EvalError(EvalError::BlameError(l, _))) => {
+                assert!(l.access_path.is_empty());
+                assert_eq!(l.value, Some(String::from("{c = ...}")));
+            }
+            res => panic!("expected a blame error, got {:?}", res),
+        }
+
+        // Nor values reached by field accesses when evaluating to a weak head normal form
+        let src = "({server = {port | Num = \"80\"}}).server.port";
+        let mut p = Program::new_from_str(src, "<test>");
+        let err = p.eval().unwrap_err();
+        match &err {
+            Error::EvalError(EvalError::BlameError(l, _)) => assert!(l.access_path.is_empty()),
+            res => panic!("expected a blame error, got {:?}", res),
+        }
+        let notes: Vec<String> = p
+            .diagnostics(&err)
+            .into_iter()
+            .flat_map(|d| d.notes)
+            .collect();
+        assert!(notes.iter().any(|n| n == "expected Num, got \"80\""));
+
+        // A query gives the access path from the root of the program
+        let src = "{server = {tls = [{port | Num = \"443\"}]}}";
+        let path = vec![Ident::from("server"), Ident::from("tls")];
+        match Program::new_from_str(src, "<test>").query(&path) {
+            Err(Error::EvalError(EvalError::BlameError(l, _))) => {
+                assert_eq!(access_path::to_string(&l.access_path), "server.tls[0].port")
+            }
+            res => panic!("expected a blame error, got {:?}", res),
+        }
+
+        // Including when the queried field is the value which breaks the contract
+        let src = "{server = {port | Num = \"80\"}}";
+        let path = vec![Ident::from("server"), Ident::from("port")];
+        match Program::new_from_str(src, "<test>").query(&path) {
+            Err(Error::EvalError(EvalError::BlameError(l, _))) => {
+                assert_eq!(access_path::to_string(&l.access_path), "server.port")
+            }
+            res => panic!("expected a blame error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn enum_payload_blame_path() {
+        use crate::label::ty_path::{self, Elem};
+
+        let src = "Assume(< (| stdio, tcp: { {| port: Num |} } |) >, `tcp {port = \"80\"})";
+        match Program::new_from_str(src, "<test>").eval_full() {
+            Err(Error::EvalError(EvalError::BlameError(l, _))) => {
+                assert_eq!(
+                    l.path,
+                    vec![
+                        Elem::Field(Ident::from("tcp")),
+                        Elem::Field(Ident::from("port"))
+                    ]
+                );
+                // The blame points to the type of the field of the payload.
+                let (start, end) = ty_path::span(l.path.iter().peekable(), &l.types);
+                assert_eq!(&l.types.to_string()[start..end], "Num");
+            }
+            res => panic!("expected a blame error, got {:?}", res),
+        }
+    }
 }
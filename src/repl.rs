@@ -0,0 +1,529 @@
+//! The Nickel REPL.
+//!
+//! A REPL session keeps a single [`Program`](../program/struct.Program.html) across inputs, and
+//! hence a single file database and import cache. Each input is either an expression, which is
+//! typechecked and evaluated, or a toplevel let binding without body:
+//!
+//! ```text
+//! nickel> let x = 1 + 1
+//! nickel> {a = x; b = x + 1}
+//! ```
+//!
+//! A toplevel let extends the environment in which subsequent inputs are evaluated. The bound
+//! expression is not evaluated right away: as for a usual let binding, it is evaluated lazily, when
+//! used for the first time.
+//!
+//! The REPL logic is independent of the terminal: see
+//! [`rustyline_frontend`](./rustyline_frontend/index.html) for the interactive command-line
+//! frontend.
+use crate::error::{Error, EvalError, ParseError};
+use crate::eval::{self, Closure, IdentKind};
+use crate::identifier::Ident;
+use crate::parser::lexer::Lexer;
+use crate::parser::{self, ExtendedTerm};
+use crate::program::Program;
+use crate::term::{RichTerm, Term, UnaryOp};
+use crate::transformations;
+use crate::typecheck::{infer_type, type_check};
+use crate::types::{AbsType, Types};
+use codespan_reporting::term::termcolor::ColorChoice;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The result of the evaluation of an input.
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalResult {
+    /// The input was an expression, which evaluated to the given term.
+    Evaluated(Term),
+    /// The input was a toplevel let, which added the given identifier to the environment.
+    Bound(Ident),
+}
+
+/// Return the type annotation of a term, if it is a `Promise` or an `Assume`.
+fn annotation(t: &Term) -> Option<Types> {
+    match t {
+        Term::Promise(ty, _, _) | Term::Assume(ty, _, _) => Some(ty.clone()),
+        _ => None,
+    }
+}
+
+/// A REPL session.
+pub struct Repl {
+    /// The program holding the file database and the import caches.
+    program: Program,
+    /// The environment in which inputs are evaluated: the standard library, extended with toplevel
+    /// let bindings.
+    env: eval::Environment,
+    /// The number of inputs submitted so far, used to name the corresponding sources.
+    input_count: usize,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    /// Create a new REPL session, with an empty environment. The standard library is loaded by
+    /// [`load_stdlib`](#method.load_stdlib).
+    pub fn new() -> Repl {
+        Repl {
+            program: Program::new_from_str("", "<repl>"),
+            env: HashMap::new(),
+            input_count: 0,
+        }
+    }
+
+    /// Load and typecheck the standard library, and add it to the environment.
+    ///
+    /// The sources of the standard library are added to the file database of the session, such
+    /// that an error can be reported by [`report`](#method.report).
+    pub fn load_stdlib(&mut self) -> Result<(), Error> {
+        self.env.extend(self.program.mk_global_env()?);
+        Ok(())
+    }
+
+    /// Set the color policy used by [`report`](#method.report).
+    pub fn set_color(&mut self, color_opt: ColorChoice) {
+        self.program.set_color(color_opt);
+    }
+
+    /// Pretty-print an error on the standard error output.
+    pub fn report(&mut self, error: Error) {
+        self.program.report(error);
+    }
+
+    /// Add an input to the file database and parse it.
+    ///
+    /// An incomplete input, such as an unclosed record, makes the parser fail with
+    /// `ParseError::UnexpectedEOF`, which can be used by a frontend to ask for more lines.
+    fn parse(&mut self, input: &str) -> Result<ExtendedTerm, Error> {
+        self.input_count += 1;
+        let file_id = self
+            .program
+            .add_source(format!("<repl-input-{}>", self.input_count), input);
+
+        parser::grammar::ExtendedTermParser::new()
+            .parse(file_id, &mut Vec::new(), Lexer::new(input))
+            .map_err(|err| Error::from(ParseError::from_lalrpop(err, file_id)))
+    }
+
+    /// Parse an input which must be an expression.
+    fn parse_term(&mut self, input: &str) -> Result<RichTerm, Error> {
+        match self.parse(input)? {
+            ExtendedTerm::RichTerm(t) => Ok(t),
+            ExtendedTerm::ToplevelLet(id, t) => {
+                Ok(RichTerm::let_in(&id.0, t, Term::Var(id.clone()).into()))
+            }
+        }
+    }
+
+    /// Typecheck and transform a term, in the current environment.
+    fn prepare(&mut self, t: RichTerm) -> Result<RichTerm, Error> {
+        type_check(&t, &self.env, &mut self.program)?;
+        Ok(transformations::transform(t, &mut self.program)?)
+    }
+
+    /// Bind an identifier to a term in the environment. The term is captured together with the
+    /// current environment, such that shadowing a binding later does not change its meaning.
+    fn bind(&mut self, id: Ident, body: RichTerm, env: eval::Environment) {
+        let closure = Closure { body, env };
+        self.env
+            .insert(id, (Rc::new(RefCell::new(closure)), IdentKind::Let(), None));
+    }
+
+    /// Evaluate an input, which is either an expression or a toplevel let binding.
+    ///
+    /// Expressions are fully evaluated (see [`eval_full`](../eval/fn.eval_full.html)).
+    pub fn eval(&mut self, input: &str) -> Result<EvalResult, Error> {
+        match self.parse(input)? {
+            ExtendedTerm::RichTerm(t) => {
+                let t = self.prepare(t)?;
+                let result = eval::eval_full(t, self.env.clone(), &mut self.program)?;
+                Ok(EvalResult::Evaluated(*result.term))
+            }
+            ExtendedTerm::ToplevelLet(id, t) => {
+                let t = self.prepare(t)?;
+                self.bind(id.clone(), t, self.env.clone());
+                Ok(EvalResult::Bound(id))
+            }
+        }
+    }
+
+    /// Return the type of an expression, as inferred by the typechecker. The expression is not
+    /// evaluated.
+    ///
+    /// Outside of a `Promise`, the typechecker does not infer anything, and the inferred type is
+    /// just `Dyn`. In this case, the type annotation of the expression, or of the variable it
+    /// refers to, is returned instead, if there is one. Otherwise, the type is inferred in strict
+    /// mode, as done by [`Program::typecheck`](../program/struct.Program.html#method.typecheck).
+    pub fn type_of(&mut self, input: &str) -> Result<Types, Error> {
+        let t = self.parse_term(input)?;
+        let inferred = type_check(&t, &self.env, &mut self.program)?;
+
+        if inferred != Types(AbsType::Dyn()) {
+            return Ok(inferred);
+        }
+
+        // The type of a variable bound by a toplevel let is the one of its definition, which is
+        // inferred in the environment of the binding.
+        let (env, program) = (&self.env, &mut self.program);
+        let ty = match t.as_ref() {
+            Term::Var(id) => env.get(id).and_then(|(thunk, _, _)| {
+                let clos = thunk.borrow();
+                annotation(clos.body.as_ref())
+                    .or_else(|| infer_type(&clos.body, &clos.env, program))
+            }),
+            t_ => annotation(t_).or_else(|| infer_type(&t, env, program)),
+        };
+
+        Ok(ty.unwrap_or(inferred))
+    }
+
+    /// Return the documentation attached to the value of an expression, if any.
+    ///
+    /// The expression is only evaluated until a docstring or a value which is not an enriched
+    /// value is found, such that the documentation is not discarded by the evaluation.
+    pub fn doc(&mut self, input: &str) -> Result<Option<String>, Error> {
+        let t = self.parse_term(input)?;
+        let t = self.prepare(t)?;
+
+        let mut clos = Closure::atomic_closure(t);
+        loop {
+            let (rt, env) = eval::eval_meta(clos, &self.env, &mut self.program)?;
+            match *rt.term {
+                Term::Docstring(s, _) => return Ok(Some(s)),
+                Term::DefaultValue(t)
+                | Term::ContractWithDefault(_, _, t)
+                | Term::ContractWithValue(_, _, t)
+                | Term::MergeStrategy(_, t)
+                | Term::Priority(_, t) => clos = Closure { body: t, env },
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Load a file, which must evaluate to a record, and add each of its fields to the
+    /// environment. The file can be a Nickel source or a data file, as for an import. Return the
+    /// list of the identifiers that were bound.
+    pub fn load(&mut self, path: &str) -> Result<Vec<Ident>, Error> {
+        let t = self.prepare(Term::Import(String::from(path)).into())?;
+
+        // Bind the content of the file to a fresh variable, such that each field is evaluated
+        // only once, even when accessed through different toplevel bindings.
+        let var = transformations::fresh_var();
+        let thunk = Rc::new(RefCell::new(Closure::atomic_closure(t)));
+        let mut env = HashMap::new();
+        env.insert(var.clone(), (thunk, IdentKind::Let(), None));
+
+        let mut global_env = self.env.clone();
+        global_env.extend(env.clone());
+        let fields = match eval::eval(Term::Var(var.clone()).into(), global_env, &mut self.program)?
+        {
+            Term::Record(map) | Term::RecRecord(map, _) => map,
+            t => {
+                return Err(Error::from(EvalError::TypeError(
+                    String::from("Record"),
+                    String::from(":load"),
+                    None,
+                    RichTerm {
+                        term: Box::new(t),
+                        pos: None,
+                    },
+                )))
+            }
+        };
+
+        let mut ids: Vec<Ident> = fields.into_keys().collect();
+        ids.sort_by(|id1, id2| id1.0.cmp(&id2.0));
+
+        for id in ids.iter() {
+            let body = Term::Op1(
+                UnaryOp::StaticAccess(id.clone()),
+                Term::Var(var.clone()).into(),
+            )
+            .into();
+            self.bind(id.clone(), body, env.clone());
+        }
+
+        Ok(ids)
+    }
+}
+
+/// The interactive frontend of the REPL, based on the
+/// [rustyline](https://crates.io/crates/rustyline) line editor.
+pub mod rustyline_frontend {
+    use super::*;
+    use rustyline::error::ReadlineError;
+    use rustyline::Editor;
+
+    /// The help message of the REPL.
+    const HELP: &str = "Enter an expression to evaluate it, or a toplevel let binding \
+                        `let <id> = <expr>` to add it to the environment.
+
+Commands:
+  :typeof <expr>   show the type of an expression, without evaluating it
+  :doc <expr>      show the documentation of the value of an expression
+  :load <file>     load a file evaluating to a record, and bind each of its fields
+  :help            show this message
+  :quit            exit the REPL";
+
+    /// A REPL command.
+    enum Command<'a> {
+        Eval(&'a str),
+        TypeOf(&'a str),
+        Doc(&'a str),
+        Load(&'a str),
+        Help,
+        Quit,
+        Unknown(&'a str),
+    }
+
+    impl<'a> Command<'a> {
+        fn parse(input: &'a str) -> Self {
+            let trimmed = input.trim_start();
+            if !trimmed.starts_with(':') {
+                return Command::Eval(input);
+            }
+
+            let (cmd, arg) = match trimmed.find(char::is_whitespace) {
+                Some(idx) => (&trimmed[1..idx], trimmed[idx..].trim()),
+                None => (&trimmed[1..], ""),
+            };
+
+            match cmd {
+                "typeof" | "t" => Command::TypeOf(arg),
+                "doc" | "d" => Command::Doc(arg),
+                "load" | "l" => Command::Load(arg),
+                "help" | "h" | "?" => Command::Help,
+                "quit" | "q" => Command::Quit,
+                _ => Command::Unknown(cmd),
+            }
+        }
+    }
+
+    /// Run a command and print its result. Return `Ok(false)` if the REPL should stop.
+    fn run(repl: &mut Repl, cmd: Command) -> Result<bool, Error> {
+        match cmd {
+            Command::Eval(input) => match repl.eval(input)? {
+                // Values which can be serialized are shown as JSON. Others, such as functions, are
+                // summarized.
+                EvalResult::Evaluated(t) => {
+                    let rt = RichTerm::from(t);
+                    match crate::serialize::to_json(&rt) {
+                        Ok(json) => println!("{}", json),
+                        Err(_) => println!("{}", rt.as_ref().shallow_repr()),
+                    }
+                }
+                EvalResult::Bound(_) => (),
+            },
+            Command::TypeOf(input) => println!("{}", repl.type_of(input)?),
+            Command::Doc(input) => match repl.doc(input)? {
+                Some(doc) => println!("{}", doc),
+                None => println!("No documentation found"),
+            },
+            Command::Load(path) => {
+                let ids = repl.load(path)?;
+                let ids: Vec<&str> = ids.iter().map(|id| id.0.as_str()).collect();
+                println!("Loaded {} symbol(s): {}", ids.len(), ids.join(", "));
+            }
+            Command::Help => println!("{}", HELP),
+            Command::Quit => return Ok(false),
+            Command::Unknown(cmd) => {
+                eprintln!("Unknown command `:{}`. Type `:help` for help.", cmd)
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Run the REPL until the end of the input or a `:quit` command.
+    ///
+    /// An input which is incomplete, such as an unclosed parenthesis, is continued on the next
+    /// line. An empty line terminates a multi-line input in any case. Errors are reported by the
+    /// REPL itself: an error is returned only if the standard library could not be loaded, once
+    /// it has been reported.
+    pub fn repl(color_opt: ColorChoice) -> Result<(), Error> {
+        let mut repl = Repl::new();
+        repl.set_color(color_opt);
+
+        if let Err(err) = repl.load_stdlib() {
+            repl.report(err.clone());
+            return Err(err);
+        }
+        let mut editor = Editor::<()>::new();
+        let mut buffer = String::new();
+
+        println!("Nickel REPL. Type `:help` for help, `:quit` to exit.");
+
+        loop {
+            let prompt = if buffer.is_empty() {
+                "nickel> "
+            } else {
+                "......> "
+            };
+
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                // Ctrl-C cancels the current input
+                Err(ReadlineError::Interrupted) => {
+                    buffer.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(err) => {
+                    eprintln!("Error when reading the input: {}", err);
+                    break;
+                }
+            };
+
+            let continued = !buffer.is_empty();
+            buffer.push_str(&line);
+            buffer.push('\n');
+
+            if buffer.trim().is_empty() {
+                buffer.clear();
+                continue;
+            }
+
+            let input = std::mem::take(&mut buffer);
+            match run(&mut repl, Command::parse(&input)) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(Error::ParseError(
+                    ParseError::UnexpectedEOF(..) | ParseError::UnterminatedComment(..),
+                )) if !(continued && line.trim().is_empty()) => {
+                    buffer = input;
+                    continue;
+                }
+                Err(err) => repl.report(err),
+            }
+
+            editor.add_history_entry(input.trim_end());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl() -> Repl {
+        let mut repl = Repl::new();
+        repl.load_stdlib().unwrap();
+        repl
+    }
+
+    fn eval(repl: &mut Repl, input: &str) -> Term {
+        match repl.eval(input) {
+            Ok(EvalResult::Evaluated(t)) => t,
+            res => panic!("expected a value, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn toplevel_let() {
+        let mut repl = repl();
+
+        assert_eq!(
+            repl.eval("let x = 1 + 1"),
+            Ok(EvalResult::Bound(Ident::from("x")))
+        );
+        assert_eq!(eval(&mut repl, "x + 1"), Term::Num(3.0));
+        assert_eq!(
+            repl.eval("let y = x * 2"),
+            Ok(EvalResult::Bound(Ident::from("y")))
+        );
+
+        // Shadowing does not change the value of previous bindings
+        repl.eval("let x = 10").unwrap();
+        assert_eq!(eval(&mut repl, "y"), Term::Num(4.0));
+        assert_eq!(eval(&mut repl, "let z = x in z"), Term::Num(10.0));
+
+        assert_eq!(
+            repl.eval("let rec fact = fun n => if n == 0 then 1 else n * fact (n - 1)"),
+            Ok(EvalResult::Bound(Ident::from("fact")))
+        );
+        assert_eq!(eval(&mut repl, "fact 4"), Term::Num(24.0));
+
+        // `rec` is only a keyword right after `let`
+        assert_eq!(
+            repl.eval("let rec = fact 3"),
+            Ok(EvalResult::Bound(Ident::from("rec")))
+        );
+        assert_eq!(eval(&mut repl, "rec + 1"), Term::Num(7.0));
+    }
+
+    #[test]
+    fn incomplete_input() {
+        let mut repl = repl();
+
+        match repl.eval("{a = 1;") {
+            Err(Error::ParseError(ParseError::UnexpectedEOF(..))) => (),
+            res => panic!("expected an unexpected end of file, got {:?}", res),
+        }
+        assert!(repl.eval("{a = 1;\nb = 2}").is_ok());
+    }
+
+    #[test]
+    fn type_of() {
+        let mut repl = repl();
+
+        repl.eval("let f = Promise(Num -> Num, fun x => x + 1)")
+            .unwrap();
+        assert_eq!(repl.type_of("f").unwrap().to_string(), "Num -> Num");
+        assert!(repl.type_of("Promise(Num, true)").is_err());
+
+        assert_eq!(repl.type_of("1").unwrap().to_string(), "Num");
+        assert_eq!(repl.type_of("\"a\" ++ \"b\"").unwrap().to_string(), "Str");
+        assert_eq!(repl.type_of("f 1").unwrap().to_string(), "Num");
+
+        repl.eval("let x = 1").unwrap();
+        assert_eq!(repl.type_of("x").unwrap().to_string(), "Num");
+        // The type of a binding does not depend on whether it has been evaluated
+        repl.eval("x").unwrap();
+        assert_eq!(repl.type_of("x").unwrap().to_string(), "Num");
+
+        // Terms which are not well-typed in strict mode are still given the type `Dyn`
+        repl.eval("let d = if true then 1 else \"a\"").unwrap();
+        assert_eq!(repl.type_of("d").unwrap().to_string(), "Dyn");
+    }
+
+    #[test]
+    fn doc() {
+        let mut repl = repl();
+
+        repl.eval("let r = {a = Docstring(\"the a field\", 1); b = 2}")
+            .unwrap();
+        assert_eq!(repl.doc("r.a"), Ok(Some(String::from("the a field"))));
+        assert_eq!(repl.doc("r.b"), Ok(None));
+        assert_eq!(
+            repl.doc("merge {a = Docstring(\"doc\", Default(1))} {a = 2}"),
+            Ok(None)
+        );
+        assert_eq!(
+            repl.doc("(merge {a = Docstring(\"doc\", Default(1))} {b = 2}).a"),
+            Ok(Some(String::from("doc")))
+        );
+    }
+
+    #[test]
+    fn load() {
+        let path =
+            std::env::temp_dir().join(format!("nickel-repl-load-{}.json", std::process::id()));
+        std::fs::write(&path, "{\"b\": 1, \"a\": {\"c\": true}}").unwrap();
+
+        let mut repl = repl();
+        let ids = repl.load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(ids, Ok(vec![Ident::from("a"), Ident::from("b")]));
+        assert_eq!(eval(&mut repl, "b + 1"), Term::Num(2.0));
+        assert_eq!(eval(&mut repl, "a.c"), Term::Bool(true));
+        assert!(repl.load("does_not_exist.ncl").is_err());
+    }
+}
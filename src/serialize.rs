@@ -0,0 +1,545 @@
+//! Serialization of an evaluated program to various data format.
+//!
+//! The term to serialize must have been fully evaluated beforehand (see
+//! [`Program::eval_full`](../program/struct.Program.html#method.eval_full)). Records are exported
+//! as maps with sorted keys, lists as sequences, enum tags as strings and enum variants as maps
+//! with a single entry from the tag to the value. Default values and docstrings are transparently
+//! unwrapped. Other terms, such as functions, labels or contracts without a definition, are not
+//! serializable: use [`validate`](./fn.validate.html) to check a term beforehand and get back a
+//! positioned error.
+//!
+//! Each supported format is implemented as a [`Backend`](./trait.Backend.html), which can impose
+//! additional restrictions on the exported value. For example, the top-level value of a TOML
+//! document must be a record, and JSON cannot represent `NaN` or infinite numbers.
+use crate::error::SerializationError;
+use crate::term::{RichTerm, Term};
+use serde::ser::{Error, Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// The available export formats.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExportFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportFormat::Json => write!(f, "JSON"),
+            ExportFormat::Yaml => write!(f, "YAML"),
+            ExportFormat::Toml => write!(f, "TOML"),
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "yaml" | "yml" => Ok(ExportFormat::Yaml),
+            "toml" => Ok(ExportFormat::Toml),
+            _ => Err(format!(
+                "unknown export format `{}` (expected json, yaml or toml)",
+                s
+            )),
+        }
+    }
+}
+
+/// Serialize a number. Numbers without a fractional part are serialized as integers when
+/// possible, as Nickel does not distinguish integers from floats.
+fn serialize_num<S>(n: f64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        serializer.serialize_i64(n as i64)
+    } else {
+        serializer.serialize_f64(n)
+    }
+}
+
+impl Serialize for Term {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Term::Bool(b) => serializer.serialize_bool(*b),
+            Term::Num(n) => serialize_num(*n, serializer),
+            Term::Str(s) => serializer.serialize_str(s),
+            Term::Enum(id) => serializer.serialize_str(&id.0),
+            Term::EnumVariant(id, t) => {
+                let mut map_ser = serializer.serialize_map(Some(1))?;
+                map_ser.serialize_entry(&id.0, t)?;
+                map_ser.end()
+            }
+            Term::Record(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|(id1, _), (id2, _)| id1.0.cmp(&id2.0));
+
+                let mut map_ser = serializer.serialize_map(Some(entries.len()))?;
+                for (id, t) in entries {
+                    map_ser.serialize_entry(&id.0, t)?;
+                }
+                map_ser.end()
+            }
+            Term::List(ts) => {
+                let mut seq_ser = serializer.serialize_seq(Some(ts.len()))?;
+                for t in ts {
+                    seq_ser.serialize_element(t)?;
+                }
+                seq_ser.end()
+            }
+            Term::DefaultValue(t)
+            | Term::ContractWithDefault(_, _, t)
+            | Term::ContractWithValue(_, _, t)
+            | Term::Docstring(_, t)
+            | Term::MergeStrategy(_, t)
+            | Term::Priority(_, t) => t.serialize(serializer),
+            t => Err(S::Error::custom(format!(
+                "non serializable term: {}",
+                t.shallow_repr()
+            ))),
+        }
+    }
+}
+
+impl Serialize for RichTerm {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.term.serialize(serializer)
+    }
+}
+
+/// Check that a term is serializable, that is that it only consists of records, lists and
+/// values of primitive types.
+///
+/// Return the first non serializable subterm otherwise, which is used to report an error at the
+/// right position.
+pub fn validate(t: &RichTerm) -> Result<(), SerializationError> {
+    match t.term.as_ref() {
+        Term::Bool(_) | Term::Num(_) | Term::Str(_) | Term::Enum(_) => Ok(()),
+        Term::Record(map) => map.values().try_for_each(validate),
+        Term::List(ts) => ts.iter().try_for_each(validate),
+        Term::EnumVariant(_, t) => validate(t),
+        Term::DefaultValue(t)
+        | Term::ContractWithDefault(_, _, t)
+        | Term::ContractWithValue(_, _, t)
+        | Term::Docstring(_, t)
+        | Term::MergeStrategy(_, t)
+        | Term::Priority(_, t) => validate(t),
+        _ => Err(SerializationError::NotSerializable(t.clone())),
+    }
+}
+
+/// A serialization backend for a specific format.
+pub trait Backend {
+    /// The format implemented by this backend.
+    const FORMAT: ExportFormat;
+
+    /// Check the restrictions specific to this format. The term has already been checked by
+    /// [`validate`](./fn.validate.html), and is thus made only of serializable values.
+    fn check(_t: &RichTerm) -> Result<(), SerializationError> {
+        Ok(())
+    }
+
+    /// Serialize a valid term to a string.
+    fn write(t: &RichTerm) -> Result<String, String>;
+}
+
+/// The JSON backend.
+pub struct Json;
+
+/// The YAML backend.
+pub struct Yaml;
+
+/// The TOML backend.
+pub struct Toml;
+
+impl Backend for Json {
+    const FORMAT: ExportFormat = ExportFormat::Json;
+
+    /// JSON has no representation for `NaN` and infinite numbers.
+    fn check(t: &RichTerm) -> Result<(), SerializationError> {
+        check_finite(Self::FORMAT, t)
+    }
+
+    fn write(t: &RichTerm) -> Result<String, String> {
+        serde_json::to_string_pretty(t).map_err(|err| err.to_string())
+    }
+}
+
+impl Backend for Yaml {
+    const FORMAT: ExportFormat = ExportFormat::Yaml;
+
+    fn write(t: &RichTerm) -> Result<String, String> {
+        serde_yaml::to_string(t).map_err(|err| err.to_string())
+    }
+}
+
+impl Backend for Toml {
+    const FORMAT: ExportFormat = ExportFormat::Toml;
+
+    /// A TOML document is a table: the top-level value must be a record. Records inside a list
+    /// are written as an array of tables, which can't hold other values.
+    fn check(t: &RichTerm) -> Result<(), SerializationError> {
+        match unwrap_enriched(t).term.as_ref() {
+            Term::Record(_) => check_tables(Self::FORMAT, t),
+            _ => Err(SerializationError::NonRecordTopLevel(
+                Self::FORMAT,
+                t.clone(),
+            )),
+        }
+    }
+
+    fn write(t: &RichTerm) -> Result<String, String> {
+        // In a TOML table, all the plain values must come before the sub-tables. Going through
+        // `toml::Value` takes care of this reordering, which a direct serialization of the
+        // record with sorted keys does not.
+        toml::Value::try_from(t)
+            .and_then(|value| toml::to_string_pretty(&value))
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Strip the enriched values wrapping a term, if any.
+fn unwrap_enriched(t: &RichTerm) -> &RichTerm {
+    match t.term.as_ref() {
+        Term::DefaultValue(t)
+        | Term::ContractWithDefault(_, _, t)
+        | Term::ContractWithValue(_, _, t)
+        | Term::Docstring(_, t)
+        | Term::MergeStrategy(_, t)
+        | Term::Priority(_, t) => unwrap_enriched(t),
+        _ => t,
+    }
+}
+
+/// Whether a term is serialized as a table, that is a record or an enum variant.
+fn is_table(t: &RichTerm) -> bool {
+    matches!(
+        unwrap_enriched(t).term.as_ref(),
+        Term::Record(_) | Term::EnumVariant(_, _)
+    )
+}
+
+/// Whether a term is or contains a table, possibly inside nested lists.
+fn has_table(t: &RichTerm) -> bool {
+    match unwrap_enriched(t).term.as_ref() {
+        Term::List(ts) => ts.iter().any(has_table),
+        _ => is_table(t),
+    }
+}
+
+/// Check that the tables inside a list are only found in lists of tables, which are the only ones
+/// that can be written as arrays of tables. Return the first list mixing tables with other values
+/// otherwise.
+fn check_tables(format: ExportFormat, t: &RichTerm) -> Result<(), SerializationError> {
+    match unwrap_enriched(t).term.as_ref() {
+        Term::Record(map) => map.values().try_for_each(|t| check_tables(format, t)),
+        Term::EnumVariant(_, t) => check_tables(format, t),
+        Term::List(ts) if ts.iter().all(is_table) => {
+            ts.iter().try_for_each(|t| check_tables(format, t))
+        }
+        Term::List(ts) if ts.iter().any(has_table) => {
+            Err(SerializationError::MixedList(format, t.clone()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that a term does not contain any `NaN` or infinite number.
+fn check_finite(format: ExportFormat, t: &RichTerm) -> Result<(), SerializationError> {
+    match unwrap_enriched(t).term.as_ref() {
+        Term::Num(n) if !n.is_finite() => {
+            Err(SerializationError::UnrepresentableNum(format, t.clone()))
+        }
+        Term::Record(map) => map.values().try_for_each(|t| check_finite(format, t)),
+        Term::List(ts) => ts.iter().try_for_each(|t| check_finite(format, t)),
+        Term::EnumVariant(_, t) => check_finite(format, t),
+        _ => Ok(()),
+    }
+}
+
+/// Validate and serialize a term using the backend `B`.
+pub fn to_string<B: Backend>(t: &RichTerm) -> Result<String, SerializationError> {
+    validate(t)?;
+    B::check(t)?;
+    B::write(t).map_err(|msg| SerializationError::Other(B::FORMAT, msg))
+}
+
+/// Validate and serialize a term to a JSON string.
+pub fn to_json(t: &RichTerm) -> Result<String, SerializationError> {
+    to_string::<Json>(t)
+}
+
+/// Validate and serialize a term to a YAML string.
+pub fn to_yaml(t: &RichTerm) -> Result<String, SerializationError> {
+    to_string::<Yaml>(t)
+}
+
+/// Validate and serialize a term to a TOML string.
+pub fn to_toml(t: &RichTerm) -> Result<String, SerializationError> {
+    to_string::<Toml>(t)
+}
+
+/// Validate and serialize a term to a string in the given format.
+pub fn export(t: &RichTerm, format: ExportFormat) -> Result<String, SerializationError> {
+    match format {
+        ExportFormat::Json => to_json(t),
+        ExportFormat::Yaml => to_yaml(t),
+        ExportFormat::Toml => to_toml(t),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identifier::Ident;
+    use crate::program::Program;
+    use crate::term::MergePriority;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn eval_full(s: &str) -> RichTerm {
+        Program::new_from_str(s, "<test>")
+            .eval_full()
+            .expect("evaluation shouldn't fail")
+    }
+
+    fn assert_json_eq(s: &str, expected: serde_json::Value) {
+        let json = to_json(&eval_full(s)).expect("serialization shouldn't fail");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn basic() {
+        assert_json_eq("1 + 1", json!(2));
+        assert_json_eq("0.5", json!(0.5));
+        assert_json_eq("isZero 1", json!(false));
+        assert_json_eq("\"a\" ++ \"b\"", json!("ab"));
+        assert_json_eq("`foo", json!("foo"));
+        assert_json_eq("[1, \"a\", (2 + 2)]", json!([1, "a", 4]));
+    }
+
+    #[test]
+    fn records() {
+        assert_json_eq(
+            "{a = 1; b = {c = a + 1; d = [`x]}; e = Default(true); f = Docstring(\"doc\", \"f\")}",
+            json!({"a": 1, "b": {"c": 2, "d": ["x"]}, "e": true, "f": "f"}),
+        );
+        assert_json_eq(
+            "merge {a = Default(1); b = 2} {a = 3}",
+            json!({"a": 3, "b": 2}),
+        );
+    }
+
+    #[test]
+    fn priorities() {
+        let mut record = HashMap::new();
+        record.insert(
+            Ident(String::from("a")),
+            Term::Priority(MergePriority::Force, Term::Num(1.0).into()).into(),
+        );
+        record.insert(
+            Ident(String::from("b")),
+            Term::Docstring(
+                String::from("doc"),
+                Term::Priority(MergePriority::Numeral(-1.0), Term::Bool(true).into()).into(),
+            )
+            .into(),
+        );
+        let t: RichTerm = Term::Record(record).into();
+
+        assert_eq!(validate(&t), Ok(()));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&to_json(&t).unwrap()).unwrap(),
+            json!({"a": 1, "b": true})
+        );
+
+        let mut record = HashMap::new();
+        record.insert(
+            Ident(String::from("a")),
+            Term::Priority(MergePriority::Force, Term::Num(f64::NAN).into()).into(),
+        );
+
+        match to_json(&Term::Record(record).into()) {
+            Err(SerializationError::UnrepresentableNum(ExportFormat::Json, _)) => (),
+            res => panic!("expected an unrepresentable number error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn sorted_keys() {
+        let json = to_json(&eval_full("{c = 1; a = 2; b = 3}")).unwrap();
+        let a = json.find("\"a\"").unwrap();
+        let b = json.find("\"b\"").unwrap();
+        let c = json.find("\"c\"").unwrap();
+        assert!(a < b && b < c);
+    }
+
+    #[test]
+    fn non_serializable() {
+        match to_json(&eval_full("{a = 1; b = {c = fun x => x}}")) {
+            Err(SerializationError::NotSerializable(rt)) => {
+                assert!(matches!(rt.as_ref(), Term::Fun(_, _)));
+                assert!(rt.pos.is_some());
+            }
+            res => panic!("expected a serialization error, got {:?}", res),
+        }
+
+        match to_json(&eval_full("[1, (fun x => x)]")) {
+            Err(SerializationError::NotSerializable(_)) => (),
+            res => panic!("expected a serialization error, got {:?}", res),
+        }
+
+        assert!(validate(&Term::Sym(0).into()).is_err());
+    }
+
+    #[test]
+    fn yaml() {
+        let yaml = to_yaml(&eval_full(
+            "{a = 1; b = [\"x\", (0.5 + 0)]; c = {d = true}}",
+        ))
+        .unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<serde_json::Value>(&yaml).unwrap(),
+            json!({"a": 1, "b": ["x", 0.5], "c": {"d": true}})
+        );
+        assert!(to_yaml(&eval_full("1 + 1")).is_ok());
+        assert!(to_yaml(&Term::Num(f64::NAN).into()).is_ok());
+    }
+
+    #[test]
+    fn toml() {
+        let toml = to_toml(&eval_full("{a = {b = 1}; c = \"d\"; e = [1, 2]}")).unwrap();
+        assert_eq!(
+            toml.parse::<toml::Value>().unwrap(),
+            toml::toml! {
+                c = "d"
+                e = [1, 2]
+                [a]
+                b = 1
+            }
+        );
+
+        // The error points to the source of the top-level value, even when it was computed.
+        for src in &["[1, 2]", "let x = 1 in x + 1"] {
+            match to_toml(&eval_full(src)) {
+                Err(SerializationError::NonRecordTopLevel(ExportFormat::Toml, rt)) => {
+                    assert!(rt.pos.is_some())
+                }
+                res => panic!("expected a top-level error, got {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn variants() {
+        assert_json_eq(
+            "{a = `tcp {port = 80}; b = [`stdio, (`file \"log\")]}",
+            json!({"a": {"tcp": {"port": 80}}, "b": ["stdio", {"file": "log"}]}),
+        );
+
+        let yaml = to_yaml(&eval_full("{a = `tcp {port = 80}; b = `file \"log\"}")).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<serde_json::Value>(&yaml).unwrap(),
+            json!({"a": {"tcp": {"port": 80}}, "b": {"file": "log"}})
+        );
+
+        let toml = to_toml(&eval_full(
+            "{a = `tcp {port = 80}; b = `file \"log\"; c = [(`x 1), (`y 2)]}",
+        ))
+        .unwrap();
+        assert_eq!(
+            toml.parse::<toml::Value>().unwrap(),
+            toml::toml! {
+                [a.tcp]
+                port = 80
+                [b]
+                file = "log"
+                [[c]]
+                x = 1
+                [[c]]
+                y = 2
+            }
+        );
+        // A variant is a table, which can't be mixed with other values in a TOML list.
+        match to_toml(&eval_full("{a = [(`x 1), `y]}")) {
+            Err(SerializationError::MixedList(ExportFormat::Toml, _)) => (),
+            res => panic!("expected a mixed list error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn toml_mixed_lists() {
+        for src in &[
+            "{a = [{x = 1}, 2]}",
+            "{a = [[1], [{b = 1}]]}",
+            "{a = [[{b = 1}], [{b = 2}]]}",
+            "{a = {b = [{c = [1, {d = 1}]}]}}",
+        ] {
+            match to_toml(&eval_full(src)) {
+                Err(SerializationError::MixedList(ExportFormat::Toml, rt)) => {
+                    assert!(matches!(rt.as_ref(), Term::List(_)));
+                    assert!(rt.pos.is_some());
+                }
+                res => panic!("expected a mixed list error, got {:?}", res),
+            }
+        }
+
+        let toml = to_toml(&eval_full("{a = [{x = 1}, {x = 2}]; b = [[1], [\"c\"]]}")).unwrap();
+        assert_eq!(
+            toml.parse::<toml::Value>().unwrap(),
+            toml::toml! {
+                b = [[1], ["c"]]
+                [[a]]
+                x = 1
+                [[a]]
+                x = 2
+            }
+        );
+        // Mixed lists are only a restriction of TOML.
+        assert!(to_json(&eval_full("{a = [{x = 1}, 2]}")).is_ok());
+    }
+
+    #[test]
+    fn unrepresentable_num() {
+        let mut record = HashMap::new();
+        record.insert(
+            Ident(String::from("a")),
+            Term::List(vec![Term::Num(f64::INFINITY).into()]).into(),
+        );
+        let t: RichTerm = Term::Record(record).into();
+
+        match to_json(&t) {
+            Err(SerializationError::UnrepresentableNum(ExportFormat::Json, _)) => (),
+            res => panic!("expected an unrepresentable number error, got {:?}", res),
+        }
+        assert!(to_toml(&t).is_ok());
+
+        match to_json(&eval_full("{a = `Foo (1e300 * 1e300)}")) {
+            Err(SerializationError::UnrepresentableNum(ExportFormat::Json, _)) => (),
+            res => panic!("expected an unrepresentable number error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn format_names() {
+        for format in &[ExportFormat::Json, ExportFormat::Yaml, ExportFormat::Toml] {
+            assert_eq!(format.to_string().parse::<ExportFormat>(), Ok(*format));
+        }
+        assert!("xml".parse::<ExportFormat>().is_err());
+    }
+}
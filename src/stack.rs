@@ -2,8 +2,10 @@
 //!
 //! See [eval](../eval/index.html).
 use crate::eval::Closure;
+use crate::label::access_path;
 use crate::operation::OperationCont;
 use crate::position::RawSpan;
+use crate::term::UnaryOp;
 use std::cell::RefCell;
 use std::rc::Weak;
 
@@ -61,6 +63,12 @@ impl IntoIterator for Stack {
     }
 }
 
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Stack {
     pub fn new() -> Stack {
         Stack(Vec::new())
@@ -141,12 +149,21 @@ impl Stack {
     pub fn is_top_cont(&self) -> bool {
         self.0.last().map(Marker::is_cont).unwrap_or(false)
     }
+
+    /// Return the access path of the innermost term being fully evaluated, that is the one of the
+    /// last `DeepSeq` continuation on the stack.
+    pub fn deep_seq_path(&self) -> Option<&access_path::Path> {
+        self.0.iter().rev().find_map(|marker| match marker {
+            Marker::Cont(OperationCont::Op1(UnaryOp::DeepSeq(path), _, _), _, _) => Some(path),
+            _ => None,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::term::{Term, UnaryOp};
+    use crate::term::Term;
     use std::rc::Rc;
 
     impl Stack {
@@ -166,7 +183,7 @@ mod tests {
     }
 
     fn some_cont() -> OperationCont {
-        OperationCont::Op1(UnaryOp::IsZero(), None)
+        OperationCont::Op1(UnaryOp::IsZero(), None, true)
     }
 
     fn some_arg_marker() -> Marker {
@@ -17,11 +17,12 @@
 //! the term level, and together with [merge](../merge/index.html), they allow for flexible and
 //! modular definitions of contracts, record and metadata all together.
 use crate::identifier::Ident;
-use crate::label::Label;
+use crate::label::{access_path, Label};
 use crate::position::RawSpan;
 use crate::types::{AbsType, Types};
 use codespan::FileId;
 use std::collections::HashMap;
+use std::fmt;
 
 /// The AST of a Nickel expression.
 ///
@@ -47,23 +48,44 @@ pub enum Term {
     StrChunks(Vec<StrChunk<RichTerm>>),
     /// A function.
     Fun(Ident, RichTerm),
+    /// A function whose argument is destructured by a pattern.
+    ///
+    /// Desugared to a `Fun` followed by `Let` bindings by the [destructuring
+    /// transformation](../transformations/desugar_destructuring/index.html).
+    FunPattern(Pattern, RichTerm),
     /// A blame label.
     Lbl(Label),
 
     /// A let binding.
     Let(Ident, RichTerm, RichTerm),
+    /// A let binding destructuring the bound term by a pattern.
+    ///
+    /// Desugared to `Let` bindings by the [destructuring
+    /// transformation](../transformations/desugar_destructuring/index.html).
+    LetPattern(Pattern, RichTerm, RichTerm),
+    /// A recursive let binding of one or several terms, which can reference each others.
+    LetRec(Vec<(Ident, RichTerm)>, RichTerm),
+    /// A pattern matching expression `match { pattern => term, .. } t`.
+    ///
+    /// Desugared to a sequence of tests by the [match
+    /// transformation](../transformations/desugar_match/index.html).
+    Match(Vec<(MatchPattern, RichTerm)>, RichTerm),
     /// An application.
     App(RichTerm, RichTerm),
     /// A variable.
     Var(Ident),
 
-    /// An enum variant.
+    /// An enum tag.
     Enum(Ident),
+    /// An enum tag carrying a value, such as `` `Tcp {port = 80} ``.
+    EnumVariant(Ident, RichTerm),
 
     /// A record, mapping identifiers to terms.
     Record(HashMap<Ident, RichTerm>),
-    /// A recursive record, where the fields can reference each others.
-    RecRecord(HashMap<Ident, RichTerm>),
+    /// A recursive record, where the fields can reference each others. The positions of the
+    /// names of the fields written in a record literal are kept aside, as for the fields of a
+    /// [record pattern](struct.FieldPattern.html).
+    RecRecord(HashMap<Ident, RichTerm>, HashMap<Ident, RawSpan>),
 
     /// A list.
     List(Vec<RichTerm>),
@@ -94,7 +116,7 @@ pub enum Term {
     ///
     /// Wrapped terms are introduced by contracts on polymorphic types. Take the following example:
     ///
-    /// ```
+    /// ```text
     /// let f = Assume(forall a. forall b. a -> b -> a, fun x y => y) in
     /// f true "a"
     /// ```
@@ -102,12 +124,12 @@ pub enum Term {
     /// This function is ill-typed. To check that, a polymorphic contract will:
     /// - Assign a unique identifier to each type variable: say `a => 1`, `b => 2`
     /// - For each cast on a negative occurrence of a type variable `a` or `b` (corresponding to an
-    /// argument position), tag the argument with the associated identifier. In our example, `f
+    ///   argument position), tag the argument with the associated identifier. In our example, `f
     /// true "a"` will push `Wrapped(1, true)` then `Wrapped(2, "a")` on the stack.
     /// - For each cast on a positive occurrence of a type variable, this contract check that the
-    /// term is of the form `Wrapped(id, term)` where `id` corresponds to the identifier of the
-    /// type variable. In our example, the last cast to `a` finds `Wrapped(2, "a")`, while it
-    /// expected `Wrapped(1, _)`, hence it raises a positive blame.
+    ///   term is of the form `Wrapped(id, term)` where `id` corresponds to the identifier of the
+    ///   type variable. In our example, the last cast to `a` finds `Wrapped(2, "a")`, while it
+    ///   expected `Wrapped(1, _)`, hence it raises a positive blame.
     Wrapped(i32, RichTerm),
 
     /// A contract. Enriched value.
@@ -128,9 +150,37 @@ pub enum Term {
     /// value, as both need to be remembered.
     ContractWithDefault(Types, Label, RichTerm),
 
+    /// A contract combined with a definition. Enriched value.
+    ///
+    /// This is the result of merging a contract and a value, as in a field `port | Num = 80`. The
+    /// contract is remembered, such that it can be queried and enforced on a definition which
+    /// overrides this one. Otherwise, it evaluates to the value checked against the contract.
+    ContractWithValue(Types, Label, RichTerm),
+
     /// A term together with its documentation string. Enriched value.
     Docstring(String, RichTerm),
 
+    /// An optional record field without a definition. Enriched value.
+    ///
+    /// The inner term holds the other metadata of the field, such as a contract or a
+    /// documentation. Such a field is considered absent by record operations and record contracts,
+    /// but is kept around to be merged. It stops being optional as soon as it is merged with
+    /// another field.
+    Optional(RichTerm),
+
+    /// A term together with the strategy used to merge lists. Enriched value.
+    ///
+    /// The strategy applies when the term, or what it is merged with, evaluates to a list. It is
+    /// kept by merge, such that it also applies to the following merges.
+    MergeStrategy(MergeStrategy, RichTerm),
+
+    /// A definition together with its merge priority. Enriched value.
+    ///
+    /// When merged with another definition, the one with the highest priority is kept and the
+    /// other one is dropped, instead of requiring them to be equal. A definition without priority
+    /// has the normal priority, while a default value has a lower priority than any other.
+    Priority(MergePriority, RichTerm),
+
     /// An unresolved import.
     Import(String),
     /// A resolved import (which has already been loaded and parsed).
@@ -145,6 +195,180 @@ pub enum StrChunk<E> {
     Expr(E),
 }
 
+/// The strategy used to merge two lists (see the [merge module](../merge/index.html)).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MergeStrategy {
+    /// Merge the elements pairwise. The lists must have the same length. This is the default.
+    Elementwise,
+    /// Concatenate the lists.
+    Concat,
+}
+
+impl fmt::Display for MergeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeStrategy::Elementwise => write!(f, "elementwise"),
+            MergeStrategy::Concat => write!(f, "concat"),
+        }
+    }
+}
+
+/// The priority of a definition (see the [merge module](../merge/index.html)).
+///
+/// Priorities are ordered: numerals are compared as numbers, and are all lower than `Force`.
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub enum MergePriority {
+    /// A numeral priority, which may be negative.
+    Numeral(f64),
+    /// The highest priority.
+    Force,
+}
+
+impl MergePriority {
+    /// The priority of a definition without explicit priority.
+    pub const NORMAL: MergePriority = MergePriority::Numeral(0.0);
+}
+
+impl fmt::Display for MergePriority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergePriority::Numeral(n) => write!(f, "priority {}", n),
+            MergePriority::Force => write!(f, "force"),
+        }
+    }
+}
+
+/// A destructuring pattern, which binds variables to parts of a value.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    /// A variable, bound to the whole value.
+    Ident(Ident),
+    /// A record pattern `{a, b = pattern, c ? default, ..}`.
+    ///
+    /// Without the final `..`, the pattern is closed: the typechecker then gives the destructured
+    /// value a record type with exactly the fields of the pattern.
+    Record(Vec<FieldPattern>, /* open */ bool, Option<RawSpan>),
+    /// A list pattern `[a, b, c]`, which binds the first elements of a list.
+    List(Vec<Pattern>, Option<RawSpan>),
+}
+
+/// A field of a record pattern.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FieldPattern {
+    /// The name of the field.
+    pub id: Ident,
+    /// The pattern matched against the content of the field. For the field `a` in `{a}`, this is
+    /// the variable `a` itself.
+    pub pattern: Pattern,
+    /// The value used when the field is missing.
+    pub default: Option<RichTerm>,
+    pub pos: Option<RawSpan>,
+}
+
+/// The pattern of a case of a `match` expression.
+#[derive(Debug, PartialEq, Clone)]
+pub enum MatchPattern {
+    /// An enum tag, with a pattern for the value of variants.
+    Enum(Ident, Option<Pattern>),
+    /// A boolean constant.
+    Bool(bool),
+    /// A number constant.
+    Num(f64),
+    /// A string constant.
+    Str(String),
+    /// A destructuring pattern, which matches any value of the right shape: a variable matches
+    /// any value, and a record pattern matches records with the fields of the pattern.
+    Destruct(Pattern),
+    /// The wildcard `_`, which matches any value.
+    Wildcard,
+}
+
+impl MatchPattern {
+    /// Determine if the pattern matches any value.
+    pub fn is_catch_all(&self) -> bool {
+        matches!(
+            self,
+            MatchPattern::Wildcard | MatchPattern::Destruct(Pattern::Ident(_))
+        )
+    }
+}
+
+impl Pattern {
+    /// Recursively apply a function to the default values of a pattern.
+    pub fn apply_to_rich_terms<F>(&mut self, func: &F)
+    where
+        F: Fn(&mut RichTerm),
+    {
+        match self {
+            Pattern::Ident(_) => (),
+            Pattern::Record(fields, _, _) => fields.iter_mut().for_each(|field| {
+                if let Some(ref mut t) = field.default {
+                    func(t);
+                }
+                field.pattern.apply_to_rich_terms(func);
+            }),
+            Pattern::List(pats, _) => pats
+                .iter_mut()
+                .for_each(|pat| pat.apply_to_rich_terms(func)),
+        }
+    }
+
+    /// Erase recursively the positional information of the pattern, but not the one of the
+    /// default values.
+    pub fn clean_pos(&mut self) {
+        match self {
+            Pattern::Ident(_) => (),
+            Pattern::Record(fields, _, pos) => {
+                *pos = None;
+                fields.iter_mut().for_each(|field| {
+                    field.pos = None;
+                    field.pattern.clean_pos();
+                });
+            }
+            Pattern::List(pats, pos) => {
+                *pos = None;
+                pats.iter_mut().for_each(Pattern::clean_pos);
+            }
+        }
+    }
+
+    /// Apply a transformation on the default values of a pattern (see
+    /// [`RichTerm::traverse`](struct.RichTerm.html#method.traverse)).
+    pub fn traverse<F, S, E>(self, f: &mut F, state: &mut S) -> Result<Pattern, E>
+    where
+        F: FnMut(RichTerm, &mut S) -> Result<RichTerm, E>,
+    {
+        match self {
+            pat @ Pattern::Ident(_) => Ok(pat),
+            Pattern::Record(fields, open, pos) => {
+                let fields_res: Result<Vec<FieldPattern>, E> = fields
+                    .into_iter()
+                    .map(|field| {
+                        let default = field
+                            .default
+                            .map(|t| t.traverse(f, state))
+                            .map_or(Ok(None), |res| res.map(Some))?;
+                        let pattern = field.pattern.traverse(f, state)?;
+                        Ok(FieldPattern {
+                            pattern,
+                            default,
+                            ..field
+                        })
+                    })
+                    .collect();
+
+                Ok(Pattern::Record(fields_res?, open, pos))
+            }
+            Pattern::List(pats, pos) => {
+                let pats_res: Result<Vec<Pattern>, E> =
+                    pats.into_iter().map(|pat| pat.traverse(f, state)).collect();
+
+                Ok(Pattern::List(pats_res?, pos))
+            }
+        }
+    }
+}
+
 impl Term {
     /// Recursively apply a function to all `Term`s contained in a `RichTerm`.
     pub fn apply_to_rich_terms<F>(&mut self, func: F)
@@ -163,7 +387,7 @@ impl Term {
                     func(def)
                 }
             }
-            Record(ref mut static_map) | RecRecord(ref mut static_map) => {
+            Record(ref mut static_map) | RecRecord(ref mut static_map, _) => {
                 static_map.iter_mut().for_each(|e| {
                     let (_, t) = e;
                     func(t);
@@ -186,13 +410,18 @@ impl Term {
             | Import(_)
             | ResolvedImport(_) => {}
             Fun(_, ref mut t)
+            | EnumVariant(_, ref mut t)
             | Op1(_, ref mut t)
             | Promise(_, _, ref mut t)
             | Assume(_, _, ref mut t)
             | Wrapped(_, ref mut t)
             | DefaultValue(ref mut t)
             | Docstring(_, ref mut t)
-            | ContractWithDefault(_, _, ref mut t) => {
+            | Optional(ref mut t)
+            | MergeStrategy(_, ref mut t)
+            | Priority(_, ref mut t)
+            | ContractWithDefault(_, _, ref mut t)
+            | ContractWithValue(_, _, ref mut t) => {
                 func(t);
             }
             Let(_, ref mut t1, ref mut t2)
@@ -201,6 +430,28 @@ impl Term {
                 func(t1);
                 func(t2);
             }
+            FunPattern(ref mut pat, ref mut t) => {
+                pat.apply_to_rich_terms(&func);
+                func(t);
+            }
+            LetPattern(ref mut pat, ref mut t1, ref mut t2) => {
+                pat.apply_to_rich_terms(&func);
+                func(t1);
+                func(t2);
+            }
+            LetRec(ref mut bindings, ref mut t) => {
+                bindings.iter_mut().for_each(|(_, t)| func(t));
+                func(t);
+            }
+            Match(ref mut cases, ref mut t) => {
+                cases.iter_mut().for_each(|(pat, t)| {
+                    if let MatchPattern::Destruct(pat) | MatchPattern::Enum(_, Some(pat)) = pat {
+                        pat.apply_to_rich_terms(&func);
+                    }
+                    func(t);
+                });
+                func(t);
+            }
             List(ref mut terms) => terms.iter_mut().for_each(|t| {
                 func(t);
             }),
@@ -222,18 +473,25 @@ impl Term {
             Term::Bool(_) => Some("Bool"),
             Term::Num(_) => Some("Num"),
             Term::Str(_) => Some("Str"),
-            Term::Fun(_, _) => Some("Fun"),
+            Term::Fun(_, _) | Term::FunPattern(_, _) => Some("Fun"),
             Term::Lbl(_) => Some("Label"),
-            Term::Enum(_) => Some("Enum"),
-            Term::Record(_) | Term::RecRecord(_) => Some("Record"),
+            Term::Enum(_) | Term::EnumVariant(_, _) => Some("Enum"),
+            Term::Record(_) | Term::RecRecord(_, _) => Some("Record"),
             Term::List(_) => Some("List"),
             Term::Sym(_) => Some("Sym"),
             Term::Wrapped(_, _) => Some("Wrapped"),
             Term::Contract(_, _)
             | Term::ContractWithDefault(_, _, _)
+            | Term::ContractWithValue(_, _, _)
             | Term::Docstring(_, _)
+            | Term::Optional(_)
+            | Term::MergeStrategy(_, _)
+            | Term::Priority(_, _)
             | Term::DefaultValue(_) => Some("EnrichedValue"),
             Term::Let(_, _, _)
+            | Term::LetPattern(_, _, _)
+            | Term::LetRec(_, _)
+            | Term::Match(_, _)
             | Term::App(_, _)
             | Term::Var(_)
             | Term::Op1(_, _)
@@ -244,7 +502,7 @@ impl Term {
             | Term::ResolvedImport(_)
             | Term::StrChunks(_) => None,
         }
-        .map(|s| String::from(s))
+        .map(String::from)
     }
 
     /// Return a shallow string representation of a term, used for error reporting.
@@ -256,7 +514,7 @@ impl Term {
             Term::Str(s) => format!("\"{}\"", s),
             Term::StrChunks(chunks) => {
                 let chunks_str: Vec<String> = chunks
-                    .into_iter()
+                    .iter()
                     .map(|chunk| match chunk {
                         StrChunk::Literal(s) => s,
                         StrChunk::Expr(_) => "${ ... }",
@@ -266,10 +524,11 @@ impl Term {
 
                 format!("\"{}\"", chunks_str.join(""))
             }
-            Term::Fun(_, _) => String::from("<func>"),
+            Term::Fun(_, _) | Term::FunPattern(_, _) => String::from("<func>"),
             Term::Lbl(_) => String::from("<label>"),
             Term::Enum(Ident(s)) => format!("`{}", s),
-            Term::Record(_) | Term::RecRecord(_) => String::from("{ ... }"),
+            Term::EnumVariant(Ident(s), ref t) => format!("`{} {}", s, (*t.term).shallow_repr()),
+            Term::Record(_) | Term::RecRecord(_, _) => String::from("{ ... }"),
             Term::List(_) => String::from("[ ... ]"),
             Term::Sym(_) => String::from("<sym>"),
             Term::Wrapped(_, _) => String::from("<wrapped>"),
@@ -277,12 +536,27 @@ impl Term {
             Term::ContractWithDefault(_, _, ref t) => {
                 format!("<enriched:contract,default={}>", (*t.term).shallow_repr())
             }
+            Term::ContractWithValue(_, _, ref t) => {
+                format!("<enriched:contract,value={}>", (*t.term).shallow_repr())
+            }
             Term::Docstring(_, ref t) => {
                 format!("<enriched:doc,term={}>", (*t.term).shallow_repr())
             }
+            Term::Optional(ref t) => {
+                format!("<enriched:optional,term={}>", (*t.term).shallow_repr())
+            }
+            Term::MergeStrategy(s, ref t) => {
+                format!("<enriched:merge={},term={}>", s, (*t.term).shallow_repr())
+            }
+            Term::Priority(p, ref t) => {
+                format!("<enriched:{},term={}>", p, (*t.term).shallow_repr())
+            }
             Term::DefaultValue(ref t) => format!("<enriched:default={}", (*t.term).shallow_repr()),
             Term::Var(Ident(id)) => id.clone(),
             Term::Let(_, _, _)
+            | Term::LetPattern(_, _, _)
+            | Term::LetRec(_, _)
+            | Term::Match(_, _)
             | Term::App(_, _)
             | Term::Op1(_, _)
             | Term::Op2(_, _, _)
@@ -300,12 +574,17 @@ impl Term {
             | Term::Num(_)
             | Term::Str(_)
             | Term::Fun(_, _)
+            | Term::FunPattern(_, _)
             | Term::Lbl(_)
             | Term::Enum(_)
+            | Term::EnumVariant(_, _)
             | Term::Record(_)
             | Term::List(_)
             | Term::Sym(_) => true,
             Term::Let(_, _, _)
+            | Term::LetPattern(_, _, _)
+            | Term::LetRec(_, _)
+            | Term::Match(_, _)
             | Term::App(_, _)
             | Term::Var(_)
             | Term::Op1(_, _)
@@ -316,11 +595,15 @@ impl Term {
             | Term::Contract(_, _)
             | Term::DefaultValue(_)
             | Term::ContractWithDefault(_, _, _)
+            | Term::ContractWithValue(_, _, _)
             | Term::Docstring(_, _)
+            | Term::Optional(_)
+            | Term::MergeStrategy(_, _)
+            | Term::Priority(_, _)
             | Term::Import(_)
             | Term::ResolvedImport(_)
             | Term::StrChunks(_)
-            | Term::RecRecord(_) => false,
+            | Term::RecRecord(_, _) => false,
         }
     }
 
@@ -330,20 +613,29 @@ impl Term {
             Term::Contract(_, _)
             | Term::DefaultValue(_)
             | Term::ContractWithDefault(_, _, _)
-            | Term::Docstring(_, _) => true,
+            | Term::ContractWithValue(_, _, _)
+            | Term::Docstring(_, _)
+            | Term::Optional(_)
+            | Term::MergeStrategy(_, _)
+            | Term::Priority(_, _) => true,
             Term::Bool(_)
             | Term::Num(_)
             | Term::Str(_)
             | Term::StrChunks(_)
             | Term::Fun(_, _)
+            | Term::FunPattern(_, _)
             | Term::Lbl(_)
             | Term::Enum(_)
+            | Term::EnumVariant(_, _)
             | Term::Record(_)
-            | Term::RecRecord(_)
+            | Term::RecRecord(_, _)
             | Term::List(_)
             | Term::Sym(_)
             | Term::Wrapped(_, _)
             | Term::Let(_, _, _)
+            | Term::LetPattern(_, _, _)
+            | Term::LetRec(_, _)
+            | Term::Match(_, _)
             | Term::App(_, _)
             | Term::Var(_)
             | Term::Op1(_, _)
@@ -368,9 +660,14 @@ impl Term {
             | Term::Enum(_)
             | Term::Sym(_) => true,
             Term::Let(_, _, _)
+            | Term::LetPattern(_, _, _)
+            | Term::LetRec(_, _)
+            | Term::Match(_, _)
             | Term::Record(_)
             | Term::List(_)
+            | Term::EnumVariant(_, _)
             | Term::Fun(_, _)
+            | Term::FunPattern(_, _)
             | Term::App(_, _)
             | Term::Var(_)
             | Term::Op1(_, _)
@@ -381,11 +678,15 @@ impl Term {
             | Term::Contract(_, _)
             | Term::DefaultValue(_)
             | Term::ContractWithDefault(_, _, _)
+            | Term::ContractWithValue(_, _, _)
             | Term::Docstring(_, _)
+            | Term::Optional(_)
+            | Term::MergeStrategy(_, _)
+            | Term::Priority(_, _)
             | Term::Import(_)
             | Term::ResolvedImport(_)
             | Term::StrChunks(_)
-            | Term::RecRecord(_) => false,
+            | Term::RecRecord(_, _) => false,
         }
     }
 }
@@ -468,7 +769,7 @@ pub enum UnaryOp<CapturedTerm> {
     /// If the argument is a label with a [type path](../label/enum.TyPath.html) representing some
     /// subtype of the type of the original contract, as in:
     ///
-    /// ```
+    /// ```text
     /// (Num -> Num) -> Num
     ///  ^^^^^^^^^^ type path
     /// ------------------- original type
@@ -476,7 +777,7 @@ pub enum UnaryOp<CapturedTerm> {
     ///
     /// Then `GoDom` evaluates to a copy of this label, where the path has gone forward into the domain:
     ///
-    /// ```
+    /// ```text
     /// (Num -> Num) -> Num
     ///  ^^^ new type path
     /// ------------------- original type
@@ -498,7 +799,10 @@ pub enum UnaryOp<CapturedTerm> {
     ///
     /// Recursive here means that the evaluation does not stop at a WHNF, but the content of lists
     /// and records is also recursively forced.
-    DeepSeq(),
+    ///
+    /// The access path is the one of the argument in the term being fully evaluated, and is
+    /// recorded in the labels of the contracts broken while forcing it.
+    DeepSeq(access_path::Path),
 
     /// Return the head of a list.
     ListHead(),
@@ -514,6 +818,29 @@ pub enum UnaryOp<CapturedTerm> {
 
     /// Return the names of the fields of a record as a string list.
     FieldsOf(),
+
+    /// Test if the argument is an enum variant with the given tag.
+    ///
+    /// Only generated by the desugaring of a `match` expression, or by the contract of an enum type.
+    IsVariant(Ident),
+
+    /// Return the value carried by an enum variant.
+    ///
+    /// Only generated by the desugaring of a `match` expression, or by the contract of an enum type.
+    Payload(),
+
+    /// Only generated by the desugaring of a `match` expression. Fail with a non-exhaustive match
+    /// error reporting its argument, which is the value that no case matched.
+    MatchFailure(),
+
+    /// Only generated by the desugaring of a closed record pattern. Return its argument if it is a
+    /// record without other fields than the given ones, and fail with an unexpected field error
+    /// otherwise.
+    CheckFields(Vec<Ident>),
+
+    /// Only generated by the desugaring of a list pattern. Return its argument if it is a list of
+    /// the given length, and fail with a pattern mismatch error otherwise.
+    CheckLength(usize),
 }
 
 impl<Ty> UnaryOp<Ty> {
@@ -562,7 +889,7 @@ impl<Ty> UnaryOp<Ty> {
             Wrap() => Wrap(),
 
             Seq() => Seq(),
-            DeepSeq() => DeepSeq(),
+            DeepSeq(p) => DeepSeq(p),
 
             ListHead() => ListHead(),
             ListTail() => ListTail(),
@@ -580,6 +907,12 @@ impl<Ty> UnaryOp<Ty> {
             ),
 
             FieldsOf() => FieldsOf(),
+
+            IsVariant(id) => IsVariant(id),
+            Payload() => Payload(),
+            MatchFailure() => MatchFailure(),
+            CheckFields(ids) => CheckFields(ids),
+            CheckLength(n) => CheckLength(n),
         }
     }
 }
@@ -617,6 +950,9 @@ pub enum BinaryOp<CapturedTerm> {
     ///
     /// See `GoDom`.
     GoField(),
+    /// Raise a blame, as `Blame` in [`UnaryOp`](enum.UnaryOp.html), and record a truncated
+    /// representation of the first argument as the offending value in the label.
+    BlameWith(),
     /// Extend a record with a dynamic field.
     ///
     /// Dynamic means that the field name may be an expression instead of a statically known
@@ -627,8 +963,15 @@ pub enum BinaryOp<CapturedTerm> {
     DynRemove(),
     /// Access the field of record. The field name is given as an arbitrary Nickel expression.
     DynAccess(),
+    /// Restrict a record to one of its fields. Contrary to building a new record from the content
+    /// of the field, the definition of the field is kept, such that a recursive record restricted
+    /// this way can still be merged.
+    SelectField(),
     /// Test if a record has a specific field.
     HasField(),
+    /// Test if a record has a specific optional field without a definition, which is not seen by
+    /// `HasField`.
+    HasOptionalField(),
     /// Concatenate two lists.
     ListConcat(),
     /// Map a function on each element of a list.
@@ -637,6 +980,10 @@ pub enum BinaryOp<CapturedTerm> {
     ListElemAt(),
     /// The merge operator (see the [merge module](../merge/index.html)).
     Merge(),
+    /// The merge operator, merging lists with the given strategy, and the given field of the
+    /// merged records. Generated by the merge of records and of `MergeStrategy` enriched values.
+    /// The field path is only used for error reporting.
+    MergeWith(MergeStrategy, Vec<Ident>),
 }
 
 impl<Ty> BinaryOp<Ty> {
@@ -658,21 +1005,22 @@ impl<Ty> BinaryOp<Ty> {
             GreaterOrEq() => GreaterOrEq(),
             Unwrap() => Unwrap(),
             GoField() => GoField(),
+            BlameWith() => BlameWith(),
             DynRemove() => DynRemove(),
             DynAccess() => DynAccess(),
+            SelectField() => SelectField(),
             HasField() => HasField(),
+            HasOptionalField() => HasOptionalField(),
             ListConcat() => ListConcat(),
             ListMap() => ListMap(),
             ListElemAt() => ListElemAt(),
             Merge() => Merge(),
+            MergeWith(strategy, path) => MergeWith(strategy, path),
         }
     }
 
     pub fn is_strict(&self) -> bool {
-        match self {
-            BinaryOp::Merge() => false,
-            _ => true,
-        }
+        !matches!(self, BinaryOp::Merge() | BinaryOp::MergeWith(_, _))
     }
 }
 
@@ -696,6 +1044,16 @@ impl RichTerm {
     /// It allows to use rust `Eq` trait to compare the values of the underlying terms.
     pub fn clean_pos(&mut self) {
         self.pos = None;
+        match self.term.as_mut() {
+            Term::FunPattern(pat, _) | Term::LetPattern(pat, _, _) => pat.clean_pos(),
+            Term::RecRecord(_, spans) => spans.clear(),
+            Term::Match(cases, _) => cases.iter_mut().for_each(|(pat, _)| {
+                if let MatchPattern::Destruct(pat) | MatchPattern::Enum(_, Some(pat)) = pat {
+                    pat.clean_pos();
+                }
+            }),
+            _ => (),
+        }
         self.term
             .apply_to_rich_terms(|rt: &mut Self| rt.clean_pos());
     }
@@ -758,6 +1116,27 @@ impl RichTerm {
                     state,
                 )
             }
+            Term::EnumVariant(id, t) => {
+                let t = t.traverse(f, state)?;
+                f(
+                    RichTerm {
+                        term: Box::new(Term::EnumVariant(id, t)),
+                        pos,
+                    },
+                    state,
+                )
+            }
+            Term::FunPattern(pat, t) => {
+                let pat = pat.traverse(f, state)?;
+                let t = t.traverse(f, state)?;
+                f(
+                    RichTerm {
+                        term: Box::new(Term::FunPattern(pat, t)),
+                        pos,
+                    },
+                    state,
+                )
+            }
             Term::Let(id, t1, t2) => {
                 let t1 = t1.traverse(f, state)?;
                 let t2 = t2.traverse(f, state)?;
@@ -769,6 +1148,57 @@ impl RichTerm {
                     state,
                 )
             }
+            Term::LetPattern(pat, t1, t2) => {
+                let pat = pat.traverse(f, state)?;
+                let t1 = t1.traverse(f, state)?;
+                let t2 = t2.traverse(f, state)?;
+                f(
+                    RichTerm {
+                        term: Box::new(Term::LetPattern(pat, t1, t2)),
+                        pos,
+                    },
+                    state,
+                )
+            }
+            Term::Match(cases, t) => {
+                let cases_res: Result<Vec<(MatchPattern, RichTerm)>, E> = cases
+                    .into_iter()
+                    .map(|(pat, t)| {
+                        let pat = match pat {
+                            MatchPattern::Destruct(pat) => {
+                                MatchPattern::Destruct(pat.traverse(f, state)?)
+                            }
+                            MatchPattern::Enum(id, Some(pat)) => {
+                                MatchPattern::Enum(id, Some(pat.traverse(f, state)?))
+                            }
+                            pat => pat,
+                        };
+                        Ok((pat, t.traverse(f, state)?))
+                    })
+                    .collect();
+                let t = t.traverse(f, state)?;
+                f(
+                    RichTerm {
+                        term: Box::new(Term::Match(cases_res?, t)),
+                        pos,
+                    },
+                    state,
+                )
+            }
+            Term::LetRec(bindings, t) => {
+                let bindings_res: Result<Vec<(Ident, RichTerm)>, E> = bindings
+                    .into_iter()
+                    .map(|(id, t)| t.traverse(f, state).map(|t_ok| (id, t_ok)))
+                    .collect();
+                let t = t.traverse(f, state)?;
+                f(
+                    RichTerm {
+                        term: Box::new(Term::LetRec(bindings_res?, t)),
+                        pos,
+                    },
+                    state,
+                )
+            }
             Term::App(t1, t2) => {
                 let t1 = t1.traverse(f, state)?;
                 let t2 = t2.traverse(f, state)?;
@@ -872,7 +1302,7 @@ impl RichTerm {
                     state,
                 )
             }
-            Term::RecRecord(map) => {
+            Term::RecRecord(map, spans) => {
                 // The annotation on `map_res` uses Result's corresponding trait to convert from
                 // Iterator<Result> to a Result<Iterator>
                 let map_res: Result<HashMap<Ident, RichTerm>, E> = map
@@ -882,7 +1312,7 @@ impl RichTerm {
                     .collect();
                 f(
                     RichTerm {
-                        term: Box::new(Term::RecRecord(map_res?)),
+                        term: Box::new(Term::RecRecord(map_res?, spans)),
                         pos,
                     },
                     state,
@@ -953,6 +1383,21 @@ impl RichTerm {
                     state,
                 )
             }
+            Term::ContractWithValue(ty, lbl, t) => {
+                let ty = match ty {
+                    Types(AbsType::Flat(t)) => Types(AbsType::Flat(t.traverse(f, state)?)),
+                    ty => ty,
+                };
+
+                let t = t.traverse(f, state)?;
+                f(
+                    RichTerm {
+                        term: Box::new(Term::ContractWithValue(ty, lbl, t)),
+                        pos,
+                    },
+                    state,
+                )
+            }
             Term::Docstring(s, t) => {
                 let t = t.traverse(f, state)?;
                 f(
@@ -963,6 +1408,36 @@ impl RichTerm {
                     state,
                 )
             }
+            Term::Optional(t) => {
+                let t = t.traverse(f, state)?;
+                f(
+                    RichTerm {
+                        term: Box::new(Term::Optional(t)),
+                        pos,
+                    },
+                    state,
+                )
+            }
+            Term::MergeStrategy(strategy, t) => {
+                let t = t.traverse(f, state)?;
+                f(
+                    RichTerm {
+                        term: Box::new(Term::MergeStrategy(strategy, t)),
+                        pos,
+                    },
+                    state,
+                )
+            }
+            Term::Priority(priority, t) => {
+                let t = t.traverse(f, state)?;
+                f(
+                    RichTerm {
+                        term: Box::new(Term::Priority(priority, t)),
+                        pos,
+                    },
+                    state,
+                )
+            }
         }
     }
 }
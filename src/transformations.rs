@@ -22,7 +22,7 @@ generate_counter!(FreshVarCounter, usize);
 /// bindings put at the beginning of the WHNF.
 ///
 /// For example, take the expression
-/// ```
+/// ```text
 /// let x = {a = (1 + 1);} in x.a + x.a
 /// ```
 ///
@@ -30,11 +30,11 @@ generate_counter!(FreshVarCounter, usize);
 /// is never updated. Without additional machinery, `a` will be recomputed each time is it used,
 /// two times here.
 ///
-/// The transformation replaces such subexpressions, namely the content of the fields
-/// of records and the elements of lists - `(1 + 1)` in our example -, with fresh variables
-/// introduced by `let`  added at the head of the term:
+/// The transformation replaces such subexpressions, namely the content of the fields of records,
+/// the elements of lists and the value of enum variants - `(1 + 1)` in our example -, with fresh
+/// variables introduced by `let`  added at the head of the term:
 ///
-/// ```
+/// ```text
 /// let x = (let var = 1 + 1 in {a = var;}) in x.a + x.a
 /// ```
 ///
@@ -54,9 +54,9 @@ pub mod share_normal_form {
     /// This function is not recursive: it just tries to apply one step of the transformation to
     /// the top-level node of the AST. For example, it transforms `[1 + 1, [1 + 2]]` to `let %0 = 1
     /// + 1 in [%0, [1 + 2]]`: the nested subterm `[1 + 2]` is left as it was. If the term is
-    /// neither a record, a list nor an enriched value, it is returned the same.  In other words,
-    /// the transformation is implemented as rewrite rules, and must be used in conjunction a
-    /// traversal to obtain a full transformation.
+    ///   neither a record, a list, an enum variant nor an enriched value, it is returned the same.
+    ///   In other words, the transformation is implemented as rewrite rules, and must be used in
+    ///   conjunction a traversal to obtain a full transformation.
     pub fn transform_one(rt: RichTerm) -> RichTerm {
         let RichTerm { term, pos } = rt;
         let pos = pos.clone();
@@ -79,7 +79,7 @@ pub mod share_normal_form {
 
                 with_bindings(Term::Record(map), bindings, pos)
             }
-            Term::RecRecord(map) => {
+            Term::RecRecord(map, spans) => {
                 // When a recursive record is evaluated, all fields need to be turned to closures
                 // anyway (see the corresponding case in `eval::eval()`), which is what the share
                 // normal form transformation does. This is why the test is more lax here than for
@@ -101,7 +101,7 @@ pub mod share_normal_form {
                     })
                     .collect();
 
-                with_bindings(Term::RecRecord(map), bindings, pos)
+                with_bindings(Term::RecRecord(map, spans), bindings, pos)
             }
             Term::List(ts) => {
                 let mut bindings = Vec::with_capacity(ts.len());
@@ -121,6 +121,21 @@ pub mod share_normal_form {
 
                 with_bindings(Term::List(ts), bindings, pos)
             }
+            Term::EnumVariant(id, t) => {
+                if should_share(&t.term) {
+                    let fresh_var = fresh_var();
+                    let inner = RichTerm {
+                        term: Box::new(Term::EnumVariant(id, Term::Var(fresh_var.clone()).into())),
+                        pos,
+                    };
+                    Term::Let(fresh_var, t, inner).into()
+                } else {
+                    RichTerm {
+                        term: Box::new(Term::EnumVariant(id, t)),
+                        pos,
+                    }
+                }
+            }
             Term::DefaultValue(t) => {
                 if should_share(&t.term) {
                     let fresh_var = fresh_var();
@@ -155,6 +170,25 @@ pub mod share_normal_form {
                     }
                 }
             }
+            Term::ContractWithValue(ty, lbl, t) => {
+                if should_share(&t.term) {
+                    let fresh_var = fresh_var();
+                    let inner = RichTerm {
+                        term: Box::new(Term::ContractWithValue(
+                            ty,
+                            lbl,
+                            Term::Var(fresh_var.clone()).into(),
+                        )),
+                        pos,
+                    };
+                    Term::Let(fresh_var, t, inner).into()
+                } else {
+                    RichTerm {
+                        term: Box::new(Term::ContractWithValue(ty, lbl, t)),
+                        pos,
+                    }
+                }
+            }
             Term::Docstring(s, t) => {
                 if should_share(&t.term) {
                     let fresh_var = fresh_var();
@@ -170,6 +204,42 @@ pub mod share_normal_form {
                     }
                 }
             }
+            Term::MergeStrategy(strategy, t) => {
+                if should_share(&t.term) {
+                    let fresh_var = fresh_var();
+                    let inner = RichTerm {
+                        term: Box::new(Term::MergeStrategy(
+                            strategy,
+                            Term::Var(fresh_var.clone()).into(),
+                        )),
+                        pos,
+                    };
+                    Term::Let(fresh_var, t, inner).into()
+                } else {
+                    RichTerm {
+                        term: Box::new(Term::MergeStrategy(strategy, t)),
+                        pos,
+                    }
+                }
+            }
+            Term::Priority(priority, t) => {
+                if should_share(&t.term) {
+                    let fresh_var = fresh_var();
+                    let inner = RichTerm {
+                        term: Box::new(Term::Priority(
+                            priority,
+                            Term::Var(fresh_var.clone()).into(),
+                        )),
+                        pos,
+                    };
+                    Term::Let(fresh_var, t, inner).into()
+                } else {
+                    RichTerm {
+                        term: Box::new(Term::Priority(priority, t)),
+                        pos,
+                    }
+                }
+            }
             t => RichTerm {
                 term: Box::new(t),
                 pos,
@@ -183,17 +253,17 @@ pub mod share_normal_form {
     /// duplicating any work. On the other hand, a WHNF which can contain other shareable
     /// subexpressions, such as a record, should be shared.
     fn should_share(t: &Term) -> bool {
-        match t {
+        !matches!(
+            t,
             Term::Bool(_)
-            | Term::Num(_)
-            | Term::Str(_)
-            | Term::Lbl(_)
-            | Term::Sym(_)
-            | Term::Var(_)
-            | Term::Enum(_)
-            | Term::Fun(_, _) => false,
-            _ => true,
-        }
+                | Term::Num(_)
+                | Term::Str(_)
+                | Term::Lbl(_)
+                | Term::Sym(_)
+                | Term::Var(_)
+                | Term::Enum(_)
+                | Term::Fun(_, _)
+        )
     }
 
     /// Bind a list of pairs `(identifier, term)` in a term.
@@ -206,15 +276,366 @@ pub mod share_normal_form {
         bindings: Vec<(Ident, RichTerm)>,
         pos: Option<RawSpan>,
     ) -> RichTerm {
-        let result = bindings.into_iter().fold(
+        bindings.into_iter().fold(
             RichTerm {
                 term: Box::new(body),
                 pos,
             },
             |acc, (id, t)| Term::Let(id, t, acc).into(),
-        );
+        )
+    }
+}
+
+/// Desugaring of destructuring patterns.
+///
+/// Functions and let bindings with a pattern are rewritten to a function or a let binding of a
+/// fresh variable, followed by one let binding per variable of the pattern. Record fields are
+/// extracted by a static access, positioned at the field of the pattern, such that destructuring
+/// a record without this field fails with a `FieldMissing` error pointing to the pattern. For
+/// example,
+///
+/// ```text
+/// fun {host, port ? 80, ..} => body
+/// ```
+///
+/// is desugared to
+///
+/// ```text
+/// fun %0 => let host = %0.host in let port = if hasField "port" %0 then %0.port else 80 in body
+/// ```
+///
+/// Unless a record pattern ends with `..`, the value must not have other fields than the ones of
+/// the pattern: the fields are then extracted from `%checkFields% [host, port] %0` instead, which
+/// fails with an unexpected field error otherwise. The fields without a default value are checked
+/// to be present beforehand, such that a missing field is reported first. The pattern `{host,
+/// port ? 80}` thus extracts its fields from
+///
+/// ```text
+/// if hasField "host" %0 then %checkFields% [host, port] %0 else %0.host
+/// ```
+///
+/// The elements of a list pattern are extracted by `elemAt` from `%checkLength% n %0`, which fails
+/// with a pattern mismatch error if the list does not have the length `n` of the pattern. As for
+/// missing fields, this is only checked once a variable of the pattern is used.
+pub mod desugar_destructuring {
+    use super::fresh_var;
+    use crate::identifier::Ident;
+    use crate::term::{BinaryOp, Pattern, RichTerm, Term, UnaryOp};
+
+    /// Desugar the top-level node of an AST if it is a function or a let binding with a pattern,
+    /// or return the term unchanged.
+    ///
+    /// As for the share normal form, this transformation is not recursive and must be used in
+    /// conjunction with a traversal.
+    pub fn transform_one(rt: RichTerm) -> RichTerm {
+        let RichTerm { term, pos } = rt;
+        match *term {
+            Term::FunPattern(pat, body) => {
+                let x = fresh_var();
+                let body = destruct(pat, &x, body);
+                RichTerm {
+                    term: Box::new(Term::Fun(x, body)),
+                    pos,
+                }
+            }
+            Term::LetPattern(pat, bound, body) => {
+                let x = fresh_var();
+                let body = destruct(pat, &x, body);
+                RichTerm {
+                    term: Box::new(Term::Let(x, bound, body)),
+                    pos,
+                }
+            }
+            t => RichTerm {
+                term: Box::new(t),
+                pos,
+            },
+        }
+    }
 
-        result.into()
+    /// Bind the variables of a pattern to the corresponding parts of `value` in `body`.
+    pub fn bind(pat: Pattern, value: RichTerm, body: RichTerm) -> RichTerm {
+        match pat {
+            Pattern::Ident(id) => Term::Let(id, value, body).into(),
+            pat => {
+                let x = fresh_var();
+                Term::Let(x.clone(), value, destruct(pat, &x, body)).into()
+            }
+        }
+    }
+
+    /// Bind the variables of a pattern to the corresponding parts of the variable `x` in `body`.
+    pub fn destruct(pat: Pattern, x: &Ident, body: RichTerm) -> RichTerm {
+        let var = || RichTerm::from(Term::Var(x.clone()));
+
+        match pat {
+            Pattern::Ident(id) => Term::Let(id, var(), body).into(),
+            Pattern::Record(fields, false, pos) => {
+                let checked = fresh_var();
+                let ids = fields.iter().map(|field| field.id.clone()).collect();
+                let check = RichTerm {
+                    term: Box::new(Term::Op1(UnaryOp::CheckFields(ids), var())),
+                    pos,
+                };
+                let check = fields
+                    .iter()
+                    .rev()
+                    .filter(|field| field.default.is_none())
+                    .fold(check, |check, field| {
+                        let has_field = Term::Op2(
+                            BinaryOp::HasField(),
+                            Term::Str(field.id.0.clone()).into(),
+                            var(),
+                        );
+                        let access = RichTerm {
+                            term: Box::new(Term::Op1(
+                                UnaryOp::StaticAccess(field.id.clone()),
+                                var(),
+                            )),
+                            pos: field.pos.clone(),
+                        };
+                        RichTerm::ite(has_field.into(), check, access)
+                    });
+                let body = destruct(Pattern::Record(fields, true, None), &checked, body);
+                Term::Let(checked, check, body).into()
+            }
+            Pattern::Record(fields, true, _) => {
+                fields.into_iter().rev().fold(body, |body, field| {
+                    let access = RichTerm {
+                        term: Box::new(Term::Op1(UnaryOp::StaticAccess(field.id.clone()), var())),
+                        pos: field.pos,
+                    };
+                    let value = match field.default {
+                        Some(default) => RichTerm::ite(
+                            Term::Op2(
+                                BinaryOp::HasField(),
+                                Term::Str(field.id.0.clone()).into(),
+                                var(),
+                            )
+                            .into(),
+                            access,
+                            default,
+                        ),
+                        None => access,
+                    };
+
+                    bind(field.pattern, value, body)
+                })
+            }
+            Pattern::List(pats, pos) => {
+                let checked = fresh_var();
+                let check = RichTerm {
+                    term: Box::new(Term::Op1(UnaryOp::CheckLength(pats.len()), var())),
+                    pos: pos.clone(),
+                };
+                let body = pats
+                    .into_iter()
+                    .enumerate()
+                    .rev()
+                    .fold(body, |body, (i, pat)| {
+                        let elem = RichTerm {
+                            term: Box::new(Term::Op2(
+                                BinaryOp::ListElemAt(),
+                                Term::Var(checked.clone()).into(),
+                                Term::Num(i as f64).into(),
+                            )),
+                            pos: pos.clone(),
+                        };
+
+                        bind(pat, elem, body)
+                    });
+                Term::Let(checked, check, body).into()
+            }
+        }
+    }
+}
+
+/// Desugaring of match expressions.
+///
+/// The matched value is bound to a fresh variable, and the cases are rewritten to a chain of
+/// if-then-else testing each pattern in order. The body of a case is wrapped in the let bindings of
+/// the variables of its pattern, as done by the [destructuring
+/// transformation](../desugar_destructuring/index.html). If no case matches, the evaluation fails
+/// with a non-exhaustive match error. For example,
+///
+/// ```text
+/// match { `a => 1, {x, y ? 0} => x + y } t
+/// ```
+///
+/// is desugared to
+///
+/// ```text
+/// let %0 = t in
+/// if %0 == `a then 1
+/// else if isRecord %0 && hasField "x" %0 && 0 + 1 + (if hasField "y" %0 then 1 else 0) == length (fieldsOf %0)
+///   then let x = %0.x in let y = if hasField "y" %0 then %0.y else 0 in x + y
+/// else %matchFailure% %0
+/// ```
+///
+/// Contrarily to destructuring, record patterns only match records with no other fields than the
+/// ones of the pattern, unless they end with `..`, and list patterns only match lists of the same
+/// length. A tag followed by a pattern, as in `` `Tcp {port} ``, only matches variants with this
+/// tag whose value matches the pattern, while a bare tag only matches the bare tag.
+pub mod desugar_match {
+    use super::{desugar_destructuring, fresh_var};
+    use crate::identifier::Ident;
+    use crate::term::{BinaryOp, MatchPattern, Pattern, RichTerm, Term, UnaryOp};
+
+    /// Desugar the top-level node of an AST if it is a match expression, or return the term
+    /// unchanged.
+    ///
+    /// As for the share normal form, this transformation is not recursive and must be used in
+    /// conjunction with a traversal.
+    pub fn transform_one(rt: RichTerm) -> RichTerm {
+        let RichTerm { term, pos } = rt;
+        match *term {
+            Term::Match(cases, t) => {
+                let x = fresh_var();
+                let var = || RichTerm::from(Term::Var(x.clone()));
+                let failure = RichTerm {
+                    term: Box::new(Term::Op1(UnaryOp::MatchFailure(), var())),
+                    pos,
+                };
+
+                let payload = || RichTerm::from(Term::Op1(UnaryOp::Payload(), var()));
+
+                let chain = cases.into_iter().rev().fold(failure, |next, (pat, body)| {
+                    let test = match &pat {
+                        MatchPattern::Enum(id, None) => Some(eq(var(), Term::Enum(id.clone()))),
+                        MatchPattern::Enum(id, Some(pat)) => {
+                            let is_variant = Term::Op1(UnaryOp::IsVariant(id.clone()), var());
+                            let tests = std::iter::once(is_variant.into())
+                                .chain(test(pat, payload()))
+                                .collect();
+                            Some(and_all(tests))
+                        }
+                        MatchPattern::Bool(b) => Some(eq(var(), Term::Bool(*b))),
+                        MatchPattern::Num(n) => Some(eq(var(), Term::Num(*n))),
+                        MatchPattern::Str(s) => Some(eq(var(), Term::Str(s.clone()))),
+                        MatchPattern::Destruct(pat) => test(pat, var()),
+                        MatchPattern::Wildcard => None,
+                    };
+                    let body = match pat {
+                        MatchPattern::Destruct(pat) => {
+                            desugar_destructuring::destruct(pat, &x, body)
+                        }
+                        MatchPattern::Enum(_, Some(pat)) => {
+                            desugar_destructuring::bind(pat, payload(), body)
+                        }
+                        _ => body,
+                    };
+
+                    match test {
+                        Some(test) => RichTerm::ite(test, body, next),
+                        None => body,
+                    }
+                });
+
+                Term::Let(x, t, chain).into()
+            }
+            t => RichTerm {
+                term: Box::new(t),
+                pos,
+            },
+        }
+    }
+
+    /// Build the test of a destructuring pattern against a value, or return `None` if the pattern
+    /// matches any value.
+    fn test(pat: &Pattern, value: RichTerm) -> Option<RichTerm> {
+        match pat {
+            Pattern::Ident(_) => None,
+            Pattern::Record(fields, open, _) => {
+                let has_field = |id: &Ident| -> RichTerm {
+                    Term::Op2(
+                        BinaryOp::HasField(),
+                        Term::Str(id.0.clone()).into(),
+                        value.clone(),
+                    )
+                    .into()
+                };
+                let access = |id: &Ident| -> RichTerm {
+                    Term::Op1(UnaryOp::StaticAccess(id.clone()), value.clone()).into()
+                };
+                let mut tests = vec![Term::Op1(UnaryOp::IsRecord(), value.clone()).into()];
+
+                for field in fields.iter() {
+                    let sub_test = test(&field.pattern, access(&field.id));
+                    match (&field.default, sub_test) {
+                        (None, sub_test) => {
+                            tests.push(has_field(&field.id));
+                            tests.extend(sub_test);
+                        }
+                        (Some(_), Some(sub_test)) => tests.push(RichTerm::ite(
+                            has_field(&field.id),
+                            sub_test,
+                            Term::Bool(true).into(),
+                        )),
+                        (Some(_), None) => (),
+                    }
+                }
+
+                if !open {
+                    // The required fields are present at this point: the record has no other
+                    // fields if its size is the number of required fields plus the number of
+                    // optional fields which are present.
+                    let size = fields
+                        .iter()
+                        .fold(RichTerm::from(Term::Num(0.0)), |acc, field| {
+                            let count = match field.default {
+                                Some(_) => RichTerm::ite(
+                                    has_field(&field.id),
+                                    Term::Num(1.0).into(),
+                                    Term::Num(0.0).into(),
+                                ),
+                                None => Term::Num(1.0).into(),
+                            };
+                            Term::Op2(BinaryOp::Plus(), acc, count).into()
+                        });
+                    let length = Term::Op1(
+                        UnaryOp::ListLength(),
+                        Term::Op1(UnaryOp::FieldsOf(), value.clone()).into(),
+                    );
+                    tests.push(eq(size, length));
+                }
+
+                Some(and_all(tests))
+            }
+            Pattern::List(pats, _) => {
+                let mut tests = vec![
+                    Term::Op1(UnaryOp::IsList(), value.clone()).into(),
+                    eq(
+                        Term::Op1(UnaryOp::ListLength(), value.clone()).into(),
+                        Term::Num(pats.len() as f64),
+                    ),
+                ];
+
+                tests.extend(pats.iter().enumerate().filter_map(|(i, pat)| {
+                    let elem = Term::Op2(
+                        BinaryOp::ListElemAt(),
+                        value.clone(),
+                        Term::Num(i as f64).into(),
+                    );
+                    test(pat, elem.into())
+                }));
+
+                Some(and_all(tests))
+            }
+        }
+    }
+
+    /// Build the test `t1 == t2`.
+    fn eq(t1: RichTerm, t2: Term) -> RichTerm {
+        Term::Op2(BinaryOp::Eq(), t1, t2.into()).into()
+    }
+
+    /// Build the lazy conjunction of a non-empty list of tests, which are evaluated in order.
+    fn and_all(tests: Vec<RichTerm>) -> RichTerm {
+        let mut tests = tests.into_iter().rev();
+        let last = tests.next().expect("and_all(): empty list of tests");
+        tests.fold(last, |acc, test| {
+            RichTerm::app(Term::Op1(UnaryOp::BoolAnd(), test).into(), acc)
+        })
     }
 }
 
@@ -280,8 +701,8 @@ struct TransformState<'a, R> {
     parent: Option<PathBuf>,
 }
 
-/// Apply all program transformations, which are currently the share normal form transformation and
-/// import resolution.
+/// Apply all program transformations, which are currently the desugaring of match expressions and
+/// destructuring patterns, the share normal form transformation and import resolution.
 ///
 /// All resolved imports are stacked during the transformation. Once the term has been traversed,
 /// the elements of this stack are processed (and so on, if these elements also have non resolved
@@ -322,6 +743,8 @@ where
     // Apply one step of each transformation. If an import is resolved, then stack it.
     rt.traverse(
         &mut |rt: RichTerm, state: &mut TransformState<R>| -> Result<RichTerm, ImportError> {
+            let rt = desugar_match::transform_one(rt);
+            let rt = desugar_destructuring::transform_one(rt);
             let rt = share_normal_form::transform_one(rt);
             let (rt, pending) =
                 import_resolution::transform_one(rt, state.resolver, &state.parent)?;
@@ -337,7 +760,7 @@ where
 }
 
 /// Generate a new fresh variable which do not clash with user-defined variables.
-fn fresh_var() -> Ident {
+pub fn fresh_var() -> Ident {
     Ident(format!("%{}", FreshVarCounter::next()))
 }
 
@@ -364,18 +787,25 @@ impl Closurizable for RichTerm {
             env: with_env,
         };
 
-        env.insert(var.clone(), (Rc::new(RefCell::new(c)), IdentKind::Record()));
+        env.insert(
+            var.clone(),
+            (Rc::new(RefCell::new(c)), IdentKind::Record(), None),
+        );
 
         Term::Var(var).into()
     }
 }
 
 impl Closurizable for Types {
-    /// Pack the contract of a type together with an environment as a closure.
+    /// Pack the contracts of a type together with an environment as closures.
     ///
-    /// Extract the underlying contract, closurize it and wrap it back as a flat type (an opaque
-    /// type defined by a custom contract).
+    /// Only the custom contracts of flat types may refer to the environment, the other contracts
+    /// being builtins from the global environment. The custom contracts are closurized, and the
+    /// rest of the type is kept as is, such that it can still be printed.
     fn closurize(self, env: &mut Environment, with_env: Environment) -> Types {
-        Types(AbsType::Flat(self.contract().closurize(env, with_env)))
+        match self.0 {
+            AbsType::Flat(t) => Types(AbsType::Flat(t.closurize(env, with_env))),
+            ty => Types(ty.map(|ty| Box::new(ty.closurize(env, with_env.clone())))),
+        }
     }
 }
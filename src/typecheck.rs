@@ -4,10 +4,10 @@
 //!
 //! Typechecking can be made in to different modes:
 //! - **Strict**: correspond to traditional typechecking in strongly, statically typed languages.
-//! This happens inside a `Promise` block.
+//!   This happens inside a `Promise` block.
 //! - **Non strict**: do not enforce any typing, but still store the annotations of let bindings in
-//! the environment, and continue to traverse the AST looking for other `Promise` blocks to
-//! typecheck.
+//!   the environment, and continue to traverse the AST looking for other `Promise` blocks to
+//!   typecheck.
 //!
 //! The algorithm starts in non strict mode. It is switched to strict mode when entering a
 //! `Promise` block, and is switched to non-strict mode when entering an `Assume` block.  `Promise`
@@ -20,7 +20,7 @@
 //! expressions (the type of `bound_exp` in `let x = bound_exp in body`) is inferred in strict
 //! mode, but it is never implicitly generalized. For example, the following program is rejected:
 //!
-//! ```
+//! ```text
 //! // Rejected
 //! Promise(Num, let id = fun x => x in seq (id "a") (id 5))
 //! ```
@@ -33,7 +33,7 @@
 //! can interact with other parts of type inference. If polymorphism is required, a simple
 //! annotation is sufficient:
 //!
-//! ```
+//! ```text
 //! // Accepted
 //! Promise(Num, let id = Promise(forall a. a -> a, fun x => x) in seq (id "a") (id 5))
 //! ```
@@ -45,7 +45,7 @@ use crate::identifier::Ident;
 use crate::label::ty_path;
 use crate::position::RawSpan;
 use crate::program::ImportResolver;
-use crate::term::{BinaryOp, RichTerm, StrChunk, Term, UnaryOp};
+use crate::term::{BinaryOp, MatchPattern, Pattern, RichTerm, StrChunk, Term, UnaryOp};
 use crate::types::{AbsType, Types};
 use std::collections::{HashMap, HashSet};
 
@@ -57,8 +57,8 @@ pub enum RowUnifError {
     /// The RHS had a binding that was not in the LHS.
     ExtraRow(Ident),
     /// There were two incompatible definitions for the same row.
-    RowMismatch(Ident, UnifError),
-    /// Tried to unify an enum row and a record row.
+    RowMismatch(Ident, Box<UnifError>),
+    /// Tried to unify a bare enum tag with a variant carrying a value.
     RowKindMismatch(Ident, Option<TypeWrapper>, Option<TypeWrapper>),
     /// One of the row was ill-formed (typically, a tail was neither a row nor a variable).
     ///
@@ -91,11 +91,11 @@ impl RowUnifError {
             RowUnifError::RowKindMismatch(id, tyw1, tyw2) => {
                 UnifError::RowKindMismatch(id, tyw1, tyw2)
             }
-            RowUnifError::RowMismatch(id, err) => {
-                UnifError::RowMismatch(id, left, right, Box::new(err))
-            }
+            RowUnifError::RowMismatch(id, err) => UnifError::RowMismatch(id, left, right, err),
             RowUnifError::IllformedRow(tyw) => UnifError::IllformedRow(tyw),
-            RowUnifError::UnsatConstr(id, tyw) => UnifError::RowConflict(id, tyw, left, right),
+            RowUnifError::UnsatConstr(id, tyw) => {
+                UnifError::RowConflict(id, tyw.map(Box::new), left, right)
+            }
             RowUnifError::WithConst(c, tyw) => UnifError::WithConst(c, tyw),
             RowUnifError::ConstMismatch(c1, c2) => UnifError::ConstMismatch(c1, c2),
         }
@@ -109,7 +109,7 @@ pub enum UnifError {
     TypeMismatch(TypeWrapper, TypeWrapper),
     /// There are two incompatible definitions for the same row.
     RowMismatch(Ident, TypeWrapper, TypeWrapper, Box<UnifError>),
-    /// Tried to unify an enum row and a record row.
+    /// Tried to unify a bare enum tag with a variant carrying a value.
     RowKindMismatch(Ident, Option<TypeWrapper>, Option<TypeWrapper>),
     /// Tried to unify two distinct type constants.
     ConstMismatch(usize, usize),
@@ -121,7 +121,7 @@ pub enum UnifError {
     IllformedRow(TypeWrapper),
     /// Tried to unify a unification variable with a row type violating the [row
     /// constraints](./type.RowConstr.html) of the variable.
-    RowConflict(Ident, Option<TypeWrapper>, TypeWrapper, TypeWrapper),
+    RowConflict(Ident, Option<Box<TypeWrapper>>, TypeWrapper, TypeWrapper),
     /// Tried to unify a type constant with another different type.
     WithConst(usize, TypeWrapper),
     /// A flat type, which is an opaque type corresponding to custom contracts, contained a Nickel
@@ -160,9 +160,9 @@ impl UnifError {
     /// # Parameters
     ///
     /// - `state`: the state of unification. Used to access the unification table, and the original
-    /// names of of unification variable or type constant.
+    ///   names of of unification variable or type constant.
     /// - `names`: a [name registry](./reporting/struct.NameReg.html), structure used to assign
-    /// unique a humain-readable names to unification variables and type constants.
+    ///   unique a humain-readable names to unification variables and type constants.
     /// - `pos_opt`: the position span of the expression that failed to typecheck.
     pub fn to_typecheck_err_(
         self,
@@ -226,7 +226,7 @@ impl UnifError {
             }
             UnifError::RowConflict(id, tyw, left, right) => TypecheckError::RowConflict(
                 id,
-                tyw.map(|tyw| reporting::to_type(state, names, tyw)),
+                tyw.map(|tyw| Box::new(reporting::to_type(state, names, *tyw))),
                 reporting::to_type(state, names, left),
                 reporting::to_type(state, names, right),
                 pos_opt,
@@ -335,7 +335,7 @@ impl<'a> Envs<'a> {
     pub fn mk_global(eval_env: &eval::Environment, table: &mut UnifTable) -> Environment {
         eval_env
             .iter()
-            .map(|(id, (rc, _))| {
+            .map(|(id, (rc, _, _))| {
                 (
                     id.clone(),
                     apparent_type(rc.borrow().body.as_ref(), table, false),
@@ -372,6 +372,11 @@ pub struct State<'a> {
     ///
     /// Used for error reporting.
     names: &'a mut HashMap<usize, Ident>,
+    /// If set, record the type of each subterm which has a position.
+    ///
+    /// Used by the language server to answer hover requests (see
+    /// [`type_check_with_table`](fn.type_check_with_table.html)).
+    type_table: Option<&'a mut Vec<(RawSpan, TypeWrapper)>>,
 }
 
 /// Typecheck a term.
@@ -388,12 +393,68 @@ pub fn type_check(
         table: &mut UnifTable::new(),
         constr: &mut RowConstr::new(),
         names: &mut HashMap::new(),
+        type_table: None,
+    };
+    let ty = TypeWrapper::Ptr(new_var(state.table));
+    let global = Envs::mk_global(global_eval_env, state.table);
+    type_check_(&mut state, Envs::from_global(&global), false, t, ty.clone())?;
+
+    Ok(to_type(state.table, ty))
+}
+
+/// Infer the type of a term in strict mode, as if it was the body of a `Promise`.
+///
+/// Outside of a `Promise`, [`type_check`](./fn.type_check.html) does not infer anything, and the
+/// type of an unannotated term is just `Dyn`. This function gives a more precise type to a term
+/// which is well-typed in strict mode, and returns `None` otherwise.
+pub fn infer_type(
+    t: &RichTerm,
+    global_eval_env: &eval::Environment,
+    resolver: &mut dyn ImportResolver,
+) -> Option<Types> {
+    let mut state = State {
+        resolver,
+        table: &mut UnifTable::new(),
+        constr: &mut RowConstr::new(),
+        names: &mut HashMap::new(),
+        type_table: None,
+    };
+    let ty = TypeWrapper::Ptr(new_var(state.table));
+    let global = Envs::mk_global(global_eval_env, state.table);
+    type_check_(&mut state, Envs::from_global(&global), true, t, ty.clone()).ok()?;
+
+    Some(to_type(state.table, ty))
+}
+
+/// Typecheck a term, and return the type of each of its subterms which has a position together
+/// with the inferred type.
+///
+/// Same as [`type_check`](./fn.type_check.html). Outside of a `Promise`, the typechecker does not
+/// infer anything and the recorded types are mostly `Dyn`, but annotated terms and terms inside
+/// a `Promise` get their actual type.
+pub fn type_check_with_table(
+    t: &RichTerm,
+    global_eval_env: &eval::Environment,
+    resolver: &mut dyn ImportResolver,
+) -> Result<(Types, Vec<(RawSpan, Types)>), TypecheckError> {
+    let mut type_table = Vec::new();
+    let mut table = UnifTable::new();
+    let mut state = State {
+        resolver,
+        table: &mut table,
+        constr: &mut RowConstr::new(),
+        names: &mut HashMap::new(),
+        type_table: Some(&mut type_table),
     };
     let ty = TypeWrapper::Ptr(new_var(state.table));
     let global = Envs::mk_global(global_eval_env, state.table);
     type_check_(&mut state, Envs::from_global(&global), false, t, ty.clone())?;
 
-    Ok(to_type(&state.table, ty))
+    let types = type_table
+        .into_iter()
+        .map(|(span, tyw)| (span, to_type(&table, tyw)))
+        .collect();
+    Ok((to_type(&table, ty), types))
 }
 
 /// Typecheck a term using the given global typing environment. Same as
@@ -416,11 +477,12 @@ pub fn type_check_in_env(
         table: &mut UnifTable::new(),
         constr: &mut RowConstr::new(),
         names: &mut HashMap::new(),
+        type_table: None,
     };
     let ty = TypeWrapper::Ptr(new_var(state.table));
     type_check_(&mut state, Envs::from_global(global), false, t, ty.clone())?;
 
-    Ok(to_type(&state.table, ty))
+    Ok(to_type(state.table, ty))
 }
 
 /// Typecheck a term against a specific type.
@@ -441,6 +503,10 @@ fn type_check_(
 ) -> Result<(), TypecheckError> {
     let RichTerm { term: t, pos } = rt;
 
+    if let (Some(type_table), Some(span)) = (state.type_table.as_mut(), pos) {
+        type_table.push((span.clone(), ty.clone()));
+    }
+
     match t.as_ref() {
         Term::Bool(_) => unify(state, strict, ty, TypeWrapper::Concrete(AbsType::Bool()))
             .map_err(|err| err.to_typecheck_err(state, &rt.pos)),
@@ -481,6 +547,17 @@ fn type_check_(
             envs.insert(x.clone(), src);
             type_check_(state, envs, strict, t, trg)
         }
+        Term::FunPattern(pat, t) => {
+            let src = TypeWrapper::Ptr(new_var(state.table));
+            let trg = TypeWrapper::Ptr(new_var(state.table));
+            let arr =
+                TypeWrapper::Concrete(AbsType::arrow(Box::new(src.clone()), Box::new(trg.clone())));
+
+            unify(state, strict, ty, arr).map_err(|err| err.to_typecheck_err(state, &rt.pos))?;
+
+            bind_pattern(state, &mut envs, strict, pat, src)?;
+            type_check_(state, envs, strict, t, trg)
+        }
         Term::List(terms) => {
             unify(state, strict, ty, TypeWrapper::Concrete(AbsType::List()))
                 .map_err(|err| err.to_typecheck_err(state, &rt.pos))?;
@@ -509,10 +586,167 @@ fn type_check_(
             let ty_let = apparent_type(re.as_ref(), state.table, strict);
             type_check_(state, envs.clone(), strict, re, ty_let.clone())?;
 
-            // TODO move this up once lets are rec
             envs.insert(x.clone(), ty_let);
             type_check_(state, envs, strict, rt, ty)
         }
+        Term::LetRec(bindings, rt) => {
+            // As for recursive records, the apparent type of each bound term is put in the
+            // environment before typechecking the bound terms, such that an annotated recursive
+            // function is checked against its own annotation.
+            let tys: Vec<TypeWrapper> = bindings
+                .iter()
+                .map(|(x, re)| {
+                    let ty_let = apparent_type(re.as_ref(), state.table, strict);
+                    envs.insert(x.clone(), ty_let.clone());
+                    ty_let
+                })
+                .collect();
+
+            bindings.iter().zip(tys).try_for_each(
+                |((_, re), ty_let)| -> Result<(), TypecheckError> {
+                    type_check_(state, envs.clone(), strict, re, ty_let)
+                },
+            )?;
+
+            type_check_(state, envs, strict, rt, ty)
+        }
+        Term::LetPattern(pat, re, rt) => {
+            let ty_let = apparent_type(re.as_ref(), state.table, strict);
+            type_check_(state, envs.clone(), strict, re, ty_let.clone())?;
+
+            bind_pattern(state, &mut envs, strict, pat, ty_let)?;
+            type_check_(state, envs, strict, rt, ty)
+        }
+        Term::Match(cases, t) => {
+            // As for switch, the matched value must be an enum whose row only contains the tags of
+            // the cases, unless there is a catch-all case. Missing cases for a closed enum type
+            // are thus reported as a type error in strict mode.
+            let src = TypeWrapper::Ptr(new_var(state.table));
+            let closed = !cases.iter().any(|(pat, _)| pat.is_catch_all());
+            let mut tags: Vec<(Ident, Option<TypeWrapper>)> = Vec::new();
+            let mut payload_tys = Vec::with_capacity(cases.len());
+
+            for (pat, _) in cases.iter() {
+                let mut payload_ty = None;
+                let const_ty = match pat {
+                    MatchPattern::Enum(id, pat_opt) => {
+                        let ty_opt = pat_opt
+                            .as_ref()
+                            .map(|_| TypeWrapper::Ptr(new_var(state.table)));
+
+                        // The values of the variants of several cases with the same tag have the
+                        // same type.
+                        match tags.iter().find(|(id2, _)| id2 == id) {
+                            Some((_, Some(ty))) if ty_opt.is_some() => {
+                                payload_ty = Some(ty.clone())
+                            }
+                            Some((_, None)) if ty_opt.is_none() => (),
+                            Some((_, prev)) if strict => {
+                                return Err(UnifError::RowKindMismatch(
+                                    id.clone(),
+                                    prev.clone(),
+                                    ty_opt,
+                                )
+                                .to_typecheck_err(state, &rt.pos))
+                            }
+                            Some(_) => payload_ty = ty_opt,
+                            None => {
+                                payload_ty = ty_opt.clone();
+                                tags.push((id.clone(), ty_opt));
+                            }
+                        }
+                        None
+                    }
+                    MatchPattern::Bool(_) => Some(AbsType::Bool()),
+                    MatchPattern::Num(_) => Some(AbsType::Num()),
+                    MatchPattern::Str(_) => Some(AbsType::Str()),
+                    MatchPattern::Destruct(_) | MatchPattern::Wildcard => None,
+                };
+
+                if let Some(const_ty) = const_ty {
+                    unify(state, strict, src.clone(), TypeWrapper::Concrete(const_ty))
+                        .map_err(|err| err.to_typecheck_err(state, &rt.pos))?;
+                }
+
+                payload_tys.push(payload_ty);
+            }
+
+            let exhaustive_check = !tags.is_empty() && closed;
+            let case_tags: Vec<Ident> = tags.iter().map(|(id, _)| id.clone()).collect();
+            if !tags.is_empty() {
+                let tail = if closed {
+                    TypeWrapper::Concrete(AbsType::RowEmpty())
+                } else {
+                    TypeWrapper::Ptr(new_var(state.table))
+                };
+                let row = tags.into_iter().rev().fold(tail, |acc, (id, ty)| {
+                    TypeWrapper::Concrete(AbsType::RowExtend(id, ty.map(Box::new), Box::new(acc)))
+                });
+
+                unify(
+                    state,
+                    strict,
+                    src.clone(),
+                    TypeWrapper::Concrete(AbsType::Enum(Box::new(row))),
+                )
+                .map_err(|err| err.to_typecheck_err(state, &rt.pos))?;
+            }
+
+            // The type of the matched value is unified with the one of the cases separately, so
+            // that the tags of this type without a case, or an open row which may contain such
+            // tags, are reported as a non-exhaustive match. A tag of a case which is absent from
+            // the type of the matched value is reported as a missing row of this type instead:
+            // row unification does not tell on which side an extra row is, but only the tags of
+            // the matched value can be absent from the cases.
+            let t_ty = TypeWrapper::Ptr(new_var(state.table));
+            type_check_(state, envs.clone(), strict, t, t_ty.clone())?;
+            unify(state, strict, src.clone(), t_ty.clone()).map_err(|err| {
+                let non_exhaustive = if exhaustive_check {
+                    enum_tags(state.table, &t_ty)
+                        .map(|(tags, open)| {
+                            let missing: Vec<Ident> = tags
+                                .into_iter()
+                                .filter(|id| !case_tags.contains(id))
+                                .collect();
+                            (missing, open)
+                        })
+                        .filter(|(missing, open)| !missing.is_empty() || *open)
+                } else {
+                    None
+                };
+
+                match (err, non_exhaustive) {
+                    (_, Some((missing, open))) => TypecheckError::NonExhaustiveMatch(
+                        missing,
+                        open,
+                        rt.pos.clone(),
+                        t.pos.clone(),
+                    ),
+                    (UnifError::ExtraRow(id, tyw1, tyw2), None) if case_tags.contains(&id) => {
+                        UnifError::MissingRow(id, tyw1, tyw2).to_typecheck_err(state, &t.pos)
+                    }
+                    (err, None) => err.to_typecheck_err(state, &t.pos),
+                }
+            })?;
+
+            cases.iter().zip(payload_tys).try_for_each(
+                |((pat, body), payload_ty)| -> Result<(), TypecheckError> {
+                    let mut envs = envs.clone();
+
+                    match (pat, payload_ty) {
+                        (MatchPattern::Destruct(pat), _) => {
+                            bind_pattern(state, &mut envs, strict, pat, src.clone())?
+                        }
+                        (MatchPattern::Enum(_, Some(pat)), Some(payload_ty)) => {
+                            bind_pattern(state, &mut envs, strict, pat, payload_ty)?
+                        }
+                        _ => (),
+                    }
+
+                    type_check_(state, envs, strict, body, ty.clone())
+                },
+            )
+        }
         Term::App(e, t) => {
             let src = TypeWrapper::Ptr(new_var(state.table));
             let arr = TypeWrapper::Concrete(AbsType::arrow(Box::new(src.clone()), Box::new(ty)));
@@ -525,7 +759,7 @@ fn type_check_(
         }
         Term::Var(x) => {
             let x_ty = envs
-                .get(&x)
+                .get(x)
                 .ok_or_else(|| TypecheckError::UnboundIdentifier(x.clone(), pos.clone()))?;
 
             let instantiated = instantiate_foralls_with(state, x_ty.clone(), TypeWrapper::Ptr);
@@ -544,10 +778,25 @@ fn type_check_(
             )
             .map_err(|err| err.to_typecheck_err(state, &rt.pos))
         }
-        Term::Record(stat_map) | Term::RecRecord(stat_map) => {
+        Term::EnumVariant(id, t) => {
+            let row = TypeWrapper::Ptr(new_var(state.table));
+            let payload = TypeWrapper::Ptr(new_var(state.table));
+            unify(
+                state,
+                strict,
+                ty,
+                TypeWrapper::Concrete(AbsType::Enum(Box::new(TypeWrapper::Concrete(
+                    AbsType::RowExtend(id.clone(), Some(Box::new(payload.clone())), Box::new(row)),
+                )))),
+            )
+            .map_err(|err| err.to_typecheck_err(state, &rt.pos))?;
+
+            type_check_(state, envs, strict, t, payload)
+        }
+        Term::Record(stat_map) | Term::RecRecord(stat_map, _) => {
             // For recursive records, we look at the apparent type of each field and bind it in
             // env before actually typechecking the content of fields
-            if let Term::RecRecord(_) = t.as_ref() {
+            if let Term::RecRecord(_, _) = t.as_ref() {
                 envs.local.extend(
                     stat_map.iter().map(|(id, rt)| {
                         (id.clone(), apparent_type(rt.as_ref(), state.table, strict))
@@ -564,19 +813,19 @@ fn type_check_(
             if let TypeWrapper::Concrete(AbsType::DynRecord(rec_ty)) = root_ty.clone() {
                 // Checking for an dynamic record
                 stat_map
-                    .into_iter()
+                    .iter()
                     .try_for_each(|(_, t)| -> Result<(), TypecheckError> {
                         type_check_(state, envs.clone(), strict, t, (*rec_ty).clone())
                     })
             } else {
-                let row = stat_map.into_iter().try_fold(
+                let row = stat_map.iter().try_fold(
                     TypeWrapper::Concrete(AbsType::RowEmpty()),
                     |acc, (id, field)| -> Result<TypeWrapper, TypecheckError> {
                         // In the case of a recursive record, new types (either type variables or
                         // annotations) have already be determined and put in the typing
                         // environment, and we need to use the same.
-                        let ty = if let Term::RecRecord(_) = t.as_ref() {
-                            envs.get(&id).unwrap().clone()
+                        let ty = if let Term::RecRecord(_, _) = t.as_ref() {
+                            envs.get(id).unwrap().clone()
                         } else {
                             TypeWrapper::Ptr(new_var(state.table))
                         };
@@ -635,7 +884,7 @@ fn type_check_(
                 .map_err(|err| err.to_typecheck_err(state, &rt.pos))?;
             type_check_(state, envs, true, t, instantiated)
         }
-        Term::Assume(ty2, _, t) => {
+        Term::Assume(ty2, _, t) | Term::ContractWithValue(ty2, _, t) => {
             unify(state, strict, ty.clone(), to_typewrapper(ty2.clone()))
                 .map_err(|err| err.to_typecheck_err(state, &rt.pos))?;
             let new_ty = TypeWrapper::Ptr(new_var(state.table));
@@ -646,20 +895,105 @@ fn type_check_(
         Term::Wrapped(_, t)
         | Term::DefaultValue(t)
         | Term::ContractWithDefault(_, _, t)
-        | Term::Docstring(_, t) => type_check_(state, envs, strict, t, ty),
+        | Term::Docstring(_, t)
+        | Term::Optional(t)
+        | Term::MergeStrategy(_, t)
+        | Term::Priority(_, t) => type_check_(state, envs, strict, t, ty),
         Term::Contract(_, _) => Ok(()),
         Term::Import(_) => unify(state, strict, ty, TypeWrapper::Concrete(AbsType::Dyn()))
             .map_err(|err| err.to_typecheck_err(state, &rt.pos)),
         Term::ResolvedImport(file_id) => {
             let t = state
                 .resolver
-                .get(file_id.clone())
+                .get(*file_id)
                 .expect("Internal error: resolved import not found ({:?}) during typechecking.");
             type_check_in_env(&t, envs.global, state.resolver).map(|_ty| ())
         }
     }
 }
 
+/// Bind the variables of a destructuring pattern in the typing environment, given the type `ty` of
+/// the destructured value.
+///
+/// A record pattern requires `ty` to be a record type with one row per field without a default
+/// value. This row type is closed, unless the pattern ends with `..`. Row types can't express
+/// optional fields: if a field has a default value, the row type is open instead, and the type of
+/// the variable is the one of the default value, which is checked in the environment of the
+/// previous fields, as for the successive let bindings the pattern is desugared to. A value
+/// provided for such a field is thus not checked statically, and the absence of other fields is
+/// only checked at run time. As for list literals, the elements of a list pattern are of type
+/// `Dyn`, and are checked in non strict mode.
+fn bind_pattern(
+    state: &mut State,
+    envs: &mut Envs,
+    strict: bool,
+    pat: &Pattern,
+    ty: TypeWrapper,
+) -> Result<(), TypecheckError> {
+    match pat {
+        Pattern::Ident(x) => {
+            envs.insert(x.clone(), ty);
+            Ok(())
+        }
+        Pattern::Record(fields, open, pos) => {
+            let tys: Vec<TypeWrapper> = fields
+                .iter()
+                .map(|_| TypeWrapper::Ptr(new_var(state.table)))
+                .collect();
+            let tail = if *open || fields.iter().any(|field| field.default.is_some()) {
+                TypeWrapper::Ptr(new_var(state.table))
+            } else {
+                TypeWrapper::Concrete(AbsType::RowEmpty())
+            };
+            let row = fields
+                .iter()
+                .zip(tys.iter())
+                .filter(|(field, _)| field.default.is_none())
+                .rev()
+                .fold(tail, |acc, (field, ty)| {
+                    TypeWrapper::Concrete(AbsType::RowExtend(
+                        field.id.clone(),
+                        Some(Box::new(ty.clone())),
+                        Box::new(acc),
+                    ))
+                });
+
+            unify(
+                state,
+                strict,
+                ty,
+                TypeWrapper::Concrete(AbsType::StaticRecord(Box::new(row))),
+            )
+            .map_err(|err| err.to_typecheck_err(state, pos))?;
+
+            fields
+                .iter()
+                .zip(tys)
+                .try_for_each(|(field, ty)| -> Result<(), TypecheckError> {
+                    if let Some(default) = &field.default {
+                        type_check_(state, envs.clone(), strict, default, ty.clone())?;
+                    }
+
+                    bind_pattern(state, envs, strict, &field.pattern, ty)
+                })
+        }
+        Pattern::List(pats, pos) => {
+            unify(state, strict, ty, TypeWrapper::Concrete(AbsType::List()))
+                .map_err(|err| err.to_typecheck_err(state, pos))?;
+
+            pats.iter().try_for_each(|pat| {
+                bind_pattern(
+                    state,
+                    envs,
+                    false,
+                    pat,
+                    TypeWrapper::Concrete(AbsType::Dyn()),
+                )
+            })
+        }
+    }
+}
+
 /// Determine the apparent type of a let-bound expression.
 ///
 /// When a let-binding `let x = bound_exp in body` is processed, the type of `bound_exp` must be
@@ -675,7 +1009,9 @@ fn type_check_(
 ///       associated to `bound_exp`.
 fn apparent_type(t: &Term, table: &mut UnifTable, strict: bool) -> TypeWrapper {
     match t {
-        Term::Assume(ty, _, _) | Term::Promise(ty, _, _) => to_typewrapper(ty.clone()),
+        Term::Assume(ty, _, _) | Term::Promise(ty, _, _) | Term::ContractWithValue(ty, _, _) => {
+            to_typewrapper(ty.clone())
+        }
         _ if strict => TypeWrapper::Ptr(new_var(table)),
         _ => TypeWrapper::Concrete(AbsType::Dyn()),
     }
@@ -775,7 +1111,7 @@ fn row_add(
         }
         TypeWrapper::Ptr(root) => {
             if let Some(set) = state.constr.get(&root) {
-                if set.contains(&id) {
+                if set.contains(id) {
                     return Err(RowUnifError::UnsatConstr(id.clone(), ty.map(|tyw| *tyw)));
                 }
             }
@@ -946,6 +1282,14 @@ pub fn unify_(
             Ok(())
         }
 
+        // A variable can't be unified with a type which contains it, as in `fun x => x x`: this
+        // would create a cyclic type.
+        (TypeWrapper::Ptr(p), s @ TypeWrapper::Concrete(_)) if occurs(state.table, p, &s) => {
+            Err(UnifError::TypeMismatch(TypeWrapper::Ptr(p), s))
+        }
+        (s @ TypeWrapper::Concrete(_), TypeWrapper::Ptr(p)) if occurs(state.table, p, &s) => {
+            Err(UnifError::TypeMismatch(s, TypeWrapper::Ptr(p)))
+        }
         (TypeWrapper::Ptr(p), s @ TypeWrapper::Concrete(_))
         | (TypeWrapper::Ptr(p), s @ TypeWrapper::Constant(_))
         | (s @ TypeWrapper::Concrete(_), TypeWrapper::Ptr(p))
@@ -963,6 +1307,23 @@ pub fn unify_(
     }
 }
 
+/// Check if the unification variable `p`, which must be a root, occurs in a type.
+fn occurs(table: &UnifTable, p: usize, tyw: &TypeWrapper) -> bool {
+    match tyw {
+        TypeWrapper::Ptr(q) => match get_root(table, *q) {
+            TypeWrapper::Ptr(root) => root == p,
+            root => occurs(table, p, &root),
+        },
+        TypeWrapper::Constant(_) => false,
+        TypeWrapper::Concrete(ty) => {
+            let mut found = false;
+            ty.clone()
+                .map(|tyw| found = found || occurs(table, p, &tyw));
+            found
+        }
+    }
+}
+
 /// Try to unify two row types. Return an [`IllformedRow`](./enum.RowUnifError.html#variant.IllformedRow) error if one of the given type
 /// is not a row type.
 pub fn unify_rows(
@@ -982,7 +1343,7 @@ pub fn unify_rows(
             match (ty, ty2) {
                 (None, None) => Ok(()),
                 (Some(ty), Some(ty2)) => unify_(state, *ty, *ty2)
-                    .map_err(|err| RowUnifError::RowMismatch(id.clone(), err)),
+                    .map_err(|err| RowUnifError::RowMismatch(id.clone(), Box::new(err))),
                 (ty1, ty2) => Err(RowUnifError::RowKindMismatch(
                     id,
                     ty1.map(|t| *t),
@@ -1364,7 +1725,7 @@ pub fn get_uop_type(
             ))
         }
         // forall a b. a -> b -> b
-        UnaryOp::Seq() | UnaryOp::DeepSeq() => {
+        UnaryOp::Seq() | UnaryOp::DeepSeq(_) => {
             let fst = TypeWrapper::Ptr(new_var(state.table));
             let snd = TypeWrapper::Ptr(new_var(state.table));
 
@@ -1400,6 +1761,31 @@ pub fn get_uop_type(
             )))),
             Box::new(TypeWrapper::Concrete(AbsType::List())),
         )),
+        // Dyn -> a
+        UnaryOp::MatchFailure() => TypeWrapper::Concrete(AbsType::arrow(
+            Box::new(TypeWrapper::Concrete(AbsType::Dyn())),
+            Box::new(TypeWrapper::Ptr(new_var(state.table))),
+        )),
+        // forall a. a -> a
+        UnaryOp::CheckFields(_) => {
+            let ty = TypeWrapper::Ptr(new_var(state.table));
+            TypeWrapper::Concrete(AbsType::arrow(Box::new(ty.clone()), Box::new(ty)))
+        }
+        // List -> List
+        UnaryOp::CheckLength(_) => TypeWrapper::Concrete(AbsType::arrow(
+            Box::new(TypeWrapper::Concrete(AbsType::List())),
+            Box::new(TypeWrapper::Concrete(AbsType::List())),
+        )),
+        // Dyn -> Bool
+        UnaryOp::IsVariant(_) => TypeWrapper::Concrete(AbsType::arrow(
+            Box::new(TypeWrapper::Concrete(AbsType::Dyn())),
+            Box::new(TypeWrapper::Concrete(AbsType::Bool())),
+        )),
+        // Dyn -> a
+        UnaryOp::Payload() => TypeWrapper::Concrete(AbsType::arrow(
+            Box::new(TypeWrapper::Concrete(AbsType::Dyn())),
+            Box::new(TypeWrapper::Ptr(new_var(state.table))),
+        )),
     })
 }
 
@@ -1469,6 +1855,18 @@ pub fn get_bop_type(
                 Box::new(TypeWrapper::Concrete(AbsType::Dyn())),
             ))),
         ))),
+        // forall a. Dyn -> Dyn -> a
+        BinaryOp::BlameWith() => {
+            let res = TypeWrapper::Ptr(new_var(state.table));
+
+            Ok(TypeWrapper::Concrete(AbsType::arrow(
+                Box::new(TypeWrapper::Concrete(AbsType::Dyn())),
+                Box::new(TypeWrapper::Concrete(AbsType::arrow(
+                    Box::new(TypeWrapper::Concrete(AbsType::Dyn())),
+                    Box::new(res),
+                ))),
+            )))
+        }
         // forall a. Str -> { _ : a} -> a
         BinaryOp::DynAccess() => {
             let res = TypeWrapper::Ptr(new_var(state.table));
@@ -1503,7 +1901,7 @@ pub fn get_bop_type(
             )))
         }
         // forall a. Str -> { _ : a } -> { _ : a}
-        BinaryOp::DynRemove() => {
+        BinaryOp::DynRemove() | BinaryOp::SelectField() => {
             let res = TypeWrapper::Ptr(new_var(state.table));
 
             Ok(TypeWrapper::Concrete(AbsType::arrow(
@@ -1526,6 +1924,14 @@ pub fn get_bop_type(
             ))),
             Box::new(TypeWrapper::Concrete(AbsType::Bool())),
         ))),
+        // Str -> Dyn -> Bool
+        BinaryOp::HasOptionalField() => Ok(TypeWrapper::Concrete(AbsType::Arrow(
+            Box::new(TypeWrapper::Concrete(AbsType::Arrow(
+                Box::new(TypeWrapper::Concrete(AbsType::Str())),
+                Box::new(TypeWrapper::Concrete(AbsType::Dyn())),
+            ))),
+            Box::new(TypeWrapper::Concrete(AbsType::Bool())),
+        ))),
         // List -> List -> List
         BinaryOp::ListConcat() => Ok(TypeWrapper::Concrete(AbsType::Arrow(
             Box::new(TypeWrapper::Concrete(AbsType::List())),
@@ -1557,7 +1963,7 @@ pub fn get_bop_type(
             ))),
         ))),
         // Dyn -> Dyn -> Dyn
-        BinaryOp::Merge() => Ok(TypeWrapper::Concrete(AbsType::arrow(
+        BinaryOp::Merge() | BinaryOp::MergeWith(_, _) => Ok(TypeWrapper::Concrete(AbsType::arrow(
             Box::new(TypeWrapper::Concrete(AbsType::Dyn())),
             Box::new(TypeWrapper::Concrete(AbsType::arrow(
                 Box::new(TypeWrapper::Concrete(AbsType::Dyn())),
@@ -1618,6 +2024,33 @@ fn constraint(state: &mut State, x: TypeWrapper, id: Ident) -> Result<(), RowUni
     }
 }
 
+/// Return the tags of an enum type, together with a boolean indicating if its row is open, that is
+/// if it ends with a rigid type variable instead of the empty row. Return `None` if the type is
+/// not an enum type.
+fn enum_tags(table: &UnifTable, ty: &TypeWrapper) -> Option<(Vec<Ident>, bool)> {
+    let resolve = |ty: &TypeWrapper| match ty {
+        TypeWrapper::Ptr(p) => get_root(table, *p),
+        ty => ty.clone(),
+    };
+
+    let mut row = match resolve(ty) {
+        TypeWrapper::Concrete(AbsType::Enum(row)) => resolve(&row),
+        _ => return None,
+    };
+    let mut tags = Vec::new();
+
+    loop {
+        match row {
+            TypeWrapper::Concrete(AbsType::RowExtend(id, _, tail)) => {
+                tags.push(id);
+                row = resolve(&tail);
+            }
+            TypeWrapper::Constant(_) => return Some((tags, true)),
+            _ => return Some((tags, false)),
+        }
+    }
+}
+
 /// Follow the links in the unification table to find the representative of the equivalence class
 /// of unification variable `x`.
 ///
@@ -1654,7 +2087,9 @@ mod tests {
     fn parse_and_typecheck(s: &str) -> Result<Types, TypecheckError> {
         let id = Files::new().add("<test>", s);
 
-        if let Ok(p) = parser::grammar::TermParser::new().parse(id, lexer::Lexer::new(&s)) {
+        if let Ok(p) =
+            parser::grammar::TermParser::new().parse(id, &mut Vec::new(), lexer::Lexer::new(s))
+        {
             type_check_no_import(&p)
         } else {
             panic!("Couldn't parse {}", s)
@@ -1667,7 +2102,7 @@ mod tests {
 
         type_check_no_import(&Term::Bool(true).into())?;
         type_check_no_import(&Term::Num(45.).into())?;
-        type_check_no_import(&RichTerm::fun(String::from("x"), RichTerm::var("x".into())).into())?;
+        type_check_no_import(&RichTerm::fun(String::from("x"), RichTerm::var("x".into())))?;
         type_check_no_import(&RichTerm::let_in(
             "x",
             Term::Num(3.).into(),
@@ -2001,6 +2436,202 @@ mod tests {
         .unwrap_err();
     }
 
+    #[test]
+    fn recursive_lets() {
+        parse_and_typecheck(
+            "let rec f = Promise(Num -> Num, fun n => if n == 0 then 0 else f (n - 1)) in
+            Promise(Num, f 3)",
+        )
+        .unwrap();
+        parse_and_typecheck(
+            "let rec f = Promise(Num -> Num, fun n => if n == 0 then 0 else f true) in f 3",
+        )
+        .unwrap_err();
+        parse_and_typecheck(
+            "let rec f = Promise(Num -> Bool, fun n => if n == 0 then 0 else f (n - 1)) in f 3",
+        )
+        .unwrap_err();
+
+        parse_and_typecheck(
+            "let rec
+                even = Promise(Num -> Bool, fun n => if n == 0 then true else odd (n - 1)),
+                odd = Promise(Num -> Bool, fun n => if n == 0 then false else even (n - 1))
+            in Promise(Bool, odd 3)",
+        )
+        .unwrap();
+        parse_and_typecheck(
+            "let rec
+                even = Promise(Num -> Bool, fun n => if n == 0 then true else odd (n - 1)),
+                odd = Promise(Num -> Num, fun n => if n == 0 then 1 else even (n - 1))
+            in odd 3",
+        )
+        .unwrap_err();
+
+        // Without annotation, recursive terms are inferred inside a promise.
+        parse_and_typecheck(
+            "Promise(Num, let rec f = fun n => if n == 0 then 0 else f (n - 1) in f 3)",
+        )
+        .unwrap();
+        parse_and_typecheck(
+            "Promise(Num, let rec f = fun n => if n == 0 then 0 else f true in f 3)",
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn destructuring() {
+        parse_and_typecheck("Promise(Num, (fun {a, b} => a + b) {a = 1; b = 2})").unwrap();
+        parse_and_typecheck("Promise(Num, (fun {a} => a) {a = true})").unwrap_err();
+        parse_and_typecheck("Promise(Num, (fun {a} => a) {b = 1})").unwrap_err();
+
+        // Record patterns are closed, unless they end with `..`.
+        parse_and_typecheck("Promise(Num, (fun {a} => a) {a = 1; b = 2})").unwrap_err();
+        parse_and_typecheck("Promise(Num, (fun {a, ..} => a) {a = 1; b = 2})").unwrap();
+        parse_and_typecheck(
+            "Promise(forall r. { {| a: Num, b: Bool, | r} } -> Num,
+                fun {a, b, ..} => if b then a else 0)",
+        )
+        .unwrap();
+        parse_and_typecheck("Promise(forall r. { {| a: Num, | r} } -> Num, fun {a, ..} => a + 1)")
+            .unwrap();
+        parse_and_typecheck("Promise(forall r. { {| a: Num, | r} } -> Num, fun {a} => a + 1)")
+            .unwrap_err();
+
+        parse_and_typecheck("Promise(Num, let {a, b = {c}} = {a = 1; b = {c = 2}} in a + c)")
+            .unwrap();
+        parse_and_typecheck("Promise(Bool, let {a = {c}} = {a = {c = 2}} in c)").unwrap_err();
+
+        // Default values give their type to the variable, and may refer to previous fields. A
+        // field with a default value may be absent.
+        parse_and_typecheck("Promise(Num, (fun {a, b ? a + 1} => b) {a = 1; b = 2})").unwrap();
+        parse_and_typecheck("Promise(Num, (fun {a, b ? 1} => a + b) {a = 1})").unwrap();
+        parse_and_typecheck("Promise(Num, (fun {a, b ? 1} => a + b) {b = 1})").unwrap_err();
+        parse_and_typecheck("Promise(Num, (fun {a ? true} => a) {a = 1})").unwrap_err();
+        parse_and_typecheck("Promise(Num, (fun {a ? b, b} => a) {a = 1; b = 2})").unwrap_err();
+
+        parse_and_typecheck("Promise(List, (fun [x, y] => [y, x]) [1, 2])").unwrap();
+        parse_and_typecheck("Promise(List, (fun [x, y] => [y, x]) 1)").unwrap_err();
+    }
+
+    #[test]
+    fn matches() {
+        parse_and_typecheck(
+            "Promise(< (| a, b, c, |) > -> Num, fun x => match { `a => 1, `b => 2, `c => 3 } x)",
+        )
+        .unwrap();
+        match parse_and_typecheck(
+            "Promise(< (| a, b, c, |) > -> Num, fun x => match { `a => 1, `b => 2 } x)",
+        ) {
+            Err(TypecheckError::NonExhaustiveMatch(tags, false, Some(_), Some(_))) => {
+                assert_eq!(tags, vec![Ident::from("c")])
+            }
+            res => panic!("expected a non-exhaustive match, got {:?}", res),
+        }
+        // A case whose tag is not in the type of the matched value is not a missing case, in
+        // whichever order the tags appear.
+        for cases in &["`a => 1, `b => 2, `c => 3", "`c => 1, `a => 2, `b => 3"] {
+            match parse_and_typecheck(&format!(
+                "Promise(< (| a, b |) > -> Num, fun x => match {{ {} }} x)",
+                cases
+            )) {
+                Err(TypecheckError::MissingRow(id, _, _, _)) => assert_eq!(id, Ident::from("c")),
+                res => panic!("expected a missing row, got {:?}", res),
+            }
+        }
+        match parse_and_typecheck(
+            "Promise(< (| a, b, c |) > -> Num, fun x => match { `b => 1, `a => 2 } x)",
+        ) {
+            Err(TypecheckError::NonExhaustiveMatch(tags, false, _, _)) => {
+                assert_eq!(tags, vec![Ident::from("c")])
+            }
+            res => panic!("expected a non-exhaustive match, got {:?}", res),
+        }
+        match parse_and_typecheck(
+            "Promise(< (| a, b, c, d |) > -> Num, fun x => match { `b => 1 } x)",
+        ) {
+            Err(TypecheckError::NonExhaustiveMatch(tags, false, _, _)) => assert_eq!(
+                tags,
+                vec![Ident::from("a"), Ident::from("c"), Ident::from("d")]
+            ),
+            res => panic!("expected a non-exhaustive match, got {:?}", res),
+        }
+        // The type of the matched value may contain other tags than the ones of the cases if its
+        // row is open.
+        match parse_and_typecheck(
+            "let f = Promise(forall r. < (| foo, bar | r) > -> Num,
+                fun x => match { `foo => 1, `bar => 2 } x) in
+            f `foo",
+        ) {
+            Err(TypecheckError::NonExhaustiveMatch(tags, true, _, _)) => {
+                assert_eq!(tags, Vec::new())
+            }
+            res => panic!("expected a non-exhaustive match, got {:?}", res),
+        }
+        match parse_and_typecheck(
+            "Promise(forall r. < (| foo, bar | r) > -> Num, fun x => match { `foo => 1 } x)",
+        ) {
+            Err(TypecheckError::NonExhaustiveMatch(tags, true, _, _)) => {
+                assert_eq!(tags, vec![Ident::from("bar")])
+            }
+            res => panic!("expected a non-exhaustive match, got {:?}", res),
+        }
+        parse_and_typecheck(
+            "Promise(forall r. < (| foo, bar | r) > -> Num,
+                fun x => match { `foo => 1, `bar => 2, _ => 3 } x)",
+        )
+        .unwrap();
+        parse_and_typecheck(
+            "Promise(< (| a, b, c, |) > -> Num, fun x => match { `a => 1, _ => 2 } x)",
+        )
+        .unwrap();
+        parse_and_typecheck(
+            "Promise(< (| a, b, c, |) > -> Num, fun x => match { `a => 1, `d => 2, y => 3 } x)",
+        )
+        .unwrap_err();
+        parse_and_typecheck("Promise(Num, match { `a => 1, `b => true } `a)").unwrap_err();
+
+        parse_and_typecheck("Promise(Num -> Bool, fun x => match { 0 => true, _ => false } x)")
+            .unwrap();
+        parse_and_typecheck("Promise(Str -> Bool, fun x => match { 0 => true, _ => false } x)")
+            .unwrap_err();
+        parse_and_typecheck("Promise(Num, match { {a, b} => a + b, _ => 0 } {a = 1; b = 2})")
+            .unwrap();
+        parse_and_typecheck("Promise(Num, match { {a} => a } {a = true})").unwrap_err();
+
+        // The cases are not checked outside of a promise.
+        parse_and_typecheck("match { `a => 1, 1 => `a, {x} => x } 1").unwrap();
+    }
+
+    #[test]
+    fn enum_variants() {
+        parse_and_typecheck("Promise(< (| a: Num, b |) >, `a 1)").unwrap();
+        parse_and_typecheck("Promise(< (| a: Num, b |) >, `a true)").unwrap_err();
+        parse_and_typecheck("Promise(< (| a: Num, b |) >, `a)").unwrap_err();
+        parse_and_typecheck("Promise(< (| a, b |) >, `a 1)").unwrap_err();
+
+        parse_and_typecheck(
+            "Promise(< (| tcp: { {| port: Num |} }, stdio |) > -> Num,
+                fun x => match { `tcp {port} => port, `stdio => 0 } x)",
+        )
+        .unwrap();
+        match parse_and_typecheck(
+            "Promise(< (| tcp: { {| port: Num |} }, stdio |) > -> Num,
+                fun x => match { `tcp {port} => port } x)",
+        ) {
+            Err(TypecheckError::NonExhaustiveMatch(tags, false, _, _)) => {
+                assert_eq!(tags, vec![Ident::from("stdio")])
+            }
+            res => panic!("expected a non-exhaustive match, got {:?}", res),
+        }
+        parse_and_typecheck(
+            "Promise(< (| tcp: Num, stdio |) > -> Num,
+                fun x => match { `tcp p => p, `stdio p => p } x)",
+        )
+        .unwrap_err();
+        parse_and_typecheck("Promise(Num, match { `a x => x, `a => 0 } (`a 1))").unwrap_err();
+        parse_and_typecheck("Promise(Num, match { `a x => x + 1, `b => 0 } (`a 1))").unwrap();
+    }
+
     #[test]
     fn dynamic_record_simple() {
         parse_and_typecheck("Promise({ _ : Num }, { $(if true then \"foo\" else \"bar\") = 2; } )")
@@ -2083,7 +2714,7 @@ mod tests {
                 ),
                 resolver,
             )
-        };
+        }
 
         type_check_in_env(
             &mk_import("good", &mut resolver).unwrap(),
@@ -2132,4 +2763,14 @@ mod tests {
             "Promise({ {| f : Num -> Num, |} }, { f = fun x => if isZero x then false else 1 + (f (x + (-1)))})"
         ).unwrap_err();
     }
+
+    #[test]
+    fn cyclic_types() {
+        parse_and_typecheck("Promise(Dyn, fun x => x x)").unwrap_err();
+        parse_and_typecheck(
+            "Promise(Num, let y = fun f => (fun x => f (x x)) (fun x => f (x x)) in 1)",
+        )
+        .unwrap_err();
+        parse_and_typecheck("Promise(Num, let f = fun g => g 1 in f (fun x => x))").unwrap();
+    }
 }
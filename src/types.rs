@@ -25,7 +25,7 @@
 //! can be abstracted over, leaving the row open for future extension. A simple and demonstrative
 //! example is field access:
 //!
-//! ```
+//! ```text
 //! let f = Promise(forall a. { myField : Num, a} -> Num, fun rec => rec.myField)
 //! ```
 //!
@@ -41,8 +41,10 @@
 //!
 //! # Enum types
 //!
-//! An enum type is also a row type, but each list element only contains an identifier without an
-//! associated type. It indicates which tag the enum can contain.
+//! An enum type is also a row type. Each element is a tag, optionally associated with the type of
+//! the value it carries. For example, the type `< (| Tcp: { {| port: Num |} }, Stdio |) >`
+//! indicates that the enum is either the bare tag `` `Stdio ``, or a variant `` `Tcp r `` where
+//! `r` is a record with a numeric field `port`.
 //!
 //! # Contracts
 //!
@@ -52,7 +54,7 @@
 //! enriched values `Contract` or `ContractDefault`. They ensure sane interaction between typed and
 //! untyped parts.
 use crate::identifier::Ident;
-use crate::term::{RichTerm, Term, UnaryOp};
+use crate::term::{BinaryOp, RichTerm, Term, UnaryOp};
 use std::collections::HashMap;
 use std::fmt;
 
@@ -85,7 +87,7 @@ pub enum AbsType<Ty> {
     /// A row type.
     RowExtend(
         Ident,
-        Option<Ty>, /* Type of the field, or None for enums */
+        Option<Ty>, /* Type of the field, or None for bare enum tags */
         Ty,         /* Tail (another row) */
     ),
     /// An enum type, wrapping a row type for enums.
@@ -138,10 +140,7 @@ impl<Ty> AbsType<Ty> {
 
     /// Determine if a type is a row type.
     pub fn is_row_type(&self) -> bool {
-        match self {
-            AbsType::RowExtend(_, _, _) | AbsType::RowEmpty() => true,
-            _ => false,
-        }
+        matches!(self, AbsType::RowExtend(_, _, _) | AbsType::RowEmpty())
     }
 }
 
@@ -163,11 +162,11 @@ impl Types {
     /// # Arguments
     ///
     /// - `h` is an environment mapping type variables to contracts. Type variables are introduced
-    /// locally when opening a `forall`.
+    ///   locally when opening a `forall`.
     /// - `pol` is the current polarity, which is toggled when generating a contract for the argument
-    /// of an arrow type (see [`Label`](../label/struct.label.html)).
+    ///   of an arrow type (see [`Label`](../label/struct.label.html)).
     /// - `sy` is a counter used to generate fresh symbols for `forall` contracts (see `Wrapped` in
-    /// [terms](../term/enum.Term.html).
+    ///   [terms](../term/enum.Term.html).
     pub fn contract_open(
         &self,
         mut h: HashMap<Ident, (RichTerm, RichTerm)>,
@@ -218,16 +217,62 @@ impl Types {
             }
             AbsType::RowEmpty() | AbsType::RowExtend(_, _, _) => RichTerm::var("fail".to_string()),
             AbsType::Enum(ref r) => {
-                fn form(ty: Types, h: HashMap<Ident, (RichTerm, RichTerm)>) -> RichTerm {
+                fn form(
+                    sy: &mut i32,
+                    pol: bool,
+                    ty: Types,
+                    h: HashMap<Ident, (RichTerm, RichTerm)>,
+                ) -> RichTerm {
+                    let x = || Ident("x".to_string());
+
                     match ty.0 {
                         AbsType::RowEmpty() => RichTerm::var("fail".to_string()),
-                        AbsType::RowExtend(_, Some(_), _) => {
-                            panic!("It should be a row without type")
+                        AbsType::RowExtend(id, Some(ty), rest) => {
+                            let payload_contr = ty.contract_open(h.clone(), pol, sy);
+                            let rest_contract = form(sy, pol, *rest, h);
+                            let case = Term::Fun(
+                                x(),
+                                Term::Op1(UnaryOp::IsVariant(id.clone()), Term::Var(x()).into())
+                                    .into(),
+                            );
+                            // Rebuild the variant, with the contract of its type applied to its
+                            // value. The type path of the label goes into the type of the variant,
+                            // so that a blame points to it instead of the whole enum type.
+                            let payload_lbl = Term::Op2(
+                                BinaryOp::GoField(),
+                                Term::Str(id.0.clone()).into(),
+                                RichTerm::var("l".to_string()),
+                            );
+                            let payload = Term::Fun(
+                                Ident("l".to_string()),
+                                Term::Fun(
+                                    x(),
+                                    Term::EnumVariant(
+                                        id,
+                                        RichTerm::app(
+                                            RichTerm::app(payload_contr, payload_lbl.into()),
+                                            Term::Op1(UnaryOp::Payload(), Term::Var(x()).into())
+                                                .into(),
+                                        ),
+                                    )
+                                    .into(),
+                                )
+                                .into(),
+                            );
+
+                            RichTerm::app(
+                                RichTerm::app(
+                                    RichTerm::app(
+                                        RichTerm::var("variant_extend".to_string()),
+                                        rest_contract,
+                                    ),
+                                    case.into(),
+                                ),
+                                payload.into(),
+                            )
                         }
                         AbsType::RowExtend(id, None, rest) => {
-                            let rest_contract = form(*rest, h);
-                            let mut map = HashMap::new();
-                            map.insert(id, Term::Bool(true).into());
+                            let rest_contract = form(sy, pol, *rest, h);
 
                             RichTerm::app(
                                 RichTerm::app(
@@ -235,10 +280,11 @@ impl Types {
                                     rest_contract,
                                 ),
                                 Term::Fun(
-                                    Ident("x".to_string()),
-                                    Term::Op1(
-                                        UnaryOp::Switch(map, Some(Term::Bool(false).into())),
-                                        Term::Var(Ident("x".to_string())).into(),
+                                    x(),
+                                    Term::Op2(
+                                        BinaryOp::Eq(),
+                                        Term::Var(x()).into(),
+                                        Term::Enum(id).into(),
                                     )
                                     .into(),
                                 )
@@ -255,7 +301,7 @@ impl Types {
                     }
                 }
 
-                form(*r.clone(), h)
+                form(sy, pol, *r.clone(), h)
             }
             AbsType::StaticRecord(ref ty) => {
                 fn form(
@@ -268,7 +314,7 @@ impl Types {
                         AbsType::RowEmpty() => RichTerm::var(String::from("empty_tail")),
                         AbsType::Var(id) => {
                             let (_, rt) = h
-                                .get(&id)
+                                .get(id)
                                 .unwrap_or_else(|| panic!("Unbound type variable {:?}", id));
                             rt.clone()
                         }
@@ -332,7 +378,7 @@ impl Types {
         let next = self.row_find(&path[0]);
 
         if path.len() == 1 {
-            return next;
+            next
         } else {
             match next {
                 Some(ty) => ty.row_find_path(&path[1..]),
@@ -362,10 +408,16 @@ impl fmt::Display for Types {
                 }
                 write!(f, ". {}", curr)
             }
-            AbsType::Enum(row) => write!(f, "< (| {}) >", row),
-            AbsType::StaticRecord(row) => write!(f, "{{ {{| {}}} }}", row),
+            AbsType::Enum(row) => match row.0 {
+                AbsType::Var(_) => write!(f, "< (| | {}) >", row),
+                _ => write!(f, "< (| {}) >", row),
+            },
+            AbsType::StaticRecord(row) => match row.0 {
+                AbsType::Var(_) => write!(f, "{{ {{| | {}}} }}", row),
+                _ => write!(f, "{{ {{| {}}} }}", row),
+            },
             AbsType::DynRecord(ty) => write!(f, "{{_: {}}}", ty),
-            AbsType::RowEmpty() => write!(f, " |"),
+            AbsType::RowEmpty() => write!(f, "|"),
             AbsType::RowExtend(Ident(id), ty_opt, tail) => {
                 write!(f, "{}", id)?;
 
@@ -374,7 +426,7 @@ impl fmt::Display for Types {
                 }
 
                 match tail.0 {
-                    AbsType::RowEmpty() => write!(f, "{}", tail),
+                    AbsType::RowEmpty() => write!(f, " {}", tail),
                     AbsType::Var(_) => write!(f, " | {}", tail),
                     _ => write!(f, ", {}", tail),
                 }
@@ -402,7 +454,9 @@ mod test {
         let id = Files::new().add("<test>", wrapper.clone());
         println!("wrapper: {}", wrapper);
 
-        let rt = TermParser::new().parse(id, Lexer::new(&wrapper)).unwrap();
+        let rt = TermParser::new()
+            .parse(id, &mut Vec::new(), Lexer::new(&wrapper))
+            .unwrap();
 
         match *rt.term {
             Term::Contract(ty, _) => ty,
@@ -433,8 +487,14 @@ mod test {
 
         assert_format_eq("{ {| x: (Bool -> Bool) -> Bool, y: Bool |} }");
         assert_format_eq("{ {| x: Bool, y: Bool, z: Bool | r} }");
+        assert_format_eq("{ {| |} }");
+        assert_format_eq("{ {| | r} }");
 
         assert_format_eq("< (| a, b, c, d |) >");
         assert_format_eq("< (| tag1, tag2, tag3 | r) >");
+        assert_format_eq("< (| tcp: { {| port: Num |} }, stdio |) >");
+        assert_format_eq("< (| a: Num, b: Str -> Str | r) >");
+        assert_format_eq("< (| |) >");
+        assert_format_eq("forall r. < (| | r) > -> Num");
     }
 }
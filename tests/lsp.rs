@@ -0,0 +1,119 @@
+//! Run the language server as `nickel lsp`, and talk to it over its standard input and output.
+use lsp_server::{Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidOpenTextDocument, Exit, Initialized, Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::{HoverRequest, Initialize, Request as RequestTrait, Shutdown};
+use lsp_types::{
+    DidOpenTextDocumentParams, Hover, HoverContents, HoverParams, InitializeParams,
+    InitializedParams, Position, PublishDiagnosticsParams, TextDocumentIdentifier,
+    TextDocumentItem, TextDocumentPositionParams, Url,
+};
+use std::io::{BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// A scripted client, talking to a server running in a child process.
+struct Client {
+    server: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: i32,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_nickel"))
+            .arg("lsp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start the language server");
+        let stdin = server.stdin.take().unwrap();
+        let stdout = BufReader::new(server.stdout.take().unwrap());
+
+        Client {
+            server,
+            stdin,
+            stdout,
+            next_id: 0,
+        }
+    }
+
+    fn send(&mut self, msg: Message) {
+        msg.write(&mut self.stdin).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Message {
+        Message::read(&mut self.stdout)
+            .unwrap()
+            .expect("the language server closed its output")
+    }
+
+    fn request<R: RequestTrait>(&mut self, params: R::Params) -> R::Result {
+        self.next_id += 1;
+        let id = RequestId::from(self.next_id);
+        self.send(Request::new(id.clone(), String::from(R::METHOD), params).into());
+
+        match self.receive() {
+            Message::Response(Response {
+                id: resp_id,
+                result,
+                error: None,
+            }) => {
+                assert_eq!(resp_id, id);
+                // A `null` result, as the one of `shutdown`, is read back as no result.
+                serde_json::from_value(result.unwrap_or_default()).unwrap()
+            }
+            msg => panic!("expected a response, got {:?}", msg),
+        }
+    }
+
+    fn notify<N: NotificationTrait>(&mut self, params: N::Params) {
+        self.send(Notification::new(String::from(N::METHOD), params).into());
+    }
+}
+
+#[test]
+fn stdio() {
+    let mut client = Client::start();
+    let uri = Url::parse("file:///test.ncl").unwrap();
+
+    let result = client.request::<Initialize>(InitializeParams::default());
+    assert!(result.capabilities.hover_provider.is_some());
+    client.notify::<Initialized>(InitializedParams {});
+
+    client.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+        text_document: TextDocumentItem::new(
+            uri.clone(),
+            String::from("nickel"),
+            0,
+            String::from("let x = Assume(Num, 1) in x + 1"),
+        ),
+    });
+    match client.receive() {
+        Message::Notification(notif) => {
+            let params: PublishDiagnosticsParams =
+                notif.extract(PublishDiagnostics::METHOD).unwrap();
+            assert_eq!(params.uri, uri);
+            assert_eq!(params.diagnostics, Vec::new());
+        }
+        msg => panic!("expected diagnostics, got {:?}", msg),
+    }
+
+    let hover: Option<Hover> = client.request::<HoverRequest>(HoverParams {
+        text_document_position_params: TextDocumentPositionParams::new(
+            TextDocumentIdentifier::new(uri),
+            Position::new(0, 26),
+        ),
+        work_done_progress_params: Default::default(),
+    });
+    match hover.expect("expected hover information").contents {
+        HoverContents::Markup(content) => assert!(content.value.contains("x : Num")),
+        contents => panic!("unexpected hover contents {:?}", contents),
+    }
+
+    client.request::<Shutdown>(());
+    client.notify::<Exit>(());
+    assert!(client.server.wait().unwrap().success());
+}